@@ -0,0 +1 @@
+"""Python-side helpers for hackweek-instant-codecoverage."""
@@ -0,0 +1,48 @@
+"""coverage.py plugin that labels every line with the test that ran it.
+
+The dynamic context of a test function is its pytest node id without
+parameters, e.g. ``tests/test_calc.py::TestAdd::test_add``, which is the
+format the engine's impact data is keyed by. Enable it with::
+
+    [run]
+    plugins = instant_patch.coverage_plugin
+
+The engine generates such an rcfile for the default runner, so this is only
+needed when running coverage by hand.
+"""
+
+import os
+
+import coverage
+
+
+def _is_test_file(path):
+    name = os.path.basename(path)
+    return name.endswith(".py") and (
+        name.startswith("test_") or name.endswith("_test.py")
+    )
+
+
+class TestContexts(coverage.CoveragePlugin):
+    def __init__(self):
+        self.root = os.getcwd()
+
+    def dynamic_context(self, frame):
+        code = frame.f_code
+        if not code.co_name.startswith("test") or not _is_test_file(code.co_filename):
+            return None
+        path = os.path.relpath(code.co_filename, self.root).replace(os.sep, "/")
+        qualname = getattr(code, "co_qualname", None)
+        if qualname is not None and "<locals>" not in qualname:
+            names = qualname.split(".")
+        else:
+            # before Python 3.11, recover the class from the bound instance
+            owner = frame.f_locals.get("self")
+            names = [code.co_name]
+            if owner is not None:
+                names.insert(0, type(owner).__name__)
+        return "::".join([path] + names)
+
+
+def coverage_init(reg, options):
+    reg.add_dynamic_context(TestContexts())
@@ -0,0 +1,73 @@
+"""pytest plugin that streams collection and results to the engine.
+
+The engine loads it with ``-p instant_patch.pytest_plugin`` and sets
+``INSTANT_PATCH_REPORT`` to the ``host:port`` it listens on. Every event is
+one JSON object per line:
+
+    {"event": "collected", "nodeid": "tests/test_calc.py::test_add"}
+    {"event": "report", "nodeid": "...", "when": "call", "outcome": "passed", "duration": 0.01}
+    {"event": "finished", "exitstatus": 0}
+
+Without the variable the plugin does nothing.
+"""
+
+import json
+import os
+import socket
+
+ENV = "INSTANT_PATCH_REPORT"
+
+
+class _Connection:
+    def __init__(self, address):
+        host, _, port = address.rpartition(":")
+        self._socket = socket.create_connection((host, int(port)))
+        self._file = self._socket.makefile("w", encoding="utf-8")
+
+    def send(self, **event):
+        self._file.write(json.dumps(event) + "\n")
+        self._file.flush()
+
+    def close(self):
+        self._file.close()
+        self._socket.close()
+
+
+_connection = None
+
+
+def pytest_configure(config):
+    global _connection
+    address = os.environ.get(ENV)
+    # xdist workers report to the controller, which reports to the engine
+    if not address or hasattr(config, "workerinput") or _connection is not None:
+        return
+    try:
+        _connection = _Connection(address)
+    except (OSError, ValueError):
+        _connection = None
+
+
+def pytest_collection_finish(session):
+    if _connection is not None:
+        for item in session.items:
+            _connection.send(event="collected", nodeid=item.nodeid)
+
+
+def pytest_runtest_logreport(report):
+    if _connection is not None:
+        _connection.send(
+            event="report",
+            nodeid=report.nodeid,
+            when=report.when,
+            outcome=report.outcome,
+            duration=report.duration,
+        )
+
+
+def pytest_sessionfinish(session, exitstatus):
+    global _connection
+    if _connection is not None:
+        _connection.send(event="finished", exitstatus=int(exitstatus))
+        _connection.close()
+        _connection = None
@@ -0,0 +1,104 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::selection::{SelectedTest, SelectionContext, TestSelector};
+
+/// Command template used with the Bazel strategy; test ids are target labels.
+pub const BAZEL_COMMAND_TEMPLATE: &str = "bazel test {tests}";
+
+/// Files marking the root of a Bazel workspace.
+const WORKSPACE_FILES: [&str; 3] = ["MODULE.bazel", "WORKSPACE", "WORKSPACE.bazel"];
+
+/// Whether `root` is the root of a Bazel workspace.
+pub fn is_workspace(root: &Path) -> bool {
+    WORKSPACE_FILES.iter().any(|name| root.join(name).is_file())
+}
+
+/// Query for the `kind` test targets that depend on any of `paths`, given
+/// relative to the workspace root.
+pub fn rdeps_query(kind: &str, paths: &[&str]) -> String {
+    let files: Vec<String> = paths
+        .iter()
+        .map(|path| format!("\"{}\"", path.replace('"', "")))
+        .collect();
+    format!("kind({}, rdeps(//..., set({})))", kind, files.join(" "))
+}
+
+/// Selects the test targets whose dependencies include a changed file, as
+/// reported by `bazel query`. Run the selection with
+/// `BAZEL_COMMAND_TEMPLATE`.
+pub struct BazelSelector {
+    root: PathBuf,
+    binary: String,
+    kind: String,
+}
+
+impl BazelSelector {
+    pub fn new<P: Into<PathBuf>>(root: P) -> BazelSelector {
+        BazelSelector {
+            root: root.into(),
+            binary: "bazel".to_string(),
+            kind: "py_test".to_string(),
+        }
+    }
+
+    /// The Bazel executable, `bazel` by default.
+    pub fn binary<S: Into<String>>(mut self, binary: S) -> BazelSelector {
+        self.binary = binary.into();
+        self
+    }
+
+    /// The rule kind of test targets, `py_test` by default.
+    pub fn kind<S: Into<String>>(mut self, kind: S) -> BazelSelector {
+        self.kind = kind.into();
+        self
+    }
+
+    fn query(&self, paths: &[&str]) -> Result<Vec<String>, String> {
+        let output = Command::new(&self.binary)
+            .args(["query", "--keep_going", "--output=label"])
+            .arg(rdeps_query(&self.kind, paths))
+            .current_dir(&self.root)
+            .output()
+            .map_err(|e| format!("{}: {}", self.binary, e))?;
+        // 3 means some files were not in any package, which happens for
+        // deleted and unbuilt files; the rest of the answer still holds
+        if !matches!(output.status.code(), Some(0) | Some(3)) {
+            return Err(format!(
+                "bazel query failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("//") || line.starts_with('@'))
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+impl TestSelector for BazelSelector {
+    fn name(&self) -> &str {
+        "bazel"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let paths: BTreeSet<&str> = ctx.hunks.iter().map(|hunk| hunk.path.as_str()).collect();
+        if paths.is_empty() {
+            return Vec::new();
+        }
+        match self.query(&paths.into_iter().collect::<Vec<_>>()) {
+            Ok(labels) => labels
+                .into_iter()
+                .map(|label| SelectedTest::new(label.as_str(), "depends on a changed file"))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("{}", e);
+                Vec::new()
+            }
+        }
+    }
+}
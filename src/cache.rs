@@ -0,0 +1,272 @@
+use git2::{ObjectType, Oid};
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::selection::ImpactData;
+use crate::statefile;
+
+/// The database in the state directory holding recorded coverage.
+pub const CACHE_FILE: &str = "impact.db";
+
+/// How many base commits keep their coverage. Storing data for another
+/// base drops the least recently stored beyond this.
+pub const KEPT_BASES: usize = 8;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS base (rev TEXT PRIMARY KEY, stored INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS line (
+    rev TEXT NOT NULL,
+    test TEXT NOT NULL,
+    path TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    lines TEXT NOT NULL,
+    PRIMARY KEY (rev, test, path)
+);
+CREATE TABLE IF NOT EXISTS arc (
+    rev TEXT NOT NULL,
+    path TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    arcs TEXT NOT NULL,
+    PRIMARY KEY (rev, path)
+);
+";
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("failed to create {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("impact cache {path}: {source}")]
+    Sqlite {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+    #[error("impact cache {path} holds malformed data: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// Per-test coverage kept between sessions in `.instant-patch/impact.db`,
+/// so a restart starts from what earlier runs recorded instead of running
+/// everything again. Data is keyed by the base commit it was gathered
+/// against and by the git blob hash of each file when it was recorded; a
+/// file's data is dropped once its content no longer matches, since its
+/// line numbers may no longer hold.
+#[derive(Debug, Clone)]
+pub struct ImpactCache {
+    path: PathBuf,
+}
+
+impl ImpactCache {
+    /// Opens the cache of the repository at `root`, creating it if needed.
+    pub fn open(root: &Path) -> Result<ImpactCache, CacheError> {
+        let dir = statefile::state_dir(root).map_err(|source| CacheError::Io {
+            path: root.join(statefile::STATE_DIR),
+            source,
+        })?;
+        let cache = ImpactCache {
+            path: dir.join(CACHE_FILE),
+        };
+        cache
+            .connect()?
+            .execute_batch(SCHEMA)
+            .map_err(|e| cache.sqlite(e))?;
+        Ok(cache)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn connect(&self) -> Result<Connection, CacheError> {
+        Connection::open(&self.path).map_err(|e| self.sqlite(e))
+    }
+
+    fn sqlite(&self, source: rusqlite::Error) -> CacheError {
+        CacheError::Sqlite {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    fn json(&self, source: serde_json::Error) -> CacheError {
+        CacheError::Json {
+            path: self.path.clone(),
+            source,
+        }
+    }
+
+    /// The coverage recorded against `rev` for files under `root` that still
+    /// read as they did when it was recorded. Data for files that changed
+    /// since is deleted.
+    pub fn load(&self, rev: &str, root: &Path) -> Result<ImpactData, CacheError> {
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction().map_err(|e| self.sqlite(e))?;
+        let mut hashes = FileHashes::new(root);
+        let mut impact = ImpactData::default();
+        let mut stale: BTreeSet<String> = BTreeSet::new();
+        {
+            let mut statement = transaction
+                .prepare("SELECT test, path, hash, lines FROM line WHERE rev = ?1")
+                .map_err(|e| self.sqlite(e))?;
+            let mut rows = statement.query([rev]).map_err(|e| self.sqlite(e))?;
+            while let Some(row) = rows.next().map_err(|e| self.sqlite(e))? {
+                let (test, path, hash, lines): (String, String, String, String) = (
+                    row.get(0).map_err(|e| self.sqlite(e))?,
+                    row.get(1).map_err(|e| self.sqlite(e))?,
+                    row.get(2).map_err(|e| self.sqlite(e))?,
+                    row.get(3).map_err(|e| self.sqlite(e))?,
+                );
+                if hashes.get(&path) != Some(hash.as_str()) {
+                    stale.insert(path);
+                    continue;
+                }
+                let lines: Vec<usize> = serde_json::from_str(&lines).map_err(|e| self.json(e))?;
+                impact
+                    .lines
+                    .entry(test)
+                    .or_default()
+                    .entry(path)
+                    .or_default()
+                    .extend(lines);
+            }
+
+            let mut statement = transaction
+                .prepare("SELECT path, hash, arcs FROM arc WHERE rev = ?1")
+                .map_err(|e| self.sqlite(e))?;
+            let mut rows = statement.query([rev]).map_err(|e| self.sqlite(e))?;
+            while let Some(row) = rows.next().map_err(|e| self.sqlite(e))? {
+                let (path, hash, arcs): (String, String, String) = (
+                    row.get(0).map_err(|e| self.sqlite(e))?,
+                    row.get(1).map_err(|e| self.sqlite(e))?,
+                    row.get(2).map_err(|e| self.sqlite(e))?,
+                );
+                if hashes.get(&path) != Some(hash.as_str()) {
+                    stale.insert(path);
+                    continue;
+                }
+                let arcs: BTreeMap<usize, BTreeSet<i64>> =
+                    serde_json::from_str(&arcs).map_err(|e| self.json(e))?;
+                impact.arcs.insert(
+                    path,
+                    arcs.into_iter()
+                        .map(|(from, to)| (from, to.into_iter().collect()))
+                        .collect(),
+                );
+            }
+        }
+        if !stale.is_empty() {
+            tracing::debug!(
+                files = stale.len(),
+                "dropping cached coverage of changed files"
+            );
+        }
+        for path in &stale {
+            transaction
+                .execute("DELETE FROM line WHERE rev = ?1 AND path = ?2", [rev, path])
+                .and_then(|_| {
+                    transaction.execute("DELETE FROM arc WHERE rev = ?1 AND path = ?2", [rev, path])
+                })
+                .map_err(|e| self.sqlite(e))?;
+        }
+        transaction.commit().map_err(|e| self.sqlite(e))?;
+        Ok(impact)
+    }
+
+    /// Replaces what is kept for `rev` with `impact`, hashing each file
+    /// under `root` as it reads now. Files that no longer exist are left
+    /// out.
+    pub fn store(&self, rev: &str, impact: &ImpactData, root: &Path) -> Result<(), CacheError> {
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction().map_err(|e| self.sqlite(e))?;
+        let mut hashes = FileHashes::new(root);
+        transaction
+            .execute("DELETE FROM line WHERE rev = ?1", [rev])
+            .and_then(|_| transaction.execute("DELETE FROM arc WHERE rev = ?1", [rev]))
+            .map_err(|e| self.sqlite(e))?;
+        for (test, files) in &impact.lines {
+            for (path, lines) in files {
+                let Some(hash) = hashes.get(path) else {
+                    continue;
+                };
+                let lines: BTreeSet<usize> = lines.iter().copied().collect();
+                let lines = serde_json::to_string(&lines).map_err(|e| self.json(e))?;
+                transaction
+                    .execute(
+                        "INSERT INTO line (rev, test, path, hash, lines) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![rev, test, path, hash, lines],
+                    )
+                    .map_err(|e| self.sqlite(e))?;
+            }
+        }
+        for (path, arcs) in &impact.arcs {
+            let Some(hash) = hashes.get(path) else {
+                continue;
+            };
+            let arcs: BTreeMap<usize, BTreeSet<i64>> = arcs
+                .iter()
+                .map(|(from, to)| (*from, to.iter().copied().collect()))
+                .collect();
+            let arcs = serde_json::to_string(&arcs).map_err(|e| self.json(e))?;
+            transaction
+                .execute(
+                    "INSERT INTO arc (rev, path, hash, arcs) VALUES (?1, ?2, ?3, ?4)",
+                    params![rev, path, hash, arcs],
+                )
+                .map_err(|e| self.sqlite(e))?;
+        }
+        transaction
+            .execute(
+                "INSERT INTO base (rev, stored) VALUES (?1, \
+                 (SELECT coalesce(max(stored), 0) + 1 FROM base)) \
+                 ON CONFLICT (rev) DO UPDATE SET stored = excluded.stored",
+                [rev],
+            )
+            .map_err(|e| self.sqlite(e))?;
+        transaction
+            .execute_batch(&format!(
+                "DELETE FROM base WHERE rev NOT IN \
+                 (SELECT rev FROM base ORDER BY stored DESC LIMIT {kept});
+                 DELETE FROM line WHERE rev NOT IN (SELECT rev FROM base);
+                 DELETE FROM arc WHERE rev NOT IN (SELECT rev FROM base);",
+                kept = KEPT_BASES
+            ))
+            .map_err(|e| self.sqlite(e))?;
+        transaction.commit().map_err(|e| self.sqlite(e))
+    }
+}
+
+/// Git blob hashes of files under a root, each read once.
+struct FileHashes<'a> {
+    root: &'a Path,
+    hashes: HashMap<String, Option<String>>,
+}
+
+impl<'a> FileHashes<'a> {
+    fn new(root: &'a Path) -> FileHashes<'a> {
+        FileHashes {
+            root,
+            hashes: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, path: &str) -> Option<&str> {
+        let root = self.root;
+        self.hashes
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let content = fs::read(root.join(path)).ok()?;
+                Oid::hash_object(ObjectType::Blob, &content)
+                    .ok()
+                    .map(|oid| oid.to_string())
+            })
+            .as_deref()
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use tree_sitter::{Node, Tree};
+
+use crate::diff::BetterDiff;
+use crate::imports::ImportGraph;
+use crate::language::Language;
+use crate::selection::{SelectedTest, SelectionContext, TestSelector};
+
+/// How many calls deep a test is followed looking for a changed function:
+/// the test's own calls, the helpers they call, and theirs.
+pub const CALL_DEPTH: usize = 3;
+
+/// A function definition and the names of everything it calls, including
+/// from functions nested in it. Methods are called through an attribute,
+/// so calls are recorded by bare name and a method matches any function of
+/// that name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub path: String,
+    pub name: String,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub calls: BTreeSet<String>,
+}
+
+impl Function {
+    /// Whether `hunk` changed any of the definition.
+    pub fn touched_by(&self, hunk: &BetterDiff) -> bool {
+        hunk.path == self.path && hunk.touches(&(self.start_byte..self.end_byte))
+    }
+}
+
+/// Every function defined in the parsed files. Only Python is understood;
+/// other languages have none.
+pub fn functions(
+    language: Language,
+    content: &HashMap<String, String>,
+    trees: &HashMap<String, Tree>,
+) -> Vec<Function> {
+    if !language.is_python() {
+        return Vec::new();
+    }
+    let mut functions = Vec::new();
+    for (path, tree) in trees {
+        let Some(source) = content.get(path) else {
+            continue;
+        };
+        for node in descendants(tree.root_node()) {
+            if node.kind() != "function_definition" {
+                continue;
+            }
+            let Some(name) = node
+                .child_by_field_name("name")
+                .and_then(|name| name.utf8_text(source.as_bytes()).ok())
+            else {
+                continue;
+            };
+            functions.push(Function {
+                path: path.clone(),
+                name: name.to_string(),
+                start_byte: node.start_byte(),
+                end_byte: node.end_byte(),
+                calls: called_names(node, source.as_bytes()),
+            });
+        }
+    }
+    functions.sort_by(|a, b| (&a.path, a.start_byte).cmp(&(&b.path, b.start_byte)));
+    functions
+}
+
+fn called_names(function: Node, source: &[u8]) -> BTreeSet<String> {
+    descendants(function)
+        .into_iter()
+        .filter(|node| node.kind() == "call")
+        .filter_map(|call| {
+            let callee = call.child_by_field_name("function")?;
+            let name = match callee.kind() {
+                "attribute" => callee.child_by_field_name("attribute")?,
+                "identifier" => callee,
+                _ => return None,
+            };
+            name.utf8_text(source).ok().map(str::to_string)
+        })
+        .collect()
+}
+
+fn descendants(node: Node) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut cursor = node.walk();
+    'outer: loop {
+        nodes.push(cursor.node());
+        if cursor.goto_first_child() {
+            continue;
+        }
+        loop {
+            if cursor.node() == node {
+                break 'outer;
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+            if !cursor.goto_parent() {
+                break 'outer;
+            }
+        }
+    }
+    nodes
+}
+
+/// The first of `targets` reached from `calls` within `depth` calls,
+/// following what `callees` says each name calls.
+pub fn reaches<'a>(
+    calls: &'a BTreeSet<String>,
+    callees: &HashMap<&str, BTreeSet<&'a str>>,
+    targets: &BTreeSet<&str>,
+    depth: usize,
+) -> Option<&'a str> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut frontier: BTreeSet<&str> = calls.iter().map(String::as_str).collect();
+    for _ in 0..depth {
+        if let Some(target) = frontier.iter().find(|name| targets.contains(*name)) {
+            return Some(target);
+        }
+        seen.extend(frontier.iter().copied());
+        frontier = frontier
+            .iter()
+            .flat_map(|name| callees.get(name).into_iter().flatten().copied())
+            .filter(|name| !seen.contains(name))
+            .collect();
+    }
+    None
+}
+
+/// Selects the tests that call a changed function, directly or through up
+/// to `CALL_DEPTH` levels of helpers, standing in for coverage before any
+/// has been recorded. A change outside every function, such as to a
+/// module-level constant, selects the tests in files importing the file
+/// instead.
+pub struct CallSelector {
+    language: Language,
+}
+
+impl CallSelector {
+    pub fn new(language: Language) -> CallSelector {
+        CallSelector { language }
+    }
+}
+
+impl TestSelector for CallSelector {
+    fn name(&self) -> &str {
+        "calls"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let functions = functions(self.language, ctx.new_content, ctx.new_trees);
+        let mut changed: BTreeSet<&str> = BTreeSet::new();
+        let mut outside: BTreeSet<&str> = BTreeSet::new();
+        for hunk in ctx.hunks {
+            let touched: Vec<&Function> = functions.iter().filter(|f| f.touched_by(hunk)).collect();
+            if touched.is_empty() {
+                outside.insert(hunk.path.as_str());
+            }
+            changed.extend(touched.iter().map(|f| f.name.as_str()));
+        }
+
+        let mut callees: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+        for function in &functions {
+            callees
+                .entry(function.name.as_str())
+                .or_default()
+                .extend(function.calls.iter().map(String::as_str));
+        }
+        let definitions: HashMap<(&str, &str), &Function> = functions
+            .iter()
+            .map(|f| ((f.path.as_str(), f.name.as_str()), f))
+            .collect();
+        let dependents = match outside.is_empty() {
+            true => BTreeMap::new(),
+            false => ImportGraph::build(
+                self.language,
+                ctx.new_content,
+                ctx.new_trees,
+                ctx.old_content,
+            )
+            .dependents(outside),
+        };
+
+        ctx.new_tests
+            .iter()
+            .filter_map(|id| {
+                let (file, name) = id.split_once("::")?;
+                let name = name.rsplit("::").next().unwrap_or(name);
+                let called = definitions
+                    .get(&(file, name))
+                    .and_then(|test| reaches(&test.calls, &callees, &changed, CALL_DEPTH));
+                let reason = match (called, dependents.get(file)) {
+                    (Some(function), _) => format!("calls {}", function),
+                    (None, Some(origin)) => format!("imports {}", origin),
+                    (None, None) => return None,
+                };
+                Some(SelectedTest::new(id.clone(), reason))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+use crate::vcs;
+
+#[derive(Debug, Error)]
+pub enum CiError {
+    #[error("no base to diff against: {0}")]
+    NoBase(String),
+    #[error("failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to fetch base {base}: {message}")]
+    Fetch { base: String, message: String },
+    #[error("upload failed: {0}")]
+    Upload(String),
+    #[error("failed to post comment: {0}")]
+    Comment(String),
+}
+
+/// Where a pull or merge request's base lives and how to fetch it if the
+/// checkout does not have it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrBase {
+    pub rev: String,
+    pub refspec: String,
+}
+
+impl PrBase {
+    pub fn commit(sha: &str) -> PrBase {
+        PrBase {
+            rev: sha.to_string(),
+            refspec: sha.to_string(),
+        }
+    }
+
+    pub fn branch(name: &str) -> PrBase {
+        PrBase {
+            rev: format!("origin/{}", name),
+            refspec: format!("+refs/heads/{0}:refs/remotes/origin/{0}", name),
+        }
+    }
+}
+
+/// Fetches the base from `origin` unless it is already present. Shallow
+/// checkouts, the default on most CI systems, only get the one base commit.
+pub fn ensure_fetched(root: &Path, base: &PrBase) -> Result<(), CiError> {
+    let fetch_error = |message: String| CiError::Fetch {
+        base: base.rev.clone(),
+        message,
+    };
+    let repo = vcs::open_git(root).map_err(|e| fetch_error(e.to_string()))?;
+    if repo.revparse_single(&base.rev).is_ok() {
+        return Ok(());
+    }
+    let mut git = Command::new("git");
+    git.current_dir(root).args(["fetch", "--no-tags"]);
+    if repo.is_shallow() {
+        git.arg("--depth=1");
+    }
+    let output = git
+        .args(["origin", &base.refspec])
+        .output()
+        .map_err(|e| fetch_error(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(fetch_error(stderr.trim().to_string()));
+    }
+    Ok(())
+}
+
+pub fn append_file(path: &Path, content: &str) -> Result<(), CiError> {
+    let io = |source| CiError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(io)
+}
+
+/// Posts the outcome of a one-shot CI run to Slack when `SLACK_BOT_TOKEN`
+/// and `SLACK_CHANNEL`, or `SLACK_WEBHOOK_URL`, are set. Failing to notify is
+/// only a warning.
+#[cfg(feature = "slack")]
+pub fn notify_slack(
+    root: &Path,
+    selection: &crate::selection::Selection,
+    result: &crate::runner::RunResult,
+    coverage: &crate::coverage::PatchCoverage,
+) {
+    use crate::slack::{SlackNotifier, SlackTarget};
+
+    let target = match SlackTarget::from_env(|name| std::env::var(name).ok()) {
+        Some(target) => target,
+        None => return,
+    };
+    let failing = crate::nvim::failing_tests(root, result);
+    let ids: Vec<&str> = failing.iter().map(|test| test.id.as_str()).collect();
+    if let Err(e) = SlackNotifier::new(target).notify(selection, result, coverage, &ids) {
+        tracing::warn!("{}", e);
+    }
+}
@@ -0,0 +1,91 @@
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::ci::CiError;
+use crate::coverage::PatchCoverage;
+use crate::engine::{Engine, EngineError};
+use crate::runner;
+use crate::vcs;
+
+/// Flag uploads are tagged with, so Codecov can carry selective results
+/// forward alongside full CI runs.
+pub const DEFAULT_FLAG: &str = "instant-patch";
+
+/// Converts patch coverage to Codecov's JSON format: path -> line -> hits,
+/// or `"1/2"` for a partial branch. Only changed lines are listed, so lines
+/// this run did not look at are left for other uploads to account for.
+pub fn codecov_json(coverage: &PatchCoverage) -> Value {
+    let files: Map<String, Value> = coverage
+        .files
+        .iter()
+        .map(|file| {
+            let lines: Map<String, Value> = file
+                .changed_lines
+                .iter()
+                .map(|line| {
+                    let hits = match file.partial_lines.contains(line) {
+                        true => json!("1/2"),
+                        false => json!(usize::from(file.covered_lines.contains(line))),
+                    };
+                    (line.to_string(), hits)
+                })
+                .collect();
+            (file.path.clone(), Value::Object(lines))
+        })
+        .collect();
+    json!({ "coverage": files })
+}
+
+/// Arguments for the Codecov CLI to upload `report` for commit `head`,
+/// compared against `parent`.
+pub fn upload_args(report: &Path, flag: &str, head: &str, parent: &str) -> Vec<String> {
+    [
+        "codecovcli",
+        "upload-process",
+        "--disable-search",
+        "--file",
+        &report.to_string_lossy(),
+        "--flag",
+        flag,
+        "--sha",
+        head,
+        "--parent-sha",
+        parent,
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+/// Runs one cycle, writes the changed lines' coverage to `output` and, when
+/// `upload` is set, hands it to `codecovcli` tagged with `flag`. Returns
+/// whether the tests passed.
+pub fn report(
+    engine: &Engine,
+    output: &Path,
+    upload: bool,
+    flag: &str,
+) -> Result<bool, EngineError> {
+    let result = engine.run_once()?;
+    let coverage = engine.patch_coverage()?;
+    let report = serde_json::to_string_pretty(&codecov_json(&coverage)).map_err(CiError::from)?;
+    fs::write(output, report).map_err(|source| CiError::Io {
+        path: output.to_path_buf(),
+        source,
+    })?;
+    if upload {
+        let repo = vcs::open_git(engine.root())?;
+        let head = repo.head()?.peel_to_commit()?.id().to_string();
+        let parent = engine.base_commit()?;
+        let args = upload_args(output, flag, &head, &parent);
+        let status = runner::command(&args)
+            .current_dir(engine.root())
+            .status()
+            .map_err(|e| CiError::Upload(format!("{}: {}", args[0], e)))?;
+        if !status.success() {
+            return Err(CiError::Upload(format!("{} exited with {}", args[0], status)).into());
+        }
+    }
+    Ok(result.success())
+}
@@ -0,0 +1,200 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use toml::{Table, Value};
+
+use crate::language::Language;
+use crate::selection::{EmptySelection, ImpactStrategy};
+
+/// Project config file, read from the repository root.
+pub const CONFIG_FILE: &str = ".instantcov.toml";
+
+/// The newest config format this build understands.
+pub const CURRENT_VERSION: i64 = 1;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("`version` must be an integer")]
+    InvalidVersion,
+    #[error("config version {found} is newer than the supported version {CURRENT_VERSION}; upgrade the tool")]
+    UnsupportedVersion { found: i64 },
+}
+
+/// What the working tree is compared against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BaseMode {
+    /// The commit `base` names.
+    #[default]
+    Commit,
+    /// The newest commit both `HEAD` and `base` descend from, so a branch is
+    /// measured by every change made on it.
+    MergeBase,
+}
+
+impl BaseMode {
+    /// The base used when none is given: `HEAD`, or `origin/main` to measure
+    /// a branch against.
+    pub fn default_base(self) -> &'static str {
+        match self {
+            BaseMode::Commit => "HEAD",
+            BaseMode::MergeBase => "origin/main",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub version: i64,
+    /// Branch, tag or commit to diff against.
+    pub base: Option<String>,
+    /// `"commit"` or `"merge-base"`.
+    pub base_mode: Option<BaseMode>,
+    /// Test command, with `{tests}` standing in for the selected ids.
+    pub command: Option<String>,
+    pub language: Option<Language>,
+    /// `"skip"`, `"all"` or `{ smoke = ["id", ...] }`.
+    pub on_empty: Option<EmptySelection>,
+    /// `"coverage"` or `"bazel"`.
+    pub impact: Option<ImpactStrategy>,
+    /// Running container to `docker exec` tests in.
+    pub container: Option<String>,
+    /// Globs, relative to the root, narrowing which source files are watched
+    /// and analysed.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Globs of source files never to watch or analyse, such as `.venv/**`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Globs a function's name must match to be a test, such as `*_test`,
+    /// replacing the language's convention.
+    #[serde(default)]
+    pub test_names: Vec<String>,
+    /// Milliseconds to wait for edits to settle before running.
+    pub debounce_ms: Option<u64>,
+    /// Milliseconds between rescans of the tree, to poll instead of relying
+    /// on change events.
+    pub poll_ms: Option<u64>,
+    /// Globs, relative to the root, of other files whose changes start a
+    /// cycle, such as `pytest.ini` or test fixtures.
+    #[serde(default)]
+    pub watch: Vec<String>,
+    /// Globs, relative to the root, of directories and files the watcher
+    /// skips, on top of `.git`, `__pycache__`, `.venv` and coverage output.
+    #[serde(default)]
+    pub watch_ignore: Vec<String>,
+    /// Directories, relative to the root, watched besides it, such as
+    /// sources kept outside the checkout.
+    #[serde(default)]
+    pub watch_roots: Vec<String>,
+    /// How many directory levels below each watched root are watched.
+    pub watch_max_depth: Option<usize>,
+    /// Globs, relative to the root, of directories watched without the
+    /// directories below them.
+    #[serde(default)]
+    pub watch_non_recursive: Vec<String>,
+    /// Tests to select when artifacts outside the code change.
+    #[serde(default)]
+    pub data_dependencies: Vec<DataDependency>,
+}
+
+/// Tests that read data or model files, declared as
+///
+/// ```toml
+/// [[data_dependencies]]
+/// paths = ["data/*.csv.dvc", "models/**"]
+/// tests = ["tests/test_model.py::test_accuracy"]
+/// ```
+///
+/// `paths` are globs relative to the repository root.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataDependency {
+    pub paths: Vec<String>,
+    pub tests: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            version: CURRENT_VERSION,
+            base: None,
+            base_mode: None,
+            command: None,
+            language: None,
+            on_empty: None,
+            impact: None,
+            container: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            test_names: Vec::new(),
+            debounce_ms: None,
+            poll_ms: None,
+            watch: Vec::new(),
+            watch_ignore: Vec::new(),
+            watch_roots: Vec::new(),
+            watch_max_depth: None,
+            watch_non_recursive: Vec::new(),
+            data_dependencies: Vec::new(),
+        }
+    }
+}
+
+/// Upgrades a table from the version it is keyed by to the next one.
+type Migration = fn(&mut Table);
+
+/// One entry per format change, in order; `MIGRATIONS[n]` upgrades version
+/// `n + 1` to `n + 2`.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Brings an older table up to `CURRENT_VERSION`. Files without a `version`
+/// key predate versioning and are treated as version 1.
+fn migrate(table: &mut Table) -> Result<(), ConfigError> {
+    let mut version = match table.get("version") {
+        None => 1,
+        Some(Value::Integer(version)) if *version >= 1 => *version,
+        Some(_) => return Err(ConfigError::InvalidVersion),
+    };
+    if version > CURRENT_VERSION {
+        return Err(ConfigError::UnsupportedVersion { found: version });
+    }
+    while version < CURRENT_VERSION {
+        MIGRATIONS[(version - 1) as usize](table);
+        version += 1;
+    }
+    table.insert("version".to_string(), Value::Integer(version));
+    Ok(())
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        let mut table: Table = toml::from_str(source)?;
+        migrate(&mut table)?;
+        Ok(Table::try_into(table)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let source = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Config::parse(&source)
+    }
+
+    /// The `CONFIG_FILE` at `root`, if the project has one.
+    pub fn discover(root: &Path) -> Result<Option<Config>, ConfigError> {
+        let path = root.join(CONFIG_FILE);
+        match path.is_file() {
+            true => Config::load(&path).map(Some),
+            false => Ok(None),
+        }
+    }
+}
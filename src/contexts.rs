@@ -0,0 +1,181 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::runner::parse_template;
+
+/// Module coverage.py loads the companion plugin from.
+pub const PLUGIN_MODULE: &str = "instant_patch.coverage_plugin";
+
+const PACKAGE: [(&str, &str); 3] = [
+    (
+        "instant_patch/__init__.py",
+        include_str!("../python/instant_patch/__init__.py"),
+    ),
+    (
+        "instant_patch/coverage_plugin.py",
+        include_str!("../python/instant_patch/coverage_plugin.py"),
+    ),
+    (
+        "instant_patch/pytest_plugin.py",
+        include_str!("../python/instant_patch/pytest_plugin.py"),
+    ),
+];
+
+/// Files coverage.py reads its settings from, in its order of preference,
+/// with the prefix its section names carry in each.
+pub(crate) const CONFIG_FILES: [(&str, &str); 3] = [
+    (".coveragerc", ""),
+    ("setup.cfg", "coverage:"),
+    ("tox.ini", "coverage:"),
+];
+
+#[derive(Debug, Error)]
+pub enum ContextError {
+    #[error("failed to write {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Rewrites a coverage.py config so it loads the plugin. `existing` is the
+/// project's config file and `prefix` the prefix of its coverage sections;
+/// other sections are dropped, and `dynamic_context`, which would compete
+/// with the plugin, is removed.
+pub fn rcfile(existing: &str, prefix: &str) -> String {
+    let mut lines = Vec::new();
+    let mut keep = prefix.is_empty();
+    let mut in_run = false;
+    let mut has_run = false;
+    let mut added = false;
+    for line in existing.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            if in_run && !added {
+                lines.push(format!("plugins = {}", PLUGIN_MODULE));
+                added = true;
+            }
+            let name = name.trim();
+            keep = name.starts_with(prefix);
+            let name = name.strip_prefix(prefix).unwrap_or(name);
+            in_run = keep && name == "run";
+            has_run |= in_run;
+            if keep {
+                lines.push(format!("[{}]", name));
+            }
+            continue;
+        }
+        if !keep {
+            continue;
+        }
+        let key = match line.starts_with(char::is_whitespace) {
+            true => "",
+            false => line.split(['=', ':']).next().unwrap_or_default().trim(),
+        };
+        if in_run && key == "dynamic_context" {
+            continue;
+        }
+        lines.push(line.to_string());
+        if in_run && key == "plugins" {
+            lines.push(format!("    {}", PLUGIN_MODULE));
+            added = true;
+        }
+    }
+    if !has_run {
+        lines.push("[run]".to_string());
+    }
+    if !added {
+        lines.push(format!("plugins = {}", PLUGIN_MODULE));
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// When `template` runs pytest-cov (`--cov`), returns it with
+/// `--cov-context=test` added, which records the same node ids. pytest-cov
+/// reads its own config file, so the generated rcfile does not apply.
+pub fn with_cov_context(template: &str) -> Option<String> {
+    let mut args = parse_template(template).ok()?;
+    let cov = args
+        .iter()
+        .position(|arg| arg == "--cov" || arg.starts_with("--cov="))?;
+    if !args.iter().any(|arg| arg.starts_with("--cov-context")) {
+        args.insert(cov + 1, "--cov-context=test".to_string());
+    }
+    Some(shell_words::join(args))
+}
+
+/// The plugin package and rcfile written for one repository.
+#[derive(Debug, Clone)]
+pub struct ContextSetup {
+    /// Directory to put on `PYTHONPATH` so the plugin can be imported.
+    pub python_path: PathBuf,
+    pub rcfile: PathBuf,
+}
+
+impl ContextSetup {
+    /// Environment for the test command: `COVERAGE_RCFILE` pointing at the
+    /// generated rcfile and the plugin prepended to `PYTHONPATH`.
+    pub fn env(&self) -> Vec<(&'static str, OsString)> {
+        let mut paths = vec![self.python_path.clone()];
+        if let Some(existing) = env::var_os("PYTHONPATH") {
+            paths.extend(env::split_paths(&existing));
+        }
+        let python_path =
+            env::join_paths(paths).unwrap_or_else(|_| self.python_path.clone().into());
+        vec![
+            ("COVERAGE_RCFILE", self.rcfile.clone().into_os_string()),
+            ("PYTHONPATH", python_path),
+        ]
+    }
+}
+
+fn write_if_changed(path: &Path, content: &str) -> Result<(), ContextError> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+    let io = |source| ContextError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io)?;
+    }
+    fs::write(path, content).map_err(io)
+}
+
+/// Writes the plugin and an rcfile built from the project's coverage config
+/// at `root` into the temporary directory, outside the working tree so the
+/// watcher and discovery never see them.
+pub fn install(root: &Path) -> Result<ContextSetup, ContextError> {
+    let dir = env::temp_dir().join(format!(
+        "{}-{}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    ));
+    let python_path = dir.join("python");
+    for (path, source) in PACKAGE {
+        write_if_changed(&python_path.join(path), source)?;
+    }
+
+    let config = CONFIG_FILES.iter().find_map(|(name, prefix)| {
+        let source = fs::read_to_string(root.join(name)).ok()?;
+        let has_sections = prefix.is_empty() || source.contains(&format!("[{}", prefix));
+        has_sections.then(|| rcfile(&source, prefix))
+    });
+    let mut hasher = DefaultHasher::new();
+    root.canonicalize()
+        .unwrap_or_else(|_| root.to_path_buf())
+        .hash(&mut hasher);
+    let rcfile_path = dir.join(format!("coveragerc-{:016x}", hasher.finish()));
+    write_if_changed(&rcfile_path, &config.unwrap_or_else(|| rcfile("", "")))?;
+    Ok(ContextSetup {
+        python_path,
+        rcfile: rcfile_path,
+    })
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::diff::BetterDiff;
+use crate::selection::ImpactData;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePatchCoverage {
+    pub path: String,
+    /// Line numbers added or modified by the patch.
+    pub changed_lines: Vec<usize>,
+    /// The subset of `changed_lines` executed during the run.
+    pub covered_lines: Vec<usize>,
+    /// Changed lines that branch and ran, but not down every branch. They
+    /// count as neither covered nor uncovered.
+    #[serde(default)]
+    pub partial_lines: Vec<usize>,
+    /// The same lines broken down by the diff hunk that changed them, in
+    /// file order.
+    #[serde(default)]
+    pub hunks: Vec<HunkCoverage>,
+}
+
+/// The lines one diff hunk adds or modifies, `first` to `last`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HunkCoverage {
+    pub first: usize,
+    pub last: usize,
+    pub changed_lines: Vec<usize>,
+    pub covered_lines: Vec<usize>,
+    #[serde(default)]
+    pub partial_lines: Vec<usize>,
+}
+
+impl HunkCoverage {
+    pub fn uncovered_lines(&self) -> Vec<usize> {
+        uncovered(
+            &self.changed_lines,
+            &self.covered_lines,
+            &self.partial_lines,
+        )
+    }
+}
+
+impl FilePatchCoverage {
+    pub fn uncovered_lines(&self) -> Vec<usize> {
+        uncovered(
+            &self.changed_lines,
+            &self.covered_lines,
+            &self.partial_lines,
+        )
+    }
+
+    /// Moves the covered lines `is_partial` picks to `partial_lines`, here
+    /// and in the hunks.
+    fn mark_partial<F: Fn(usize) -> bool>(&mut self, is_partial: F) {
+        let split = |covered: &mut Vec<usize>, partial: &mut Vec<usize>| {
+            let (taken, full): (Vec<usize>, Vec<usize>) =
+                covered.iter().partition(|&&line| is_partial(line));
+            *covered = full;
+            partial.extend(taken);
+        };
+        split(&mut self.covered_lines, &mut self.partial_lines);
+        for hunk in &mut self.hunks {
+            split(&mut hunk.covered_lines, &mut hunk.partial_lines);
+        }
+    }
+}
+
+fn uncovered(changed: &[usize], covered: &[usize], partial: &[usize]) -> Vec<usize> {
+    changed
+        .iter()
+        .filter(|line| !covered.contains(line) && !partial.contains(line))
+        .copied()
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchCoverage {
+    pub files: Vec<FilePatchCoverage>,
+}
+
+impl PatchCoverage {
+    pub fn changed(&self) -> usize {
+        self.files.iter().map(|f| f.changed_lines.len()).sum()
+    }
+
+    pub fn covered(&self) -> usize {
+        self.files.iter().map(|f| f.covered_lines.len()).sum()
+    }
+
+    /// Percentage of changed lines covered, or `None` when nothing changed.
+    pub fn percent(&self) -> Option<f64> {
+        match self.changed() {
+            0 => None,
+            changed => Some(self.covered() as f64 * 100.0 / changed as f64),
+        }
+    }
+
+    pub fn partial(&self) -> usize {
+        self.files.iter().map(|f| f.partial_lines.len()).sum()
+    }
+
+    /// Takes the lines `excluded` lists (path -> line numbers) out of the
+    /// patch, dropping hunks and files left with no changed lines.
+    pub fn exclude_lines(&mut self, excluded: &HashMap<String, BTreeSet<usize>>) {
+        for file in &mut self.files {
+            let excluded = match excluded.get(&file.path) {
+                Some(excluded) => excluded,
+                None => continue,
+            };
+            let keep = |lines: &mut Vec<usize>| lines.retain(|line| !excluded.contains(line));
+            keep(&mut file.changed_lines);
+            keep(&mut file.covered_lines);
+            keep(&mut file.partial_lines);
+            for hunk in &mut file.hunks {
+                keep(&mut hunk.changed_lines);
+                keep(&mut hunk.covered_lines);
+                keep(&mut hunk.partial_lines);
+            }
+            file.hunks.retain(|hunk| !hunk.changed_lines.is_empty());
+        }
+        self.files.retain(|file| !file.changed_lines.is_empty());
+    }
+
+    /// Marks covered lines that are branch points, according to
+    /// `branch_lines` (path -> line numbers), but took fewer than two of
+    /// their branches in `impact`. Does nothing for files `impact` has no
+    /// branch data for.
+    pub fn mark_partial_branches(
+        &mut self,
+        impact: &ImpactData,
+        branch_lines: &HashMap<String, BTreeSet<usize>>,
+    ) {
+        for file in &mut self.files {
+            let (arcs, branches) = match (impact.arcs.get(&file.path), branch_lines.get(&file.path))
+            {
+                (Some(arcs), Some(branches)) => (arcs, branches),
+                _ => continue,
+            };
+            file.mark_partial(|line| {
+                branches.contains(&line) && arcs.get(&line).map_or(0, HashSet::len) < 2
+            });
+        }
+    }
+
+    /// Whether at least `threshold` percent of the changed lines are
+    /// covered. A patch changing no lines meets any threshold.
+    pub fn meets(&self, threshold: f64) -> bool {
+        self.percent().is_none_or(|percent| percent >= threshold)
+    }
+}
+
+/// Groups sorted line numbers into `(first, last)` runs of consecutive lines.
+pub fn line_blocks(lines: &[usize]) -> Vec<(usize, usize)> {
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match blocks.last_mut() {
+            Some((_, last)) if *last + 1 == line => *last = line,
+            _ => blocks.push((line, line)),
+        }
+    }
+    blocks
+}
+
+/// Lines added or modified by `hunks`, numbered from 1, and which of them
+/// some test in `impact` executed.
+pub fn patch_coverage(hunks: &[BetterDiff], impact: &ImpactData) -> PatchCoverage {
+    let mut changed: BTreeMap<&str, BTreeMap<usize, BTreeSet<usize>>> = BTreeMap::new();
+    for hunk in hunks {
+        let rows = hunk.added_rows();
+        if !rows.is_empty() {
+            changed
+                .entry(hunk.path.as_str())
+                .or_default()
+                .entry(rows.start + 1)
+                .or_default()
+                .extend(rows.map(|row| row + 1));
+        }
+    }
+    let files = changed
+        .into_iter()
+        .map(|(path, hunks)| {
+            let is_covered = |line: &&usize| {
+                impact
+                    .lines
+                    .values()
+                    .any(|files| files.get(path).is_some_and(|l| l.contains(line)))
+            };
+            let hunks: Vec<HunkCoverage> = hunks
+                .into_values()
+                .map(|lines| HunkCoverage {
+                    first: *lines.first().unwrap_or(&0),
+                    last: *lines.last().unwrap_or(&0),
+                    covered_lines: lines.iter().filter(is_covered).copied().collect(),
+                    changed_lines: lines.into_iter().collect(),
+                    partial_lines: Vec::new(),
+                })
+                .collect();
+            let lines: BTreeSet<usize> = hunks
+                .iter()
+                .flat_map(|hunk| hunk.changed_lines.iter().copied())
+                .collect();
+            FilePatchCoverage {
+                path: path.to_string(),
+                covered_lines: lines.iter().filter(is_covered).copied().collect(),
+                changed_lines: lines.into_iter().collect(),
+                partial_lines: Vec::new(),
+                hunks,
+            }
+        })
+        .collect();
+    PatchCoverage { files }
+}
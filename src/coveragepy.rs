@@ -0,0 +1,431 @@
+use glob::Pattern;
+use regex::Regex;
+use rusqlite::{Connection, OpenFlags};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::contexts::CONFIG_FILES;
+use crate::selection::ImpactData;
+
+/// Where `coverage run` writes its data, relative to the directory it runs
+/// in.
+pub const DATA_FILE: &str = ".coverage";
+
+/// coverage.py's default `exclude_lines`, matching `# pragma: no cover`.
+pub const DEFAULT_EXCLUDE: &str = r"#\s*(pragma|PRAGMA)[:\s]?\s*(no|NO)\s*(cover|COVER)";
+
+/// Phases pytest-cov appends to a test's context with `--cov-context=test`.
+const PHASES: [&str; 3] = ["|setup", "|run", "|teardown"];
+
+#[derive(Debug, Error)]
+pub enum CoveragePyError {
+    #[error("failed to read coverage data {path}: {source}")]
+    Sqlite {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+    #[error("{0} has no line data; was it written by coverage.py 5 or later?")]
+    NoLineData(PathBuf),
+    #[error("failed to read coverage settings from {path}: {message}")]
+    Config { path: PathBuf, message: String },
+}
+
+/// The lines and files the project's coverage.py settings leave out of
+/// coverage: `exclude_lines` and `exclude_also` regexes and `omit` globs.
+#[derive(Debug, Clone)]
+pub struct Exclusions {
+    lines: Vec<Regex>,
+    omit: Vec<Pattern>,
+}
+
+impl Default for Exclusions {
+    fn default() -> Exclusions {
+        Exclusions {
+            lines: vec![Regex::new(DEFAULT_EXCLUDE).expect("default pattern is valid")],
+            omit: Vec::new(),
+        }
+    }
+}
+
+/// Settings read from one of coverage.py's config files, as raw strings.
+#[derive(Debug, Default)]
+struct Settings {
+    exclude_lines: Option<Vec<String>>,
+    exclude_also: Vec<String>,
+    omit: Vec<String>,
+}
+
+impl Exclusions {
+    /// Reads the settings from the first of `.coveragerc`, `setup.cfg`,
+    /// `tox.ini` and `pyproject.toml` at `root` with coverage sections, as
+    /// coverage.py does. Without any, only the default pragma applies.
+    pub fn discover(root: &Path) -> Result<Exclusions, CoveragePyError> {
+        let ini = CONFIG_FILES.iter().find_map(|(name, prefix)| {
+            let source = fs::read_to_string(root.join(name)).ok()?;
+            let has_sections = prefix.is_empty() || source.contains(&format!("[{}", prefix));
+            has_sections.then(|| (root.join(name), ini_settings(&source, prefix)))
+        });
+        let (path, settings) = match ini {
+            Some(found) => found,
+            None => {
+                let path = root.join("pyproject.toml");
+                match fs::read_to_string(&path) {
+                    Ok(source) => {
+                        let settings =
+                            toml_settings(&source).map_err(|message| CoveragePyError::Config {
+                                path: path.clone(),
+                                message,
+                            })?;
+                        (path, settings)
+                    }
+                    Err(_) => return Ok(Exclusions::default()),
+                }
+            }
+        };
+        let config = |message: String| CoveragePyError::Config {
+            path: path.clone(),
+            message,
+        };
+        let mut lines = settings
+            .exclude_lines
+            .unwrap_or_else(|| vec![DEFAULT_EXCLUDE.to_string()]);
+        lines.extend(settings.exclude_also);
+        Ok(Exclusions {
+            lines: lines
+                .iter()
+                .map(|pattern| Regex::new(pattern).map_err(|e| config(e.to_string())))
+                .collect::<Result<_, _>>()?,
+            omit: settings
+                .omit
+                .iter()
+                .map(|pattern| Pattern::new(pattern).map_err(|e| config(e.to_string())))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether `path`, relative to `root`, is omitted from coverage.
+    /// coverage.py matches `omit` against absolute paths, so patterns such
+    /// as `*/migrations/*` are tried against both.
+    pub fn omits(&self, path: &str, root: &Path) -> bool {
+        let absolute = root.join(path);
+        self.omit
+            .iter()
+            .any(|pattern| pattern.matches(path) || pattern.matches_path(&absolute))
+    }
+
+    /// 0-based rows of `source` matching an exclusion regex.
+    pub fn matching_rows(&self, source: &str) -> BTreeSet<usize> {
+        source
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| self.lines.iter().any(|regex| regex.is_match(line)))
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    /// Lines of Python `source` excluded from coverage: those matching an
+    /// exclusion regex, and when such a line starts a statement, every line
+    /// of it, so a `def` or `if` marked `# pragma: no cover` takes its body
+    /// along.
+    #[cfg(feature = "python")]
+    pub fn excluded_lines(&self, source: &str) -> BTreeSet<usize> {
+        let matched = self.matching_rows(source);
+        if matched.is_empty() {
+            return matched;
+        }
+        let mut parser = tree_sitter::Parser::new();
+        let tree = match parser.set_language(&tree_sitter_python::LANGUAGE.into()) {
+            Ok(()) => parser.parse(source, None),
+            Err(_) => None,
+        };
+        let mut excluded: BTreeSet<usize> = matched.iter().map(|row| row + 1).collect();
+        let root = match &tree {
+            Some(tree) => tree.root_node(),
+            None => return excluded,
+        };
+        for row in matched {
+            // the outermost statement starting on the row
+            let mut node = root;
+            while let Some(child) = (0..node.named_child_count())
+                .filter_map(|i| node.named_child(i))
+                .find(|child| child.start_position().row <= row && last_row(child) >= row)
+            {
+                node = child;
+                // a block starts with its first statement, which is the one
+                // to exclude
+                if child.start_position().row == row && child.kind() != "block" {
+                    break;
+                }
+            }
+            if node.id() != root.id() && node.kind() != "block" && node.start_position().row == row
+            {
+                excluded.extend(row + 1..=last_row(&node) + 1);
+            }
+        }
+        excluded
+    }
+}
+
+/// The last row holding any of `node`. Blocks end at the start of the line
+/// after them, which does not count.
+#[cfg(feature = "python")]
+fn last_row(node: &tree_sitter::Node) -> usize {
+    let end = node.end_position();
+    match end.column {
+        0 => end.row.saturating_sub(1).max(node.start_position().row),
+        _ => end.row,
+    }
+}
+
+/// Coverage settings from an INI-style file whose coverage sections carry
+/// `prefix`. Values may continue on indented lines; lists are split on
+/// newlines and, for `omit`, commas.
+fn ini_settings(source: &str, prefix: &str) -> Settings {
+    let mut settings = Settings::default();
+    let mut section = String::new();
+    let mut key = String::new();
+    let mut values: Vec<(String, String, String)> = Vec::new();
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            section = name.trim().to_string();
+            key.clear();
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some((_, _, value)) = values.last_mut().filter(|_| !key.is_empty()) {
+                value.push('\n');
+                value.push_str(trimmed);
+            }
+            continue;
+        }
+        if let Some((name, value)) = trimmed.split_once(['=', ':']) {
+            key = name.trim().to_string();
+            values.push((section.clone(), key.clone(), value.trim().to_string()));
+        }
+    }
+    let items = |value: &str, separators: &[char]| -> Vec<String> {
+        value
+            .split(separators)
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    for (section, key, value) in values {
+        let section = match section.strip_prefix(prefix) {
+            Some(section) => section,
+            None => continue,
+        };
+        match (section, key.as_str()) {
+            ("report", "exclude_lines") => settings.exclude_lines = Some(items(&value, &['\n'])),
+            ("report", "exclude_also") => settings.exclude_also = items(&value, &['\n']),
+            ("run" | "report", "omit") => settings.omit.extend(items(&value, &['\n', ','])),
+            _ => {}
+        }
+    }
+    settings
+}
+
+/// Coverage settings from the `[tool.coverage]` tables of `pyproject.toml`.
+fn toml_settings(source: &str) -> Result<Settings, String> {
+    let document: toml::Table = source.parse().map_err(|e: toml::de::Error| e.to_string())?;
+    let coverage = document.get("tool").and_then(|tool| tool.get("coverage"));
+    let list = |table: &str, key: &str| -> Option<Vec<String>> {
+        let values = coverage?.get(table)?.get(key)?.as_array()?;
+        Some(
+            values
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect(),
+        )
+    };
+    let mut omit = list("run", "omit").unwrap_or_default();
+    omit.extend(list("report", "omit").unwrap_or_default());
+    Ok(Settings {
+        exclude_lines: list("report", "exclude_lines"),
+        exclude_also: list("report", "exclude_also").unwrap_or_default(),
+        omit,
+    })
+}
+
+/// Line numbers set in a coverage.py numbits blob: bit `n % 8` of byte
+/// `n / 8` stands for line `n`.
+pub fn numbits_to_lines(numbits: &[u8]) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for (byte_index, byte) in numbits.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (1 << bit) != 0 {
+                lines.push(byte_index * 8 + bit);
+            }
+        }
+    }
+    lines
+}
+
+/// The test id a context names, without the phase pytest-cov adds. The empty
+/// context holds lines run outside any test, such as module imports.
+pub fn context_test_id(context: &str) -> &str {
+    PHASES
+        .iter()
+        .find_map(|phase| context.strip_suffix(phase))
+        .unwrap_or(context)
+}
+
+/// Lines of Python `source` that coverage.py measures as branches: the
+/// headers of `if`, `elif`, `while` and `for` statements, each with a branch
+/// into its body and one past it.
+#[cfg(feature = "python")]
+pub fn branch_lines(source: &str) -> BTreeSet<usize> {
+    let mut parser = tree_sitter::Parser::new();
+    let tree = match parser.set_language(&tree_sitter_python::LANGUAGE.into()) {
+        Ok(()) => parser.parse(source, None),
+        Err(_) => None,
+    };
+    let mut lines = BTreeSet::new();
+    let mut cursor = match &tree {
+        Some(tree) => tree.walk(),
+        None => return lines,
+    };
+    // a preorder walk over every node
+    loop {
+        let node = cursor.node();
+        if let "if_statement" | "elif_clause" | "while_statement" | "for_statement" = node.kind() {
+            lines.insert(node.start_position().row + 1);
+        }
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        loop {
+            if !cursor.goto_parent() {
+                return lines;
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+}
+
+/// `path` as recorded by coverage.py, relative to `root` with forward
+/// slashes, or `None` for files outside it.
+fn relative_path(path: &str, root: &Path, canonical_root: &Path) -> Option<String> {
+    let recorded = Path::new(path);
+    let relative = match recorded.is_absolute() {
+        true => recorded
+            .strip_prefix(canonical_root)
+            .or_else(|_| recorded.strip_prefix(root))
+            .ok()?,
+        false => recorded,
+    };
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Reads the lines each test executed from the coverage.py SQLite data file
+/// at `path`, keyed by paths relative to `root`. Files outside `root`, such
+/// as installed packages, are left out. With branch coverage on, a line
+/// counts as executed when an arc starts or ends at it, and the arcs are
+/// kept to find partial branches.
+pub fn read(path: &Path, root: &Path) -> Result<ImpactData, CoveragePyError> {
+    let sqlite = |source| CoveragePyError::Sqlite {
+        path: path.to_path_buf(),
+        source,
+    };
+    let connection =
+        Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(sqlite)?;
+    let has_table = |name: &str| {
+        connection
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+                [name],
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|count| count > 0)
+    };
+    let has_lines = has_table("line_bits").map_err(sqlite)?;
+    let has_arcs = has_table("arc").map_err(sqlite)?;
+    if !has_lines && !has_arcs {
+        return Err(CoveragePyError::NoLineData(path.to_path_buf()));
+    }
+
+    let canonical_root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let relative = |file: &str| relative_path(file, root, &canonical_root);
+    let mut impact = ImpactData::default();
+    let record = |impact: &mut ImpactData, context: &str, file: String, lines: &[usize]| {
+        impact
+            .lines
+            .entry(context_test_id(context).to_string())
+            .or_default()
+            .entry(file)
+            .or_default()
+            .extend(lines.iter().filter(|&&line| line > 0));
+    };
+
+    if has_lines {
+        let mut statement = connection
+            .prepare(
+                "SELECT context.context, file.path, line_bits.numbits FROM line_bits \
+                 JOIN file ON file.id = line_bits.file_id \
+                 JOIN context ON context.id = line_bits.context_id",
+            )
+            .map_err(sqlite)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, Vec<u8>>(2)?))
+            })
+            .map_err(sqlite)?;
+        for row in rows {
+            let (context, file, numbits): (String, String, _) = row.map_err(sqlite)?;
+            if let Some(file) = relative(&file) {
+                record(&mut impact, &context, file, &numbits_to_lines(&numbits));
+            }
+        }
+    }
+    if has_arcs {
+        let mut statement = connection
+            .prepare(
+                "SELECT context.context, file.path, arc.fromno, arc.tono FROM arc \
+                 JOIN file ON file.id = arc.file_id \
+                 JOIN context ON context.id = arc.context_id",
+            )
+            .map_err(sqlite)?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })
+            .map_err(sqlite)?;
+        for row in rows {
+            let (context, file, from, to): (String, String, i64, i64) = row.map_err(sqlite)?;
+            let file = match relative(&file) {
+                Some(file) => file,
+                None => continue,
+            };
+            // negative line numbers mark entering and leaving code objects
+            let lines: Vec<usize> = [from, to]
+                .into_iter()
+                .filter_map(|line| usize::try_from(line).ok())
+                .collect();
+            if let Ok(from) = usize::try_from(from) {
+                impact
+                    .arcs
+                    .entry(file.clone())
+                    .or_default()
+                    .entry(from)
+                    .or_default()
+                    .insert(to);
+            }
+            record(&mut impact, &context, file, &lines);
+        }
+    }
+    Ok(impact)
+}
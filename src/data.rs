@@ -0,0 +1,206 @@
+use git2::{DiffOptions, ObjectType, Repository};
+use glob::Pattern;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::config::DataDependency;
+use crate::discovery::slash_path;
+use crate::selection::{SelectedTest, SelectionContext, TestSelector};
+use crate::vcs;
+
+/// Size and modification time, enough to notice a rewritten artifact
+/// without hashing gigabytes of data.
+type Fingerprint = (u64, Option<SystemTime>);
+
+/// A `DataDependency` with its globs compiled.
+#[derive(Debug, Clone)]
+pub struct CompiledDependency {
+    pub patterns: Vec<Pattern>,
+    pub tests: Vec<String>,
+}
+
+impl CompiledDependency {
+    pub fn new(dependency: &DataDependency) -> Result<CompiledDependency, glob::PatternError> {
+        Ok(CompiledDependency {
+            patterns: dependency
+                .paths
+                .iter()
+                .map(|path| Pattern::new(path))
+                .collect::<Result<_, _>>()?,
+            tests: dependency.tests.clone(),
+        })
+    }
+
+    /// Whether `path`, relative to the repository root, is one of the
+    /// declared artifacts.
+    pub fn matches(&self, path: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// Selects the tests declared to depend on data artifacts when any of those
+/// artifacts changed, even if no code did. Files git tracks, such as DVC
+/// pointer files, are compared against the base commit; ignored files, where
+/// the data itself usually lives, against how they looked when the selector
+/// first saw them.
+pub struct DataSelector {
+    root: PathBuf,
+    base: String,
+    dependencies: Vec<CompiledDependency>,
+    /// Ignored artifacts as they were on the first check.
+    first_seen: Mutex<Option<HashMap<String, Fingerprint>>>,
+}
+
+impl DataSelector {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(
+        root: P,
+        base: S,
+        dependencies: Vec<CompiledDependency>,
+    ) -> DataSelector {
+        DataSelector {
+            root: root.into(),
+            base: base.into(),
+            dependencies,
+            first_seen: Mutex::new(None),
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.dependencies.iter().any(|dep| dep.matches(path))
+    }
+
+    /// Declared artifacts that changed, relative to the root.
+    pub fn changed_paths(&self) -> Result<BTreeSet<String>, git2::Error> {
+        let repo = vcs::open_git(&self.root)?;
+        let mut changed = self.tracked_changes(&repo)?;
+        changed.extend(self.ignored_changes(&repo));
+        Ok(changed)
+    }
+
+    fn tracked_changes(&self, repo: &Repository) -> Result<BTreeSet<String>, git2::Error> {
+        let tree = repo.revparse_single(&self.base)?.peel(ObjectType::Tree)?;
+        let mut options = DiffOptions::new();
+        options.include_untracked(true).recurse_untracked_dirs(true);
+        for dep in &self.dependencies {
+            for pattern in &dep.patterns {
+                options.pathspec(pattern.as_str());
+            }
+        }
+        let diff = repo.diff_tree_to_workdir_with_index(tree.as_tree(), Some(&mut options))?;
+        Ok(diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .filter_map(Path::to_str)
+            .filter(|path| self.matches(path))
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn ignored_changes(&self, repo: &Repository) -> BTreeSet<String> {
+        let mut files = Vec::new();
+        for dep in &self.dependencies {
+            for pattern in &dep.patterns {
+                walk(
+                    &self.root.join(literal_prefix(pattern.as_str())),
+                    &mut files,
+                );
+            }
+        }
+        let mut current: HashMap<String, Fingerprint> = HashMap::new();
+        for path in files {
+            let relative = path.strip_prefix(&self.root).ok().and_then(Path::to_str);
+            let relative = match relative.map(slash_path) {
+                Some(relative) if self.matches(&relative) => relative,
+                _ => continue,
+            };
+            if !repo.is_path_ignored(&relative).unwrap_or(false) {
+                continue;
+            }
+            if let Ok(metadata) = fs::metadata(&path) {
+                current.insert(relative, (metadata.len(), metadata.modified().ok()));
+            }
+        }
+
+        let mut first_seen = self.first_seen.lock().unwrap_or_else(|e| e.into_inner());
+        let first = match first_seen.as_ref() {
+            Some(first) => first,
+            None => {
+                *first_seen = Some(current);
+                return BTreeSet::new();
+            }
+        };
+        let mut changed: BTreeSet<String> = current
+            .iter()
+            .filter(|(path, fingerprint)| first.get(*path) != Some(fingerprint))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            first
+                .keys()
+                .filter(|path| !current.contains_key(*path))
+                .cloned(),
+        );
+        changed
+    }
+}
+
+/// The directories of `pattern` before its first wildcard.
+fn literal_prefix(pattern: &str) -> &str {
+    let wildcard = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    match pattern[..wildcard].rfind('/') {
+        Some(slash) => &pattern[..slash],
+        None => "",
+    }
+}
+
+/// Collects every file under `dir`, which may also be a single file.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            if dir.is_file() {
+                files.push(dir.to_path_buf());
+            }
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() && entry.file_name() != ".git" => walk(&entry.path(), files),
+            Ok(kind) if kind.is_file() => files.push(entry.path()),
+            _ => {}
+        }
+    }
+}
+
+impl TestSelector for DataSelector {
+    fn name(&self) -> &str {
+        "data-dependencies"
+    }
+
+    fn select(&self, _ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let changed = match self.changed_paths() {
+            Ok(changed) => changed,
+            Err(e) => {
+                tracing::warn!("cannot check data dependencies: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut selected = Vec::new();
+        for dep in &self.dependencies {
+            if let Some(path) = changed.iter().find(|path| dep.matches(path)) {
+                let reason = format!("data dependency {} changed", path);
+                selected.extend(
+                    dep.tests
+                        .iter()
+                        .map(|id| SelectedTest::new(id.clone(), reason.clone())),
+                );
+            }
+        }
+        selected
+    }
+}
@@ -0,0 +1,265 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+use thiserror::Error;
+
+use crate::runner::{self, display_command, render_command, RunResult, Runner, RunnerError};
+use crate::selection::Selection;
+
+/// Where the devcontainer spec allows the config to live, in order.
+const CONFIG_FILES: [&str; 2] = [".devcontainer/devcontainer.json", ".devcontainer.json"];
+
+/// coverage.py's data file, relative to the workspace folder.
+const DEFAULT_DATA_FILE: &str = ".coverage";
+
+#[derive(Debug, Error)]
+pub enum DevcontainerError {
+    #[error("failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// The parts of `devcontainer.json` that matter for running tests.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevcontainerConfig {
+    /// Where the repository is mounted inside the container.
+    pub workspace_folder: Option<String>,
+}
+
+impl DevcontainerConfig {
+    /// `workspaceFolder`, or `/workspaces/<name of root>` as the
+    /// devcontainer CLI defaults to.
+    pub fn workspace_folder(&self, root: &Path) -> String {
+        if let Some(folder) = &self.workspace_folder {
+            return folder.clone();
+        }
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        format!("/workspaces/{}", name)
+    }
+}
+
+/// Turns JSON with comments and trailing commas, as `devcontainer.json`
+/// is written, into plain JSON.
+pub fn strip_jsonc(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => while chars.next_if(|&c| c != '\n').is_some() {},
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = '\0';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                out.push(' ');
+            }
+            '}' | ']' => {
+                let trimmed = out.trim_end().len();
+                if out[..trimmed].ends_with(',') {
+                    out.truncate(trimmed - 1);
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reads the devcontainer config of the repository at `root`, if it has one.
+pub fn load(root: &Path) -> Result<Option<DevcontainerConfig>, DevcontainerError> {
+    for name in CONFIG_FILES {
+        let path = root.join(name);
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(source) => return Err(DevcontainerError::Io { path, source }),
+        };
+        return serde_json::from_str(&strip_jsonc(&source))
+            .map(Some)
+            .map_err(|source| DevcontainerError::Parse { path, source });
+    }
+    Ok(None)
+}
+
+/// Whether this process itself runs in a container, in which case tests
+/// run locally as usual.
+pub fn in_container() -> bool {
+    ["REMOTE_CONTAINERS", "CODESPACES", "DEVCONTAINER"]
+        .iter()
+        .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+        || Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+}
+
+/// Whether `binary` can be found on `PATH`.
+pub fn on_path(binary: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(binary).is_file()))
+}
+
+/// How commands reach the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecTarget {
+    /// `devcontainer exec`, which finds or starts the container from the
+    /// repository's config.
+    Devcontainer,
+    /// `docker exec` into a running container by name or id.
+    Docker(String),
+}
+
+/// Runs the selection inside the repository's devcontainer while this
+/// process stays on the host. The repository is usually mounted into the
+/// container, so coverage data lands in the working tree; when the run did
+/// not update it there, it is copied back out of the container.
+pub struct DevcontainerRunner {
+    root: PathBuf,
+    template: String,
+    target: ExecTarget,
+    workspace_folder: String,
+    data_file: String,
+}
+
+impl DevcontainerRunner {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(
+        root: P,
+        template: S,
+        config: &DevcontainerConfig,
+    ) -> DevcontainerRunner {
+        let root = root.into();
+        DevcontainerRunner {
+            workspace_folder: config.workspace_folder(&root),
+            root,
+            template: template.into(),
+            target: ExecTarget::Devcontainer,
+            data_file: DEFAULT_DATA_FILE.to_string(),
+        }
+    }
+
+    /// Use `docker exec` into `container` instead of the devcontainer CLI.
+    pub fn container<S: Into<String>>(mut self, container: S) -> DevcontainerRunner {
+        self.target = ExecTarget::Docker(container.into());
+        self
+    }
+
+    /// coverage.py's data file relative to the workspace, `.coverage` by
+    /// default.
+    pub fn data_file<S: Into<String>>(mut self, data_file: S) -> DevcontainerRunner {
+        self.data_file = data_file.into();
+        self
+    }
+
+    /// The host-side arguments that run `args` in the container.
+    pub fn exec_command(&self, args: Vec<String>) -> Vec<String> {
+        let mut command: Vec<String> = match &self.target {
+            ExecTarget::Devcontainer => vec![
+                "devcontainer".to_string(),
+                "exec".to_string(),
+                "--workspace-folder".to_string(),
+                self.host_root().display().to_string(),
+            ],
+            ExecTarget::Docker(container) => vec![
+                "docker".to_string(),
+                "exec".to_string(),
+                "-w".to_string(),
+                self.workspace_folder.clone(),
+                container.clone(),
+            ],
+        };
+        command.extend(args);
+        command
+    }
+
+    fn host_root(&self) -> PathBuf {
+        self.root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone())
+    }
+
+    fn container_data_file(&self) -> String {
+        format!(
+            "{}/{}",
+            self.workspace_folder.trim_end_matches('/'),
+            self.data_file
+        )
+    }
+
+    /// Copies the data file out of the container to the same place in the
+    /// working tree.
+    fn fetch_data_file(&self) -> Result<(), RunnerError> {
+        let host = self.root.join(&self.data_file);
+        let output = match &self.target {
+            ExecTarget::Docker(container) => Command::new("docker")
+                .arg("cp")
+                .arg(format!("{}:{}", container, self.container_data_file()))
+                .arg(&host)
+                .output()?,
+            ExecTarget::Devcontainer => {
+                let args = vec!["cat".to_string(), self.container_data_file()];
+                let command = self.exec_command(args);
+                let output = runner::command(&command).output()?;
+                if output.status.success() {
+                    fs::write(&host, &output.stdout)?;
+                }
+                output
+            }
+        };
+        if !output.status.success() {
+            tracing::warn!(
+                "cannot copy {} out of the container: {}",
+                self.data_file,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Runner for DevcontainerRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        let args = self.exec_command(render_command(&self.template, &selection.ids())?);
+        let data_file = self.root.join(&self.data_file);
+        let before = modified(&data_file);
+        let output = runner::command(&args).current_dir(&self.root).output()?;
+        let result = RunResult::from_output(display_command(&args), output);
+        // a mounted workspace already has the new data
+        if result.exit_code.is_some() && modified(&data_file) == before {
+            self.fetch_data_file()?;
+        }
+        Ok(result)
+    }
+}
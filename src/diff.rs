@@ -0,0 +1,435 @@
+#[cfg(not(target_arch = "wasm32"))]
+use git2::{DiffFindOptions, DiffOptions, Object, Patch, Repository};
+use similar::{DiffOp, TextDiff};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use thiserror::Error;
+use tree_sitter::{InputEdit, Point, Tree};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::discovery::{FileFailure, PathFilter};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::language::Language;
+
+#[derive(Debug, Error)]
+pub enum DiffError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("{0} is not a commit")]
+    NotACommit(String),
+    #[error("diff delta has no path")]
+    MissingPath,
+    #[error("path is not valid UTF-8: {0}")]
+    NonUtf8Path(String),
+    #[error("{0} contains non UTF-8 content")]
+    NonUtf8Content(String),
+    #[error("failed to read {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("{0} changed while it was being diffed")]
+    StaleContent(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct BetterDiff {
+    pub path: String,
+    pub start_offset: usize,
+    pub deletion_end: usize,
+    pub addition_end: usize,
+    pub start_point: Point,
+    pub addition_point: Point,
+    pub deletion_point: Point,
+}
+
+impl BetterDiff {
+    /// 0-based rows of the new file covered by the added text.
+    pub fn added_rows(&self) -> std::ops::Range<usize> {
+        let start = self.start_point.row;
+        match self.addition_end == self.start_offset {
+            true => start..start,
+            false => start..self.addition_point.row + usize::from(self.addition_point.column > 0),
+        }
+    }
+
+    /// Whether the hunk changed any of the bytes `range` spans in the new
+    /// file. A hunk that only deletes changes what it deleted from.
+    pub fn touches(&self, range: &std::ops::Range<usize>) -> bool {
+        let end = self.addition_end.max(self.deletion_end);
+        match end == self.start_offset {
+            true => range.start <= self.start_offset && self.start_offset < range.end,
+            false => range.start < end && self.start_offset < range.end,
+        }
+    }
+}
+
+impl std::fmt::Display for BetterDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "BetterDiff: {} {} {} {}",
+            self.path, self.start_offset, self.deletion_end, self.addition_end,
+        )
+    }
+}
+
+// hunks always start at column 0, so the end is found by walking the text
+fn end_point(start_row: usize, text: &str) -> Point {
+    let rows = text.matches('\n').count();
+    let column = match text.rfind('\n') {
+        Some(newline) => text.len() - newline - 1,
+        None => text.len(),
+    };
+    Point {
+        row: start_row + rows,
+        column,
+    }
+}
+
+/// Byte offset of the start of every line in a file, followed by the end of
+/// the last line, so hunk line ranges map straight onto byte ranges.
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> LineIndex<'a> {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        if starts[starts.len() - 1] != text.len() {
+            starts.push(text.len());
+        }
+        LineIndex { text, starts }
+    }
+
+    fn offset(&self, line: usize) -> Option<usize> {
+        self.starts.get(line).copied()
+    }
+
+    fn lines(&self, start: usize, len: usize) -> Option<&'a str> {
+        Some(&self.text[self.offset(start)?..self.offset(start + len)?])
+    }
+}
+
+/// Builds the edit replacing `old_len` lines at `old_index` with `new_len`
+/// lines at `new_index` (both 0-based). Everything before the hunk already
+/// matches the new file, so the start is taken from the new side.
+fn hunk_edit(
+    path: &str,
+    old: &LineIndex,
+    new: &LineIndex,
+    (old_index, old_len): (usize, usize),
+    (new_index, new_len): (usize, usize),
+) -> Option<BetterDiff> {
+    let deletion = old.lines(old_index, old_len)?;
+    let addition = new.lines(new_index, new_len)?;
+    let start_offset = new.offset(new_index)?;
+    Some(BetterDiff {
+        path: path.to_string(),
+        start_offset,
+        addition_end: start_offset + addition.len(),
+        deletion_end: start_offset + deletion.len(),
+        start_point: Point {
+            row: new_index,
+            column: 0,
+        },
+        addition_point: end_point(new_index, addition),
+        deletion_point: end_point(new_index, deletion),
+    })
+}
+
+// git numbers lines from 1, except that an empty side points at the line
+// before the change
+#[cfg(not(target_arch = "wasm32"))]
+fn hunk_range(start: u32, lines: u32) -> (usize, usize) {
+    match lines {
+        0 => (start as usize, 0),
+        _ => (start as usize - 1, lines as usize),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn blob_content(repo: &Repository, id: git2::Oid, path: &str) -> Result<String, DiffError> {
+    if id.is_zero() {
+        return Ok(String::new());
+    }
+    String::from_utf8(repo.find_blob(id)?.content().to_vec())
+        .map_err(|_| DiffError::NonUtf8Content(path.to_string()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn workdir_content(
+    repo: &Repository,
+    path: Option<&Path>,
+    display: &str,
+) -> Result<String, DiffError> {
+    let full = match (repo.workdir(), path) {
+        (Some(workdir), Some(path)) => workdir.join(path),
+        _ => return Ok(String::new()),
+    };
+    match std::fs::read(&full) {
+        Ok(bytes) => {
+            String::from_utf8(bytes).map_err(|_| DiffError::NonUtf8Content(display.to_string()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(DiffError::Io(display.to_string(), e)),
+    }
+}
+
+/// Computes one `BetterDiff` per hunk against the working tree. Byte ranges
+/// come from indexing the lines of the base blob and the working copy, and
+/// each hunk is expressed in terms of the file after all earlier hunks have
+/// been applied, which is the order `edit_tree` applies them in. Untracked
+/// files are included, every line an addition. Files that cannot be read as
+/// text are recorded in `failures` and skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_diff(
+    repo: &Repository,
+    commit: &Object,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<Vec<BetterDiff>, DiffError> {
+    let tree = commit_tree(commit)?;
+    let mut diffs = repo.diff_tree_to_workdir(Some(&tree), Some(&mut workdir_options()))?;
+    collect_hunks(&mut diffs, language, filter, failures, |delta, path| {
+        Ok((
+            blob_content(repo, delta.old_file().id(), path)?,
+            workdir_content(repo, delta.new_file().path(), path)?,
+        ))
+    })
+}
+
+/// Like `get_diff`, but against the index, so only staged changes are seen.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_staged_diff(
+    repo: &Repository,
+    commit: &Object,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<Vec<BetterDiff>, DiffError> {
+    let tree = commit_tree(commit)?;
+    let mut diffs = repo.diff_tree_to_index(Some(&tree), None, Some(&mut diff_options()))?;
+    collect_hunks(&mut diffs, language, filter, failures, |delta, path| {
+        Ok((
+            blob_content(repo, delta.old_file().id(), path)?,
+            blob_content(repo, delta.new_file().id(), path)?,
+        ))
+    })
+}
+
+/// Like `get_diff`, but between the trees of two commits, so nothing has to
+/// be checked out.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_commit_diff(
+    repo: &Repository,
+    from: &Object,
+    to: &Object,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<Vec<BetterDiff>, DiffError> {
+    let (from, to) = (commit_tree(from)?, commit_tree(to)?);
+    let mut diffs = repo.diff_tree_to_tree(Some(&from), Some(&to), Some(&mut diff_options()))?;
+    collect_hunks(&mut diffs, language, filter, failures, |delta, path| {
+        Ok((
+            blob_content(repo, delta.old_file().id(), path)?,
+            blob_content(repo, delta.new_file().id(), path)?,
+        ))
+    })
+}
+
+/// Changes in the working tree that are not staged, diffed against the
+/// index.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn get_unstaged_diff(
+    repo: &Repository,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<Vec<BetterDiff>, DiffError> {
+    let mut diffs = repo.diff_index_to_workdir(None, Some(&mut workdir_options()))?;
+    collect_hunks(&mut diffs, language, filter, failures, |delta, path| {
+        Ok((
+            blob_content(repo, delta.old_file().id(), path)?,
+            workdir_content(repo, delta.new_file().path(), path)?,
+        ))
+    })
+}
+
+/// Options for every diff: submodules have a history of their own, so
+/// their commits moving is not a change to analyse.
+#[cfg(not(target_arch = "wasm32"))]
+fn diff_options() -> DiffOptions {
+    let mut options = DiffOptions::new();
+    options.context_lines(0).ignore_submodules(true);
+    options
+}
+
+/// Options for diffing against the working tree, where a new file counts
+/// even before it is added.
+#[cfg(not(target_arch = "wasm32"))]
+fn workdir_options() -> DiffOptions {
+    let mut options = diff_options();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    options
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn commit_tree<'r>(commit: &Object<'r>) -> Result<git2::Tree<'r>, DiffError> {
+    let commit = commit
+        .as_commit()
+        .ok_or_else(|| DiffError::NotACommit(commit.id().to_string()))?;
+    Ok(commit.tree()?)
+}
+
+/// Turns every hunk of `diffs` into a `BetterDiff`, reading both sides of
+/// each file through `contents`. Renames are detected first, so a moved
+/// file gives only the lines that changed, under its new path. Deltas are
+/// walked once and only the files kept get a patch generated.
+#[cfg(not(target_arch = "wasm32"))]
+fn collect_hunks<F>(
+    diffs: &mut git2::Diff,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+    contents: F,
+) -> Result<Vec<BetterDiff>, DiffError>
+where
+    F: Fn(&git2::DiffDelta, &str) -> Result<(String, String), DiffError>,
+{
+    diffs.find_similar(Some(
+        DiffFindOptions::new().renames(true).for_untracked(true),
+    ))?;
+    let mut v = Vec::new();
+    for (idx, delta) in diffs.deltas().enumerate() {
+        // both sides name the file, unless it was renamed
+        let path = delta.new_file().path().ok_or(DiffError::MissingPath)?;
+        if !language.matches_path(path) {
+            continue;
+        }
+        let path = match path.to_str() {
+            Some(path) => path.to_string(),
+            None => {
+                let path = path.to_string_lossy().to_string();
+                failures.push(FileFailure::new(path.clone(), DiffError::NonUtf8Path(path)));
+                continue;
+            }
+        };
+        if !filter.matches(&path) {
+            continue;
+        }
+        let patch = match Patch::from_diff(diffs, idx)? {
+            Some(patch) => patch,
+            None => continue,
+        };
+        let (old_content, new_content) = match contents(&delta, &path) {
+            Ok(contents) => contents,
+            Err(e @ (DiffError::NonUtf8Content(_) | DiffError::Io(..))) => {
+                failures.push(FileFailure::new(path, e));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let old_index = LineIndex::new(&old_content);
+        let new_index = LineIndex::new(&new_content);
+        v.reserve(patch.num_hunks());
+        for hunk_i in 0..patch.num_hunks() {
+            let (hunk, _) = patch.hunk(hunk_i)?;
+            let edit = hunk_edit(
+                &path,
+                &old_index,
+                &new_index,
+                hunk_range(hunk.old_start(), hunk.old_lines()),
+                hunk_range(hunk.new_start(), hunk.new_lines()),
+            )
+            .ok_or_else(|| DiffError::StaleContent(path.clone()))?;
+            v.push(edit);
+        }
+    }
+    Ok(v)
+}
+
+/// Computes the same hunks as `get_diff` from two in-memory versions of a
+/// file, without needing a repository.
+pub fn diff_contents(path: &str, old: &str, new: &str) -> Vec<BetterDiff> {
+    let old_index = LineIndex::new(old);
+    let new_index = LineIndex::new(new);
+    let mut v = Vec::new();
+    // the new-side index of delete ops is not reliable, so track it ourselves
+    let mut new_line = 0;
+    for op in TextDiff::from_lines(old, new).ops() {
+        let (old_line, old_len, new_len) = match *op {
+            DiffOp::Equal { len, .. } => {
+                new_line += len;
+                continue;
+            }
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => (old_index, old_len, 0),
+            DiffOp::Insert {
+                old_index, new_len, ..
+            } => (old_index, 0, new_len),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_len,
+                ..
+            } => (old_index, old_len, new_len),
+        };
+        v.extend(hunk_edit(
+            path,
+            &old_index,
+            &new_index,
+            (old_line, old_len),
+            (new_line, new_len),
+        ));
+        new_line += new_len;
+    }
+    v
+}
+
+/// Whether applying `hunks`, those of one file in order, to `old` gives
+/// `new`, so a tree of `old` edited by `edit_tree` still fits everything
+/// outside them and can be reparsed incrementally. A file whose line
+/// endings alone changed has no hunks but does not fit.
+pub fn hunks_reproduce<'a, I: IntoIterator<Item = &'a BetterDiff>>(
+    hunks: I,
+    old: &str,
+    new: &str,
+) -> bool {
+    let mut text = old.as_bytes().to_vec();
+    for d in hunks {
+        let fits = d.start_offset <= d.deletion_end && d.deletion_end <= text.len();
+        match new.as_bytes().get(d.start_offset..d.addition_end) {
+            Some(added) if fits => {
+                text.splice(d.start_offset..d.deletion_end, added.iter().copied());
+            }
+            _ => return false,
+        }
+    }
+    text == new.as_bytes()
+}
+
+pub fn edit_tree(vd: &[BetterDiff], tree_map: &mut HashMap<String, Tree>) {
+    for d in vd {
+        // files that are new in the workdir have no old tree to edit
+        let t = match tree_map.get_mut(&d.path) {
+            Some(t) => t,
+            None => continue,
+        };
+        t.edit(&InputEdit {
+            start_byte: d.start_offset,
+            old_end_byte: d.deletion_end,
+            new_end_byte: d.addition_end,
+            start_position: d.start_point,
+            old_end_position: d.deletion_point,
+            new_end_position: d.addition_point,
+        });
+    }
+}
@@ -0,0 +1,618 @@
+#[cfg(not(target_arch = "wasm32"))]
+use git2::{Object, ObjectType, Repository};
+#[cfg(not(target_arch = "wasm32"))]
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard};
+use std::{collections::HashMap, collections::HashSet};
+use thiserror::Error;
+use tree_sitter::{LanguageError, Node, Parser, QueryCapture, QueryCursor, QueryError, Tree};
+
+use crate::language::Language;
+
+#[derive(Debug, Error)]
+pub enum DiscoveryError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("{0} is not a commit")]
+    NotACommit(String),
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("invalid glob pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+    #[error("failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("path is not valid UTF-8: {0}")]
+    NonUtf8Path(PathBuf),
+    #[error("{0} contains non UTF-8 content")]
+    NonUtf8Content(String),
+    #[error("error loading grammar: {0}")]
+    Language(#[from] LanguageError),
+    #[error("invalid query: {0:?}")]
+    Query(#[from] QueryError),
+    #[error("failed to parse {0}")]
+    Parse(String),
+    #[error("{0} is a git submodule or nested checkout, not analysed")]
+    NestedCheckout(String),
+}
+
+/// Narrows the files of a language that are watched and analysed, e.g. to
+/// leave out virtualenvs and generated code. Globs match paths relative to
+/// the root; without `include` globs every file is included.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PathFilter {
+    pub fn new<S: AsRef<str>>(
+        include: &[S],
+        exclude: &[S],
+    ) -> Result<PathFilter, glob::PatternError> {
+        let compile = |patterns: &[S]| {
+            patterns
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern.as_ref()))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(PathFilter {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    /// Whether `path`, relative to the root, is included and not excluded.
+    pub fn matches(&self, path: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(path)))
+            && !self.exclude.iter().any(|p| p.matches(path))
+    }
+}
+
+/// Which names mark a test, for languages whose tests are found by name:
+/// globs such as `test*` or `*_test`, matched against the whole name.
+/// Without configured globs the language's own convention applies, such as
+/// pytest's `test*`.
+#[derive(Debug, Clone, Default)]
+pub struct TestNames {
+    patterns: Vec<glob::Pattern>,
+}
+
+impl TestNames {
+    pub fn new<S: AsRef<str>>(
+        language: Language,
+        patterns: &[S],
+    ) -> Result<TestNames, glob::PatternError> {
+        let patterns = match patterns.is_empty() {
+            true => language
+                .test_names()
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<Result<_, _>>()?,
+            false => patterns
+                .iter()
+                .map(|pattern| glob::Pattern::new(pattern.as_ref()))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(TestNames { patterns })
+    }
+
+    /// The language's own convention.
+    pub fn of(language: Language) -> TestNames {
+        TestNames::new::<&str>(language, &[]).unwrap_or_default()
+    }
+
+    /// Whether `name` is a test's name. With no globs every name is.
+    pub fn matches(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(name))
+    }
+}
+
+/// A file left out of a cycle because it could not be read or analysed. One
+/// bad file is reported on its own instead of failing the whole cycle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileFailure {
+    pub path: String,
+    pub error: String,
+}
+
+impl FileFailure {
+    pub fn new<S: Into<String>, E: ToString>(path: S, error: E) -> FileFailure {
+        FileFailure {
+            path: path.into(),
+            error: error.to_string(),
+        }
+    }
+}
+
+pub fn print_tree(
+    content_map: HashMap<String, String>,
+    tree_map: HashMap<String, Tree>,
+    names: &TestNames,
+) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    let mut paths: Vec<&String> = tree_map.keys().collect();
+    paths.sort();
+    paths.into_iter().for_each(|path| {
+        let tree = &tree_map[path];
+        let source = match content_map.get(path) {
+            Some(content) => content.as_bytes(),
+            None => return,
+        };
+        let mut cursor = tree.walk();
+        'outer: loop {
+            if cursor.node().is_named() && cursor.node().kind() == "function_definition" {
+                let name = cursor
+                    .node()
+                    .child_by_field_name("name")
+                    .and_then(|name| name.utf8_text(source).ok());
+                if let Some(name) = name.filter(|name| names.matches(name)) {
+                    tracing::trace!(
+                        node = ?cursor.node(),
+                        text = cursor.node().utf8_text(source).unwrap_or_default(),
+                        children = ?cursor
+                            .node()
+                            .named_children(&mut tree.walk())
+                            .collect::<Vec<_>>(),
+                        "found test"
+                    );
+                    ret.push(name.to_string())
+                }
+            }
+
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+
+            loop {
+                if !cursor.goto_parent() {
+                    break 'outer;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+    });
+    ret
+}
+
+pub fn get_tests(
+    content_map: &HashMap<String, String>,
+    tree_map: &HashMap<String, Tree>,
+    language: Language,
+    names: &TestNames,
+) -> Result<HashSet<String>, DiscoveryError> {
+    let q = language.compiled_test_query();
+    let name_index = q.capture_index_for_name("name");
+    let mut v: HashSet<String> = HashSet::new();
+    for (path, tree) in tree_map {
+        let source = match content_map.get(path) {
+            Some(content) => content.as_bytes(),
+            None => continue,
+        };
+        let mut qc = QueryCursor::new();
+        let qm = qc.matches(q, tree.root_node(), source);
+        qm.for_each(|query_match| {
+            query_match
+                .captures
+                .iter()
+                .filter(|capture| Some(capture.index) == name_index)
+                .filter(|capture| {
+                    let name = capture.node.utf8_text(source).unwrap_or_default();
+                    names.matches(name)
+                })
+                .for_each(|capture: &QueryCapture| {
+                    if let Some(id) = test_id(path, capture.node, source, language) {
+                        v.insert(id);
+                    }
+                })
+        });
+    }
+    Ok(v)
+}
+
+/// The id of the test named by `name`: its path, the scopes enclosing it
+/// from the outermost, such as pytest's classes, and its name, joined by
+/// `::`. None when a scope is one the runner does not collect tests from.
+fn test_id(path: &str, name: Node, source: &[u8], language: Language) -> Option<String> {
+    let support = language.support();
+    let mut parts = vec![name.utf8_text(source).ok()?];
+    let mut ancestor = name.parent();
+    while let Some(node) = ancestor {
+        if let Some(scope) = support.test_scope_name(node, source) {
+            if !support.is_test_scope(node, source) {
+                return None;
+            }
+            parts.push(scope);
+        }
+        ancestor = node.parent();
+    }
+    parts.push(path);
+    parts.reverse();
+    Some(parts.join("::"))
+}
+
+/// The byte range of every test's definition, by test id, for telling
+/// which tests an edit touched. Names are not checked, so callers look up
+/// the ids `get_tests` found.
+pub fn get_test_ranges(
+    content_map: &HashMap<String, String>,
+    tree_map: &HashMap<String, Tree>,
+    language: Language,
+) -> Result<HashMap<String, Range<usize>>, DiscoveryError> {
+    let q = language.compiled_test_query();
+    let name_index = q.capture_index_for_name("name");
+    let mut ranges = HashMap::new();
+    for (path, tree) in tree_map {
+        let source = match content_map.get(path) {
+            Some(content) => content.as_bytes(),
+            None => continue,
+        };
+        let mut qc = QueryCursor::new();
+        for query_match in qc.matches(q, tree.root_node(), source) {
+            for capture in query_match.captures {
+                if Some(capture.index) != name_index {
+                    continue;
+                }
+                let mut definition = capture.node;
+                while definition.kind() != language.test_definition_kind() {
+                    match definition.parent() {
+                        Some(parent) => definition = parent,
+                        None => break,
+                    }
+                }
+                // editing a decorator, such as a parametrize list, edits the test
+                if let Some(decorated) = definition
+                    .parent()
+                    .filter(|parent| parent.kind() == "decorated_definition")
+                {
+                    definition = decorated;
+                }
+                if let Some(id) = test_id(path, capture.node, source, language) {
+                    ranges.insert(id, definition.byte_range());
+                }
+            }
+        }
+    }
+    Ok(ranges)
+}
+
+/// A path relative to the root with `/` between its components, as git
+/// writes it and test ids use it, whatever separator the platform has.
+pub fn slash_path(path: &str) -> String {
+    path.replace(std::path::MAIN_SEPARATOR, "/")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn create_old_content_map(
+    repo: &Repository,
+    commit: &Object,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<HashMap<String, String>, DiscoveryError> {
+    let mut old_content_map: HashMap<String, String> = HashMap::new();
+    let mut error: Option<DiscoveryError> = None;
+
+    let commit = commit
+        .as_commit()
+        .ok_or_else(|| DiscoveryError::NotACommit(commit.id().to_string()))?;
+
+    commit
+        .tree()?
+        .walk(git2::TreeWalkMode::PreOrder, |s, entry| {
+            let name = match entry.name() {
+                Some(name) => name,
+                None => return git2::TreeWalkResult::Ok,
+            };
+            if entry.kind() != Some(ObjectType::Blob) || !language.matches_path(Path::new(name)) {
+                return git2::TreeWalkResult::Ok;
+            }
+            let path = match s.is_empty() {
+                true => name.to_string(),
+                false => format!("{}{}", s, name),
+            };
+            if !filter.matches(&path) {
+                return git2::TreeWalkResult::Ok;
+            }
+            let blob = match entry.to_object(repo).and_then(|o| o.peel_to_blob()) {
+                Ok(blob) => blob,
+                Err(e) => {
+                    error = Some(e.into());
+                    return git2::TreeWalkResult::Abort;
+                }
+            };
+            match String::from_utf8(blob.content().to_vec()) {
+                Ok(content) => {
+                    old_content_map.insert(path, content);
+                }
+                Err(_) => failures.push(FileFailure::new(
+                    path.clone(),
+                    DiscoveryError::NonUtf8Content(path),
+                )),
+            }
+            git2::TreeWalkResult::Ok
+        })?;
+    match error {
+        Some(e) => Err(e),
+        None => Ok(old_content_map),
+    }
+}
+
+/// Contents of the staged version of every file, for analysing exactly what
+/// is about to be committed.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn create_index_content_map(
+    repo: &Repository,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<HashMap<String, String>, DiscoveryError> {
+    let mut index_content_map = HashMap::new();
+    for entry in repo.index()?.iter() {
+        let path = match std::str::from_utf8(&entry.path) {
+            Ok(path) => path.to_string(),
+            Err(_) => {
+                let path = String::from_utf8_lossy(&entry.path).to_string();
+                let error = DiscoveryError::NonUtf8Path(PathBuf::from(&path));
+                failures.push(FileFailure::new(path, error));
+                continue;
+            }
+        };
+        if !language.matches_path(Path::new(&path)) || !filter.matches(&path) {
+            continue;
+        }
+        let blob = repo.find_blob(entry.id)?;
+        match String::from_utf8(blob.content().to_vec()) {
+            Ok(content) => {
+                index_content_map.insert(path, content);
+            }
+            Err(_) => failures.push(FileFailure::new(
+                path.clone(),
+                DiscoveryError::NonUtf8Content(path),
+            )),
+        }
+    }
+    Ok(index_content_map)
+}
+
+/// Contents of every `language` file in the working tree `filter` matches.
+/// Paths ignored by `.gitignore` or `.ignore` files, such as `.venv/` or
+/// `build/`, are left out without being walked. Submodules, and any other
+/// checkout nested in this one, have a history of their own, so they are
+/// recorded in `failures` and skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn create_new_content_map(
+    root: &Path,
+    language: Language,
+    filter: &PathFilter,
+    failures: &mut Vec<FileFailure>,
+) -> Result<HashMap<String, String>, DiscoveryError> {
+    let mut new_content_map = HashMap::new();
+    let relative =
+        |path: &Path| slash_path(&path.strip_prefix(root).unwrap_or(path).to_string_lossy());
+    let nested = Arc::new(Mutex::new(Vec::new()));
+    let found = nested.clone();
+    let walker = WalkBuilder::new(root)
+        .hidden(false)
+        .follow_links(true)
+        .filter_entry(move |entry| {
+            if entry.file_name() == ".git" {
+                return false;
+            }
+            let checkout = entry.depth() > 0 && is_checkout(entry.path());
+            if checkout {
+                found
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(entry.path().to_path_buf());
+            }
+            !checkout
+        })
+        .build();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(ignore::Error::WithPath { path, err }) => {
+                failures.push(FileFailure::new(relative(&path), err));
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("{}", e);
+                continue;
+            }
+        };
+        let pathbuf = entry.into_path();
+        if !pathbuf.is_file() || !language.matches_path(&pathbuf) {
+            continue;
+        }
+        let path = match pathbuf.strip_prefix(root).unwrap_or(&pathbuf).to_str() {
+            Some(path) => slash_path(path),
+            None => {
+                let error = DiscoveryError::NonUtf8Path(pathbuf.clone());
+                failures.push(FileFailure::new(relative(&pathbuf), error));
+                continue;
+            }
+        };
+        if !filter.matches(&path) {
+            continue;
+        }
+        match fs::read_to_string(&pathbuf) {
+            Ok(content) => {
+                new_content_map.insert(path, content);
+            }
+            Err(source) => {
+                let error = DiscoveryError::Io {
+                    path: pathbuf.clone(),
+                    source,
+                };
+                failures.push(FileFailure::new(path, error));
+            }
+        }
+    }
+    for dir in nested.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        let path = relative(dir);
+        failures.push(FileFailure::new(
+            path.clone(),
+            DiscoveryError::NestedCheckout(path),
+        ));
+    }
+    Ok(new_content_map)
+}
+
+/// Reads `changed` again into `content_map`, the contents
+/// `create_new_content_map` gave before, leaving every other file as it
+/// was. False when the tree has to be walked again instead: a changed path
+/// is not in `content_map`, so it may be new or not analysed, or it cannot
+/// be read. `content_map` may then be partly updated.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn refresh_content_map<'a, I: IntoIterator<Item = &'a str>>(
+    root: &Path,
+    content_map: &mut HashMap<String, String>,
+    changed: I,
+) -> bool {
+    for path in changed {
+        let Some(content) = content_map.get_mut(path) else {
+            return false;
+        };
+        match fs::read_to_string(root.join(path)) {
+            Ok(new) => *content = new,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+/// Whether `dir` is the top of a git checkout, as a submodule is.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn is_checkout(dir: &Path) -> bool {
+    dir.join(".git").exists()
+}
+
+pub fn create_parser(language: Language) -> Result<Parser, DiscoveryError> {
+    let mut parser = Parser::new();
+    parser.set_language(&language.grammar())?;
+    Ok(parser)
+}
+
+/// Parses every file into `tree_map`. A tree already there for a file,
+/// edited with `edit_tree` to match its content, is parsed incrementally,
+/// reusing what the edits left alone. A file the parser gives up on, or
+/// panics on, is recorded in `failures` and left out.
+pub fn parse_all(
+    parser: &mut Parser,
+    content_map: &HashMap<String, String>,
+    tree_map: &mut HashMap<String, Tree>,
+    failures: &mut Vec<FileFailure>,
+) {
+    for (path, content) in content_map {
+        match parse_file(parser, path, content, tree_map.get(path)) {
+            Ok(tree) => {
+                tree_map.insert(path.to_string(), tree);
+            }
+            Err(failure) => failures.push(failure),
+        }
+    }
+}
+
+fn parse_file(
+    parser: &mut Parser,
+    path: &str,
+    content: &str,
+    old_tree: Option<&Tree>,
+) -> Result<Tree, FileFailure> {
+    match panic::catch_unwind(AssertUnwindSafe(|| parser.parse(content, old_tree))) {
+        Ok(Some(tree)) => Ok(tree),
+        Ok(None) => Err(FileFailure::new(
+            path,
+            DiscoveryError::Parse(path.to_string()),
+        )),
+        Err(_) => {
+            parser.reset();
+            Err(FileFailure::new(path, "parser panicked"))
+        }
+    }
+}
+
+/// Trees parsed in earlier cycles, keyed by path and a hash of the content
+/// they were parsed from, so files that did not change are not parsed
+/// again. Only the trees the last cycle used are kept.
+#[derive(Debug, Default)]
+pub struct TreeCache {
+    trees: Mutex<HashMap<(String, u64), Tree>>,
+    used: Mutex<HashSet<(String, u64)>>,
+}
+
+impl TreeCache {
+    pub fn new() -> TreeCache {
+        TreeCache::default()
+    }
+
+    /// Like `parse_all`, but takes the tree of any file parsed before with
+    /// the same content from the cache instead.
+    pub fn parse_all(
+        &self,
+        parser: &mut Parser,
+        content_map: &HashMap<String, String>,
+        tree_map: &mut HashMap<String, Tree>,
+        failures: &mut Vec<FileFailure>,
+    ) {
+        let mut parsed = 0;
+        for (path, content) in content_map {
+            let key = (path.clone(), content_hash(content));
+            let cached = self.lock().get(&key).cloned();
+            let tree = match cached {
+                Some(tree) => tree,
+                None => match parse_file(parser, path, content, tree_map.get(path)) {
+                    Ok(tree) => {
+                        parsed += 1;
+                        self.lock().insert(key.clone(), tree.clone());
+                        tree
+                    }
+                    Err(failure) => {
+                        failures.push(failure);
+                        continue;
+                    }
+                },
+            };
+            tree_map.insert(path.clone(), tree);
+            self.used
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(key);
+        }
+        tracing::debug!(parsed, reused = content_map.len() - parsed, "parsed files");
+    }
+
+    /// Drops every tree not used since the last sweep.
+    pub fn sweep(&self) {
+        let used = std::mem::take(&mut *self.used.lock().unwrap_or_else(|e| e.into_inner()));
+        self.lock().retain(|key, _| used.contains(key));
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(String, u64), Tree>> {
+        self.trees.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
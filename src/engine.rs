@@ -0,0 +1,1767 @@
+use glob::Pattern;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+
+use crate::bazel::{is_workspace, BazelSelector, BAZEL_COMMAND_TEMPLATE};
+use crate::cache::ImpactCache;
+use crate::ci::CiError;
+use crate::config::{BaseMode, Config, DataDependency, CONFIG_FILE};
+use crate::contexts;
+use crate::coverage::{patch_coverage, PatchCoverage};
+use crate::coveragepy::{self, Exclusions};
+use crate::data::{CompiledDependency, DataSelector};
+use crate::devcontainer::{self, DevcontainerError, DevcontainerRunner};
+use crate::diff::{diff_contents, BetterDiff, DiffError};
+use crate::discovery::{
+    create_new_content_map, is_checkout, slash_path, DiscoveryError, PathFilter, TestNames,
+};
+#[cfg(feature = "go")]
+use crate::gocover;
+use crate::hooks::HookError;
+use crate::html;
+#[cfg(feature = "java")]
+use crate::jacoco;
+use crate::language::Language;
+#[cfg(feature = "rust")]
+use crate::llvmcov;
+use crate::nvim::NvimError;
+use crate::patch::PatchError;
+use crate::report::{self, JsonReport, OutputFormat, ReportError};
+use crate::rpc::RpcError;
+use crate::runner::{
+    parse_template, validate_test_id, LocalRunner, RunResult, Runner, RunnerError,
+};
+use crate::selection::{
+    default_selector, select_changes, CompositeSelector, EmptySelection, ImpactData,
+    ImpactStrategy, SelectedTest, Selection, TestSelector,
+};
+#[cfg(feature = "sentry")]
+use crate::sentry::{SentryError, SentryReporter};
+#[cfg(feature = "ruby")]
+use crate::simplecov;
+use crate::state::{EngineState, LastDiff};
+use crate::statefile;
+use crate::trace::{CycleTrace, TraceContext};
+use crate::triggers::{self, TriggerSelector};
+use crate::vcs::{self, ChangeScope, GitVcs, Vcs, VcsError, VcsKind};
+use crate::watch;
+use crate::watch::{IgnoreList, WatchControl, WatchError};
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    #[error("{0} is not inside a git repository")]
+    NotARepository(PathBuf),
+    #[error("failed to open repository at {path}: {source}")]
+    OpenRepository { path: PathBuf, source: git2::Error },
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("cannot resolve base {base}: {source}")]
+    InvalidBase { base: String, source: git2::Error },
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error(transparent)]
+    Diff(#[from] DiffError),
+    #[error(transparent)]
+    Discovery(#[from] DiscoveryError),
+    #[error(transparent)]
+    Runner(#[from] RunnerError),
+    #[error(transparent)]
+    Watch(#[from] WatchError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error(transparent)]
+    Nvim(#[from] NvimError),
+    #[error(transparent)]
+    Devcontainer(#[from] DevcontainerError),
+    #[error(transparent)]
+    Hook(#[from] HookError),
+    #[error(transparent)]
+    Ci(#[from] CiError),
+    #[error(transparent)]
+    Vcs(#[from] VcsError),
+    #[error(transparent)]
+    Report(#[from] ReportError),
+    #[error(transparent)]
+    Patch(#[from] PatchError),
+}
+
+pub struct Engine {
+    root: PathBuf,
+    base: String,
+    base_mode: BaseMode,
+    language: Language,
+    /// Which of the language's files are watched and analysed.
+    filter: PathFilter,
+    test_names: TestNames,
+    selector: CompositeSelector,
+    runner: Box<dyn Runner>,
+    /// The command the runner runs, for passing it tests in its own form.
+    command_template: String,
+    /// Lines each test executed, reloaded from coverage.py's data file after
+    /// every run.
+    impact: RwLock<ImpactData>,
+    /// The base commit the impact data was gathered against.
+    impact_base: Mutex<Option<String>>,
+    /// `None` outside a repository, where there is no baseline.
+    vcs: Option<Box<dyn Vcs>>,
+    on_empty: EmptySelection,
+    data: Vec<CompiledDependency>,
+    /// Whether pytest settings files and `conftest.py` changes rerun the
+    /// tests below them.
+    triggers: bool,
+    /// Other files whose changes start a cycle.
+    watch_patterns: Vec<Pattern>,
+    /// Directories and files the watcher skips.
+    watch_ignore: IgnoreList,
+    /// Directories watched besides the root.
+    watch_roots: Vec<PathBuf>,
+    /// Pauses and resumes the watcher.
+    watch_control: WatchControl,
+    /// Whether `watch` returns when `CONFIG_FILE` changes.
+    reload_config: bool,
+    debounce: Duration,
+    /// How often to rescan the tree instead of relying on change events.
+    poll: Option<Duration>,
+    dry_run: bool,
+    output: OutputFormat,
+    /// Where reports go instead of stdout.
+    output_file: Option<PathBuf>,
+    html_dir: Option<PathBuf>,
+    open_html: bool,
+    /// Patch coverage, in percent, below which a run fails.
+    fail_under: Option<f64>,
+    /// Lines and files coverage.py is set up to leave out.
+    exclusions: Exclusions,
+    state: Arc<EngineState>,
+    state_file: bool,
+    /// Where recorded coverage is kept between sessions, if anywhere.
+    cache: Option<ImpactCache>,
+    /// Spans of the cycle in progress, when traces are exported.
+    trace: Mutex<Option<Arc<CycleTrace>>>,
+    #[cfg(feature = "otlp")]
+    otlp_endpoint: Option<String>,
+    #[cfg(feature = "sentry")]
+    sentry: Option<SentryReporter>,
+}
+
+pub struct EngineBuilder {
+    root: PathBuf,
+    base: String,
+    base_mode: BaseMode,
+    language: Language,
+    /// `None` until set, for the language's or Bazel's own command.
+    command_template: Option<String>,
+    runner: Option<Box<dyn Runner>>,
+    selector: CompositeSelector,
+    impact: ImpactData,
+    no_baseline_fallback: bool,
+    scope: ChangeScope,
+    on_empty: EmptySelection,
+    strategy: ImpactStrategy,
+    data_dependencies: Vec<DataDependency>,
+    container: Option<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    test_names: Vec<String>,
+    watch_patterns: Vec<String>,
+    watch_ignore: Vec<String>,
+    watch_roots: Vec<PathBuf>,
+    watch_max_depth: Option<usize>,
+    watch_non_recursive: Vec<String>,
+    debounce: Duration,
+    poll: Option<Duration>,
+    dry_run: bool,
+    output: OutputFormat,
+    output_file: Option<PathBuf>,
+    html_dir: Option<PathBuf>,
+    open_html: bool,
+    fail_under: Option<f64>,
+    state_file: bool,
+    impact_cache: bool,
+    reload_config: bool,
+    watch_control: WatchControl,
+    vcs: Option<Box<dyn Vcs>>,
+    #[cfg(feature = "otlp")]
+    otlp_endpoint: Option<String>,
+    #[cfg(feature = "sentry")]
+    sentry_dsn: Option<String>,
+}
+
+impl EngineBuilder {
+    /// Starts from the checkout containing `root`, which may be any
+    /// directory in it.
+    pub fn new<P: Into<PathBuf>>(root: P) -> EngineBuilder {
+        let root = vcs::checkout_root(&root.into());
+        EngineBuilder {
+            base: "HEAD".to_string(),
+            base_mode: BaseMode::default(),
+            language: Language::detect(&root).unwrap_or_default(),
+            command_template: None,
+            root,
+            runner: None,
+            selector: CompositeSelector::new(),
+            impact: ImpactData::default(),
+            no_baseline_fallback: false,
+            scope: ChangeScope::All,
+            on_empty: EmptySelection::default(),
+            strategy: ImpactStrategy::default(),
+            data_dependencies: Vec::new(),
+            container: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            test_names: Vec::new(),
+            watch_patterns: Vec::new(),
+            watch_ignore: Vec::new(),
+            watch_roots: Vec::new(),
+            watch_max_depth: None,
+            watch_non_recursive: Vec::new(),
+            debounce: watch::DEFAULT_DEBOUNCE,
+            poll: None,
+            dry_run: false,
+            output: OutputFormat::default(),
+            output_file: None,
+            html_dir: None,
+            open_html: false,
+            fail_under: None,
+            state_file: false,
+            impact_cache: false,
+            reload_config: false,
+            watch_control: WatchControl::default(),
+            vcs: None,
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "sentry")]
+            sentry_dsn: None,
+        }
+    }
+
+    /// The branch, tag or commit the working tree is compared against.
+    pub fn base<S: Into<String>>(mut self, base: S) -> EngineBuilder {
+        self.base = base.into();
+        self
+    }
+
+    /// Compare against the commit `base` names, or against where the
+    /// branch forked from it.
+    pub fn base_mode(mut self, mode: BaseMode) -> EngineBuilder {
+        self.base_mode = mode;
+        self
+    }
+
+    pub fn language(mut self, language: Language) -> EngineBuilder {
+        self.language = language;
+        self
+    }
+
+    /// Command used by the default runner; `{tests}` is replaced by the
+    /// selected test ids. Defaults to the language's own command.
+    pub fn command_template<S: Into<String>>(mut self, template: S) -> EngineBuilder {
+        self.command_template = Some(template.into());
+        self
+    }
+
+    pub fn runner<R: Runner + 'static>(mut self, runner: R) -> EngineBuilder {
+        self.runner = Some(Box::new(runner));
+        self
+    }
+
+    pub fn selector<S: TestSelector + 'static>(mut self, selector: S) -> EngineBuilder {
+        self.selector.register(selector);
+        self
+    }
+
+    pub fn impact_data(mut self, impact: ImpactData) -> EngineBuilder {
+        self.impact = impact;
+        self
+    }
+
+    /// Outside a git repository, treat every discovered test as changed
+    /// instead of failing to build.
+    pub fn no_baseline_fallback(mut self, enabled: bool) -> EngineBuilder {
+        self.no_baseline_fallback = enabled;
+        self
+    }
+
+    /// Analyse the index instead of the working tree, so only staged changes
+    /// count. `false` leaves the scope as it was.
+    pub fn staged(mut self, enabled: bool) -> EngineBuilder {
+        if enabled {
+            self.scope = ChangeScope::Staged;
+        }
+        self
+    }
+
+    /// Compare the working tree against the index instead of the base, so
+    /// only changes not yet staged count. `false` leaves the scope as it
+    /// was.
+    pub fn unstaged(mut self, enabled: bool) -> EngineBuilder {
+        if enabled {
+            self.scope = ChangeScope::Unstaged;
+        }
+        self
+    }
+
+    /// What to run when a change selects no tests. Defaults to skipping the
+    /// run.
+    pub fn on_empty(mut self, on_empty: EmptySelection) -> EngineBuilder {
+        self.on_empty = on_empty;
+        self
+    }
+
+    /// How affected tests are found. With `ImpactStrategy::Bazel` the
+    /// selection is made of target labels and, unless a command was set,
+    /// run with `bazel test`.
+    pub fn impact_strategy(mut self, strategy: ImpactStrategy) -> EngineBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Select `dependency.tests` whenever a file matching `dependency.paths`
+    /// changes.
+    pub fn data_dependency(mut self, dependency: DataDependency) -> EngineBuilder {
+        self.data_dependencies.push(dependency);
+        self
+    }
+
+    /// Run tests with `docker exec` in this running container, by name or
+    /// id, instead of locally or through the devcontainer CLI.
+    pub fn container<S: Into<String>>(mut self, container: S) -> EngineBuilder {
+        self.container = Some(container.into());
+        self
+    }
+
+    /// Only watch and analyse the language's files matching `pattern`, a
+    /// glob relative to the root. May be given several times.
+    pub fn include<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Never watch or analyse files matching `pattern`, e.g. `.venv/**`,
+    /// even if they are included.
+    pub fn exclude<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Only count functions whose name matches `pattern`, a glob such as
+    /// `*_test`, as tests, instead of following the language's convention.
+    /// May be given several times.
+    pub fn test_name<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.test_names.push(pattern.into());
+        self
+    }
+
+    /// Also start a cycle when a file matching `pattern`, a glob relative to
+    /// the root, changes.
+    pub fn watch_pattern<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.watch_patterns.push(pattern.into());
+        self
+    }
+
+    /// Never watch paths matching `pattern`, a glob relative to the root, or
+    /// anything below a matching directory. `.git`, Python's caches and
+    /// virtualenv and coverage.py's output are always skipped.
+    pub fn watch_ignore<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.watch_ignore.push(pattern.into());
+        self
+    }
+
+    /// Also watch the directory `dir`, relative to the root unless absolute,
+    /// such as sources kept outside the checkout or one of several packages.
+    /// Its changes start a cycle like those under the root.
+    pub fn watch_root<P: Into<PathBuf>>(mut self, dir: P) -> EngineBuilder {
+        self.watch_roots.push(dir.into());
+        self
+    }
+
+    /// Watch no directory more than `depth` levels below a watched root, so
+    /// huge trees do not register a watch for every directory at startup.
+    pub fn watch_max_depth(mut self, depth: usize) -> EngineBuilder {
+        self.watch_max_depth = Some(depth);
+        self
+    }
+
+    /// Watch directories matching `pattern`, a glob relative to the root,
+    /// but none of the directories below them.
+    pub fn watch_non_recursive<S: Into<String>>(mut self, pattern: S) -> EngineBuilder {
+        self.watch_non_recursive.push(pattern.into());
+        self
+    }
+
+    /// How long edits must settle before a cycle starts.
+    pub fn debounce(mut self, debounce: Duration) -> EngineBuilder {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Rescan the tree every `interval` instead of relying on the system's
+    /// change events, which network filesystems, Docker bind mounts and WSL
+    /// may not deliver.
+    pub fn poll(mut self, interval: Duration) -> EngineBuilder {
+        self.poll = Some(interval);
+        self
+    }
+
+    /// Print each selection, one test id per line, instead of running it.
+    pub fn dry_run(mut self, enabled: bool) -> EngineBuilder {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Read baselines and changes through `vcs` instead of the system
+    /// detected at the root.
+    pub fn vcs<V: Vcs + 'static>(mut self, vcs: V) -> EngineBuilder {
+        self.vcs = Some(Box::new(vcs));
+        self
+    }
+
+    /// Report each cycle in `format`.
+    pub fn output(mut self, format: OutputFormat) -> EngineBuilder {
+        self.output = format;
+        self
+    }
+
+    /// Write the report to `path` after every cycle instead of printing it.
+    pub fn output_file<P: Into<PathBuf>>(mut self, path: P) -> EngineBuilder {
+        self.output_file = Some(path.into());
+        self
+    }
+
+    /// Write an HTML page of the annotated diff into `dir` after every run.
+    pub fn html_dir<P: Into<PathBuf>>(mut self, dir: P) -> EngineBuilder {
+        self.html_dir = Some(dir.into());
+        self
+    }
+
+    /// Open the HTML page in the browser each time it is written.
+    pub fn open_html(mut self, enabled: bool) -> EngineBuilder {
+        self.open_html = enabled;
+        self
+    }
+
+    /// Fail runs whose patch coverage is below `percent`.
+    pub fn fail_under(mut self, percent: f64) -> EngineBuilder {
+        self.fail_under = Some(percent);
+        self
+    }
+
+    /// Keep `.instant-patch/state.json` up to date with the selection, the
+    /// last result and the uncovered lines of every changed file.
+    pub fn state_file(mut self, enabled: bool) -> EngineBuilder {
+        self.state_file = enabled;
+        self
+    }
+
+    /// Keep recorded coverage in `.instant-patch/impact.db`, keyed by base
+    /// commit, and start from it, so a restart can select tests by coverage
+    /// without running the whole suite again.
+    pub fn impact_cache(mut self, enabled: bool) -> EngineBuilder {
+        self.impact_cache = enabled;
+        self
+    }
+
+    /// Have `watch` return as soon as `CONFIG_FILE` changes, so the caller
+    /// can build an engine from the new settings and watch again.
+    pub fn reload_config(mut self, enabled: bool) -> EngineBuilder {
+        self.reload_config = enabled;
+        self
+    }
+
+    /// Pause, resume and rerun through `control`, which may outlive the
+    /// engine, such as one a signal handler was set up for.
+    pub fn watch_control(mut self, control: WatchControl) -> EngineBuilder {
+        self.watch_control = control;
+        self
+    }
+
+    /// Applies every setting present in `config`, leaving the rest as they
+    /// are.
+    pub fn config(mut self, config: &Config) -> EngineBuilder {
+        if let Some(base) = &config.base {
+            self.base = base.clone();
+        }
+        if let Some(mode) = config.base_mode {
+            self.base_mode = mode;
+        }
+        if let Some(command) = &config.command {
+            self.command_template = Some(command.clone());
+        }
+        if let Some(language) = config.language {
+            self.language = language;
+        }
+        if let Some(on_empty) = &config.on_empty {
+            self.on_empty = on_empty.clone();
+        }
+        if let Some(strategy) = config.impact {
+            self.strategy = strategy;
+        }
+        if let Some(container) = &config.container {
+            self.container = Some(container.clone());
+        }
+        if let Some(debounce) = config.debounce_ms {
+            self.debounce = Duration::from_millis(debounce);
+        }
+        if let Some(poll) = config.poll_ms {
+            self.poll = Some(Duration::from_millis(poll));
+        }
+        if let Some(depth) = config.watch_max_depth {
+            self.watch_max_depth = Some(depth);
+        }
+        self.include.extend(config.include.iter().cloned());
+        self.exclude.extend(config.exclude.iter().cloned());
+        self.test_names.extend(config.test_names.iter().cloned());
+        self.watch_patterns.extend(config.watch.iter().cloned());
+        self.watch_ignore
+            .extend(config.watch_ignore.iter().cloned());
+        self.watch_roots
+            .extend(config.watch_roots.iter().map(PathBuf::from));
+        self.watch_non_recursive
+            .extend(config.watch_non_recursive.iter().cloned());
+        self.data_dependencies
+            .extend(config.data_dependencies.iter().cloned());
+        self
+    }
+
+    /// Push metrics to an OTLP/HTTP collector after every cycle.
+    #[cfg(feature = "otlp")]
+    pub fn otlp_endpoint<S: Into<String>>(mut self, endpoint: S) -> EngineBuilder {
+        self.otlp_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Report cycle errors and recurring test failures to this Sentry DSN.
+    #[cfg(feature = "sentry")]
+    pub fn sentry_dsn<S: Into<String>>(mut self, dsn: S) -> EngineBuilder {
+        self.sentry_dsn = Some(dsn.into());
+        self
+    }
+
+    /// Checks that the repository opens, the base resolves and the command
+    /// template is usable before handing out an `Engine`.
+    pub fn build(mut self) -> Result<Engine, EngineError> {
+        if self.strategy == ImpactStrategy::Bazel {
+            if !is_workspace(&self.root) {
+                return Err(EngineError::InvalidConfig(format!(
+                    "{} is not the root of a Bazel workspace",
+                    self.root.display()
+                )));
+            }
+            self.selector.register(BazelSelector::new(&self.root));
+        }
+        let command_template = match self.command_template.take() {
+            Some(template) => template,
+            None if self.strategy == ImpactStrategy::Bazel => BAZEL_COMMAND_TEMPLATE.to_string(),
+            None => self.language.command_template_at(&self.root).to_string(),
+        };
+        if let Err(e) = parse_template(&command_template) {
+            return Err(EngineError::InvalidConfig(e.to_string()));
+        }
+        if let EmptySelection::Smoke(ids) = &self.on_empty {
+            if ids.is_empty() {
+                return Err(EngineError::InvalidConfig(
+                    "the smoke set for empty selections has no tests".to_string(),
+                ));
+            }
+            for id in ids {
+                validate_test_id(id).map_err(|e| EngineError::InvalidConfig(e.to_string()))?;
+            }
+        }
+        let mut impact_base = None;
+        let vcs = match self
+            .vcs
+            .take()
+            .map_or_else(|| vcs::open(&self.root, self.scope), Ok)
+        {
+            Ok(vcs) => {
+                impact_base = Some(vcs::resolve(vcs.as_ref(), &self.base, self.base_mode)?);
+                Some(vcs)
+            }
+            Err(EngineError::NotARepository(_)) if self.no_baseline_fallback => None,
+            Err(e) => return Err(e),
+        };
+
+        let mut data = Vec::new();
+        for dependency in &self.data_dependencies {
+            for id in &dependency.tests {
+                validate_test_id(id).map_err(|e| EngineError::InvalidConfig(e.to_string()))?;
+            }
+            data.push(CompiledDependency::new(dependency).map_err(|e| {
+                EngineError::InvalidConfig(format!("invalid data dependency path: {}", e))
+            })?);
+        }
+        // selectors reading git themselves get the commit a merge base
+        // resolved to
+        let selector_base = match (self.base_mode, &impact_base) {
+            (BaseMode::MergeBase, Some(rev)) => rev.clone(),
+            _ => self.base.clone(),
+        };
+        if !data.is_empty() && vcs.is_some() {
+            // registering a selector replaces the default one, so keep it
+            if self.selector.is_empty() {
+                self.selector = default_selector(self.language);
+            }
+            self.selector
+                .register(DataSelector::new(&self.root, &selector_base, data.clone()));
+        }
+        let triggers = self.language.is_python()
+            && self.strategy != ImpactStrategy::Bazel
+            && vcs.is_some()
+            && VcsKind::detect(&self.root) == Some(VcsKind::Git);
+        if triggers {
+            if self.selector.is_empty() {
+                self.selector = default_selector(self.language);
+            }
+            self.selector
+                .register(TriggerSelector::new(&self.root, &selector_base));
+        }
+
+        let filter = PathFilter::new(&self.include, &self.exclude)
+            .map_err(|e| EngineError::InvalidConfig(format!("invalid path pattern: {}", e)))?;
+        let test_names = TestNames::new(self.language, &self.test_names)
+            .map_err(|e| EngineError::InvalidConfig(format!("invalid test name pattern: {}", e)))?;
+        let watch_patterns = self
+            .watch_patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| EngineError::InvalidConfig(format!("invalid watch pattern: {}", e)))?;
+        let mut watch_ignore = IgnoreList::new(&self.watch_ignore)
+            .and_then(|ignore| ignore.non_recursive(&self.watch_non_recursive))
+            .map_err(|e| EngineError::InvalidConfig(format!("invalid ignore pattern: {}", e)))?;
+        if let Some(depth) = self.watch_max_depth {
+            watch_ignore = watch_ignore.max_depth(depth);
+        }
+        let mut watch_roots = Vec::new();
+        for dir in &self.watch_roots {
+            let dir = self.root.join(dir);
+            if !dir.is_dir() {
+                return Err(EngineError::InvalidConfig(format!(
+                    "{} is not a directory to watch",
+                    dir.display()
+                )));
+            }
+            watch_roots.push(dir.canonicalize().unwrap_or(dir));
+        }
+
+        #[cfg(feature = "sentry")]
+        let sentry = match &self.sentry_dsn {
+            Some(dsn) => Some(SentryReporter::new(
+                dsn.parse()
+                    .map_err(|e: SentryError| EngineError::InvalidConfig(e.to_string()))?,
+            )),
+            None => None,
+        };
+
+        let exclusions = exclusions(&self.root);
+
+        let cache = match self.impact_cache {
+            true => ImpactCache::open(&self.root)
+                .inspect_err(|e| tracing::warn!("{}", e))
+                .ok(),
+            false => None,
+        };
+        // start from earlier runs' coverage unless the caller supplied some
+        let mut impact = self.impact;
+        if impact.lines.is_empty() {
+            if let (Some(cache), Some(rev)) = (&cache, &impact_base) {
+                impact = cache.load(rev, &self.root).unwrap_or_else(|e| {
+                    tracing::warn!("{}", e);
+                    ImpactData::default()
+                });
+            }
+            let data_file = self.root.join(data_file(self.language, &self.root));
+            if data_file.is_file() {
+                if let Some(data) = read_data_file(self.language, &data_file, &self.root) {
+                    impact.merge(data);
+                }
+            }
+        }
+
+        let runner: Box<dyn Runner> = match self.runner {
+            Some(runner) => runner,
+            None if self.strategy == ImpactStrategy::Bazel => {
+                Box::new(LocalRunner::new(&self.root, &command_template))
+            }
+            None => match devcontainer_runner(&self.root, &command_template, self.container)? {
+                Some(runner) => Box::new(runner),
+                None => Box::new(local_runner(&self.root, &command_template)),
+            },
+        };
+        Ok(Engine {
+            root: self.root,
+            base: self.base,
+            base_mode: self.base_mode,
+            language: self.language,
+            filter,
+            test_names,
+            selector: self.selector,
+            runner,
+            command_template,
+            impact: RwLock::new(impact),
+            impact_base: Mutex::new(impact_base),
+            vcs,
+            on_empty: self.on_empty,
+            data,
+            triggers,
+            watch_patterns,
+            watch_ignore,
+            watch_roots,
+            watch_control: self.watch_control,
+            reload_config: self.reload_config,
+            debounce: self.debounce,
+            poll: self.poll,
+            dry_run: self.dry_run,
+            output: self.output,
+            output_file: self.output_file,
+            html_dir: self.html_dir,
+            open_html: self.open_html,
+            fail_under: self.fail_under,
+            exclusions,
+            state: Arc::new(EngineState::new()),
+            state_file: self.state_file,
+            cache,
+            trace: Mutex::new(None),
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: self.otlp_endpoint,
+            #[cfg(feature = "sentry")]
+            sentry,
+        })
+    }
+}
+
+/// The coverage.py exclusions set up for `root`, falling back to the
+/// default pragma when the settings cannot be read.
+fn exclusions(root: &Path) -> Exclusions {
+    Exclusions::discover(root).unwrap_or_else(|e| {
+        tracing::warn!("{}", e);
+        Exclusions::default()
+    })
+}
+
+fn data_file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The coverage data file the language's runner writes for the project at
+/// `root`, relative to it.
+#[allow(unreachable_patterns)]
+#[cfg_attr(not(feature = "java"), allow(unused_variables))]
+fn data_file(language: Language, root: &Path) -> &'static str {
+    match language {
+        #[cfg(feature = "go")]
+        Language::Go => gocover::DATA_FILE,
+        #[cfg(feature = "rust")]
+        Language::Rust => llvmcov::DATA_FILE,
+        #[cfg(feature = "ruby")]
+        Language::Ruby => simplecov::DATA_FILE,
+        #[cfg(feature = "java")]
+        Language::Java => jacoco::data_file(root),
+        _ => coveragepy::DATA_FILE,
+    }
+}
+
+/// Reads the coverage data file at `path`, logging why when it cannot.
+#[allow(unreachable_patterns)]
+fn read_data_file(language: Language, path: &Path, root: &Path) -> Option<ImpactData> {
+    let impact = match language {
+        #[cfg(feature = "go")]
+        Language::Go => gocover::read(path, root).map_err(|e| e.to_string()),
+        #[cfg(feature = "rust")]
+        Language::Rust => llvmcov::read(path, root).map_err(|e| e.to_string()),
+        #[cfg(feature = "ruby")]
+        Language::Ruby => simplecov::read(path, root).map_err(|e| e.to_string()),
+        #[cfg(feature = "java")]
+        Language::Java => jacoco::read(path, root).map_err(|e| e.to_string()),
+        _ => coveragepy::read(path, root).map_err(|e| e.to_string()),
+    };
+    impact.inspect_err(|e| tracing::warn!("{}", e)).ok()
+}
+
+/// The default runner, set up so coverage.py labels every line with the test
+/// that ran it, through `--cov-context` for pytest-cov and otherwise through
+/// the companion plugin and a generated rcfile, and so pytest reports each
+/// test's outcome.
+fn local_runner(root: &Path, template: &str) -> LocalRunner {
+    let cov_template = contexts::with_cov_context(template);
+    let runner = LocalRunner::new(root, cov_template.as_deref().unwrap_or(template));
+    let setup = match contexts::install(root) {
+        Ok(setup) => setup,
+        Err(e) => {
+            tracing::warn!("{}; per-test results will not be recorded", e);
+            return runner;
+        }
+    };
+    setup
+        .env()
+        .into_iter()
+        .filter(|(key, _)| cov_template.is_none() || *key != "COVERAGE_RCFILE")
+        .fold(runner, |runner, (key, value)| runner.env(key, value))
+        .collect_results(true)
+}
+
+/// A runner that executes inside the repository's devcontainer, when a
+/// container was named or when the repository has a devcontainer config,
+/// this process runs on the host and the devcontainer CLI is installed.
+/// The plugin and rcfile `local_runner` sets up live on the host, so only
+/// pytest-cov's contexts are recorded.
+fn devcontainer_runner(
+    root: &Path,
+    template: &str,
+    container: Option<String>,
+) -> Result<Option<DevcontainerRunner>, EngineError> {
+    let config = devcontainer::load(root)?;
+    let template = contexts::with_cov_context(template).unwrap_or_else(|| template.to_string());
+    Ok(match (container, config) {
+        (Some(container), config) => Some(
+            DevcontainerRunner::new(root, template, &config.unwrap_or_default())
+                .container(container),
+        ),
+        (None, Some(config))
+            if !devcontainer::in_container() && devcontainer::on_path("devcontainer") =>
+        {
+            Some(DevcontainerRunner::new(root, template, &config))
+        }
+        (None, _) => None,
+    })
+}
+
+/// How many times `select` rebuilds before giving up on a tree that keeps
+/// changing underneath it.
+const SELECT_ATTEMPTS: usize = 3;
+
+/// How many cycles in a row may rediff only the files the watcher reported
+/// before the whole tree is diffed again, in case an event was missed.
+pub const FULL_RESYNC_EVERY: usize = 20;
+
+/// Cross-checks the diff against the content maps: files the diff calls
+/// unchanged must read the same as the baseline, and every hunk must fit in
+/// the file it was computed from.
+fn find_stale_path(
+    vd: &[BetterDiff],
+    old_content_map: &HashMap<String, String>,
+    new_content_map: &HashMap<String, String>,
+) -> Option<String> {
+    let changed: HashSet<&str> = vd.iter().map(|d| d.path.as_str()).collect();
+    for d in vd {
+        let fits = match new_content_map.get(&d.path) {
+            Some(content) => d.addition_end <= content.len(),
+            None => d.addition_end == d.start_offset,
+        };
+        if !fits {
+            return Some(d.path.clone());
+        }
+    }
+    new_content_map
+        .iter()
+        .filter(|(path, _)| !changed.contains(path.as_str()))
+        .find(|(path, content)| {
+            old_content_map
+                .get(path.as_str())
+                .is_some_and(|old| !same_text(old, content))
+        })
+        .map(|(path, _)| path.clone())
+}
+
+/// `last` with the hunks of `changed` recomputed from the content maps, or
+/// `None` when the whole tree has to be diffed again: a resync is due, or a
+/// changed path was added, deleted, renamed or is not an analysed source.
+fn rediff(
+    last: LastDiff,
+    changed: &BTreeSet<String>,
+    old_content_map: &HashMap<String, String>,
+    new_content_map: &HashMap<String, String>,
+) -> Option<LastDiff> {
+    if last.scoped + 1 >= FULL_RESYNC_EVERY {
+        return None;
+    }
+    let renamed = |path: &str| {
+        last.renames
+            .iter()
+            .any(|(from, to)| from == path || to == path)
+    };
+    let mut rediffed = Vec::new();
+    for path in changed {
+        let (Some(old), Some(new)) = (old_content_map.get(path), new_content_map.get(path)) else {
+            return None;
+        };
+        if renamed(path) {
+            return None;
+        }
+        if !same_text(old, new) {
+            rediffed.extend(diff_contents(path, old, new));
+        }
+    }
+    let mut hunks: Vec<BetterDiff> = last
+        .hunks
+        .into_iter()
+        .filter(|hunk| !changed.contains(&hunk.path))
+        .chain(rediffed)
+        .collect();
+    hunks.sort_by(|a, b| (&a.path, a.start_offset).cmp(&(&b.path, b.start_offset)));
+    Some(LastDiff {
+        hunks,
+        failures: last
+            .failures
+            .into_iter()
+            .filter(|failure| !changed.contains(&failure.path))
+            .collect(),
+        scoped: last.scoped + 1,
+        ..last
+    })
+}
+
+/// Equal up to line endings, which git may convert on checkout.
+fn same_text(a: &str, b: &str) -> bool {
+    a == b || a.replace("\r\n", "\n") == b.replace("\r\n", "\n")
+}
+
+impl Engine {
+    /// An engine with default settings, diffing the working tree at `root`
+    /// against `HEAD`. Use `EngineBuilder` to customise and validate.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Engine {
+        let root = vcs::checkout_root(&root.into());
+        let language = Language::detect(&root).unwrap_or_default();
+        Engine {
+            exclusions: exclusions(&root),
+            runner: Box::new(LocalRunner::new(&root, language.command_template_at(&root))),
+            command_template: language.command_template_at(&root).to_string(),
+            vcs: Some(Box::new(GitVcs::new(&root, ChangeScope::All))),
+            root,
+            base: "HEAD".to_string(),
+            base_mode: BaseMode::default(),
+            language,
+            filter: PathFilter::default(),
+            test_names: TestNames::of(language),
+            selector: CompositeSelector::new(),
+            impact: RwLock::new(ImpactData::default()),
+            impact_base: Mutex::new(None),
+            on_empty: EmptySelection::default(),
+            data: Vec::new(),
+            triggers: false,
+            watch_patterns: Vec::new(),
+            watch_ignore: IgnoreList::default(),
+            watch_roots: Vec::new(),
+            watch_control: WatchControl::default(),
+            reload_config: false,
+            debounce: watch::DEFAULT_DEBOUNCE,
+            poll: None,
+            dry_run: false,
+            output: OutputFormat::default(),
+            output_file: None,
+            html_dir: None,
+            open_html: false,
+            fail_under: None,
+            state: Arc::new(EngineState::new()),
+            state_file: false,
+            cache: None,
+            trace: Mutex::new(None),
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "sentry")]
+            sentry: None,
+        }
+    }
+
+    pub fn builder<P: Into<PathBuf>>(root: P) -> EngineBuilder {
+        EngineBuilder::new(root)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// The id of the revision `base` currently resolves to, or of its merge
+    /// base with `HEAD` in `BaseMode::MergeBase`.
+    pub fn base_commit(&self) -> Result<String, EngineError> {
+        match &self.vcs {
+            Some(vcs) => self.resolve_base(vcs.as_ref()),
+            None => Err(EngineError::NotARepository(self.root.clone())),
+        }
+    }
+
+    fn resolve_base(&self, vcs: &dyn Vcs) -> Result<String, EngineError> {
+        vcs::resolve(vcs, &self.base, self.base_mode)
+    }
+
+    /// True when running outside a repository, where every test is selected.
+    pub fn no_baseline(&self) -> bool {
+        self.vcs.is_none()
+    }
+
+    /// Shared handle to the engine's state, for readers on other threads.
+    pub fn state(&self) -> Arc<EngineState> {
+        self.state.clone()
+    }
+
+    /// Adds a selection strategy. When none are registered the engine falls
+    /// back to selecting newly added tests and the tests covering the change.
+    pub fn register_selector<S: TestSelector + 'static>(&mut self, selector: S) {
+        self.selector.register(selector);
+    }
+
+    pub fn set_runner<R: Runner + 'static>(&mut self, runner: R) {
+        self.runner = Box::new(runner);
+    }
+
+    pub fn impact_data(&self) -> RwLockReadGuard<'_, ImpactData> {
+        self.impact.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_impact_data(&self, impact: ImpactData) {
+        *self.impact.write().unwrap_or_else(|e| e.into_inner()) = impact;
+    }
+
+    /// Folds coverage.py's data file into the impact data if it changed
+    /// since `before`, its modification time when the run started. Data
+    /// accumulates over a session for as long as the base stays the same
+    /// commit, since each run only executes the tests it selected.
+    fn reload_impact_data(&self, before: Option<SystemTime>) {
+        let path = self.root.join(data_file(self.language, &self.root));
+        let modified = data_file_modified(&path);
+        if modified.is_none() || modified == before {
+            return;
+        }
+        let Some(impact) = read_data_file(self.language, &path, &self.root) else {
+            return;
+        };
+        let rev = self
+            .vcs
+            .as_ref()
+            .and_then(|vcs| self.resolve_base(vcs.as_ref()).ok());
+        let mut base = self.impact_base.lock().unwrap_or_else(|e| e.into_inner());
+        match rev.is_some() && *base == rev {
+            true => self
+                .impact
+                .write()
+                .unwrap_or_else(|e| e.into_inner())
+                .merge(impact),
+            false => {
+                tracing::debug!(base = ?rev, "starting coverage afresh");
+                self.set_impact_data(impact);
+            }
+        }
+        if let (Some(cache), Some(rev)) = (&self.cache, &rev) {
+            if let Err(e) = cache.store(rev, &self.impact_data(), &self.root) {
+                tracing::warn!("{}", e);
+            }
+        }
+        *base = rev;
+    }
+
+    /// Selects tests for the current working tree. The diff and the file
+    /// contents are read separately, so if they disagree (a file was written
+    /// mid-cycle, or the tree was reset under us) the cached baseline is
+    /// dropped and the selection rebuilt instead of trusting either.
+    pub fn select(&self) -> Result<Selection, EngineError> {
+        self.select_scoped(None)
+    }
+
+    /// Like `select`, but only `changed`, the files the watcher saw change,
+    /// are read and diffed again; the last cycle's contents and hunks are
+    /// kept for the rest. The
+    /// whole tree is still diffed when a change adds, deletes or renames a
+    /// file, touches anything besides analysed sources, or every
+    /// `FULL_RESYNC_EVERY` cycles.
+    pub fn select_changed(&self, changed: &[PathBuf]) -> Result<Selection, EngineError> {
+        let changed: BTreeSet<String> = self.relative_paths(changed).into_iter().collect();
+        self.select_scoped(Some(&changed))
+    }
+
+    fn select_scoped(&self, changed: Option<&BTreeSet<String>>) -> Result<Selection, EngineError> {
+        let mut attempt = 1;
+        loop {
+            match self.try_select(changed) {
+                Err(EngineError::Diff(DiffError::StaleContent(path)))
+                    if attempt < SELECT_ATTEMPTS =>
+                {
+                    tracing::warn!("{} changed during selection, rebuilding", path);
+                    self.state.invalidate_baseline();
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn try_select(&self, changed: Option<&BTreeSet<String>>) -> Result<Selection, EngineError> {
+        let mut failures = Vec::new();
+        // without a baseline nothing existed before, so every test is new
+        let (old_content_map, new_content_map, vd, renames) = match &self.vcs {
+            None => (
+                Arc::new(HashMap::new()),
+                Arc::new(create_new_content_map(
+                    &self.root,
+                    self.language,
+                    &self.filter,
+                    &mut failures,
+                )?),
+                Vec::new(),
+                Vec::new(),
+            ),
+            Some(vcs) => {
+                let rev = self.resolve_base(vcs.as_ref())?;
+                let mut last = changed
+                    .and_then(|_| self.state.take_last_diff(&rev))
+                    .filter(|last| last.scoped + 1 < FULL_RESYNC_EVERY);
+                // the state no longer holds the content, so it is updated
+                // without being copied
+                let refreshed = last.as_mut().zip(changed).and_then(|(last, changed)| {
+                    vcs.refresh_working_content(Arc::make_mut(&mut last.content), changed)
+                        .then(|| (last.content.clone(), last.content_failures.clone()))
+                });
+                let (new_content_map, content_failures) = match refreshed {
+                    Some(refreshed) => refreshed,
+                    None => {
+                        let mut content_failures = Vec::new();
+                        let content = vcs.working_content(
+                            self.language,
+                            &self.filter,
+                            &mut content_failures,
+                        )?;
+                        (Arc::new(content), content_failures)
+                    }
+                };
+                let cached = self.state.baseline(&rev);
+                self.state.metrics().record_baseline(cached.is_some());
+                let old_content_map = match cached {
+                    Some(content) => content,
+                    None => self.state.set_baseline(
+                        &rev,
+                        vcs.base_content(&rev, self.language, &self.filter, &mut failures)?,
+                    ),
+                };
+                let scoped = last.zip(changed).and_then(|(last, changed)| {
+                    rediff(last, changed, &old_content_map, &new_content_map)
+                });
+                let diff = match scoped {
+                    Some(diff) => LastDiff {
+                        content: new_content_map.clone(),
+                        content_failures,
+                        ..diff
+                    },
+                    None => {
+                        let mut diff_failures = Vec::new();
+                        let hunks = self.span("diff", |_| {
+                            vcs.diff(&rev, self.language, &self.filter, &mut diff_failures)
+                        })?;
+                        LastDiff {
+                            renames: vcs.renamed_files(&rev)?,
+                            commit: rev,
+                            hunks,
+                            failures: diff_failures,
+                            content: new_content_map.clone(),
+                            content_failures,
+                            scoped: 0,
+                        }
+                    }
+                };
+                if let Some(path) = find_stale_path(&diff.hunks, &old_content_map, &new_content_map)
+                {
+                    return Err(DiffError::StaleContent(path).into());
+                }
+                failures.extend(diff.failures.iter().cloned());
+                failures.extend(diff.content_failures.iter().cloned());
+                self.state.set_last_diff(diff.clone());
+                (old_content_map, new_content_map, diff.hunks, diff.renames)
+            }
+        };
+
+        let default;
+        let selector: &dyn TestSelector = match self.selector.is_empty() {
+            true => {
+                default = default_selector(self.language);
+                &default
+            }
+            false => &self.selector,
+        };
+        let mut selection = self.span("select", |_| {
+            select_changes(
+                selector,
+                self.language,
+                &self.test_names,
+                &vd,
+                &old_content_map,
+                &new_content_map,
+                &renames,
+                &self.impact_data(),
+                self.state.trees(),
+            )
+        })?;
+        failures.append(&mut selection.skipped);
+        failures.sort_by(|a, b| a.path.cmp(&b.path));
+        failures.dedup_by(|a, b| a.path == b.path);
+        selection.skipped = failures;
+        Ok(selection)
+    }
+
+    /// Runs one select-and-run cycle, recording the outcome in the shared
+    /// state.
+    pub fn run_once(&self) -> Result<RunResult, EngineError> {
+        self.run_scoped(None)
+    }
+
+    /// Like `run_once`, rediffing only `changed` as `select_changed` does.
+    pub fn run_changed(&self, changed: &[PathBuf]) -> Result<RunResult, EngineError> {
+        let changed: BTreeSet<String> = self.relative_paths(changed).into_iter().collect();
+        self.run_scoped(Some(&changed))
+    }
+
+    fn run_scoped(&self, changed: Option<&BTreeSet<String>>) -> Result<RunResult, EngineError> {
+        let _cycle = tracing::info_span!("cycle").entered();
+        let started = Instant::now();
+        let mut selected = 0;
+        self.begin_trace();
+        let result = self.cycle(changed, &mut selected);
+        if let Err(e) = &result {
+            self.state.record_error(&e.to_string());
+        }
+        self.state
+            .metrics()
+            .record_cycle(started.elapsed(), selected, result.is_ok());
+        self.export_metrics();
+        self.export_trace(selected, &result);
+        self.report_to_sentry(&result);
+        if result.is_err() {
+            self.write_state_file();
+        }
+        result
+    }
+
+    fn write_state_file(&self) {
+        if !self.state_file {
+            return;
+        }
+        let coverage = self.patch_coverage().unwrap_or_default();
+        if let Err(e) = statefile::write(&self.root, &self.state.snapshot(), &coverage) {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    #[cfg(feature = "sentry")]
+    fn report_to_sentry(&self, result: &Result<RunResult, EngineError>) {
+        if let Some(sentry) = &self.sentry {
+            let selection = self.state.snapshot().selection;
+            match (result, &selection) {
+                (Ok(result), Some(selection)) => sentry.report_result(selection, result),
+                (Ok(_), None) => {}
+                (Err(e), _) => sentry.report_error(&e.to_string(), selection.as_ref()),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "sentry"))]
+    fn report_to_sentry(&self, _result: &Result<RunResult, EngineError>) {}
+
+    #[cfg(feature = "otlp")]
+    fn export_metrics(&self) {
+        if let Some(endpoint) = &self.otlp_endpoint {
+            let snapshot = self.state.metrics().snapshot();
+            if let Err(e) =
+                crate::metrics::export_otlp(endpoint, "instant-patch-coverage", &snapshot)
+            {
+                tracing::warn!("{}", e);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn export_metrics(&self) {}
+
+    #[cfg(feature = "otlp")]
+    fn begin_trace(&self) {
+        if self.otlp_endpoint.is_some() {
+            *self.trace.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some(Arc::new(CycleTrace::start()));
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn begin_trace(&self) {}
+
+    /// Closes the cycle's trace, after measuring patch coverage as its last
+    /// stage, and sends it to the collector.
+    #[cfg(feature = "otlp")]
+    fn export_trace(&self, selected: usize, result: &Result<RunResult, EngineError>) {
+        let endpoint = match &self.otlp_endpoint {
+            Some(endpoint) => endpoint,
+            None => return,
+        };
+        if result.is_ok() {
+            if let Ok(coverage) = self.span("coverage", |_| self.patch_coverage()) {
+                if let Some(trace) = self.current_trace() {
+                    trace.set_attribute("patch_coverage.changed_lines", coverage.changed());
+                    trace.set_attribute("patch_coverage.covered_lines", coverage.covered());
+                }
+            }
+        }
+        let trace = self.trace.lock().unwrap_or_else(|e| e.into_inner()).take();
+        let trace = match trace.map(Arc::try_unwrap) {
+            Some(Ok(trace)) => trace,
+            _ => return,
+        };
+        trace.set_attribute("tests.selected", selected);
+        let error = result.as_ref().err().map(ToString::to_string);
+        let payload = trace.finish("instant-patch-coverage", error);
+        if let Err(e) = crate::trace::export_otlp(endpoint, payload) {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    fn export_trace(&self, _selected: usize, _result: &Result<RunResult, EngineError>) {}
+
+    fn current_trace(&self) -> Option<Arc<CycleTrace>> {
+        self.trace.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Runs `f` as the phase `name` of a cycle: inside a log span, and as a
+    /// span of the cycle's trace if one is being recorded, handing it the
+    /// span's context.
+    fn span<T, E: ToString, F>(&self, name: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(Option<TraceContext>) -> Result<T, E>,
+    {
+        let _phase = tracing::info_span!("phase", name).entered();
+        match self.current_trace() {
+            Some(trace) => trace.span(name, |context| f(Some(context))),
+            None => f(None),
+        }
+    }
+
+    fn cycle(
+        &self,
+        changed: Option<&BTreeSet<String>>,
+        selected: &mut usize,
+    ) -> Result<RunResult, EngineError> {
+        let selection = self.select_scoped(changed)?;
+        *selected = selection.tests.len();
+        tracing::info!(
+            selected = selection.tests.len(),
+            skipped_files = selection.skipped.len(),
+            "selection computed"
+        );
+        report::print_failures(&selection);
+        let text = self.prints_text();
+        if self.dry_run {
+            if text {
+                report::print_ids(&selection);
+            }
+            let result = RunResult::default();
+            self.state.begin_run(&selection);
+            self.state.finish_run(&result);
+            self.write_state_file();
+            self.report(&selection, &result)?;
+            return Ok(result);
+        }
+        let selection = match (selection.is_empty(), &self.on_empty) {
+            (false, _) | (true, EmptySelection::All) => selection,
+            (true, EmptySelection::Smoke(ids)) => Selection::new(
+                ids.iter()
+                    .map(|id| SelectedTest::new(id.as_str(), "smoke"))
+                    .collect(),
+            ),
+            (true, EmptySelection::Skip) => {
+                if text {
+                    report::print_skipped();
+                }
+                let result = RunResult::default();
+                self.state.begin_run(&selection);
+                self.state.finish_run(&result);
+                self.write_state_file();
+                self.report(&selection, &result)?;
+                return Ok(result);
+            }
+        };
+        if text {
+            report::print_selection(&selection);
+        }
+        let result = self.run(&selection)?;
+        if text {
+            report::print_result(&result);
+        }
+        self.report(&selection, &result)?;
+        Ok(result)
+    }
+
+    /// Whether progress is printed for people, which stops when stdout
+    /// carries the JSON report.
+    fn prints_text(&self) -> bool {
+        self.output == OutputFormat::Text || self.report_file().is_some()
+    }
+
+    fn report_file(&self) -> Option<PathBuf> {
+        match (&self.output_file, self.output) {
+            (Some(path), _) => Some(path.clone()),
+            (None, OutputFormat::Lcov) => Some(self.root.join(report::LCOV_FILE)),
+            (None, _) => None,
+        }
+    }
+
+    /// Prints the cycle's patch coverage for people, then writes the report
+    /// in the chosen format to the report file, or to stdout if it is not
+    /// text.
+    fn report(&self, selection: &Selection, result: &RunResult) -> Result<(), EngineError> {
+        let coverage = match result.executed {
+            true => Some(self.patch_coverage()?),
+            false => None,
+        };
+        if let (true, Some(coverage)) = (self.prints_text(), &coverage) {
+            report::print_coverage(coverage);
+        }
+        if let (Some(dir), Some(coverage)) = (&self.html_dir, &coverage) {
+            self.write_html(dir, coverage)?;
+        }
+        if let (Some(threshold), Some(coverage)) = (self.fail_under, &coverage) {
+            if !coverage.meets(threshold) {
+                report::print_threshold_banner(coverage, threshold);
+            }
+        }
+        let path = self.report_file();
+        let content = match (self.output, &coverage, &path) {
+            (OutputFormat::Json, _, _) => {
+                let document = JsonReport::new(
+                    selection,
+                    Some(result).filter(|r| r.executed),
+                    coverage.as_ref(),
+                );
+                match path {
+                    Some(_) => serde_json::to_string_pretty(&document),
+                    None => serde_json::to_string(&document),
+                }
+                .map_err(ReportError::from)?
+            }
+            (OutputFormat::Text, Some(coverage), Some(_)) => report::coverage_summary(coverage),
+            (OutputFormat::Lcov, Some(coverage), _) => {
+                report::lcov(coverage).trim_end().to_string()
+            }
+            // nothing ran, or the summary above was the whole report
+            _ => return Ok(()),
+        };
+        match path {
+            Some(path) => report::write(&path, &format!("{}\n", content))?,
+            None => println!("{}", content),
+        }
+        Ok(())
+    }
+
+    /// Writes the annotated diff of `coverage` to `dir`, opening it when
+    /// asked to.
+    fn write_html(&self, dir: &Path, coverage: &PatchCoverage) -> Result<(), EngineError> {
+        let vcs = match &self.vcs {
+            Some(vcs) => vcs,
+            None => return Ok(()),
+        };
+        let rev = self.resolve_base(vcs.as_ref())?;
+        let old = vcs.base_content(&rev, self.language, &self.filter, &mut Vec::new())?;
+        let new = vcs.working_content(self.language, &self.filter, &mut Vec::new())?;
+        std::fs::create_dir_all(dir).map_err(|source| ReportError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        let path = dir.join(html::INDEX_FILE);
+        report::write(&path, &html::render(coverage, &old, &new))?;
+        if self.open_html {
+            if let Err(e) = html::open(&path) {
+                tracing::warn!("failed to open {}: {}", path.display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// `selection` with its tests replaced by what the language's runner
+    /// takes to run them, such as their files, when that differs from their
+    /// ids. Tests that map to no arguments at all would have the runner run
+    /// the whole suite, so they fall under the `on_empty` policy instead;
+    /// `None` means there is nothing to run.
+    fn test_targets(&self, selection: &Selection) -> Option<Selection> {
+        let ids = selection.ids();
+        let arguments = self
+            .language
+            .test_arguments_for(&self.command_template, &ids);
+        if arguments == ids {
+            return Some(selection.clone());
+        }
+        let arguments = match (arguments.is_empty() && !ids.is_empty(), &self.on_empty) {
+            (false, _) | (true, EmptySelection::All) => arguments,
+            (true, EmptySelection::Smoke(smoke)) => {
+                let arguments = self
+                    .language
+                    .test_arguments_for(&self.command_template, smoke);
+                if arguments.is_empty() {
+                    return None;
+                }
+                arguments
+            }
+            (true, EmptySelection::Skip) => return None,
+        };
+        let mut runnable = selection.clone();
+        runnable.tests = arguments
+            .into_iter()
+            .map(|argument| SelectedTest::new(argument, String::new()))
+            .collect();
+        Some(runnable)
+    }
+
+    /// Hands `selection` to the runner, recording the run in the shared
+    /// state. Prints nothing, so front ends that own stdout can call it.
+    pub fn run(&self, selection: &Selection) -> Result<RunResult, EngineError> {
+        self.state.begin_run(selection);
+        self.write_state_file();
+        let data_file = data_file_modified(&self.root.join(data_file(self.language, &self.root)));
+        let Some(targets) = self.test_targets(selection) else {
+            let result = RunResult::default();
+            self.state.finish_run(&result);
+            self.write_state_file();
+            return Ok(result);
+        };
+        let selection = &targets;
+        let result = self.span("run", |context| match context {
+            Some(context) => {
+                let mut traced = selection.clone();
+                traced.traceparent = Some(context.traceparent());
+                self.runner.run(&traced)
+            }
+            None => self.runner.run(selection),
+        })?;
+        if let Some(trace) = self.current_trace() {
+            trace.ingest(&result.spans);
+        }
+        if result.executed {
+            self.reload_impact_data(data_file);
+        }
+        self.state.finish_run(&result);
+        self.write_state_file();
+        Ok(result)
+    }
+
+    /// Whether patch coverage meets the `fail_under` threshold, which it
+    /// always does without one.
+    pub fn coverage_ok(&self) -> Result<bool, EngineError> {
+        match self.fail_under {
+            Some(threshold) => Ok(self.patch_coverage()?.meets(threshold)),
+            None => Ok(true),
+        }
+    }
+
+    /// Lines the working tree adds or changes relative to the base, and
+    /// which of them the impact data says some test executes.
+    pub fn patch_coverage(&self) -> Result<PatchCoverage, EngineError> {
+        let vcs = match &self.vcs {
+            Some(vcs) => vcs,
+            None => return Ok(PatchCoverage::default()),
+        };
+        let rev = self.resolve_base(vcs.as_ref())?;
+        let vd = vcs.diff(&rev, self.language, &self.filter, &mut Vec::new())?;
+        let impact = self.impact_data();
+        let mut coverage = patch_coverage(&vd, &impact);
+        coverage
+            .files
+            .retain(|file| !self.exclusions.omits(&file.path, &self.root));
+        #[cfg(feature = "python")]
+        if self.language == Language::Python {
+            self.apply_coveragepy_rules(&mut coverage, &impact, |path| {
+                std::fs::read_to_string(self.root.join(path)).ok()
+            });
+        }
+        Ok(coverage)
+    }
+
+    /// Lines revision `to` adds or changes relative to `from`, both named
+    /// as a base would be, and which of them the impact data says some test
+    /// executes. Nothing is checked out, so the impact data should come
+    /// from a run at `to`.
+    pub fn patch_coverage_between(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<PatchCoverage, EngineError> {
+        let vcs = self
+            .vcs
+            .as_ref()
+            .ok_or_else(|| EngineError::NotARepository(self.root.clone()))?;
+        let (from, to) = (vcs.resolve(from)?, vcs.resolve(to)?);
+        let vd = vcs.diff_revisions(&from, &to, self.language, &self.filter, &mut Vec::new())?;
+        let impact = self.impact_data();
+        let mut coverage = patch_coverage(&vd, &impact);
+        coverage
+            .files
+            .retain(|file| !self.exclusions.omits(&file.path, &self.root));
+        #[cfg(feature = "python")]
+        if self.language == Language::Python {
+            let sources = vcs.base_content(&to, self.language, &self.filter, &mut Vec::new())?;
+            self.apply_coveragepy_rules(&mut coverage, &impact, |path| sources.get(path).cloned());
+        }
+        Ok(coverage)
+    }
+
+    /// Drops the lines coverage.py's settings exclude from `coverage` and,
+    /// when branches were measured, marks the partial ones. `source` reads
+    /// a changed file as the coverage describes it.
+    #[cfg(feature = "python")]
+    fn apply_coveragepy_rules<F: Fn(&str) -> Option<String>>(
+        &self,
+        coverage: &mut PatchCoverage,
+        impact: &ImpactData,
+        source: F,
+    ) {
+        let mut excluded = HashMap::new();
+        let mut branch_lines = HashMap::new();
+        for file in &coverage.files {
+            let Some(source) = source(&file.path) else {
+                continue;
+            };
+            excluded.insert(file.path.clone(), self.exclusions.excluded_lines(&source));
+            if !impact.arcs.is_empty() {
+                branch_lines.insert(file.path.clone(), coveragepy::branch_lines(&source));
+            }
+        }
+        coverage.exclude_lines(&excluded);
+        coverage.mark_partial_branches(impact, &branch_lines);
+    }
+
+    /// Whether a change to `path` can affect the selection: source files of
+    /// the engine's language the filter lets through and git does not
+    /// ignore, declared data dependencies and the extra watch globs. Paths
+    /// under another watched directory are taken relative to it, and git's
+    /// ignore rules for the checkout do not apply to them.
+    pub fn is_watched(&self, path: &Path) -> bool {
+        let root = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        let (relative, outside) = match path
+            .strip_prefix(&root)
+            .or_else(|_| path.strip_prefix(&self.root))
+        {
+            Ok(relative) => (relative, false),
+            Err(_) => (
+                self.watch_roots
+                    .iter()
+                    .find_map(|dir| path.strip_prefix(dir).ok())
+                    .unwrap_or(path),
+                true,
+            ),
+        };
+        relative.to_str().is_some_and(|relative| {
+            (self.language.matches_path(path)
+                && self.filter.matches(relative)
+                && (outside || !self.is_ignored(relative)))
+                || self
+                    .watch_patterns
+                    .iter()
+                    .any(|pattern| pattern.matches(relative))
+                || self.data.iter().any(|dep| dep.matches(relative))
+                || (self.triggers && triggers::is_trigger(relative))
+                || (self.reload_config && !outside && relative == CONFIG_FILE)
+        })
+    }
+
+    /// `paths` relative to the root, or as given when outside it.
+    pub fn relative_paths(&self, paths: &[PathBuf]) -> Vec<String> {
+        let root = self
+            .root
+            .canonicalize()
+            .unwrap_or_else(|_| self.root.clone());
+        paths
+            .iter()
+            .map(|path| {
+                let relative = path
+                    .strip_prefix(&root)
+                    .or_else(|_| path.strip_prefix(&self.root))
+                    .unwrap_or(path);
+                slash_path(&relative.to_string_lossy())
+            })
+            .collect()
+    }
+
+    /// Whether git ignores `relative`, or it lies in a submodule, whose
+    /// files are not analysed.
+    fn is_ignored(&self, relative: &str) -> bool {
+        let nested = Path::new(relative)
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .any(|dir| is_checkout(&self.root.join(dir)));
+        nested
+            || self
+                .vcs
+                .as_ref()
+                .is_some_and(|vcs| vcs.is_ignored(relative))
+    }
+
+    /// How long edits must settle before a cycle starts.
+    pub fn debounce(&self) -> Duration {
+        self.debounce
+    }
+
+    /// How often the tree is rescanned, when polling was asked for.
+    pub fn poll_interval(&self) -> Option<Duration> {
+        self.poll
+    }
+
+    /// What the watcher skips.
+    pub fn watch_ignore(&self) -> &IgnoreList {
+        &self.watch_ignore
+    }
+
+    /// Pauses and resumes watching, as during a rebase.
+    pub fn watch_control(&self) -> &WatchControl {
+        &self.watch_control
+    }
+
+    /// The directories watched for changes: the root, then any others.
+    pub fn watch_roots(&self) -> Vec<PathBuf> {
+        std::iter::once(self.root.clone())
+            .chain(self.watch_roots.iter().cloned())
+            .collect()
+    }
+
+    /// Runs a cycle whenever watched files change, until the watcher stops.
+    /// With `reload_config`, a change to `CONFIG_FILE` stops it instead. The
+    /// engine is shared with the watcher's tasks, which cycles run on.
+    pub fn watch(self: &Arc<Self>) -> Result<(), EngineError> {
+        let (watched, engine) = (self.clone(), self.clone());
+        watch::watch(
+            &self.watch_roots(),
+            self.debounce,
+            &self.watch_ignore,
+            self.poll,
+            &self.watch_control,
+            move |path| watched.is_watched(path),
+            move |changed| {
+                if engine.reload_config
+                    && engine
+                        .relative_paths(changed)
+                        .iter()
+                        .any(|path| path == CONFIG_FILE)
+                {
+                    tracing::info!("{} changed, reloading it", CONFIG_FILE);
+                    engine.watch_control.stop();
+                    return;
+                }
+                tracing::debug!(files = changed.len(), "starting a cycle");
+                // a rerun asked for by hand diffs everything again
+                let result = match changed.is_empty() {
+                    true => engine.run_once(),
+                    false => engine.run_changed(changed),
+                };
+                if let Err(e) = result {
+                    tracing::error!("{}", e);
+                }
+            },
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ci::{append_file, ensure_fetched, CiError, PrBase};
+use crate::coverage::{line_blocks, PatchCoverage};
+use crate::engine::{EngineBuilder, EngineError};
+use crate::runner::RunResult;
+use crate::selection::Selection;
+
+/// Reads the base from the event payload (`pull_request.base.sha`), falling
+/// back to `GITHUB_BASE_REF`. `var` looks up environment variables.
+pub fn pr_base<F: Fn(&str) -> Option<String>>(var: F) -> Result<PrBase, CiError> {
+    if let Some(path) = var("GITHUB_EVENT_PATH") {
+        let source = fs::read_to_string(&path).map_err(|source| CiError::Io {
+            path: PathBuf::from(&path),
+            source,
+        })?;
+        let event: Value = serde_json::from_str(&source)?;
+        if let Some(sha) = event["pull_request"]["base"]["sha"].as_str() {
+            return Ok(PrBase::commit(sha));
+        }
+    }
+    match var("GITHUB_BASE_REF").filter(|base| !base.is_empty()) {
+        Some(base) => Ok(PrBase::branch(&base)),
+        None => Err(CiError::NoBase(
+            "set GITHUB_EVENT_PATH or GITHUB_BASE_REF".to_string(),
+        )),
+    }
+}
+
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Workflow commands that annotate every uncovered block of changed lines.
+pub fn annotations(coverage: &PatchCoverage) -> Vec<String> {
+    let mut annotations = Vec::new();
+    for file in &coverage.files {
+        for (first, last) in line_blocks(&file.uncovered_lines()) {
+            annotations.push(format!(
+                "::warning file={},line={},endLine={},title={}::{}",
+                escape_property(&file.path),
+                first,
+                last,
+                escape_property("Uncovered change"),
+                escape_data("Changed lines are not covered by any test"),
+            ));
+        }
+    }
+    annotations
+}
+
+/// Markdown for `$GITHUB_STEP_SUMMARY`.
+pub fn step_summary(selection: &Selection, result: &RunResult, coverage: &PatchCoverage) -> String {
+    let mut summary = String::from("## Patch coverage\n\n");
+    match coverage.percent() {
+        Some(percent) => summary.push_str(&format!(
+            "**{:.1}%** of changed lines covered ({}/{})\n\n",
+            percent,
+            coverage.covered(),
+            coverage.changed()
+        )),
+        None => summary.push_str("No changed lines.\n\n"),
+    }
+    if !coverage.files.is_empty() {
+        summary.push_str("| File | Changed | Covered | Missing |\n|---|---|---|---|\n");
+        for file in &coverage.files {
+            let missing: Vec<String> = line_blocks(&file.uncovered_lines())
+                .into_iter()
+                .map(|(first, last)| match first == last {
+                    true => first.to_string(),
+                    false => format!("{}-{}", first, last),
+                })
+                .collect();
+            summary.push_str(&format!(
+                "| `{}` | {} | {} | {} |\n",
+                file.path,
+                file.changed_lines.len(),
+                file.covered_lines.len(),
+                missing.join(", ")
+            ));
+        }
+        summary.push('\n');
+    }
+    summary.push_str(&format!(
+        "## Selected tests ({})\n\n",
+        selection.tests.len()
+    ));
+    for test in &selection.tests {
+        summary.push_str(&format!("- `{}` ({})\n", test.id, test.reason));
+    }
+    let outcome = match (result.executed, result.success()) {
+        (false, _) => "not run".to_string(),
+        (true, true) => "passed".to_string(),
+        (true, false) => format!("failed (exit code {:?})", result.exit_code),
+    };
+    summary.push_str(&format!("\nTest run: {}\n", outcome));
+    summary
+}
+
+/// Hidden first line of the comment, used to find it again on later runs.
+pub const COMMENT_MARKER: &str = "<!-- instant-patch-coverage -->";
+
+/// Reads the pull request number from the event payload, falling back to a
+/// `refs/pull/<number>/merge` `GITHUB_REF`.
+pub fn pr_number<F: Fn(&str) -> Option<String>>(var: F) -> Option<u64> {
+    let from_event = var("GITHUB_EVENT_PATH")
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|source| serde_json::from_str::<Value>(&source).ok())
+        .and_then(|event| event["pull_request"]["number"].as_u64());
+    from_event.or_else(|| {
+        var("GITHUB_REF")?
+            .strip_prefix("refs/pull/")?
+            .split('/')
+            .next()?
+            .parse()
+            .ok()
+    })
+}
+
+/// Markdown for the pull request comment: the step summary followed by the
+/// source of every uncovered changed line, read from the checkout at `root`.
+pub fn comment_body(
+    root: &Path,
+    selection: &Selection,
+    result: &RunResult,
+    coverage: &PatchCoverage,
+) -> String {
+    let mut body = format!(
+        "{}\n{}",
+        COMMENT_MARKER,
+        step_summary(selection, result, coverage)
+    );
+    let uncovered: Vec<_> = coverage
+        .files
+        .iter()
+        .filter(|file| !file.uncovered_lines().is_empty())
+        .collect();
+    if uncovered.is_empty() {
+        return body;
+    }
+    body.push_str("\n## Uncovered lines\n");
+    for file in uncovered {
+        let source = fs::read_to_string(root.join(&file.path)).unwrap_or_default();
+        let lines: Vec<&str> = source.lines().collect();
+        body.push_str(&format!("\n`{}`\n```\n", file.path));
+        for line in file.uncovered_lines() {
+            let text = lines.get(line - 1).copied().unwrap_or_default();
+            body.push_str(&format!("{:>5} | {}\n", line, text.replace("```", "` ` `")));
+        }
+        body.push_str("```\n");
+    }
+    body
+}
+
+/// The id of the comment carrying `COMMENT_MARKER` in a page of the issue
+/// comments API.
+pub fn find_comment(comments: &Value) -> Option<u64> {
+    comments.as_array()?.iter().find_map(|comment| {
+        let body = comment["body"].as_str()?;
+        match body.starts_with(COMMENT_MARKER) {
+            true => comment["id"].as_u64(),
+            false => None,
+        }
+    })
+}
+
+/// Creates the coverage comment on pull request `number` of `repository`
+/// (`owner/name`), or edits it if an earlier run left one.
+#[cfg(feature = "github-comment")]
+pub fn post_comment(
+    api_url: &str,
+    repository: &str,
+    number: u64,
+    token: &str,
+    body: &str,
+) -> Result<(), CiError> {
+    let api_url = api_url.trim_end_matches('/');
+    let auth = format!("Bearer {}", token);
+    let request = |method: &str, url: &str| {
+        ureq::request(method, url)
+            .set("Authorization", &auth)
+            .set("Accept", "application/vnd.github+json")
+    };
+    let comment_error = |e: ureq::Error| CiError::Comment(e.to_string());
+
+    let mut existing = None;
+    for page in 1.. {
+        let url = format!(
+            "{}/repos/{}/issues/{}/comments?per_page=100&page={}",
+            api_url, repository, number, page
+        );
+        let comments: Value = request("GET", &url)
+            .call()
+            .map_err(comment_error)?
+            .into_json()
+            .map_err(|e| CiError::Comment(e.to_string()))?;
+        existing = find_comment(&comments);
+        if existing.is_some() || comments.as_array().map_or(0, Vec::len) < 100 {
+            break;
+        }
+    }
+    let payload = serde_json::json!({ "body": body });
+    match existing {
+        Some(id) => request(
+            "PATCH",
+            &format!("{}/repos/{}/issues/comments/{}", api_url, repository, id),
+        )
+        .send_json(payload),
+        None => request(
+            "POST",
+            &format!(
+                "{}/repos/{}/issues/{}/comments",
+                api_url, repository, number
+            ),
+        )
+        .send_json(payload),
+    }
+    .map_err(comment_error)?;
+    Ok(())
+}
+
+/// Posts the comment when `GITHUB_TOKEN` is set. A failure to comment is
+/// only a warning; the tests decide the job.
+#[cfg(feature = "github-comment")]
+fn comment_if_configured<F: Fn(&str) -> Option<String>>(var: F, body: &str) {
+    let (token, repository) = match (var("GITHUB_TOKEN"), var("GITHUB_REPOSITORY")) {
+        (Some(token), Some(repository)) if !token.is_empty() => (token, repository),
+        _ => return,
+    };
+    let number = match pr_number(&var) {
+        Some(number) => number,
+        None => {
+            tracing::warn!("no pull request number; not commenting");
+            return;
+        }
+    };
+    let api_url = var("GITHUB_API_URL").unwrap_or_else(|| "https://api.github.com".to_string());
+    if let Err(e) = post_comment(&api_url, &repository, number, &token, body) {
+        tracing::warn!("{}", e);
+    }
+}
+
+/// The turnkey pull request job: diff against the PR base, run the selected
+/// tests, annotate uncovered changes and write the step summary. With the
+/// `github-comment` feature and `GITHUB_TOKEN` set, the coverage is also
+/// posted as a pull request comment. Returns whether the tests passed.
+pub fn check_pull_request(root: &Path, builder: EngineBuilder) -> Result<bool, EngineError> {
+    let var = |name: &str| std::env::var(name).ok();
+    let base = pr_base(var)?;
+    ensure_fetched(root, &base)?;
+    let engine = builder.base(&base.rev).build()?;
+    let result = engine.run_once()?;
+    let selection = engine.state().snapshot().selection.unwrap_or_default();
+    let coverage = engine.patch_coverage()?;
+
+    for annotation in annotations(&coverage) {
+        println!("{}", annotation);
+    }
+    if let Some(path) = var("GITHUB_STEP_SUMMARY") {
+        append_file(
+            Path::new(&path),
+            &step_summary(&selection, &result, &coverage),
+        )?;
+    }
+    #[cfg(feature = "github-comment")]
+    comment_if_configured(var, &comment_body(root, &selection, &result, &coverage));
+    #[cfg(feature = "slack")]
+    crate::ci::notify_slack(root, &selection, &result, &coverage);
+    Ok(result.success())
+}
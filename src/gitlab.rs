@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::Path;
+
+use crate::ci::{ensure_fetched, CiError, PrBase};
+use crate::coverage::PatchCoverage;
+use crate::engine::{EngineBuilder, EngineError};
+
+/// Where `check_merge_request` writes the Cobertura report, relative to the
+/// repository root. Point `artifacts:reports:coverage_report:path` here.
+pub const REPORT_PATH: &str = "patch-coverage.xml";
+
+/// Reads the merge request base from `CI_MERGE_REQUEST_DIFF_BASE_SHA`, the
+/// commit GitLab itself diffs against, falling back to the target branch.
+pub fn mr_base<F: Fn(&str) -> Option<String>>(var: F) -> Result<PrBase, CiError> {
+    let set = |name| var(name).filter(|value: &String| !value.is_empty());
+    if let Some(sha) = set("CI_MERGE_REQUEST_DIFF_BASE_SHA") {
+        return Ok(PrBase::commit(&sha));
+    }
+    match set("CI_MERGE_REQUEST_TARGET_BRANCH_NAME") {
+        Some(branch) => Ok(PrBase::branch(&branch)),
+        None => Err(CiError::NoBase(
+            "not a merge request pipeline: CI_MERGE_REQUEST_DIFF_BASE_SHA is not set".to_string(),
+        )),
+    }
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn rate(covered: usize, valid: usize) -> String {
+    match valid {
+        0 => "1".to_string(),
+        _ => format!("{:.4}", covered as f64 / valid as f64),
+    }
+}
+
+/// Renders the changed lines as a Cobertura report, the format GitLab's
+/// `coverage_report` artifact reads to mark lines in the merge request diff.
+pub fn cobertura(coverage: &PatchCoverage) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" ?>\n");
+    xml.push_str(&format!(
+        "<coverage version=\"{}\" line-rate=\"{}\" branch-rate=\"0\" lines-covered=\"{}\" \
+         lines-valid=\"{}\" branches-covered=\"0\" branches-valid=\"0\" complexity=\"0\" \
+         timestamp=\"0\">\n",
+        env!("CARGO_PKG_VERSION"),
+        rate(coverage.covered(), coverage.changed()),
+        coverage.covered(),
+        coverage.changed(),
+    ));
+    xml.push_str("  <sources>\n    <source>.</source>\n  </sources>\n  <packages>\n");
+    xml.push_str(&format!(
+        "    <package name=\".\" line-rate=\"{}\" branch-rate=\"0\" complexity=\"0\">\n      <classes>\n",
+        rate(coverage.covered(), coverage.changed())
+    ));
+    for file in &coverage.files {
+        let path = escape_xml(&file.path);
+        xml.push_str(&format!(
+            "        <class name=\"{0}\" filename=\"{0}\" line-rate=\"{1}\" branch-rate=\"0\" complexity=\"0\">\n          <methods/>\n          <lines>\n",
+            path,
+            rate(file.covered_lines.len(), file.changed_lines.len())
+        ));
+        for line in &file.changed_lines {
+            let hits = usize::from(file.covered_lines.contains(line));
+            xml.push_str(&format!(
+                "            <line number=\"{}\" hits=\"{}\"/>\n",
+                line, hits
+            ));
+        }
+        xml.push_str("          </lines>\n        </class>\n");
+    }
+    xml.push_str("      </classes>\n    </package>\n  </packages>\n</coverage>\n");
+    xml
+}
+
+/// Line for the job log that a `coverage:` regex such as
+/// `/Patch coverage: \d+\.\d+%/` picks up for the merge request widget.
+pub fn coverage_line(coverage: &PatchCoverage) -> String {
+    format!(
+        "Patch coverage: {:.2}%",
+        coverage.percent().unwrap_or(100.0)
+    )
+}
+
+/// Diffs against the merge request base, runs the selected tests, and writes
+/// the Cobertura report to `REPORT_PATH`. Returns whether the tests passed.
+pub fn check_merge_request(root: &Path, builder: EngineBuilder) -> Result<bool, EngineError> {
+    let base = mr_base(|name| std::env::var(name).ok())?;
+    ensure_fetched(root, &base)?;
+    let engine = builder.base(&base.rev).build()?;
+    let result = engine.run_once()?;
+    let coverage = engine.patch_coverage()?;
+
+    let path = root.join(REPORT_PATH);
+    fs::write(&path, cobertura(&coverage)).map_err(|source| CiError::Io { path, source })?;
+    println!("{}", coverage_line(&coverage));
+    #[cfg(feature = "slack")]
+    crate::ci::notify_slack(
+        root,
+        &engine.state().snapshot().selection.unwrap_or_default(),
+        &result,
+        &coverage,
+    );
+    Ok(result.success())
+}
@@ -0,0 +1,96 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::selection::ImpactData;
+
+/// Where the Go runner has `go test -coverprofile` write its profile.
+pub const DATA_FILE: &str = "coverage.out";
+
+#[derive(Debug, Error)]
+pub enum GoCoverError {
+    #[error("failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{0} has no `module` line")]
+    NoModule(PathBuf),
+    #[error("{path}:{line}: not a cover profile block")]
+    Malformed { path: PathBuf, line: usize },
+}
+
+/// One block of a profile: `file:startLine.startCol,endLine.endCol
+/// statements count`.
+struct Block<'a> {
+    file: &'a str,
+    start: usize,
+    end: usize,
+    count: u64,
+}
+
+impl Block<'_> {
+    fn parse(line: &str) -> Option<Block<'_>> {
+        let (file, rest) = line.rsplit_once(':')?;
+        let mut fields = rest.split(' ');
+        let (start, end) = fields.next()?.split_once(',')?;
+        let line_of = |position: &str| position.split_once('.')?.0.parse().ok();
+        let _statements = fields.next()?;
+        Some(Block {
+            file,
+            start: line_of(start)?,
+            end: line_of(end)?,
+            count: fields.next()?.parse().ok()?,
+        })
+    }
+}
+
+/// The module path `go.mod` at `root` declares, which prefixes the import
+/// paths a profile names files by.
+pub fn module_path(root: &Path) -> Result<String, GoCoverError> {
+    let path = root.join("go.mod");
+    let content = fs::read_to_string(&path).map_err(|source| GoCoverError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    content
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("module "))
+        .map(|module| module.trim().trim_matches('"').to_string())
+        .ok_or(GoCoverError::NoModule(path))
+}
+
+/// Reads a cover profile written by `go test -coverprofile`. Every line of
+/// a block that ran counts as executed. Go does not attribute blocks to
+/// tests, so everything run is recorded outside any test, which counts for
+/// patch coverage but selects nothing. Files outside the module at `root`
+/// are left out.
+pub fn read(path: &Path, root: &Path) -> Result<ImpactData, GoCoverError> {
+    let content = fs::read_to_string(path).map_err(|source| GoCoverError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let module = format!("{}/", module_path(root)?);
+    let mut files: HashMap<String, HashSet<usize>> = HashMap::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.starts_with("mode:") || line.trim().is_empty() {
+            continue;
+        }
+        let block = Block::parse(line).ok_or_else(|| GoCoverError::Malformed {
+            path: path.to_path_buf(),
+            line: number + 1,
+        })?;
+        let Some(file) = block.file.strip_prefix(&module) else {
+            continue;
+        };
+        let lines = files.entry(file.to_string()).or_default();
+        if block.count > 0 {
+            lines.extend(block.start..=block.end);
+        }
+    }
+    Ok(ImpactData {
+        lines: HashMap::from([(String::new(), files)]),
+        ..ImpactData::default()
+    })
+}
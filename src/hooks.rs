@@ -0,0 +1,163 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::coverage::PatchCoverage;
+use crate::engine::{Engine, EngineError};
+use crate::runner::RunResult;
+use crate::selection::Selection;
+use crate::vcs;
+
+/// Marks hook scripts written by `install`, so reinstalling can replace them
+/// without clobbering hooks someone else wrote.
+const MARKER: &str = "# installed by hackweek-instant-codecoverage";
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("failed to write {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{0} already exists and was not installed by this tool; pass --force to replace it")]
+    Exists(PathBuf),
+}
+
+/// Hooks written by `install`. Each runs the `hook` subcommand of the same
+/// name.
+pub const HOOKS: [&str; 3] = ["pre-commit", "post-commit", "post-merge"];
+
+/// Written to the git directory by the post-commit and post-merge hooks.
+/// Moving `HEAD` changes the baseline without touching any source file, so
+/// watchers treat a change to this file as a reason to run again.
+pub const REBASELINE_MARKER: &str = "instant-patch-rebaseline";
+
+/// Writes the hooks in `HOOKS` into the repository at `root`. Returns their
+/// paths. Nothing is written if any of them exists and was not installed by
+/// this tool, unless `force` is set.
+pub fn install(root: &Path, force: bool) -> Result<Vec<PathBuf>, HookError> {
+    let repo = vcs::open_git(root)?;
+    let hooks_dir = match repo.config()?.get_path("core.hooksPath") {
+        Ok(path) if path.is_absolute() => path,
+        Ok(path) => repo.workdir().unwrap_or(root).join(path),
+        Err(_) => vcs::common_dir(&repo).join("hooks"),
+    };
+    let hooks: Vec<(&str, PathBuf)> = HOOKS
+        .iter()
+        .map(|name| (*name, hooks_dir.join(name)))
+        .collect();
+    for (_, hook) in &hooks {
+        if let Ok(existing) = fs::read_to_string(hook) {
+            if !force && !existing.contains(MARKER) {
+                return Err(HookError::Exists(hook.clone()));
+            }
+        }
+    }
+    fs::create_dir_all(&hooks_dir).map_err(|source| HookError::Io {
+        path: hooks_dir.clone(),
+        source,
+    })?;
+    for (name, hook) in &hooks {
+        let io = |source| HookError::Io {
+            path: hook.clone(),
+            source,
+        };
+        let script = format!(
+            "#!/bin/sh\n{}\nexec {} hook {}\n",
+            MARKER,
+            env!("CARGO_PKG_NAME"),
+            name
+        );
+        fs::write(hook, script).map_err(io)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(hook, fs::Permissions::from_mode(0o755)).map_err(io)?;
+        }
+    }
+    Ok(hooks.into_iter().map(|(_, hook)| hook).collect())
+}
+
+/// Records the new `HEAD` in `REBASELINE_MARKER` after a commit or merge, so
+/// running watchers rebuild their baseline and selection straight away
+/// instead of on the next edit. Returns the marker's path.
+pub fn rebaseline(root: &Path) -> Result<PathBuf, HookError> {
+    let repo = vcs::open_git(root)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    let marker = repo.path().join(REBASELINE_MARKER);
+    fs::write(&marker, format!("{}\n", head)).map_err(|source| HookError::Io {
+        path: marker.clone(),
+        source,
+    })?;
+    Ok(marker)
+}
+
+/// Outcome of the pre-commit check.
+#[derive(Debug, Clone)]
+pub struct PreCommitReport {
+    pub selection: Selection,
+    pub result: RunResult,
+    pub coverage: PatchCoverage,
+    pub fail_under: Option<f64>,
+}
+
+impl PreCommitReport {
+    pub fn coverage_ok(&self) -> bool {
+        self.fail_under
+            .is_none_or(|threshold| self.coverage.meets(threshold))
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.success() && self.coverage_ok()
+    }
+
+    /// A short explanation suitable for printing when the commit is blocked.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "{} test(s) selected from staged changes",
+            self.selection.tests.len()
+        )];
+        if !self.result.success() {
+            lines.push(format!(
+                "tests failed (exit code {})",
+                self.result
+                    .exit_code
+                    .map_or("unknown".to_string(), |code| code.to_string())
+            ));
+        }
+        if let Some(percent) = self.coverage.percent() {
+            lines.push(format!(
+                "patch coverage {:.1}% ({} of {} changed lines)",
+                percent,
+                self.coverage.covered(),
+                self.coverage.changed()
+            ));
+        }
+        if let (false, Some(threshold)) = (self.coverage_ok(), self.fail_under) {
+            lines.push(format!(
+                "patch coverage is below the required {:.1}%",
+                threshold
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Selects tests from the staged changes, runs them and checks patch
+/// coverage against `fail_under`. `engine` should be built with
+/// `EngineBuilder::staged(true)`.
+pub fn pre_commit(
+    engine: &Engine,
+    fail_under: Option<f64>,
+) -> Result<PreCommitReport, EngineError> {
+    let result = engine.run_once()?;
+    let selection = engine.state().snapshot().selection.unwrap_or_default();
+    Ok(PreCommitReport {
+        selection,
+        result,
+        coverage: engine.patch_coverage()?,
+        fail_under,
+    })
+}
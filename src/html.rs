@@ -0,0 +1,134 @@
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::coverage::{FilePatchCoverage, PatchCoverage};
+
+/// The page written into the report directory.
+pub const INDEX_FILE: &str = "index.html";
+
+/// Lines of unchanged code shown around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;width:100%;font:13px monospace;margin-bottom:2em}\
+td{padding:0 .5em;white-space:pre}\
+td.n{color:#888;text-align:right;user-select:none;width:1%}\
+tr.gap td{background:#f0f0f0;color:#888}\
+tr.covered td{background:#dcfce7}\
+tr.uncovered td{background:#fee2e2}\
+tr.partial td{background:#fef9c3}\
+tr.removed td{background:#f5f5f5;color:#999;text-decoration:line-through}";
+
+/// A standalone page showing the diff of every file in `coverage`, from its
+/// `old` to its `new` content, with added lines green when covered, yellow
+/// when only some of their branches ran and red when they did not run.
+pub fn render(
+    coverage: &PatchCoverage,
+    old: &HashMap<String, String>,
+    new: &HashMap<String, String>,
+) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Patch coverage</title>\n<style>{}</style>\n</head>\n<body>\n",
+        STYLE
+    );
+    html.push_str(&match coverage.percent() {
+        Some(percent) => format!(
+            "<h1>Patch coverage {:.1}%</h1>\n<p>{} of {} changed lines covered</p>\n",
+            percent,
+            coverage.covered(),
+            coverage.changed()
+        ),
+        None => "<h1>No changed lines</h1>\n".to_string(),
+    });
+    for file in &coverage.files {
+        let empty = String::new();
+        let old = old.get(&file.path).unwrap_or(&empty);
+        let new = new.get(&file.path).unwrap_or(&empty);
+        html.push_str(&render_file(file, old, new));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_file(file: &FilePatchCoverage, old: &str, new: &str) -> String {
+    let mut html = format!(
+        "<h2>{} &mdash; {}/{}</h2>\n<table>\n",
+        escape(&file.path),
+        file.covered_lines.len(),
+        file.changed_lines.len()
+    );
+    let diff = TextDiff::from_lines(old, new);
+    for group in diff.grouped_ops(CONTEXT_LINES) {
+        let (first_old, first_new) = match group.first() {
+            Some(op) => (op.old_range().start + 1, op.new_range().start + 1),
+            None => continue,
+        };
+        html.push_str(&format!(
+            "<tr class=\"gap\"><td class=\"n\"></td><td class=\"n\"></td>\
+             <td>@@ -{} +{} @@</td></tr>\n",
+            first_old, first_new
+        ));
+        for change in group.iter().flat_map(|op| diff.iter_changes(op)) {
+            let (class, old_line, new_line) = match change.tag() {
+                ChangeTag::Equal => ("", change.old_index(), change.new_index()),
+                ChangeTag::Delete => ("removed", change.old_index(), None),
+                ChangeTag::Insert => {
+                    let line = change.new_index().map_or(0, |index| index + 1);
+                    let class = if file.covered_lines.contains(&line) {
+                        "covered"
+                    } else if file.partial_lines.contains(&line) {
+                        "partial"
+                    } else if file.changed_lines.contains(&line) {
+                        "uncovered"
+                    } else {
+                        // a line this diff sees as added but the patch does not
+                        ""
+                    };
+                    (class, None, change.new_index())
+                }
+            };
+            let number =
+                |index: Option<usize>| index.map_or(String::new(), |i| (i + 1).to_string());
+            html.push_str(&format!(
+                "<tr class=\"{}\"><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td></tr>\n",
+                class,
+                number(old_line),
+                number(new_line),
+                escape(change.value().trim_end_matches(['\n', '\r']))
+            ));
+        }
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Opens `path` in the desktop's default browser without waiting for it.
+pub fn open(path: &Path) -> io::Result<()> {
+    let mut command = match std::env::consts::OS {
+        "macos" => Command::new("open"),
+        "windows" => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        _ => Command::new("xdg-open"),
+    };
+    command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use tree_sitter::{Node, Tree};
+
+use crate::language::Language;
+use crate::selection::{SelectedTest, SelectionContext, TestSelector};
+
+/// Which files import which, as far as `import` and `from ... import`
+/// statements show, resolved to paths relative to the root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportGraph {
+    /// Path -> paths it imports.
+    pub imports: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl ImportGraph {
+    /// Reads the imports of every parsed file. Only Python imports are
+    /// understood; other languages give an empty graph. `known` lists paths
+    /// imports may resolve to beyond those parsed, such as deleted modules.
+    pub fn build(
+        language: Language,
+        content: &HashMap<String, String>,
+        trees: &HashMap<String, Tree>,
+        known: &HashMap<String, String>,
+    ) -> ImportGraph {
+        if !language.is_python() {
+            return ImportGraph::default();
+        }
+        let modules = ModuleIndex::new(content.keys().chain(known.keys()));
+        let mut imports = BTreeMap::new();
+        for (path, tree) in trees {
+            let Some(source) = content.get(path) else {
+                continue;
+            };
+            let resolved: BTreeSet<String> = imported_modules(path, tree, source.as_bytes())
+                .iter()
+                .flat_map(|module| modules.resolve(module))
+                .filter(|target| target != path)
+                .map(str::to_string)
+                .collect();
+            imports.insert(path.clone(), resolved);
+        }
+        ImportGraph { imports }
+    }
+
+    /// Every file importing one of `changed`, directly or through other
+    /// files, with the changed path it was reached from. A changed file is
+    /// only included when it imports another.
+    pub fn dependents<'a, I: IntoIterator<Item = &'a str>>(
+        &self,
+        changed: I,
+    ) -> BTreeMap<String, String> {
+        let mut importers: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (path, targets) in &self.imports {
+            for target in targets {
+                importers.entry(target).or_default().push(path);
+            }
+        }
+        let mut reached: BTreeMap<String, String> = BTreeMap::new();
+        let mut seen: HashSet<&str> = HashSet::new();
+        let mut queue: VecDeque<(&str, &str)> =
+            changed.into_iter().map(|path| (path, path)).collect();
+        while let Some((path, origin)) = queue.pop_front() {
+            for importer in importers.get(path).into_iter().flatten() {
+                if seen.insert(importer) {
+                    reached.insert(importer.to_string(), origin.to_string());
+                    queue.push_back((importer, origin));
+                }
+            }
+        }
+        reached
+    }
+}
+
+/// Dotted module names mapped to the files defining them. A directory
+/// without an `__init__.py` is not a package, so it may be a `sys.path`
+/// entry such as `src/` or `tests/`, and files below it are also known by
+/// the name relative to it.
+struct ModuleIndex<'a> {
+    modules: HashMap<String, Vec<&'a str>>,
+}
+
+impl<'a> ModuleIndex<'a> {
+    fn new<I: Iterator<Item = &'a String>>(paths: I) -> ModuleIndex<'a> {
+        let paths: BTreeSet<&'a str> = paths
+            .map(String::as_str)
+            .filter(|path| path.ends_with(".py"))
+            .collect();
+        let packages: HashSet<&str> = paths
+            .iter()
+            .filter_map(|path| path.strip_suffix("/__init__.py"))
+            .collect();
+        let mut modules: HashMap<String, Vec<&'a str>> = HashMap::new();
+        for path in &paths {
+            let mut parts: Vec<&str> = path.trim_end_matches(".py").split('/').collect();
+            if parts.last() == Some(&"__init__") {
+                parts.pop();
+            }
+            for start in 0..parts.len() {
+                let parent = parts[..start].join("/");
+                if start == 0 || !packages.contains(parent.as_str()) {
+                    modules
+                        .entry(parts[start..].join("."))
+                        .or_default()
+                        .push(path);
+                }
+            }
+        }
+        ModuleIndex { modules }
+    }
+
+    fn resolve(&self, module: &str) -> Vec<&'a str> {
+        self.modules.get(module).cloned().unwrap_or_default()
+    }
+}
+
+/// The modules a file depends on: each imported module, the packages
+/// containing it, whose `__init__.py` runs first, and for
+/// `from module import name` the submodule `module.name` in case `name`
+/// is one.
+fn imported_modules(path: &str, tree: &Tree, source: &[u8]) -> BTreeSet<String> {
+    let mut modules = BTreeSet::new();
+    let mut add = |module: &str| {
+        let mut prefix = String::new();
+        for part in module.split('.').filter(|part| !part.is_empty()) {
+            if !prefix.is_empty() {
+                prefix.push('.');
+            }
+            prefix.push_str(part);
+            modules.insert(prefix.clone());
+        }
+    };
+    let text = |node: Node| node.utf8_text(source).unwrap_or_default().to_string();
+    // aliased imports name the module in their own `name` field
+    let imported = |node: Node| match node.kind() {
+        "aliased_import" => node.child_by_field_name("name").map(text),
+        "dotted_name" => Some(text(node)),
+        _ => None,
+    };
+
+    let mut cursor = tree.walk();
+    'outer: loop {
+        let node = cursor.node();
+        match node.kind() {
+            "import_statement" => {
+                for name in node.children_by_field_name("name", &mut node.walk()) {
+                    if let Some(module) = imported(name) {
+                        add(&module);
+                    }
+                }
+            }
+            "import_from_statement" => {
+                let module =
+                    node.child_by_field_name("module_name").and_then(|module| {
+                        match module.kind() {
+                            "relative_import" => relative_module(path, module, source),
+                            _ => Some(text(module)),
+                        }
+                    });
+                if let Some(module) = module {
+                    add(&module);
+                    for name in node.children_by_field_name("name", &mut node.walk()) {
+                        if let Some(name) = imported(name) {
+                            add(&format!("{}.{}", module, name));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        loop {
+            if !cursor.goto_parent() {
+                break 'outer;
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+    modules
+}
+
+/// Resolves `from ..pkg import x` against the package `path` is in. Too
+/// many dots, climbing out of the repository, resolve to nothing.
+fn relative_module(path: &str, node: Node, source: &[u8]) -> Option<String> {
+    let mut dots = 0;
+    let mut name = None;
+    for child in node.named_children(&mut node.walk()) {
+        match child.kind() {
+            "import_prefix" => dots = child.utf8_text(source).ok()?.len(),
+            "dotted_name" => name = Some(child.utf8_text(source).ok()?),
+            _ => {}
+        }
+    }
+    let mut package: Vec<&str> = path.split('/').collect();
+    package.pop();
+    for _ in 1..dots {
+        package.pop()?;
+    }
+    package.extend(name);
+    Some(package.join("."))
+}
+
+/// Selects every test in a file that imports a changed file, directly or
+/// through other modules, so a change is tested even before any coverage
+/// has been recorded.
+pub struct ImportSelector {
+    language: Language,
+}
+
+impl ImportSelector {
+    pub fn new(language: Language) -> ImportSelector {
+        ImportSelector { language }
+    }
+}
+
+impl TestSelector for ImportSelector {
+    fn name(&self) -> &str {
+        "imports"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let graph = ImportGraph::build(
+            self.language,
+            ctx.new_content,
+            ctx.new_trees,
+            ctx.old_content,
+        );
+        let changed: BTreeSet<&str> = ctx.hunks.iter().map(|hunk| hunk.path.as_str()).collect();
+        let dependents = graph.dependents(changed);
+        ctx.new_tests
+            .iter()
+            .filter_map(|id| {
+                let (file, _) = id.split_once("::")?;
+                let origin = dependents.get(file)?;
+                Some(SelectedTest::new(id.clone(), format!("imports {}", origin)))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,125 @@
+use glob::glob;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::discovery::slash_path;
+use crate::language::uses_gradle;
+use crate::selection::ImpactData;
+
+/// Where `jacoco:report` writes its XML report in a Maven project.
+pub const MAVEN_DATA_FILE: &str = "target/site/jacoco/jacoco.xml";
+
+/// Where `jacocoTestReport` writes its XML report in a Gradle project, once
+/// `reports.xml.required` is set.
+pub const GRADLE_DATA_FILE: &str = "build/reports/jacoco/test/jacocoTestReport.xml";
+
+#[derive(Debug, Error)]
+pub enum JacocoError {
+    #[error("failed to read coverage data {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path} is not valid XML: {source}")]
+    Xml {
+        path: PathBuf,
+        source: roxmltree::Error,
+    },
+    #[error("{0} is not a JaCoCo report")]
+    NotAReport(PathBuf),
+}
+
+/// The report the build tool of the project at `root` writes, relative to
+/// the root.
+pub fn data_file(root: &Path) -> &'static str {
+    match uses_gradle(root) {
+        true => GRADLE_DATA_FILE,
+        false => MAVEN_DATA_FILE,
+    }
+}
+
+/// Reads a JaCoCo XML report. A line counts as executed when any of its
+/// instructions ran. JaCoCo does not attribute lines to tests, so
+/// everything run is recorded outside any test, which counts for patch
+/// coverage but selects nothing.
+///
+/// The report names files by package and file name only, so each is
+/// matched to the files under `root` at that package path, such as
+/// `src/main/java/com/example/Calc.java` for `com/example` and
+/// `Calc.java`; files with no match are left out.
+pub fn read(path: &Path, root: &Path) -> Result<ImpactData, JacocoError> {
+    let content = fs::read_to_string(path).map_err(|source| JacocoError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let document =
+        roxmltree::Document::parse_with_options(&content, options).map_err(|source| {
+            JacocoError::Xml {
+                path: path.to_path_buf(),
+                source,
+            }
+        })?;
+    if !document.root_element().has_tag_name("report") {
+        return Err(JacocoError::NotAReport(path.to_path_buf()));
+    }
+
+    let mut files: HashMap<String, HashSet<usize>> = HashMap::new();
+    let packages = document
+        .descendants()
+        .filter(|node| node.has_tag_name("package"));
+    for package in packages {
+        let name = package.attribute("name").unwrap_or_default();
+        let sourcefiles = package
+            .children()
+            .filter(|node| node.has_tag_name("sourcefile"));
+        for sourcefile in sourcefiles {
+            let Some(file) = sourcefile.attribute("name") else {
+                continue;
+            };
+            let executed: HashSet<usize> = sourcefile
+                .children()
+                .filter(|node| node.has_tag_name("line"))
+                .filter(|line| count(line.attribute("ci")) > 0)
+                .filter_map(|line| line.attribute("nr")?.parse().ok())
+                .collect();
+            let relative = match name.is_empty() {
+                true => file.to_string(),
+                false => format!("{}/{}", name, file),
+            };
+            for path in source_paths(root, &relative) {
+                files.entry(path).or_default().extend(&executed);
+            }
+        }
+    }
+    Ok(ImpactData {
+        lines: HashMap::from([(String::new(), files)]),
+        ..ImpactData::default()
+    })
+}
+
+fn count(value: Option<&str>) -> u64 {
+    value.and_then(|value| value.parse().ok()).unwrap_or(0)
+}
+
+/// The files under `root` ending in `relative`, relative to the root,
+/// leaving out build output.
+fn source_paths(root: &Path, relative: &str) -> Vec<String> {
+    let pattern = root.join("**").join(relative);
+    let Some(pattern) = pattern.to_str() else {
+        return Vec::new();
+    };
+    let Ok(paths) = glob(pattern) else {
+        return Vec::new();
+    };
+    paths
+        .flatten()
+        .filter_map(|path| Some(slash_path(path.strip_prefix(root).ok()?.to_str()?)))
+        .filter(|path| !path.starts_with("target/") && !path.starts_with("build/"))
+        .collect()
+}
@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::engine::Engine;
+use crate::rpc::{
+    error_response, notification, read_message, response, write_message, RpcError, INTERNAL_ERROR,
+    INVALID_PARAMS, METHOD_NOT_FOUND,
+};
+use crate::selection::{SelectedTest, Selection};
+use crate::watch;
+
+/// Serves the engine to editor extensions over `Content-Length` framed
+/// JSON-RPC, the transport vscode-jsonrpc speaks.
+///
+/// Requests: `getSelection`, `runSelection` (optionally `{"tests": [...]}`),
+/// `getPatchCoverage` and `shutdown`. Notifications sent to the client:
+/// `selectionChanged` after every file change, with the changed files as
+/// `changed`, and `runFinished` after every run.
+pub struct RpcServer<W> {
+    engine: Arc<Engine>,
+    output: Arc<Mutex<W>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RunParams {
+    tests: Option<Vec<String>>,
+}
+
+impl<W: Write + Send + 'static> RpcServer<W> {
+    pub fn new(engine: Arc<Engine>, output: W) -> RpcServer<W> {
+        RpcServer {
+            engine,
+            output: Arc::new(Mutex::new(output)),
+        }
+    }
+
+    /// Watches the working tree on a background thread, pushing a
+    /// `selectionChanged` notification whenever it changes.
+    pub fn spawn_watcher(&self) -> JoinHandle<()> {
+        let engine = self.engine.clone();
+        let output = self.output.clone();
+        thread::spawn(move || {
+            let (watched, selecting) = (engine.clone(), engine.clone());
+            let result = watch::watch(
+                &engine.watch_roots(),
+                engine.debounce(),
+                engine.watch_ignore(),
+                engine.poll_interval(),
+                engine.watch_control(),
+                move |path| watched.is_watched(path),
+                move |changed| {
+                    let message = match selecting.select() {
+                        Ok(selection) => {
+                            let mut params = json!(selection);
+                            params["changed"] = json!(selecting.relative_paths(changed));
+                            notification("selectionChanged", params)
+                        }
+                        Err(e) => {
+                            notification("selectionFailed", json!({ "message": e.to_string() }))
+                        }
+                    };
+                    if let Err(e) = send(&output, &message) {
+                        tracing::error!("{}", e);
+                    }
+                },
+            );
+            if let Err(e) = result {
+                tracing::error!("{}", e);
+            }
+        })
+    }
+
+    /// Handles requests from `input` until `exit` or end of input.
+    pub fn serve<R: BufRead>(&self, mut input: R) -> Result<(), RpcError> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message.method.as_deref().unwrap_or_default();
+            let result = match method {
+                "getSelection" => self
+                    .engine
+                    .select()
+                    .map(|selection| json!(selection))
+                    .map_err(|e| (INTERNAL_ERROR, e.to_string())),
+                "runSelection" => self.run_selection(message.params),
+                "getPatchCoverage" => self
+                    .engine
+                    .patch_coverage()
+                    .map(|coverage| {
+                        json!({
+                            "files": coverage.files,
+                            "changed": coverage.changed(),
+                            "covered": coverage.covered(),
+                            "percent": coverage.percent(),
+                        })
+                    })
+                    .map_err(|e| (INTERNAL_ERROR, e.to_string())),
+                "shutdown" => Ok(Value::Null),
+                "exit" => return Ok(()),
+                _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+            };
+            if let Some(id) = &message.id {
+                let reply = match result {
+                    Ok(result) => response(id, result),
+                    Err((code, error)) => error_response(id, code, &error),
+                };
+                send(&self.output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_selection(&self, params: Value) -> Result<Value, (i64, String)> {
+        let params: RunParams = match params {
+            Value::Null => RunParams::default(),
+            params => {
+                serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))?
+            }
+        };
+        let selection = match params.tests {
+            Some(ids) => Selection::new(
+                ids.iter()
+                    .map(|id| SelectedTest::new(id.as_str(), "requested"))
+                    .collect(),
+            ),
+            None => self
+                .engine
+                .select()
+                .map_err(|e| (INTERNAL_ERROR, e.to_string()))?,
+        };
+        let result = self
+            .engine
+            .run(&selection)
+            .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+        send(&self.output, &notification("runFinished", json!(result)))
+            .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+        Ok(json!(result))
+    }
+}
+
+fn send<W: Write>(output: &Mutex<W>, message: &Value) -> Result<(), RpcError> {
+    let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+    write_message(&mut *output, message)
+}
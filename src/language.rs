@@ -0,0 +1,829 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "java")]
+use std::collections::BTreeMap;
+#[cfg(any(feature = "go", feature = "java"))]
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::OnceLock;
+use tree_sitter::{Node, Query};
+
+#[cfg(not(any(
+    feature = "python",
+    feature = "javascript",
+    feature = "go",
+    feature = "rust",
+    feature = "ruby",
+    feature = "java"
+)))]
+compile_error!("enable at least one language feature: python, javascript, go, rust, ruby or java");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[cfg(feature = "python")]
+    Python,
+    #[cfg(feature = "javascript")]
+    JavaScript,
+    #[cfg(feature = "go")]
+    Go,
+    #[cfg(feature = "rust")]
+    Rust,
+    #[cfg(feature = "ruby")]
+    Ruby,
+    #[cfg(feature = "java")]
+    Java,
+}
+
+#[cfg(any(
+    feature = "python",
+    feature = "javascript",
+    feature = "go",
+    feature = "rust",
+    feature = "ruby",
+    feature = "java"
+))]
+impl Default for Language {
+    #[allow(unreachable_code)]
+    fn default() -> Language {
+        #[cfg(feature = "python")]
+        return Language::Python;
+        #[cfg(feature = "javascript")]
+        return Language::JavaScript;
+        #[cfg(feature = "go")]
+        return Language::Go;
+        #[cfg(feature = "rust")]
+        return Language::Rust;
+        #[cfg(feature = "ruby")]
+        return Language::Ruby;
+        #[cfg(feature = "java")]
+        return Language::Java;
+    }
+}
+
+impl Language {
+    /// Every language this build supports, in the order `detect` tries them.
+    /// Rails apps keep a `package.json` too, so Ruby goes before JavaScript.
+    pub const ALL: &'static [Language] = &[
+        #[cfg(feature = "python")]
+        Language::Python,
+        #[cfg(feature = "ruby")]
+        Language::Ruby,
+        #[cfg(feature = "javascript")]
+        Language::JavaScript,
+        #[cfg(feature = "go")]
+        Language::Go,
+        #[cfg(feature = "rust")]
+        Language::Rust,
+        #[cfg(feature = "java")]
+        Language::Java,
+    ];
+
+    /// The implementation of everything the engine needs to know about the
+    /// language.
+    pub fn support(&self) -> &'static dyn LanguageSupport {
+        match *self {
+            #[cfg(feature = "python")]
+            Language::Python => &Python,
+            #[cfg(feature = "javascript")]
+            Language::JavaScript => &JavaScript,
+            #[cfg(feature = "go")]
+            Language::Go => &Go,
+            #[cfg(feature = "rust")]
+            Language::Rust => &Rust,
+            #[cfg(feature = "ruby")]
+            Language::Ruby => &Ruby,
+            #[cfg(feature = "java")]
+            Language::Java => &Java,
+        }
+    }
+
+    /// The first language whose project files are at `root`, if any.
+    pub fn detect(root: &Path) -> Option<Language> {
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.support().detect(root))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.support().extension()
+    }
+
+    /// Whether this is Python, whose imports, calls and pytest settings
+    /// the engine understands beyond discovering tests.
+    #[allow(unreachable_patterns)]
+    pub fn is_python(&self) -> bool {
+        match self {
+            #[cfg(feature = "python")]
+            Language::Python => true,
+            _ => false,
+        }
+    }
+
+    pub fn matches_path(&self, path: &Path) -> bool {
+        self.support().matches_path(path)
+    }
+
+    pub fn grammar(&self) -> tree_sitter::Language {
+        self.support().grammar()
+    }
+
+    /// The kind of node enclosing a test's `@name` capture that spans the
+    /// whole test.
+    pub fn test_definition_kind(&self) -> &'static str {
+        self.support().test_definition_kind()
+    }
+
+    /// Query locating test definitions; every `@name` capture is a test name.
+    pub fn test_query(&self) -> &'static str {
+        self.support().test_query()
+    }
+
+    /// `test_query`, compiled the first time it is needed and kept, so
+    /// discovering tests only pays for matching.
+    pub fn compiled_test_query(&self) -> &'static Query {
+        static QUERIES: [OnceLock<Query>; Language::ALL.len()] =
+            [const { OnceLock::new() }; Language::ALL.len()];
+        let index = Language::ALL
+            .iter()
+            .position(|language| language == self)
+            .unwrap_or_default();
+        QUERIES[index].get_or_init(|| {
+            Query::new(&self.grammar(), self.test_query()).expect("built-in test queries are valid")
+        })
+    }
+
+    /// Globs a test's name must match, unless configured otherwise; none
+    /// means any name.
+    pub fn test_names(&self) -> &'static [&'static str] {
+        self.support().test_names()
+    }
+
+    /// The command running tests when none is configured, with a `{tests}`
+    /// placeholder.
+    pub fn command_template(&self) -> &'static str {
+        self.support().command_template()
+    }
+
+    /// The command running tests for the project at `root` when none is
+    /// configured.
+    pub fn command_template_at(&self, root: &Path) -> &'static str {
+        self.support().command_template_at(root)
+    }
+
+    /// What to pass the command in place of test `id`.
+    pub fn test_target(&self, id: &str) -> String {
+        self.support().test_target(id)
+    }
+
+    /// What to pass the command in place of the tests `ids`.
+    pub fn test_arguments(&self, ids: &[String]) -> Vec<String> {
+        self.support().test_arguments(ids)
+    }
+
+    /// What to pass `command` in place of the tests `ids`.
+    pub fn test_arguments_for(&self, command: &str, ids: &[String]) -> Vec<String> {
+        self.support().test_arguments_for(command, ids)
+    }
+}
+
+/// What the engine needs to support a language: how to recognise its
+/// projects and files, how to parse them and find the tests, and how to run
+/// those tests. Adding a language means implementing this and adding a
+/// variant to `Language`.
+pub trait LanguageSupport: Send + Sync {
+    /// Whether the project at `root` is written in this language, judged
+    /// by the files its tooling keeps at the root.
+    fn detect(&self, root: &Path) -> bool;
+
+    /// The extension of source files, without the dot.
+    fn extension(&self) -> &'static str;
+
+    fn matches_path(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == self.extension())
+    }
+
+    fn grammar(&self) -> tree_sitter::Language;
+
+    /// Query locating test definitions; every `@name` capture is a test name.
+    fn test_query(&self) -> &'static str;
+
+    /// Globs a test's name must match, for languages whose tests are found
+    /// by name, unless configured otherwise. None means any name does.
+    fn test_names(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The kind of node enclosing a test's `@name` capture that spans the
+    /// whole test.
+    fn test_definition_kind(&self) -> &'static str;
+
+    /// The kind of named node, such as a class, whose name qualifies the
+    /// ids of the tests defined in it.
+    fn test_scope_kind(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The name `node` gives the tests inside it, if it is a scope: by
+    /// default the `name` of a node of `test_scope_kind`.
+    fn test_scope_name<'a>(&self, node: Node, source: &'a [u8]) -> Option<&'a str> {
+        if Some(node.kind()) != self.test_scope_kind() {
+            return None;
+        }
+        node.child_by_field_name("name")?.utf8_text(source).ok()
+    }
+
+    /// The command running tests under coverage, with a `{tests}`
+    /// placeholder.
+    fn command_template(&self) -> &'static str;
+
+    /// The command for the project at `root`. Languages with more than one
+    /// build tool pick by the files at `root`; by default this is
+    /// `command_template`.
+    fn command_template_at(&self, _root: &Path) -> &'static str {
+        self.command_template()
+    }
+
+    /// What to pass the command in place of test `id`. Runners that cannot
+    /// select single tests take the file or package holding it.
+    fn test_target(&self, id: &str) -> String {
+        id.to_string()
+    }
+
+    /// What to pass the command in place of the tests `ids`: by default
+    /// the target of each, once.
+    fn test_arguments(&self, ids: &[String]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        ids.iter()
+            .map(|id| self.test_target(id))
+            .filter(|target| seen.insert(target.clone()))
+            .collect()
+    }
+
+    /// What to pass `command`, a template with a `{tests}` placeholder, in
+    /// place of the tests `ids`, for languages whose runners want them in
+    /// different forms; by default `test_arguments`.
+    fn test_arguments_for(&self, _command: &str, ids: &[String]) -> Vec<String> {
+        self.test_arguments(ids)
+    }
+
+    /// Whether the tests in scope `node` are collected at all. By default
+    /// every scope's are.
+    fn is_test_scope(&self, _node: Node, _source: &[u8]) -> bool {
+        true
+    }
+}
+
+fn has_any(root: &Path, files: &[&str]) -> bool {
+    files.iter().any(|file| root.join(file).exists())
+}
+
+/// The arguments of `command`, split as the runner splits it.
+#[cfg(any(feature = "python", feature = "java"))]
+fn words(command: &str) -> Vec<String> {
+    shell_words::split(command).unwrap_or_default()
+}
+
+/// pytest, run under coverage.py.
+#[cfg(feature = "python")]
+pub struct Python;
+
+#[cfg(feature = "python")]
+impl LanguageSupport for Python {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(
+            root,
+            &[
+                "pyproject.toml",
+                "setup.py",
+                "setup.cfg",
+                "requirements.txt",
+                "pytest.ini",
+                "tox.ini",
+            ],
+        )
+    }
+
+    fn extension(&self) -> &'static str {
+        "py"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_python::LANGUAGE.into()
+    }
+
+    fn test_query(&self) -> &'static str {
+        r#"(function_definition name: (identifier) @name)"#
+    }
+
+    fn test_names(&self) -> &'static [&'static str] {
+        &["test*"]
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "function_definition"
+    }
+
+    fn test_scope_kind(&self) -> Option<&'static str> {
+        Some("class_definition")
+    }
+
+    /// pytest collects methods from `Test*` classes and unittest from
+    /// `TestCase` subclasses, such as `unittest.TestCase` or Django's, also
+    /// through base classes defined in the same file.
+    fn is_test_scope(&self, node: Node, source: &[u8]) -> bool {
+        Python::is_test_class(node, source, 0)
+    }
+
+    fn command_template(&self) -> &'static str {
+        "coverage run -m pytest {tests}"
+    }
+
+    /// `python -m unittest` takes dotted names, such as
+    /// `tests.test_calc.TestCalc.test_add`. Functions outside a class are
+    /// not unittest tests, so their whole module runs.
+    fn test_arguments_for(&self, command: &str, ids: &[String]) -> Vec<String> {
+        let unittest = words(command)
+            .windows(2)
+            .any(|pair| pair[0] == "-m" && pair[1] == "unittest");
+        if !unittest {
+            return self.test_arguments(ids);
+        }
+        let mut seen = HashSet::new();
+        ids.iter()
+            .map(|id| {
+                let mut parts = id.split("::");
+                let module = parts.next().unwrap_or(id).trim_end_matches(".py");
+                let names: Vec<&str> = parts.collect();
+                let mut name = module.replace('/', ".");
+                if names.len() > 1 {
+                    name = format!("{}.{}", name, names.join("."));
+                }
+                name
+            })
+            .filter(|name| seen.insert(name.clone()))
+            .collect()
+    }
+}
+
+#[cfg(feature = "python")]
+impl Python {
+    /// How many base classes deep a class is followed looking for a
+    /// `TestCase`.
+    const BASE_DEPTH: usize = 8;
+
+    fn is_test_class(class: Node, source: &[u8], depth: usize) -> bool {
+        let text = |node: Node| node.utf8_text(source).unwrap_or_default().to_string();
+        let name = class
+            .child_by_field_name("name")
+            .map(text)
+            .unwrap_or_default();
+        if name.starts_with("Test") {
+            return true;
+        }
+        let Some(bases) = class.child_by_field_name("superclasses") else {
+            return false;
+        };
+        let bases: Vec<String> = bases
+            .named_children(&mut bases.walk())
+            .filter(|base| matches!(base.kind(), "identifier" | "attribute"))
+            .map(text)
+            .collect();
+        if bases.iter().any(|base| base.ends_with("TestCase")) {
+            return true;
+        }
+        if depth >= Python::BASE_DEPTH {
+            return false;
+        }
+        // a base defined at the top of the same file may be a TestCase
+        let mut root = class;
+        while let Some(parent) = root.parent() {
+            root = parent;
+        }
+        root.named_children(&mut root.walk())
+            .map(|node| match node.kind() {
+                "decorated_definition" => node.child_by_field_name("definition").unwrap_or(node),
+                _ => node,
+            })
+            .filter(|node| node.kind() == "class_definition")
+            .filter(|node| {
+                node.child_by_field_name("name")
+                    .is_some_and(|name| bases.contains(&text(name)))
+            })
+            .any(|base| Python::is_test_class(base, source, depth + 1))
+    }
+}
+
+/// `it` and `test` blocks, run by Jest one file at a time.
+#[cfg(feature = "javascript")]
+pub struct JavaScript;
+
+#[cfg(feature = "javascript")]
+impl LanguageSupport for JavaScript {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(root, &["package.json"])
+    }
+
+    fn extension(&self) -> &'static str {
+        "js"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_javascript::LANGUAGE.into()
+    }
+
+    fn test_query(&self) -> &'static str {
+        r#"(call_expression
+            function: (identifier) @function
+            arguments: (arguments . (string (string_fragment) @name))
+            (#match? @function "^(it|test)$"))"#
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "call_expression"
+    }
+
+    fn command_template(&self) -> &'static str {
+        "npx jest --coverage {tests}"
+    }
+
+    fn test_target(&self, id: &str) -> String {
+        id.split_once("::").map_or(id, |(file, _)| file).to_string()
+    }
+}
+
+/// `TestXxx` functions, run by `go test` one package at a time.
+#[cfg(feature = "go")]
+pub struct Go;
+
+#[cfg(feature = "go")]
+impl LanguageSupport for Go {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(root, &["go.mod"])
+    }
+
+    fn extension(&self) -> &'static str {
+        "go"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_go::LANGUAGE.into()
+    }
+
+    /// `TestMain(m *testing.M)` and functions taking anything but a
+    /// `*testing.T` are not tests.
+    fn test_query(&self) -> &'static str {
+        r#"(function_declaration
+            name: (identifier) @name
+            parameters: (parameter_list
+              .
+              (parameter_declaration
+                type: (pointer_type
+                  (qualified_type
+                    package: (package_identifier) @package
+                    name: (type_identifier) @type)))
+              .)
+            (#eq? @package "testing")
+            (#eq? @type "T"))"#
+    }
+
+    fn test_names(&self) -> &'static [&'static str] {
+        &["Test*"]
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "function_declaration"
+    }
+
+    fn command_template(&self) -> &'static str {
+        "go test -coverprofile=coverage.out -run {tests}"
+    }
+
+    fn test_target(&self, id: &str) -> String {
+        let file = id.split_once("::").map_or(id, |(file, _)| file);
+        match file.rsplit_once('/') {
+            Some((package, _)) => format!("./{}", package),
+            None => ".".to_string(),
+        }
+    }
+
+    /// A `-run` regexp matching the tests by name, then their packages.
+    /// No tests means the whole suite.
+    fn test_arguments(&self, ids: &[String]) -> Vec<String> {
+        if ids.is_empty() {
+            return vec![".".to_string(), "./...".to_string()];
+        }
+        let names: BTreeSet<&str> = ids
+            .iter()
+            .filter_map(|id| id.rsplit_once("::"))
+            .map(|(_, name)| name)
+            .collect();
+        let packages: BTreeSet<String> = ids.iter().map(|id| self.test_target(id)).collect();
+        let names: Vec<&str> = names.into_iter().collect();
+        std::iter::once(format!("^({})$", names.join("|")))
+            .chain(packages)
+            .collect()
+    }
+}
+
+/// `#[test]` functions, including `#[tokio::test]` and the like, run by
+/// `cargo test` under cargo-llvm-cov.
+#[cfg(feature = "rust")]
+pub struct Rust;
+
+#[cfg(feature = "rust")]
+impl LanguageSupport for Rust {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(root, &["Cargo.toml"])
+    }
+
+    fn extension(&self) -> &'static str {
+        "rs"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_rust::LANGUAGE.into()
+    }
+
+    fn test_query(&self) -> &'static str {
+        r#"((attribute_item
+              (attribute [(identifier) @attribute
+                          (scoped_identifier name: (identifier) @attribute)]))
+            .
+            (attribute_item)*
+            .
+            (function_item name: (identifier) @name)
+            (#eq? @attribute "test"))"#
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "function_item"
+    }
+
+    fn test_scope_kind(&self) -> Option<&'static str> {
+        Some("mod_item")
+    }
+
+    fn command_template(&self) -> &'static str {
+        "cargo llvm-cov --json --output-path target/llvm-cov.json -- --exact {tests}"
+    }
+
+    /// The test's path within its crate, as libtest names it: the module
+    /// the file defines followed by the test's own modules and name.
+    fn test_target(&self, id: &str) -> String {
+        let Some((file, name)) = id.split_once("::") else {
+            return id.to_string();
+        };
+        let mut parts: Vec<&str> = file.trim_end_matches(".rs").split('/').collect();
+        // each file in tests/, benches/, examples/ and src/bin/ is a crate root
+        let start = match parts.iter().rposition(|part| *part == "src") {
+            Some(src) if parts.get(src + 1) == Some(&"bin") => src + 3,
+            Some(src) => src + 1,
+            None => parts.len(),
+        };
+        parts.drain(..start.min(parts.len()));
+        if matches!(parts.last(), Some(&"lib" | &"main" | &"mod")) {
+            parts.pop();
+        }
+        parts.push(name);
+        parts.join("::")
+    }
+}
+
+/// RSpec examples, run under SimpleCov with an `--example` filter per
+/// test. Examples are `it`, `specify` and `example` blocks, identified by
+/// file, enclosing `describe` and `context` blocks and description, such as
+/// `spec/calc_spec.rb::Calculator::adds`.
+#[cfg(feature = "ruby")]
+pub struct Ruby;
+
+#[cfg(feature = "ruby")]
+impl Ruby {
+    /// The file and description of test `id`, whose description may itself
+    /// contain `::`. The last part is at worst the tail of the description,
+    /// which `--example` still matches since it matches substrings.
+    fn split(id: &str) -> (&str, &str) {
+        let file = id.split_once("::").map_or(id, |(file, _)| file);
+        let description = id.rsplit_once("::").map_or(id, |(_, name)| name);
+        (file, description)
+    }
+}
+
+#[cfg(feature = "ruby")]
+impl LanguageSupport for Ruby {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(root, &["Gemfile", ".rspec"])
+    }
+
+    fn extension(&self) -> &'static str {
+        "rb"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_ruby::LANGUAGE.into()
+    }
+
+    fn test_query(&self) -> &'static str {
+        r#"(call
+            method: (identifier) @method
+            arguments: (argument_list . (string (string_content) @name))
+            (#match? @method "^(it|specify|example)$"))"#
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "call"
+    }
+
+    /// `describe` and `context` blocks, `RSpec.describe` included, named by
+    /// their first argument, a string or a constant such as `Calculator`.
+    fn test_scope_name<'a>(&self, node: Node, source: &'a [u8]) -> Option<&'a str> {
+        if node.kind() != "call" {
+            return None;
+        }
+        let method = node.child_by_field_name("method")?.utf8_text(source).ok()?;
+        if !matches!(method, "describe" | "context") {
+            return None;
+        }
+        let argument = node.child_by_field_name("arguments")?.named_child(0)?;
+        let name = match argument.kind() {
+            "string" => argument.named_child(0)?,
+            "constant" | "scope_resolution" => argument,
+            _ => return None,
+        };
+        name.utf8_text(source).ok()
+    }
+
+    fn command_template(&self) -> &'static str {
+        "bundle exec rspec {tests}"
+    }
+
+    fn test_target(&self, id: &str) -> String {
+        Ruby::split(id).0.to_string()
+    }
+
+    /// The files holding the tests, then an `--example` filter with each
+    /// test's description. The filters apply to every file, so a test
+    /// sharing a description with a selected one runs too.
+    fn test_arguments(&self, ids: &[String]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let files = ids.iter().map(|id| self.test_target(id));
+        let examples = ids
+            .iter()
+            .map(|id| format!("--example={}", Ruby::split(id).1));
+        files
+            .chain(examples)
+            .filter(|argument| seen.insert(argument.clone()))
+            .collect()
+    }
+}
+
+/// JUnit `@Test` methods, run by Maven's Surefire or by Gradle under
+/// JaCoCo, whichever the project builds with.
+#[cfg(feature = "java")]
+pub struct Java;
+
+#[cfg(feature = "java")]
+impl Java {
+    const MAVEN: &'static str = "mvn org.jacoco:jacoco-maven-plugin:prepare-agent test \
+        org.jacoco:jacoco-maven-plugin:report -Dsurefire.failIfNoSpecifiedTests=false {tests}";
+    const GRADLE: &'static str = "gradle test jacocoTestReport {tests}";
+    const GRADLE_WRAPPER: &'static str = "./gradlew test jacocoTestReport {tests}";
+
+    /// The fully qualified class and the method of test `id`, taking the
+    /// package from the directories below the source root, such as
+    /// `src/test/java`, and joining nested classes with `$`.
+    fn split(id: &str) -> Option<(String, &str)> {
+        let mut parts = id.split("::");
+        let file = parts.next()?;
+        let mut scopes: Vec<&str> = parts.collect();
+        let method = scopes.pop()?;
+        if scopes.is_empty() {
+            return None;
+        }
+        let mut directories: Vec<&str> = file.split('/').collect();
+        directories.pop();
+        let package = match directories.iter().rposition(|part| *part == "java") {
+            Some(root) => &directories[root + 1..],
+            None => &[],
+        };
+        let class = scopes.join("$");
+        match package.is_empty() {
+            true => Some((class, method)),
+            false => Some((format!("{}.{}", package.join("."), class), method)),
+        }
+    }
+
+    /// The class and method of each of `ids`, warning about the ids that
+    /// name no class, which neither Surefire nor Gradle can filter on.
+    fn split_all(ids: &[String]) -> Vec<(String, &str)> {
+        let mut split = Vec::new();
+        let mut unfiltered = Vec::new();
+        for id in ids {
+            match Java::split(id) {
+                Some(test) => split.push(test),
+                None => unfiltered.push(id.as_str()),
+            }
+        }
+        if !unfiltered.is_empty() {
+            tracing::warn!(tests = ?unfiltered, "leaving out tests that name no class");
+        }
+        split
+    }
+}
+
+/// Whether the project at `root` builds with Gradle rather than Maven.
+#[cfg(feature = "java")]
+pub fn uses_gradle(root: &Path) -> bool {
+    !has_any(root, &["pom.xml"]) && has_any(root, &["build.gradle", "build.gradle.kts"])
+}
+
+#[cfg(feature = "java")]
+impl LanguageSupport for Java {
+    fn detect(&self, root: &Path) -> bool {
+        has_any(root, &["pom.xml", "build.gradle", "build.gradle.kts"])
+    }
+
+    fn extension(&self) -> &'static str {
+        "java"
+    }
+
+    fn grammar(&self) -> tree_sitter::Language {
+        tree_sitter_java::LANGUAGE.into()
+    }
+
+    fn test_query(&self) -> &'static str {
+        r#"(method_declaration
+            (modifiers
+              [(marker_annotation name: (identifier) @annotation)
+               (annotation name: (identifier) @annotation)])
+            name: (identifier) @name
+            (#match? @annotation "^(Test|ParameterizedTest|RepeatedTest)$"))"#
+    }
+
+    fn test_definition_kind(&self) -> &'static str {
+        "method_declaration"
+    }
+
+    /// Classes, interfaces, enums and records, any of which can hold tests.
+    fn test_scope_name<'a>(&self, node: Node, source: &'a [u8]) -> Option<&'a str> {
+        match node.kind() {
+            "class_declaration"
+            | "interface_declaration"
+            | "enum_declaration"
+            | "record_declaration" => node.child_by_field_name("name")?.utf8_text(source).ok(),
+            _ => None,
+        }
+    }
+
+    fn command_template(&self) -> &'static str {
+        Java::MAVEN
+    }
+
+    fn command_template_at(&self, root: &Path) -> &'static str {
+        match uses_gradle(root) {
+            true if has_any(root, &["gradlew"]) => Java::GRADLE_WRAPPER,
+            true => Java::GRADLE,
+            false => Java::MAVEN,
+        }
+    }
+
+    fn test_target(&self, id: &str) -> String {
+        Java::split(id).map_or_else(|| id.to_string(), |(class, _)| class)
+    }
+
+    /// Surefire's `-Dtest=Class#a+b,Other#c`. Nothing when no test names
+    /// a class, so the empty selection policy decides what runs.
+    fn test_arguments(&self, ids: &[String]) -> Vec<String> {
+        let mut methods: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+        for (class, method) in Java::split_all(ids) {
+            methods.entry(class).or_default().insert(method);
+        }
+        if methods.is_empty() {
+            return Vec::new();
+        }
+        let filters: Vec<String> = methods
+            .iter()
+            .map(|(class, methods)| {
+                let methods: Vec<&str> = methods.iter().copied().collect();
+                format!("{}#{}", class, methods.join("+"))
+            })
+            .collect();
+        vec![format!("-Dtest={}", filters.join(","))]
+    }
+
+    /// Gradle takes a `--tests=Class.method` filter per test.
+    fn test_arguments_for(&self, command: &str, ids: &[String]) -> Vec<String> {
+        let gradle = words(command)
+            .first()
+            .is_some_and(|program| program.ends_with("gradle") || program.ends_with("gradlew"));
+        if !gradle {
+            return self.test_arguments(ids);
+        }
+        let filters: BTreeSet<String> = Java::split_all(ids)
+            .into_iter()
+            .map(|(class, method)| format!("--tests={}.{}", class, method))
+            .collect();
+        filters.into_iter().collect()
+    }
+}
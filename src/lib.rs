@@ -0,0 +1,112 @@
+pub mod language;
+
+pub use language::Language;
+
+/// Puts everything but `language` behind at least one language feature, so
+/// a build without any shows only `language`'s explanation rather than the
+/// errors of every module that needs a language.
+macro_rules! with_a_language {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(
+                feature = "python",
+                feature = "javascript",
+                feature = "go",
+                feature = "rust",
+                feature = "ruby",
+                feature = "java"
+            ))]
+            $item
+        )*
+    };
+}
+
+with_a_language! {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod bazel;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod cache;
+    pub mod calls;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod ci;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod codecov;
+    pub mod config;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod contexts;
+    pub mod coverage;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod coveragepy;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod data;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod devcontainer;
+    pub mod diff;
+    pub mod discovery;
+    #[cfg(not(target_arch = "wasm32"))]
+    mod engine;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod github;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod gitlab;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod gocover;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod hooks;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod html;
+    pub mod imports;
+    #[cfg(all(feature = "java", not(target_arch = "wasm32")))]
+    pub mod jacoco;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod jsonrpc;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod llvmcov;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod lsp;
+    pub mod metrics;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod nvim;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod patch;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod remote;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod report;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod results;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod rpc;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod runner;
+    pub mod selection;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod sentry;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod simplecov;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod slack;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod state;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod statefile;
+    pub mod trace;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod triggers;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod vcs;
+    #[cfg(target_arch = "wasm32")]
+    mod wasm;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub mod watch;
+
+    pub use config::{BaseMode, Config};
+    pub use coverage::{FilePatchCoverage, HunkCoverage, PatchCoverage};
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use engine::{Engine, EngineBuilder, EngineError, FULL_RESYNC_EVERY};
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use runner::RunResult;
+    pub use selection::{EmptySelection, ImpactStrategy, SelectedTest, Selection};
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use state::{EngineState, Snapshot};
+}
@@ -0,0 +1,146 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::discovery::slash_path;
+use crate::selection::ImpactData;
+
+/// Where the Rust runner has `cargo llvm-cov --json` write its export.
+pub const DATA_FILE: &str = "target/llvm-cov.json";
+
+#[derive(Debug, Error)]
+pub enum LlvmCovError {
+    #[error("failed to read coverage data {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path} is not an llvm-cov JSON export: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct Export {
+    data: Vec<ExportData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportData {
+    files: Vec<ExportFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportFile {
+    filename: String,
+    /// `[line, column, count, has_count, is_region_entry, is_gap_region]`,
+    /// the last missing from older exports.
+    segments: Vec<Vec<serde_json::Value>>,
+}
+
+/// Where a region starts or ends, and the count from there on.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    line: usize,
+    count: u64,
+    has_count: bool,
+    is_region_entry: bool,
+    is_gap: bool,
+}
+
+impl Segment {
+    fn parse(values: &[serde_json::Value]) -> Option<Segment> {
+        Some(Segment {
+            line: values.first()?.as_u64()? as usize,
+            count: values.get(2)?.as_u64()?,
+            has_count: values.get(3)?.as_bool()?,
+            is_region_entry: values.get(4)?.as_bool()?,
+            is_gap: values.get(5).and_then(|v| v.as_bool()).unwrap_or(false),
+        })
+    }
+}
+
+/// Reads a `cargo llvm-cov --json` export. llvm-cov does not attribute
+/// lines to tests, so everything run is recorded outside any test, which
+/// counts for patch coverage but selects nothing. Files outside `root` are
+/// left out.
+pub fn read(path: &Path, root: &Path) -> Result<ImpactData, LlvmCovError> {
+    let content = fs::read_to_string(path).map_err(|source| LlvmCovError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let export: Export = serde_json::from_str(&content).map_err(|source| LlvmCovError::Json {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut files: HashMap<String, HashSet<usize>> = HashMap::new();
+    for file in export.data.into_iter().flat_map(|data| data.files) {
+        let filename = Path::new(&file.filename);
+        let relative = match filename.strip_prefix(&root) {
+            Ok(relative) => relative,
+            Err(_) if filename.is_relative() => filename,
+            Err(_) => continue,
+        };
+        let Some(relative) = relative.to_str() else {
+            continue;
+        };
+        let segments: Vec<Segment> = file
+            .segments
+            .iter()
+            .filter_map(|s| Segment::parse(s))
+            .collect();
+        let executed = executed_lines(&segments)
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(line, _)| line);
+        files
+            .entry(slash_path(relative))
+            .or_default()
+            .extend(executed);
+    }
+    Ok(ImpactData {
+        lines: HashMap::from([(String::new(), files)]),
+        ..ImpactData::default()
+    })
+}
+
+/// The execution count of every line with code, worked out from segments
+/// as `llvm-cov` does for its own line reports: a line takes the highest
+/// count of the regions starting on it and of the region it continues.
+fn executed_lines(segments: &[Segment]) -> BTreeMap<usize, u64> {
+    let mut lines = BTreeMap::new();
+    let (Some(first), Some(last)) = (segments.first(), segments.last()) else {
+        return lines;
+    };
+    let mut wrapped: Option<Segment> = None;
+    let mut next = 0;
+    for line in first.line..=last.line {
+        let start = next;
+        while next < segments.len() && segments[next].line == line {
+            next += 1;
+        }
+        let starting = &segments[start..next];
+        let entries = starting
+            .iter()
+            .filter(|s| s.has_count && s.is_region_entry && !s.is_gap);
+        let mapped =
+            wrapped.is_some_and(|w| w.has_count && !w.is_gap) || entries.clone().count() > 0;
+        if mapped {
+            let count = entries
+                .map(|s| s.count)
+                .chain(wrapped.filter(|w| w.has_count).map(|w| w.count))
+                .max()
+                .unwrap_or(0);
+            lines.insert(line, count);
+        }
+        if let Some(segment) = starting.last() {
+            wrapped = Some(*segment);
+        }
+    }
+    lines
+}
@@ -0,0 +1,273 @@
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::coverage::line_blocks;
+use crate::discovery::slash_path;
+use crate::engine::Engine;
+use crate::rpc::{
+    error_response, notification, read_message, response, write_message, RpcError, INTERNAL_ERROR,
+    INVALID_PARAMS, METHOD_NOT_FOUND,
+};
+use crate::selection::{SelectedTest, Selection};
+
+/// Command attached to code lenses; its single argument is the list of test
+/// ids to run.
+pub const RUN_TESTS_COMMAND: &str = "instantPatch.runTests";
+
+const SEVERITY_WARNING: u8 = 2;
+const MESSAGE_ERROR: u8 = 1;
+const MESSAGE_INFO: u8 = 3;
+
+/// Runs a minimal language server over `input`/`output` until the client
+/// sends `exit`. Diagnostics mark changed lines no test covers and are
+/// refreshed whenever a document is opened or saved.
+pub fn serve<R: BufRead, W: Write>(engine: &Engine, input: R, output: W) -> Result<(), RpcError> {
+    let root = engine
+        .root()
+        .canonicalize()
+        .unwrap_or_else(|_| engine.root().to_path_buf());
+    let mut server = Server {
+        engine,
+        root,
+        output,
+        published: BTreeSet::new(),
+    };
+    server.serve(input)
+}
+
+struct Server<'e, W> {
+    engine: &'e Engine,
+    root: PathBuf,
+    output: W,
+    /// Paths that currently have diagnostics, so they can be cleared.
+    published: BTreeSet<String>,
+}
+
+impl<W: Write> Server<'_, W> {
+    fn serve<R: BufRead>(&mut self, mut input: R) -> Result<(), RpcError> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message.method.as_deref().unwrap_or_default();
+            let result = match method {
+                "initialize" => Ok(capabilities()),
+                "initialized"
+                | "textDocument/didOpen"
+                | "textDocument/didSave"
+                | "workspace/didChangeWatchedFiles" => {
+                    self.publish_diagnostics()?;
+                    continue;
+                }
+                "textDocument/codeLens" => self.code_lenses(&message.params),
+                "workspace/executeCommand" => self.execute_command(&message.params),
+                "shutdown" => Ok(Value::Null),
+                "exit" => return Ok(()),
+                _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+            };
+            // notifications never get a reply, even when they are not understood
+            if let Some(id) = &message.id {
+                let reply = match result {
+                    Ok(result) => response(id, result),
+                    Err((code, error)) => error_response(id, code, &error),
+                };
+                write_message(&mut self.output, &reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn show_message(&mut self, kind: u8, message: &str) -> Result<(), RpcError> {
+        let params = json!({ "type": kind, "message": message });
+        write_message(
+            &mut self.output,
+            &notification("window/showMessage", params),
+        )
+    }
+
+    fn publish_diagnostics(&mut self) -> Result<(), RpcError> {
+        let coverage = match self.engine.patch_coverage() {
+            Ok(coverage) => coverage,
+            Err(e) => return self.show_message(MESSAGE_ERROR, &e.to_string()),
+        };
+        let mut published = BTreeSet::new();
+        for file in &coverage.files {
+            let diagnostics: Vec<Value> = line_blocks(&file.uncovered_lines())
+                .into_iter()
+                .map(|(first, last)| {
+                    let message = match first == last {
+                        true => "Changed line is not covered by any test".to_string(),
+                        false => format!(
+                            "Changed lines {}-{} are not covered by any test",
+                            first, last
+                        ),
+                    };
+                    json!({
+                        "range": line_range(first, last),
+                        "severity": SEVERITY_WARNING,
+                        "source": "instant-patch",
+                        "message": message,
+                    })
+                })
+                .collect();
+            if !diagnostics.is_empty() {
+                self.publish(&file.path, diagnostics)?;
+                published.insert(file.path.clone());
+            }
+        }
+        for stale in self
+            .published
+            .difference(&published)
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            self.publish(&stale, Vec::new())?;
+        }
+        self.published = published;
+        Ok(())
+    }
+
+    fn publish(&mut self, path: &str, diagnostics: Vec<Value>) -> Result<(), RpcError> {
+        let params = json!({
+            "uri": path_to_uri(&self.root.join(path)),
+            "diagnostics": diagnostics,
+        });
+        write_message(
+            &mut self.output,
+            &notification("textDocument/publishDiagnostics", params),
+        )
+    }
+
+    fn relative_path(&self, params: &Value) -> Option<String> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let path = uri_to_path(uri)?;
+        let path = path.strip_prefix(&self.root).ok()?;
+        path.to_str().map(slash_path)
+    }
+
+    fn code_lenses(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let path = match self.relative_path(params) {
+            Some(path) => path,
+            None => return Ok(json!([])),
+        };
+        let coverage = self
+            .engine
+            .patch_coverage()
+            .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+        let file = match coverage.files.iter().find(|file| file.path == path) {
+            Some(file) => file,
+            None => return Ok(json!([])),
+        };
+        let impact = self.engine.impact_data();
+        let lenses: Vec<Value> = line_blocks(&file.changed_lines)
+            .into_iter()
+            .map(|(first, last)| {
+                let tests: BTreeSet<String> = (first..=last)
+                    .flat_map(|line| impact.tests_covering(&path, line))
+                    .collect();
+                let command = match tests.len() {
+                    0 => json!({ "title": "not covered by any test", "command": "" }),
+                    n => json!({
+                        "title": format!(
+                            "covered by {} test{} / run them",
+                            n,
+                            if n == 1 { "" } else { "s" }
+                        ),
+                        "command": RUN_TESTS_COMMAND,
+                        "arguments": [tests],
+                    }),
+                };
+                json!({ "range": line_range(first, first), "command": command })
+            })
+            .collect();
+        Ok(Value::Array(lenses))
+    }
+
+    fn execute_command(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        if params["command"] != RUN_TESTS_COMMAND {
+            return Err((
+                INVALID_PARAMS,
+                format!("unknown command {}", params["command"]),
+            ));
+        }
+        let ids: Vec<String> = params["arguments"][0]
+            .as_array()
+            .map(|ids| {
+                ids.iter()
+                    .filter_map(|id| id.as_str().map(|id| id.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let selection = Selection::new(
+            ids.iter()
+                .map(|id| SelectedTest::new(id.as_str(), "requested"))
+                .collect(),
+        );
+        let (kind, message) = match self.engine.run(&selection) {
+            Ok(result) if result.success() => (
+                MESSAGE_INFO,
+                format!("{} tests passed", selection.tests.len()),
+            ),
+            Ok(result) => (
+                MESSAGE_ERROR,
+                format!("tests failed with exit code {:?}", result.exit_code),
+            ),
+            Err(e) => (MESSAGE_ERROR, e.to_string()),
+        };
+        self.show_message(kind, &message)
+            .and_then(|_| self.publish_diagnostics())
+            .map_err(|e| (INTERNAL_ERROR, e.to_string()))?;
+        Ok(Value::Null)
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": { "openClose": true, "save": true },
+            "codeLensProvider": { "resolveProvider": false },
+            "executeCommandProvider": { "commands": [RUN_TESTS_COMMAND] },
+        },
+        "serverInfo": { "name": "instant-patch", "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/// LSP range spanning whole 1-based lines `first..=last`.
+fn line_range(first: usize, last: usize) -> Value {
+    json!({
+        "start": { "line": first - 1, "character": 0 },
+        "end": { "line": last, "character": 0 },
+    })
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        match encoded[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
@@ -1,377 +1,555 @@
-use core::panic;
-use git2::{DiffLineType, DiffOptions, Object, ObjectType, Patch, Repository};
-use glob::glob;
-use notify_debouncer_full::{new_debouncer, notify::*, DebounceEventResult};
-use std::ffi::OsStr;
-use std::path::Path;
-use std::process::Command;
+use clap::{ArgAction, Parser, Subcommand};
+use hackweek_instant_codecoverage::config::CONFIG_FILE;
+use hackweek_instant_codecoverage::jsonrpc::RpcServer;
+use hackweek_instant_codecoverage::nvim::NvimServer;
+use hackweek_instant_codecoverage::patch::{self, FilePatch, PatchVcs};
+use hackweek_instant_codecoverage::report::{self, OutputFormat};
+use hackweek_instant_codecoverage::watch::WatchControl;
+use hackweek_instant_codecoverage::{
+    codecov, github, gitlab, hooks, lsp, vcs, watch, EngineBuilder, EngineError, ImpactStrategy,
+};
+use hackweek_instant_codecoverage::{BaseMode, Config, Language};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
-use std::{collections::HashMap, collections::HashSet, fs};
-use tree_sitter::{InputEdit, Point, Query, QueryCapture, QueryCursor, Tree};
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+#[derive(Parser)]
+#[command(version, about = "Run new tests and report their coverage as you edit")]
+struct Cli {
+    /// Checkout to watch and analyse, then other directories whose changes
+    /// start a run, such as sources kept outside it
+    #[arg(value_name = "PATH")]
+    paths: Vec<PathBuf>,
+    /// Log phase timings; repeat for debug output
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Only log errors
+    #[arg(short, long)]
+    quiet: bool,
+    /// Same as PATH
+    #[arg(short = 'C', long, value_name = "PATH", conflicts_with = "paths")]
+    root: Option<PathBuf>,
+    /// Branch, tag or commit to measure changes against; `HEAD` by default,
+    /// or `origin/main` in `merge-base` mode
+    #[arg(long, value_name = "REF", env = "INSTANTCOV_BASE")]
+    base: Option<String>,
+    /// `commit`, the default, to compare against the base itself, or
+    /// `merge-base` to compare against where HEAD forked from it
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = parse_value::<BaseMode>,
+        env = "INSTANTCOV_BASE_MODE"
+    )]
+    base_mode: Option<BaseMode>,
+    /// Only measure staged changes, as they would be committed
+    #[arg(long)]
+    staged: bool,
+    /// Only measure changes that are not staged yet, against the index
+    #[arg(long, conflicts_with = "staged")]
+    unstaged: bool,
+    /// Read the changes from a unified diff on stdin instead of the
+    /// repository, for checkouts without one
+    #[arg(long, conflicts_with_all = ["staged", "unstaged"])]
+    diff_from_stdin: bool,
+    /// Test command, with `{tests}` standing in for the selected ids
+    #[arg(long = "command", value_name = "TEMPLATE", env = "INSTANTCOV_COMMAND")]
+    test_command: Option<String>,
+    /// Language whose tests to select
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = parse_value::<Language>,
+        env = "INSTANTCOV_LANGUAGE"
+    )]
+    language: Option<Language>,
+    /// Only watch and analyse source files matching this glob; repeatable
+    #[arg(
+        long,
+        value_name = "GLOB",
+        env = "INSTANTCOV_INCLUDE",
+        value_delimiter = ','
+    )]
+    include: Vec<String>,
+    /// Never watch or analyse source files matching this glob; repeatable
+    #[arg(
+        long,
+        value_name = "GLOB",
+        env = "INSTANTCOV_EXCLUDE",
+        value_delimiter = ','
+    )]
+    exclude: Vec<String>,
+    /// Only count functions whose name matches this glob as tests, instead
+    /// of the language's convention; repeatable
+    #[arg(
+        long = "test-name",
+        value_name = "GLOB",
+        env = "INSTANTCOV_TEST_NAMES",
+        value_delimiter = ','
+    )]
+    test_names: Vec<String>,
+    /// Milliseconds to wait for edits to settle before running
+    #[arg(long, value_name = "MS", env = "INSTANTCOV_DEBOUNCE")]
+    debounce: Option<u64>,
+    /// Rescan the tree every MS milliseconds, 1000 if not given, instead of
+    /// relying on change events, e.g. on NFS, Docker bind mounts or WSL
+    #[arg(
+        long,
+        value_name = "MS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1000",
+        env = "INSTANTCOV_POLL"
+    )]
+    poll: Option<u64>,
+    /// Watch no directory more than N levels below the checkout or another
+    /// watched directory, for trees too large to watch whole
+    #[arg(long, value_name = "N", env = "INSTANTCOV_MAX_DEPTH")]
+    max_depth: Option<usize>,
+    /// Outside a git repository, treat every discovered test as new
+    #[arg(long)]
+    no_baseline: bool,
+    /// Select test targets with `bazel query rdeps(...)` and run them with
+    /// `bazel test`
+    #[arg(long)]
+    bazel: bool,
+    /// Run tests with `docker exec` in this running container instead of
+    /// locally
+    #[arg(long, value_name = "NAME", env = "INSTANTCOV_CONTAINER")]
+    container: Option<String>,
+    /// Hand each selection to a CI job by posting it to this URL instead of
+    /// running it locally
+    #[cfg(feature = "remote")]
+    #[arg(long, value_name = "URL")]
+    remote: Option<String>,
+    /// Print the selected test ids, one per line, instead of running them
+    #[arg(long)]
+    dry_run: bool,
+    /// Report format: `text`, `json` for one document per run, or `lcov`,
+    /// written to `lcov.info` by default
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_parser = parse_value::<OutputFormat>,
+        env = "INSTANTCOV_OUTPUT"
+    )]
+    output: Option<OutputFormat>,
+    /// Write the report to this file instead of stdout
+    #[arg(long, value_name = "PATH", env = "INSTANTCOV_OUTPUT_FILE")]
+    output_file: Option<PathBuf>,
+    /// Write an HTML page of the diff, coloured by coverage, into this
+    /// directory after every run
+    #[arg(long, value_name = "DIR", env = "INSTANTCOV_HTML")]
+    html: Option<PathBuf>,
+    /// Open the HTML page in the browser after every run
+    #[arg(long, requires = "html")]
+    open: bool,
+    /// Fail when patch coverage is below this percentage: `run` exits
+    /// non-zero and watch mode prints a banner
+    #[arg(long, value_name = "PERCENT", env = "INSTANTCOV_FAIL_UNDER")]
+    fail_under: Option<f64>,
+    /// Same as the `run` subcommand
+    #[arg(long)]
+    once: bool,
+    /// Run once against the pull request base in GitHub Actions, writing
+    /// annotations and a step summary
+    #[arg(long)]
+    github_pr: bool,
+    /// Run once against the merge request base in GitLab CI, writing a
+    /// Cobertura report of the changed lines
+    #[arg(long, conflicts_with = "github_pr")]
+    gitlab_mr: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-struct BetterDiff {
-    path: String,
-    start_offset: usize,
-    deletion_end: usize,
-    addition_end: usize,
-    start_point: Point,
-    addition_point: Point,
-    deletion_point: Point,
+#[derive(Subcommand)]
+enum Command {
+    /// Select and run tests once without watching, print patch coverage and
+    /// exit non-zero if the tests fail
+    Run,
+    /// Serve patch-coverage diagnostics to an editor over LSP on stdio
+    Lsp,
+    /// Serve selections and coverage to editor extensions over JSON-RPC on
+    /// stdio
+    Rpc,
+    /// Serve coverage marks to Neovim over msgpack-RPC on stdio, for
+    /// `jobstart` with `rpc`
+    Nvim,
+    /// Entry points called from git hooks
+    Hook {
+        #[command(subcommand)]
+        hook: Hook,
+    },
+    /// Run once and write the changed lines' coverage in Codecov's format
+    Codecov {
+        #[arg(long, default_value = "codecov.json")]
+        output: PathBuf,
+        /// Upload the report with codecovcli, comparing against the base
+        #[arg(long)]
+        upload: bool,
+        /// Codecov flag to tag the upload with
+        #[arg(long, default_value = codecov::DEFAULT_FLAG)]
+        flag: String,
+    },
+    /// Print the patch coverage of the changes between two revisions, read
+    /// from git without checking either out, against the coverage already
+    /// recorded
+    Diff {
+        #[arg(value_name = "FROM")]
+        from: String,
+        #[arg(value_name = "TO")]
+        to: String,
+    },
+    /// Install git hooks that call this tool
+    InstallHooks {
+        /// Replace existing hooks not written by this tool
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-impl std::fmt::Display for BetterDiff {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "BetterDiff: {} {} {} {}",
-            self.path, self.start_offset, self.deletion_end, self.addition_end,
-        )
-    }
+#[derive(Subcommand)]
+enum Hook {
+    /// Run the tests selected by the staged changes and block the commit if
+    /// they fail
+    PreCommit {
+        /// Also block the commit when patch coverage is below this percentage
+        #[arg(long, value_name = "PERCENT")]
+        fail_under: Option<f64>,
+    },
+    /// Wake running watchers so they rebaseline against the new commit
+    PostCommit,
+    /// Wake running watchers so they rebaseline after a merge or pull
+    PostMerge {
+        /// Whether the merge was a squash, as passed by git
+        squash: Option<u8>,
+    },
 }
 
-fn content_from_hunk(patch: &Patch, hunk_i: usize) -> (String, String, usize, usize) {
-    let mut addition = String::new();
-    let mut deletion = String::new();
-    let num_lines = patch.num_lines_in_hunk(hunk_i).unwrap();
-    let mut latest_addition: Option<String> = None;
-    let mut latest_deletion: Option<String> = None;
-    for line_i in 0..num_lines {
-        let line = patch.line_in_hunk(hunk_i, line_i).unwrap();
-        let string_to_push = std::str::from_utf8(line.content()).unwrap();
-        match line.origin_value() {
-            DiffLineType::Addition => {
-                addition.push_str(string_to_push);
-                latest_addition = Some(string_to_push.to_string());
-            }
-            DiffLineType::Deletion => {
-                deletion.push_str(string_to_push);
-                latest_deletion = Some(string_to_push.to_string());
-            }
-            _ => (),
-        }
-    }
-    let last_addition_len = match latest_addition {
-        Some(s) => s.len(),
-        None => 0,
+/// Reads a flag naming a variant of one of the library's enums.
+fn parse_value<T: for<'de> Deserialize<'de>>(name: &str) -> Result<T, String> {
+    T::deserialize(name.into_deserializer()).map_err(|e: serde::de::value::Error| e.to_string())
+}
+
+/// Logs go to stderr, leaving stdout to reports and the editor protocols.
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::ERROR,
+        (false, 0) => Level::WARN,
+        (false, 1) => Level::INFO,
+        (false, 2) => Level::DEBUG,
+        (false, _) => Level::TRACE,
     };
-    let last_deletion_len = match latest_deletion {
-        Some(s) => s.len(),
-        None => 0,
+    let span_events = match verbose {
+        0 => FmtSpan::NONE,
+        _ => FmtSpan::CLOSE,
     };
-    (addition, deletion, last_addition_len, last_deletion_len)
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(span_events)
+        .with_target(false)
+        .with_writer(io::stderr)
+        .init();
 }
 
-fn get_diff(repo: &Repository, commit: &Object) -> Vec<BetterDiff> {
-    let diffs = repo
-        .diff_tree_to_workdir(
-            Some(&commit.as_commit().unwrap().tree().unwrap()),
-            Some(&mut DiffOptions::new().context_lines(0)),
-        )
-        .unwrap();
-    let mut v = Vec::new();
-    for idx in 0..diffs.deltas().collect::<Vec<_>>().len() {
-        let patch = Patch::from_diff(&diffs, idx).unwrap().unwrap();
-        let path = patch.delta().old_file().path().unwrap();
-        let ext = path.extension();
-        match ext {
-            Some(extension) => {
-                if extension.to_str().unwrap() != "py" {
-                    continue;
-                }
-            }
-            None => continue,
-        }
-        for hunk_i in 0..patch.num_hunks() {
-            patch.hunk(hunk_i).unwrap().0.new_start();
-            // let num_lines = patch.num_lines_in_hunk(hunk_i).unwrap();
-            let (addition, deletion, addition_end_column, deletion_end_column) =
-                content_from_hunk(&patch, hunk_i);
-            let start_offset = patch.line_in_hunk(hunk_i, 0).unwrap().content_offset();
-            let addition_end = addition.len() + start_offset as usize;
-            let deletion_end = deletion.len() + start_offset as usize;
-            let start_point = (patch.hunk(hunk_i).unwrap().0.old_start(), 0);
-            let addition_point = (
-                patch.hunk(hunk_i).unwrap().0.new_lines() + start_point.0,
-                addition_end_column,
-            );
-            let deletion_point = (
-                patch.hunk(hunk_i).unwrap().0.old_lines() + start_point.0,
-                deletion_end_column,
+/// The engine `cli` and `config` describe for the checkout at `root`, with
+/// the other directories given and, with `--diff-from-stdin`, the diff read.
+fn configure(
+    cli: &Cli,
+    root: &Path,
+    watch_roots: &[PathBuf],
+    config: &Config,
+    patch: Option<&[FilePatch]>,
+) -> EngineBuilder {
+    let base_mode = cli.base_mode.or(config.base_mode).unwrap_or_default();
+    let base = cli
+        .base
+        .clone()
+        .or_else(|| config.base.clone())
+        .unwrap_or_else(|| base_mode.default_base().to_string());
+    let mut builder = EngineBuilder::new(root)
+        .config(config)
+        .base(&base)
+        .base_mode(base_mode)
+        .no_baseline_fallback(cli.no_baseline)
+        .dry_run(cli.dry_run);
+    for dir in watch_roots {
+        builder = builder.watch_root(dir);
+    }
+    if cli.staged {
+        builder = builder.staged(true);
+    }
+    if cli.unstaged {
+        builder = builder.unstaged(true);
+    }
+    if let Some(files) = patch {
+        builder = builder.vcs(PatchVcs::new(root, files.to_vec()));
+    }
+    if let Some(command) = &cli.test_command {
+        builder = builder.command_template(command);
+    }
+    if let Some(language) = cli.language {
+        builder = builder.language(language);
+    }
+    for pattern in &cli.include {
+        builder = builder.include(pattern);
+    }
+    for pattern in &cli.exclude {
+        builder = builder.exclude(pattern);
+    }
+    for pattern in &cli.test_names {
+        builder = builder.test_name(pattern);
+    }
+    if let Some(format) = cli.output {
+        builder = builder.output(format);
+    }
+    if let Some(path) = &cli.output_file {
+        builder = builder.output_file(path);
+    }
+    if let Some(dir) = &cli.html {
+        builder = builder.html_dir(dir).open_html(cli.open);
+    }
+    if let Some(percent) = cli.fail_under {
+        builder = builder.fail_under(percent);
+    }
+    if let Some(debounce) = cli.debounce {
+        builder = builder.debounce(Duration::from_millis(debounce));
+    }
+    if let Some(poll) = cli.poll {
+        builder = builder.poll(Duration::from_millis(poll));
+    }
+    if let Some(depth) = cli.max_depth {
+        builder = builder.watch_max_depth(depth);
+    }
+    if cli.bazel {
+        builder = builder.impact_strategy(ImpactStrategy::Bazel);
+    }
+    if let Some(container) = &cli.container {
+        builder = builder.container(container);
+    }
+    #[cfg(feature = "otlp")]
+    let builder = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => builder.otlp_endpoint(endpoint),
+        Err(_) => builder,
+    };
+    #[cfg(feature = "sentry")]
+    let builder = match std::env::var("SENTRY_DSN") {
+        Ok(dsn) => builder.sentry_dsn(dsn),
+        Err(_) => builder,
+    };
+    #[cfg(feature = "remote")]
+    let builder = match &cli.remote {
+        Some(url) => {
+            let runner = hackweek_instant_codecoverage::remote::RemoteRunner::new(
+                root,
+                base.as_str(),
+                url.as_str(),
             );
-            v.push(BetterDiff {
-                path: patch
-                    .delta()
-                    .old_file()
-                    .path()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-                start_offset: start_offset as usize,
-                addition_end: addition_end,
-                deletion_end: deletion_end,
-                start_point: Point {
-                    row: start_point.0 as usize,
-                    column: start_point.1,
-                },
-                addition_point: Point {
-                    row: addition_point.0 as usize,
-                    column: addition_point.1,
-                },
-                deletion_point: Point {
-                    row: deletion_point.0 as usize,
-                    column: deletion_point.1,
-                },
-            });
-            let num_lines = patch.num_lines_in_hunk(hunk_i).unwrap();
+            builder.runner(match std::env::var("INSTANT_PATCH_REMOTE_TOKEN") {
+                Ok(token) => runner.token(token),
+                Err(_) => runner,
+            })
         }
-    }
-    v
+        None => builder,
+    };
+    builder
 }
 
-pub fn print_tree(
-    content_map: HashMap<String, String>,
-    tree_map: HashMap<String, Tree>,
-) -> Vec<String> {
-    let mut ret: Vec<String> = Vec::new();
-    tree_map.iter().for_each(|(path, tree)| {
-        let mut cursor = tree.walk();
-        'outer: loop {
-            if cursor.node().is_named() {
-                match cursor.node().kind() {
-                    "function_definition" => {
-                        if cursor
-                            .node()
-                            .child_by_field_name("name")
-                            .unwrap()
-                            .utf8_text(content_map[path].as_bytes())
-                            .unwrap()
-                            .starts_with("test")
-                        {
-                            println!(
-                                "{:?} {:?} {:?}",
-                                cursor.node(),
-                                cursor
-                                    .node()
-                                    .utf8_text(content_map[path].as_bytes())
-                                    .unwrap(),
-                                cursor
-                                    .node()
-                                    .named_children(&mut tree.walk())
-                                    .collect::<Vec<_>>() // cursor
-                                                         //     .node()
-                                                         //     .child_by_field_name("name")
-                                                         //     .unwrap()
-                                                         //     .utf8_text(file_content_map[path].as_bytes())
-                            );
-                            ret.push(
-                                cursor
-                                    .node()
-                                    .child_by_field_name("name")
-                                    .unwrap()
-                                    .utf8_text(content_map[path].as_bytes())
-                                    .unwrap()
-                                    .to_string(),
-                            )
-                        }
-                    }
-                    _ => (),
-                }
-            }
-
-            if cursor.goto_first_child() || cursor.goto_next_sibling() {
-                continue;
-            }
-
-            loop {
-                if !cursor.goto_parent() {
-                    break 'outer;
-                }
-                if cursor.goto_next_sibling() {
-                    break;
+/// Watches with `builder`'s settings. Whenever `CONFIG_FILE` changes, the
+/// engine is built again from `configure` and the new file and runs once to
+/// apply it; a file that does not parse or build keeps the settings in use.
+fn watch_reloading<F: Fn(&Config) -> EngineBuilder>(
+    root: &Path,
+    builder: EngineBuilder,
+    configure: F,
+) -> Result<(), EngineError> {
+    let control = WatchControl::default();
+    #[cfg(unix)]
+    if let Err(e) = watch::toggle_on_signal(&control) {
+        tracing::warn!("cannot pause on SIGUSR1: {}", e);
+    }
+    if io::stdin().is_terminal() {
+        let control = control.clone();
+        thread::spawn(move || watch::read_commands(io::stdin().lock(), &control));
+    }
+    let build = |builder: EngineBuilder| {
+        builder
+            .state_file(true)
+            .impact_cache(true)
+            .reload_config(true)
+            .watch_control(control.clone())
+            .build()
+            .map(Arc::new)
+    };
+    let mut engine = build(builder)?;
+    loop {
+        engine.watch()?;
+        let reloaded = match Config::discover(root) {
+            Ok(config) => build(configure(&config.unwrap_or_default())).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match reloaded {
+            Ok(reloaded) => {
+                engine = reloaded;
+                if let Err(e) = engine.run_once() {
+                    tracing::error!("{}", e);
                 }
             }
+            Err(e) => tracing::error!("{}: {}; keeping the previous settings", CONFIG_FILE, e),
         }
-    });
-    ret
-}
-
-fn get_tests(
-    content_map: HashMap<String, String>,
-    tree_map: &HashMap<String, Tree>,
-) -> HashSet<String> {
-    let mut v: HashSet<String> = HashSet::new();
-    for (path, tree) in tree_map {
-        let mut q = Query::new(
-            tree_sitter_python::language(),
-            "(function_definition (identifier)@b ) @a",
-        )
-        .unwrap();
-        let mut qc = QueryCursor::new();
-        let qm = qc.matches(&mut q, tree.root_node(), content_map[path].as_bytes());
-        qm.for_each(|query_match| {
-            query_match
-                .captures
-                .iter()
-                .for_each(|capture: &QueryCapture| {
-                    let function_name = capture
-                        .node
-                        .utf8_text(content_map[path].as_bytes())
-                        .unwrap();
-                    if function_name.starts_with("test") {
-                        v.insert(format!("{}::{}", path, function_name.to_string()));
-                    }
-                })
-        });
     }
-    v
 }
 
-fn create_old_content_map(repo: &Repository, commit: &Object) -> HashMap<String, String> {
-    let mut old_content_map: HashMap<String, String> = HashMap::new();
-
-    commit
-        .as_commit()
-        .unwrap()
-        .tree()
-        .unwrap()
-        .walk(git2::TreeWalkMode::PreOrder, |s, entry| {
-            let o = entry.to_object(repo).unwrap();
-            if entry.kind().unwrap() == ObjectType::Blob && entry.name().unwrap().ends_with("py") {
-                let content = String::from_utf8(o.as_blob().unwrap().content().to_vec());
-                let path = match s.is_empty() {
-                    true => entry.name().unwrap().to_string(),
-                    false => format!("{}/{}", s, entry.name().unwrap()),
-                };
-                old_content_map.insert(path, content.unwrap());
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    init_logging(cli.verbose, cli.quiet);
+    let mut paths = cli.paths.iter().cloned();
+    let root = cli
+        .root
+        .clone()
+        .or_else(|| paths.next())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let mut watch_roots = Vec::new();
+    for dir in std::iter::once(root.clone()).chain(paths) {
+        match dir.canonicalize() {
+            Ok(canonical) if canonical.is_dir() => watch_roots.push(canonical),
+            _ => {
+                eprintln!("error: {} is not a directory", dir.display());
+                return ExitCode::FAILURE;
             }
-            return 0;
-        })
-        .unwrap();
-    old_content_map
-}
-
-fn create_new_content_map() -> HashMap<String, String> {
-    let mut new_content_map = HashMap::new();
-    let globbed = match glob("**/*.py") {
-        Err(_) => {
-            panic!("A");
         }
-        Ok(paths) => paths,
-    };
-
-    for entry in globbed {
-        let path = match entry {
-            Err(_) => panic!("B"),
-            Ok(pathbuf) => String::from(pathbuf.to_str().unwrap()),
-        };
-        let content = fs::read_to_string(path.clone()).unwrap();
-        new_content_map.insert(path, content);
-    }
-    new_content_map
-}
-
-fn create_parser() -> tree_sitter::Parser {
-    let mut parser = tree_sitter::Parser::new();
-    parser
-        .set_language(tree_sitter_python::language())
-        .expect("Error loading Python grammar");
-    parser
-}
-
-fn edit_tree(vd: Vec<BetterDiff>, tree_map: &mut HashMap<String, Tree>) {
-    for d in vd {
-        let t = tree_map.get_mut(&d.path).unwrap();
-        t.edit(&InputEdit {
-            start_byte: d.start_offset,
-            old_end_byte: d.deletion_end,
-            new_end_byte: d.addition_end,
-            start_position: d.start_point,
-            old_end_position: d.deletion_point,
-            new_end_position: d.addition_point,
-        });
     }
-}
-
-fn main() {
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    // no specific tickrate, max debounce time 2 seconds
-    let mut debouncer = new_debouncer(Duration::from_secs(2), None, tx).unwrap();
-
-    debouncer
-        .watcher()
-        .watch(Path::new("."), RecursiveMode::Recursive)
-        .unwrap();
-
-    debouncer
-        .cache()
-        .add_root(Path::new("."), RecursiveMode::Recursive);
-
-    // print all events and errors
-    for result in rx {
-        match result {
-            Ok(events) => {
-                if events.iter().any(|event| {
-                    event
-                        .paths
-                        .iter()
-                        .any(|path| path.extension().unwrap_or(OsStr::new("")) == "py")
-                }) {
-                    on_fs_event();
-                };
+    let root = vcs::checkout_root(&root);
+    let root = root.as_path();
+    let config = match Config::discover(root) {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("error: {}: {}", CONFIG_FILE, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let patch = match cli.diff_from_stdin {
+        true => match io::read_to_string(io::stdin()) {
+            Ok(text) => match patch::parse(&text) {
+                Ok(files) => Some(files),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            },
+            Err(e) => {
+                eprintln!("error: cannot read the diff from stdin: {}", e);
+                return ExitCode::FAILURE;
             }
-            Err(errors) => errors.iter().for_each(|error| println!("{error:?}")),
+        },
+        false => None,
+    };
+    let command = match cli.command.take() {
+        None if cli.once => Some(Command::Run),
+        command => command,
+    };
+    let builder_for =
+        |config: &Config| configure(&cli, root, &watch_roots[1..], config, patch.as_deref());
+    let builder = builder_for(&config);
+    let result = match command {
+        None if cli.github_pr => {
+            github::check_pull_request(root, builder).map(|passed| match passed {
+                true => ExitCode::SUCCESS,
+                false => ExitCode::FAILURE,
+            })
         }
-    }
-
-    return;
-}
-
-fn on_fs_event() {
-    let mut tree_map: HashMap<String, Tree> = HashMap::new();
-
-    let repo: Repository = match Repository::open(".") {
-        Ok(repo) => repo,
-        Err(e) => panic!("failed to open: {}", e),
+        None if cli.gitlab_mr => {
+            gitlab::check_merge_request(root, builder).map(|passed| match passed {
+                true => ExitCode::SUCCESS,
+                false => ExitCode::FAILURE,
+            })
+        }
+        None => watch_reloading(root, builder, builder_for).map(|_| ExitCode::SUCCESS),
+        Some(Command::Run) => builder.build().and_then(|engine| {
+            let result = engine.run_once()?;
+            Ok(
+                match result.success() && (!result.executed || engine.coverage_ok()?) {
+                    true => ExitCode::SUCCESS,
+                    false => ExitCode::FAILURE,
+                },
+            )
+        }),
+        Some(Command::Lsp) => builder.build().and_then(|engine| {
+            lsp::serve(&engine, io::stdin().lock(), io::stdout().lock())?;
+            Ok(ExitCode::SUCCESS)
+        }),
+        Some(Command::Rpc) => builder.build().and_then(|engine| {
+            let server = RpcServer::new(Arc::new(engine), io::stdout());
+            server.spawn_watcher();
+            server.serve(io::stdin().lock())?;
+            Ok(ExitCode::SUCCESS)
+        }),
+        Some(Command::Nvim) => builder.build().and_then(|engine| {
+            let server = NvimServer::new(Arc::new(engine), io::stdout());
+            server.spawn_watcher();
+            server.serve(io::stdin().lock())?;
+            Ok(ExitCode::SUCCESS)
+        }),
+        Some(Command::Hook {
+            hook: Hook::PreCommit { fail_under },
+        }) => builder.staged(true).build().and_then(|engine| {
+            let report = hooks::pre_commit(&engine, fail_under.or(cli.fail_under))?;
+            if report.passed() {
+                return Ok(ExitCode::SUCCESS);
+            }
+            eprintln!("commit blocked:\n{}", report.summary());
+            Ok(ExitCode::FAILURE)
+        }),
+        Some(Command::Hook {
+            hook: Hook::PostCommit | Hook::PostMerge { .. },
+        }) => hooks::rebaseline(root)
+            .map(|_| ExitCode::SUCCESS)
+            .map_err(EngineError::from),
+        Some(Command::Codecov {
+            output,
+            upload,
+            flag,
+        }) => builder.build().and_then(|engine| {
+            match codecov::report(&engine, &output, upload, &flag)? {
+                true => Ok(ExitCode::SUCCESS),
+                false => Ok(ExitCode::FAILURE),
+            }
+        }),
+        Some(Command::Diff { from, to }) => builder.build().and_then(|engine| {
+            let coverage = engine.patch_coverage_between(&from, &to)?;
+            report::print_coverage(&coverage);
+            Ok(match cli.fail_under.is_none_or(|t| coverage.meets(t)) {
+                true => ExitCode::SUCCESS,
+                false => ExitCode::FAILURE,
+            })
+        }),
+        Some(Command::InstallHooks { force }) => hooks::install(root, force)
+            .map(|hooks| {
+                for hook in hooks {
+                    println!("installed {}", hook.display());
+                }
+                ExitCode::SUCCESS
+            })
+            .map_err(EngineError::from),
     };
-    let commit = repo.revparse_single("HEAD").unwrap();
-
-    let old_content_map = create_old_content_map(&repo, &commit);
-    let new_content_map = create_new_content_map();
-
-    let mut parser = create_parser();
-
-    for (path, content) in &old_content_map {
-        let tree = parser.parse(content, None).unwrap();
-        tree_map.insert(path.to_string(), tree);
-    }
-
-    let old_tests = get_tests(old_content_map.clone(), &tree_map);
-
-    let vd = get_diff(&repo, &commit);
-
-    edit_tree(vd, &mut tree_map);
-
-    for (path, content) in &new_content_map {
-        let tree = parser.parse(content, None).unwrap();
-        tree_map.insert(path.to_string(), tree);
+    match result {
+        Ok(code) => code,
+        Err(e @ EngineError::NotARepository(_)) => {
+            eprintln!("error: {}", e);
+            eprintln!("pass the path of a git checkout, or --no-baseline to run every test");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
     }
-
-    let new_tests = get_tests(new_content_map, &tree_map);
-
-    let hs_diff = new_tests.difference(&old_tests);
-
-    let mut tests_to_run = String::new();
-    hs_diff.for_each(|diff| tests_to_run.push_str(diff.as_str()));
-
-    println!("Running {}", tests_to_run);
-
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(format!("coverage run -m pytest {}", tests_to_run))
-        .output()
-        .expect("failed to execute process");
-    let hello = output.stdout;
-    println!("{}", String::from_utf8(hello).unwrap());
 }
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("failed to export metrics to {endpoint}: {message}")]
+    Export { endpoint: String, message: String },
+}
+
+/// Counters describing how the engine behaves over its lifetime. Collection
+/// is always on and lock free; exporting them is opt-in.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    cycles: AtomicU64,
+    failed_cycles: AtomicU64,
+    tests_selected: AtomicU64,
+    baseline_hits: AtomicU64,
+    baseline_misses: AtomicU64,
+    cycle_micros_total: AtomicU64,
+    last_cycle_micros: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    pub cycles: u64,
+    pub failed_cycles: u64,
+    pub tests_selected: u64,
+    pub baseline_hits: u64,
+    pub baseline_misses: u64,
+    pub cycle_micros_total: u64,
+    pub last_cycle_micros: u64,
+}
+
+impl MetricsSnapshot {
+    pub fn baseline_hit_rate(&self) -> Option<f64> {
+        match self.baseline_hits + self.baseline_misses {
+            0 => None,
+            total => Some(self.baseline_hits as f64 / total as f64),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_cycle(&self, elapsed: Duration, tests_selected: usize, ok: bool) {
+        let micros = elapsed.as_micros() as u64;
+        self.cycles.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            self.failed_cycles.fetch_add(1, Ordering::Relaxed);
+        }
+        self.tests_selected
+            .fetch_add(tests_selected as u64, Ordering::Relaxed);
+        self.cycle_micros_total.fetch_add(micros, Ordering::Relaxed);
+        self.last_cycle_micros.store(micros, Ordering::Relaxed);
+    }
+
+    pub fn record_baseline(&self, hit: bool) {
+        match hit {
+            true => self.baseline_hits.fetch_add(1, Ordering::Relaxed),
+            false => self.baseline_misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            cycles: self.cycles.load(Ordering::Relaxed),
+            failed_cycles: self.failed_cycles.load(Ordering::Relaxed),
+            tests_selected: self.tests_selected.load(Ordering::Relaxed),
+            baseline_hits: self.baseline_hits.load(Ordering::Relaxed),
+            baseline_misses: self.baseline_misses.load(Ordering::Relaxed),
+            cycle_micros_total: self.cycle_micros_total.load(Ordering::Relaxed),
+            last_cycle_micros: self.last_cycle_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn counter(name: &str, unit: &str, value: u64, now: &str) -> Value {
+    json!({
+        "name": name,
+        "unit": unit,
+        "sum": {
+            "aggregationTemporality": 2,
+            "isMonotonic": true,
+            "dataPoints": [{ "asInt": value.to_string(), "timeUnixNano": now }],
+        },
+    })
+}
+
+fn gauge(name: &str, unit: &str, value: u64, now: &str) -> Value {
+    json!({
+        "name": name,
+        "unit": unit,
+        "gauge": {
+            "dataPoints": [{ "asInt": value.to_string(), "timeUnixNano": now }],
+        },
+    })
+}
+
+/// Renders a snapshot as an OTLP/JSON `ExportMetricsServiceRequest`.
+pub fn otlp_payload(service_name: &str, snapshot: &MetricsSnapshot) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string();
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "metrics": [
+                    counter("instant_patch.cycles", "1", snapshot.cycles, &now),
+                    counter("instant_patch.cycles.failed", "1", snapshot.failed_cycles, &now),
+                    counter("instant_patch.tests.selected", "1", snapshot.tests_selected, &now),
+                    counter("instant_patch.baseline.hits", "1", snapshot.baseline_hits, &now),
+                    counter("instant_patch.baseline.misses", "1", snapshot.baseline_misses, &now),
+                    counter("instant_patch.cycle.duration", "us", snapshot.cycle_micros_total, &now),
+                    gauge("instant_patch.cycle.last_duration", "us", snapshot.last_cycle_micros, &now),
+                ],
+            }],
+        }],
+    })
+}
+
+/// Posts a snapshot to an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+#[cfg(feature = "otlp")]
+pub fn export_otlp(
+    endpoint: &str,
+    service_name: &str,
+    snapshot: &MetricsSnapshot,
+) -> Result<(), MetricsError> {
+    let url = format!("{}/v1/metrics", endpoint.trim_end_matches('/'));
+    ureq::post(&url)
+        .send_json(otlp_payload(service_name, snapshot))
+        .map_err(|e| MetricsError::Export {
+            endpoint: url.clone(),
+            message: e.to_string(),
+        })?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use rmpv::Value;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use thiserror::Error;
+
+use crate::coverage::PatchCoverage;
+use crate::engine::Engine;
+use crate::runner::RunResult;
+use crate::selection::{SelectedTest, Selection};
+use crate::watch;
+
+const REQUEST: u64 = 0;
+const RESPONSE: u64 = 1;
+const NOTIFICATION: u64 = 2;
+
+/// Applied by Neovim with `nvim_exec_lua(LUA, [root, files])`: replaces the
+/// extmarks in the `instant_patch` namespace of every loaded buffer under
+/// `root` with signs for changed lines and virtual text for failing tests.
+const LUA: &str = r#"
+local root, files = ...
+local ns = vim.api.nvim_create_namespace('instant_patch')
+local buffers = {}
+for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+  vim.api.nvim_buf_clear_namespace(buf, ns, 0, -1)
+  if vim.api.nvim_buf_is_loaded(buf) then
+    buffers[vim.api.nvim_buf_get_name(buf)] = buf
+  end
+end
+for _, file in ipairs(files) do
+  local buf = buffers[root .. '/' .. file.path]
+  if buf then
+    local count = vim.api.nvim_buf_line_count(buf)
+    local function mark(line, opts)
+      if line >= 1 and line <= count then
+        vim.api.nvim_buf_set_extmark(buf, ns, line - 1, 0, opts)
+      end
+    end
+    for _, line in ipairs(file.covered) do
+      mark(line, { sign_text = '▎', sign_hl_group = 'DiffAdd' })
+    end
+    for _, line in ipairs(file.uncovered) do
+      mark(line, { sign_text = '▎', sign_hl_group = 'DiagnosticWarn' })
+    end
+    for _, test in ipairs(file.failing) do
+      mark(test.line, {
+        sign_text = '✗',
+        sign_hl_group = 'DiagnosticError',
+        virt_text = { { 'failed: ' .. test.id, 'DiagnosticError' } },
+      })
+    end
+  end
+end
+"#;
+
+#[derive(Debug, Error)]
+pub enum NvimError {
+    #[error("failed to read message: {0}")]
+    Decode(#[from] rmpv::decode::Error),
+    #[error("failed to write message: {0}")]
+    Encode(#[from] rmpv::encode::Error),
+    #[error("invalid msgpack-RPC message: {0}")]
+    InvalidMessage(String),
+}
+
+/// A test that failed in the last run, located at its definition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailingTest {
+    pub id: String,
+    pub path: String,
+    /// 1-based line of the test's `def`, or 1 when it cannot be found.
+    pub line: usize,
+}
+
+/// Everything drawn in one file. Lines are 1-based.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FileMarks {
+    pub path: String,
+    pub covered: Vec<usize>,
+    pub uncovered: Vec<usize>,
+    pub failing: Vec<FailingTest>,
+}
+
+/// Finds the failed tests and the line each one is defined on. Outcomes
+/// reported by the pytest plugin are used when present, otherwise the
+/// `FAILED` lines of pytest's short summary.
+pub fn failing_tests(root: &Path, result: &RunResult) -> Vec<FailingTest> {
+    let ids: Vec<&str> = match result.tests.is_empty() {
+        false => result.failed_tests(),
+        true => result
+            .stdout
+            .lines()
+            .filter_map(|line| line.strip_prefix("FAILED "))
+            .map(|rest| rest.split(" - ").next().unwrap_or(rest).trim())
+            .collect(),
+    };
+    let mut failing: Vec<FailingTest> = Vec::new();
+    for id in ids {
+        if failing.iter().any(|test| test.id == id) {
+            continue;
+        }
+        let path = id.split("::").next().unwrap_or(id);
+        let name = id.rsplit("::").next().unwrap_or(id);
+        let name = name.split('[').next().unwrap_or(name);
+        let line = fs::read_to_string(root.join(path))
+            .ok()
+            .and_then(|source| definition_line(&source, name))
+            .unwrap_or(1);
+        failing.push(FailingTest {
+            id: id.to_string(),
+            path: path.to_string(),
+            line,
+        });
+    }
+    failing
+}
+
+fn definition_line(source: &str, name: &str) -> Option<usize> {
+    let def = format!("def {}(", name);
+    source
+        .lines()
+        .position(|line| {
+            let line = line.trim_start();
+            line.strip_prefix("async ")
+                .unwrap_or(line)
+                .starts_with(&def)
+        })
+        .map(|row| row + 1)
+}
+
+/// Groups changed-line coverage and failing tests by file, sorted by path.
+pub fn marks(coverage: &PatchCoverage, failing: &[FailingTest]) -> Vec<FileMarks> {
+    let mut files: BTreeMap<String, FileMarks> = BTreeMap::new();
+    for file in &coverage.files {
+        let marks = files.entry(file.path.clone()).or_default();
+        marks.covered = file.covered_lines.clone();
+        marks.uncovered = file.uncovered_lines();
+    }
+    for test in failing {
+        files
+            .entry(test.path.clone())
+            .or_default()
+            .failing
+            .push(test.clone());
+    }
+    files
+        .into_iter()
+        .map(|(path, marks)| FileMarks { path, ..marks })
+        .collect()
+}
+
+/// The notification that has Neovim redraw `files`, for a repository checked
+/// out at `root`.
+pub fn marks_notification(root: &Path, files: &[FileMarks]) -> Value {
+    let files = to_msgpack(&serde_json::json!(files));
+    Value::Array(vec![
+        NOTIFICATION.into(),
+        "nvim_exec_lua".into(),
+        Value::Array(vec![
+            LUA.into(),
+            Value::Array(vec![root.to_string_lossy().as_ref().into(), files]),
+        ]),
+    ])
+}
+
+struct Incoming {
+    id: Option<u64>,
+    method: String,
+    params: Vec<Value>,
+}
+
+/// Reads the next request or notification, skipping responses. Returns
+/// `None` at end of input.
+fn read_message<R: Read>(input: &mut R) -> Result<Option<Incoming>, NvimError> {
+    loop {
+        let message = match rmpv::decode::read_value(input) {
+            Ok(message) => message,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = || NvimError::InvalidMessage(message.to_string());
+        let parts = message.as_array().ok_or_else(invalid)?;
+        let (id, method, params) = match parts.first().and_then(Value::as_u64) {
+            Some(REQUEST) if parts.len() == 4 => (parts[1].as_u64(), &parts[2], &parts[3]),
+            Some(NOTIFICATION) if parts.len() == 3 => (None, &parts[1], &parts[2]),
+            Some(RESPONSE) => continue,
+            _ => return Err(invalid()),
+        };
+        return Ok(Some(Incoming {
+            id,
+            method: method.as_str().ok_or_else(invalid)?.to_string(),
+            params: params.as_array().cloned().unwrap_or_default(),
+        }));
+    }
+}
+
+/// Serves the engine to Neovim over msgpack-RPC, the protocol of
+/// `jobstart(..., {'rpc': v:true})`.
+///
+/// Requests (`rpcrequest(chan, method, ...)`): `select`, `marks`, and `run`
+/// with an optional list of test ids. `refresh` may also be sent as a
+/// notification. The server redraws the editor itself by notifying
+/// `nvim_exec_lua` after every run and file change, so no plugin is needed
+/// on the Neovim side beyond starting the job.
+pub struct NvimServer<W> {
+    engine: Arc<Engine>,
+    root: PathBuf,
+    output: Arc<Mutex<W>>,
+    /// Failures from the last run, kept so later redraws still show them.
+    failing: Arc<Mutex<Vec<FailingTest>>>,
+}
+
+impl<W: Write + Send + 'static> NvimServer<W> {
+    pub fn new(engine: Arc<Engine>, output: W) -> NvimServer<W> {
+        let root = engine
+            .root()
+            .canonicalize()
+            .unwrap_or_else(|_| engine.root().to_path_buf());
+        NvimServer {
+            engine,
+            root,
+            output: Arc::new(Mutex::new(output)),
+            failing: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Watches the working tree on a background thread, redrawing the marks
+    /// whenever it changes.
+    pub fn spawn_watcher(&self) -> JoinHandle<()> {
+        let server = NvimServer {
+            engine: self.engine.clone(),
+            root: self.root.clone(),
+            output: self.output.clone(),
+            failing: self.failing.clone(),
+        };
+        thread::spawn(move || {
+            let engine = server.engine.clone();
+            let watched = engine.clone();
+            let result = watch::watch(
+                &engine.watch_roots(),
+                engine.debounce(),
+                engine.watch_ignore(),
+                engine.poll_interval(),
+                engine.watch_control(),
+                move |path| watched.is_watched(path),
+                move |_| {
+                    if let Err(e) = server.refresh() {
+                        tracing::error!("{}", e);
+                    }
+                },
+            );
+            if let Err(e) = result {
+                tracing::error!("{}", e);
+            }
+        })
+    }
+
+    /// Handles messages from `input` until end of input.
+    pub fn serve<R: Read>(&self, mut input: R) -> Result<(), NvimError> {
+        while let Some(message) = read_message(&mut input)? {
+            let result = match message.method.as_str() {
+                "select" => self
+                    .engine
+                    .select()
+                    .map_err(|e| e.to_string())
+                    .and_then(|selection| to_value(&selection)),
+                "marks" => self.marks().and_then(|files| to_value(&files)),
+                "run" => self.run(&message.params),
+                "refresh" => self.refresh().map(|_| Value::Nil),
+                method => Err(format!("unknown method {}", method)),
+            };
+            if let Some(id) = message.id {
+                let (error, result) = match result {
+                    Ok(result) => (Value::Nil, result),
+                    Err(error) => (error.into(), Value::Nil),
+                };
+                send(
+                    &self.output,
+                    &Value::Array(vec![RESPONSE.into(), id.into(), error, result]),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn marks(&self) -> Result<Vec<FileMarks>, String> {
+        let coverage = self.engine.patch_coverage().map_err(|e| e.to_string())?;
+        let failing = self.failing.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(marks(&coverage, &failing))
+    }
+
+    fn refresh(&self) -> Result<(), String> {
+        let files = self.marks()?;
+        send(&self.output, &marks_notification(&self.root, &files)).map_err(|e| e.to_string())
+    }
+
+    fn run(&self, params: &[Value]) -> Result<Value, String> {
+        let selection = match params.first().and_then(Value::as_array) {
+            Some(ids) => Selection::new(
+                ids.iter()
+                    .filter_map(Value::as_str)
+                    .map(|id| SelectedTest::new(id, "requested"))
+                    .collect(),
+            ),
+            None => self.engine.select().map_err(|e| e.to_string())?,
+        };
+        let result = self.engine.run(&selection).map_err(|e| e.to_string())?;
+        *self.failing.lock().unwrap_or_else(|e| e.into_inner()) =
+            failing_tests(&self.root, &result);
+        self.refresh()?;
+        to_value(&result)
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, String> {
+    serde_json::to_value(value)
+        .map(|value| to_msgpack(&value))
+        .map_err(|e| e.to_string())
+}
+
+/// Converts through JSON so structs become maps, which is what Lua expects;
+/// msgpack serializers default to encoding them as arrays.
+fn to_msgpack(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) => match (n.as_u64(), n.as_i64(), n.as_f64()) {
+            (Some(n), _, _) => n.into(),
+            (None, Some(n), _) => n.into(),
+            (None, None, n) => n.unwrap_or_default().into(),
+        },
+        serde_json::Value::String(s) => s.as_str().into(),
+        serde_json::Value::Array(values) => Value::Array(values.iter().map(to_msgpack).collect()),
+        serde_json::Value::Object(map) => Value::Map(
+            map.iter()
+                .map(|(key, value)| (key.as_str().into(), to_msgpack(value)))
+                .collect(),
+        ),
+    }
+}
+
+fn send<W: Write>(output: &Mutex<W>, message: &Value) -> Result<(), NvimError> {
+    let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+    rmpv::encode::write_value(&mut *output, message)?;
+    output
+        .flush()
+        .map_err(rmpv::encode::Error::InvalidDataWrite)?;
+    Ok(())
+}
@@ -0,0 +1,366 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::diff::{diff_contents, BetterDiff, DiffError};
+use crate::discovery::{create_new_content_map, refresh_content_map, FileFailure, PathFilter};
+use crate::engine::EngineError;
+use crate::language::Language;
+use crate::vcs::Vcs;
+
+/// The only revision a patch has: the files before it was applied.
+pub const PATCH_REV: &str = "patch";
+
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("line {line} of the diff: {message}")]
+    Malformed { line: usize, message: String },
+    #[error("{path} does not match the diff at line {line}")]
+    Mismatch { path: String, line: usize },
+}
+
+/// One line of a hunk, without its `' '`, `-` or `+` marker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// A `@@ -old_start,old_lines +new_start,new_lines @@` section. Line
+/// texts keep their line ending, so a line followed by `\ No newline at end
+/// of file` has none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchHunk {
+    pub old_start: usize,
+    pub new_start: usize,
+    pub lines: Vec<PatchLine>,
+}
+
+/// The changes a diff makes to one file. A path is `None` on the side where
+/// the file does not exist, as `/dev/null` says.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<PatchHunk>,
+}
+
+impl FilePatch {
+    /// The path the file is known by after the change, or before it for a
+    /// deleted file.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The file before the change, worked out by undoing the hunks on
+    /// `new`, its content after them. A deleted file is rebuilt from its
+    /// removed lines alone.
+    pub fn revert(&self, new: &str) -> Result<String, PatchError> {
+        let mismatch = |line: usize| PatchError::Mismatch {
+            path: self.path().to_string(),
+            line,
+        };
+        let lines: Vec<&str> = new.split_inclusive('\n').collect();
+        let mut old = String::new();
+        let mut next = 0;
+        for hunk in &self.hunks {
+            // a hunk adding nothing starts after its line, not on it
+            let adds = hunk
+                .lines
+                .iter()
+                .any(|line| !matches!(line, PatchLine::Removed(_)));
+            let start = match adds {
+                true => hunk.new_start.saturating_sub(1),
+                false => hunk.new_start,
+            };
+            if start < next || start > lines.len() {
+                return Err(mismatch(hunk.new_start));
+            }
+            old.extend(lines[next..start].iter().copied());
+            next = start;
+            for line in &hunk.lines {
+                match line {
+                    PatchLine::Removed(text) => old.push_str(text),
+                    PatchLine::Context(text) | PatchLine::Added(text) => {
+                        if lines.get(next) != Some(&text.as_str()) {
+                            return Err(mismatch(next + 1));
+                        }
+                        if let PatchLine::Context(text) = line {
+                            old.push_str(text);
+                        }
+                        next += 1;
+                    }
+                }
+            }
+        }
+        old.extend(lines[next..].iter().copied());
+        Ok(old)
+    }
+}
+
+/// Reads a unified diff, as `git diff`, `hg diff` or `diff -u` print it,
+/// into the changes it makes to each file. Headers other than the `---`
+/// and `+++` lines and git's `rename from`/`rename to` are skipped, so
+/// binary changes and mode changes are left out.
+pub fn parse(text: &str) -> Result<Vec<FilePatch>, PatchError> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut current: Option<FilePatch> = None;
+    let mut lines = text.split_inclusive('\n').enumerate().peekable();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        let malformed = |message: &str| PatchError::Malformed {
+            line: number,
+            message: message.to_string(),
+        };
+        let content = line.trim_end_matches(['\n', '\r']);
+        if content.starts_with("diff ") {
+            files.extend(current.take());
+            current = Some(FilePatch::default());
+        } else if let Some(path) = content.strip_prefix("rename from ") {
+            current.get_or_insert_with(FilePatch::default).old_path = Some(path.to_string());
+        } else if let Some(path) = content.strip_prefix("rename to ") {
+            current.get_or_insert_with(FilePatch::default).new_path = Some(path.to_string());
+        } else if let Some(header) = content.strip_prefix("--- ") {
+            // `diff -u` output has no `diff` line between files
+            if current.as_ref().is_some_and(|file| !file.hunks.is_empty()) {
+                files.extend(current.take());
+            }
+            let Some((_, new)) = lines.next_if(|(_, line)| line.starts_with("+++ ")) else {
+                return Err(malformed("`---` is not followed by `+++`"));
+            };
+            let new = new.trim_end_matches(['\n', '\r']);
+            let file = current.get_or_insert_with(FilePatch::default);
+            file.old_path = header_path(header, "a/");
+            file.new_path = header_path(&new["+++ ".len()..], "b/");
+        } else if content.starts_with("@@ ") {
+            let Some(file) = current.as_mut() else {
+                return Err(malformed("hunk before any file header"));
+            };
+            let (old_start, mut old_count, new_start, mut new_count) =
+                hunk_header(content).ok_or_else(|| malformed("not a hunk header"))?;
+            let mut hunk = PatchHunk {
+                old_start,
+                new_start,
+                lines: Vec::new(),
+            };
+            while old_count > 0 || new_count > 0 {
+                let Some((index, line)) = lines.next() else {
+                    return Err(malformed("the diff ends inside this hunk"));
+                };
+                // some tools drop the space of an empty context line
+                let (marker, body) = match line {
+                    "\n" | "\r\n" => (" ", line),
+                    _ => line.split_at(line.chars().next().map_or(0, char::len_utf8)),
+                };
+                let body = body.to_string();
+                let (patch_line, old, new) = match marker {
+                    " " => (PatchLine::Context(body), 1, 1),
+                    "-" => (PatchLine::Removed(body), 1, 0),
+                    "+" => (PatchLine::Added(body), 0, 1),
+                    _ => {
+                        return Err(PatchError::Malformed {
+                            line: index + 1,
+                            message: "hunk is shorter than its header says".to_string(),
+                        })
+                    }
+                };
+                if old > old_count || new > new_count {
+                    return Err(PatchError::Malformed {
+                        line: index + 1,
+                        message: "hunk is longer than its header says".to_string(),
+                    });
+                }
+                old_count -= old;
+                new_count -= new;
+                hunk.lines.push(patch_line);
+                if lines.next_if(|(_, line)| line.starts_with('\\')).is_some() {
+                    if let Some(
+                        PatchLine::Context(text)
+                        | PatchLine::Removed(text)
+                        | PatchLine::Added(text),
+                    ) = hunk.lines.last_mut()
+                    {
+                        let trimmed = text.trim_end_matches(['\n', '\r']).len();
+                        text.truncate(trimmed);
+                    }
+                }
+            }
+            file.hunks.push(hunk);
+        }
+    }
+    files.extend(current);
+    Ok(files
+        .into_iter()
+        .filter(|file| file.old_path.is_some() || file.new_path.is_some())
+        .collect())
+}
+
+/// The path a `---` or `+++` line names, without the `a/` or `b/` git puts
+/// in front or the timestamp `diff -u` puts after. `/dev/null` is no file.
+fn header_path(header: &str, prefix: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim_end();
+    let path = path.trim_matches('"');
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+/// The start and length of each side in `@@ -1,2 +3,4 @@`, a missing
+/// length meaning one line.
+fn hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut fields = line.strip_prefix("@@ ")?.split(' ');
+    let range = |field: Option<&str>, sign: char| -> Option<(usize, usize)> {
+        let range = field?.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(fields.next(), '-')?;
+    let (new_start, new_count) = range(fields.next(), '+')?;
+    Some((old_start, old_count, new_start, new_count))
+}
+
+/// Changes read from a unified diff rather than a repository, for
+/// checkouts without one. The working tree holds the files after the diff;
+/// the base is those files with the diff undone, so a working tree that no
+/// longer matches it cannot be measured.
+pub struct PatchVcs {
+    root: PathBuf,
+    files: Vec<FilePatch>,
+}
+
+impl PatchVcs {
+    pub fn new<P: Into<PathBuf>>(root: P, files: Vec<FilePatch>) -> PatchVcs {
+        PatchVcs {
+            root: root.into(),
+            files,
+        }
+    }
+
+    /// Parses `text` as a unified diff of the checkout at `root`.
+    pub fn parse<P: Into<PathBuf>>(root: P, text: &str) -> Result<PatchVcs, PatchError> {
+        Ok(PatchVcs::new(root, parse(text)?))
+    }
+
+    /// The patched files `language` and `filter` select, each with its
+    /// content before and after the diff, `""` where it does not exist.
+    /// Files the working tree does not match are recorded in `failures`.
+    fn versions(
+        &self,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Vec<(&FilePatch, String, String)> {
+        let selected = |path: &Option<String>| {
+            path.as_deref()
+                .is_some_and(|path| language.matches_path(Path::new(path)) && filter.matches(path))
+        };
+        let mut versions = Vec::new();
+        for file in &self.files {
+            if !selected(&file.old_path) && !selected(&file.new_path) {
+                continue;
+            }
+            let new = match &file.new_path {
+                Some(path) => match std::fs::read_to_string(self.root.join(path)) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        failures.push(FileFailure::new(
+                            path.as_str(),
+                            DiffError::Io(path.clone(), e),
+                        ));
+                        continue;
+                    }
+                },
+                None => String::new(),
+            };
+            match file.revert(&new) {
+                Ok(old) => versions.push((file, old, new)),
+                Err(e) => failures.push(FileFailure::new(file.path(), e)),
+            }
+        }
+        versions
+    }
+}
+
+impl Vcs for PatchVcs {
+    fn resolve(&self, _base: &str) -> Result<String, EngineError> {
+        Ok(PATCH_REV.to_string())
+    }
+
+    fn merge_base(&self, _base: &str) -> Result<String, EngineError> {
+        Ok(PATCH_REV.to_string())
+    }
+
+    fn base_content(
+        &self,
+        _rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        _failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        // `diff` reports the files that cannot be read or reverted
+        let mut ignored = Vec::new();
+        let mut content = create_new_content_map(&self.root, language, filter, &mut ignored)?;
+        for (file, old, _) in self.versions(language, filter, &mut ignored) {
+            if let Some(path) = &file.new_path {
+                content.remove(path);
+            }
+            if let Some(path) = &file.old_path {
+                if language.matches_path(Path::new(path)) && filter.matches(path) {
+                    content.insert(path.clone(), old);
+                }
+            }
+        }
+        Ok(content)
+    }
+
+    fn working_content(
+        &self,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        Ok(create_new_content_map(
+            &self.root, language, filter, failures,
+        )?)
+    }
+
+    fn refresh_working_content(
+        &self,
+        content: &mut HashMap<String, String>,
+        changed: &BTreeSet<String>,
+    ) -> bool {
+        refresh_content_map(&self.root, content, changed.iter().map(String::as_str))
+    }
+
+    fn diff(
+        &self,
+        _rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError> {
+        Ok(self
+            .versions(language, filter, failures)
+            .into_iter()
+            .flat_map(|(file, old, new)| diff_contents(file.path(), &old, &new))
+            .collect())
+    }
+
+    fn renamed_files(&self, _rev: &str) -> Result<Vec<(String, String)>, EngineError> {
+        Ok(self
+            .files
+            .iter()
+            .filter_map(|file| match (&file.old_path, &file.new_path) {
+                (Some(old), Some(new)) if old != new => Some((old.clone(), new.clone())),
+                _ => None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,122 @@
+use git2::{DiffFormat, DiffOptions, Repository};
+use serde_json::{json, Value};
+
+use crate::selection::Selection;
+#[cfg(feature = "remote")]
+use crate::vcs;
+
+/// Body posted to the remote endpoint. `patch` carries uncommitted changes,
+/// including untracked files, so the remote job can `git apply` it on top of
+/// `head`; it is null for a clean tree.
+pub fn handoff_payload(selection: &Selection, base: &str, head: &str, patch: &str) -> Value {
+    json!({
+        "selection": selection,
+        "tests": selection.ids(),
+        "base": base,
+        "head": head,
+        "patch": match patch.is_empty() {
+            true => Value::Null,
+            false => json!(patch),
+        },
+    })
+}
+
+/// The working tree's changes against `HEAD` as a patch.
+pub fn working_tree_patch(repo: &Repository) -> Result<String, git2::Error> {
+    let head = repo.head()?.peel_to_tree()?;
+    let mut options = DiffOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&head), Some(&mut options))?;
+    let mut patch = Vec::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(String::from_utf8_lossy(&patch).into_owned())
+}
+
+/// Hands the selection to a remote CI job instead of running it: posts
+/// `handoff_payload` as JSON to an endpoint that triggers the job. The run
+/// counts as not executed locally, and the endpoint's reply is kept as
+/// stdout. The cycle's trace context goes along in the payload and the
+/// `traceparent` header; spans the endpoint replies with as an OTLP/JSON
+/// export are added to the cycle's trace.
+#[cfg(feature = "remote")]
+pub struct RemoteRunner {
+    root: std::path::PathBuf,
+    base: String,
+    endpoint: String,
+    token: Option<String>,
+}
+
+#[cfg(feature = "remote")]
+impl RemoteRunner {
+    pub fn new<P: Into<std::path::PathBuf>, S: Into<String>>(
+        root: P,
+        base: S,
+        endpoint: S,
+    ) -> RemoteRunner {
+        RemoteRunner {
+            root: root.into(),
+            base: base.into(),
+            endpoint: endpoint.into(),
+            token: None,
+        }
+    }
+
+    /// Sent as a bearer token with every request.
+    pub fn token<S: Into<String>>(mut self, token: S) -> RemoteRunner {
+        self.token = Some(token.into());
+        self
+    }
+
+    fn payload(&self, selection: &Selection) -> Result<Value, git2::Error> {
+        let repo = vcs::open_git(&self.root)?;
+        let base = repo.revparse_single(&self.base)?.peel_to_commit()?.id();
+        let head = repo.head()?.peel_to_commit()?.id();
+        let patch = working_tree_patch(&repo)?;
+        Ok(handoff_payload(
+            selection,
+            &base.to_string(),
+            &head.to_string(),
+            &patch,
+        ))
+    }
+}
+
+#[cfg(feature = "remote")]
+impl crate::runner::Runner for RemoteRunner {
+    fn run(
+        &self,
+        selection: &Selection,
+    ) -> Result<crate::runner::RunResult, crate::runner::RunnerError> {
+        use crate::runner::{RunResult, RunnerError};
+
+        let payload = self
+            .payload(selection)
+            .map_err(|e| RunnerError::Remote(e.to_string()))?;
+        let mut request = ureq::post(&self.endpoint);
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        if let Some(traceparent) = &selection.traceparent {
+            request = request.set("traceparent", traceparent);
+        }
+        let response = request
+            .send_json(payload)
+            .map_err(|e| RunnerError::Remote(format!("{}: {}", self.endpoint, e)))?;
+        let stdout = response.into_string().unwrap_or_default();
+        Ok(RunResult {
+            command: format!("POST {}", self.endpoint),
+            spans: crate::trace::resource_spans(&stdout),
+            stdout,
+            ..RunResult::default()
+        })
+    }
+}
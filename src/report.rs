@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::coverage::{line_blocks, FilePatchCoverage, HunkCoverage, PatchCoverage};
+use crate::runner::RunResult;
+use crate::selection::Selection;
+use crate::statefile::write_atomic;
+
+/// Bumped whenever a field of the JSON report changes meaning or goes away.
+pub const REPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error("failed to write report {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to encode report: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// How the outcome of each cycle is reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Progress and the coverage summary for people, on stdout.
+    #[default]
+    Text,
+    /// One `JsonReport` per cycle.
+    Json,
+    /// The changed lines' hits as an LCOV tracefile, written to
+    /// `LCOV_FILE` unless another file is given.
+    Lcov,
+}
+
+/// Where LCOV output goes by default, relative to the repository root, as
+/// looked for by Coverage Gutters.
+pub const LCOV_FILE: &str = "lcov.info";
+
+/// A cycle's outcome for scripts and editor plugins.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonReport<'a> {
+    pub version: u32,
+    pub selection: &'a Selection,
+    /// `None` when nothing ran, as with `--dry-run`.
+    pub result: Option<&'a RunResult>,
+    pub coverage: Option<CoverageReport>,
+}
+
+impl<'a> JsonReport<'a> {
+    pub fn new(
+        selection: &'a Selection,
+        result: Option<&'a RunResult>,
+        coverage: Option<&PatchCoverage>,
+    ) -> JsonReport<'a> {
+        JsonReport {
+            version: REPORT_VERSION,
+            selection,
+            result,
+            coverage: coverage.map(CoverageReport::new),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoverageReport {
+    /// `None` when the patch changes no lines.
+    pub percent: Option<f64>,
+    pub changed: usize,
+    pub covered: usize,
+    pub partial: usize,
+    pub files: Vec<FileReport>,
+}
+
+impl CoverageReport {
+    pub fn new(coverage: &PatchCoverage) -> CoverageReport {
+        CoverageReport {
+            percent: coverage.percent(),
+            changed: coverage.changed(),
+            covered: coverage.covered(),
+            partial: coverage.partial(),
+            files: coverage.files.iter().map(FileReport::new).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    pub percent: f64,
+    pub changed_lines: Vec<usize>,
+    pub covered_lines: Vec<usize>,
+    pub partial_lines: Vec<usize>,
+    pub uncovered_lines: Vec<usize>,
+    pub hunks: Vec<HunkReport>,
+}
+
+impl FileReport {
+    fn new(file: &FilePatchCoverage) -> FileReport {
+        FileReport {
+            path: file.path.clone(),
+            percent: percent_of(&file.covered_lines, &file.changed_lines),
+            changed_lines: file.changed_lines.clone(),
+            covered_lines: file.covered_lines.clone(),
+            partial_lines: file.partial_lines.clone(),
+            uncovered_lines: file.uncovered_lines(),
+            hunks: file.hunks.iter().map(HunkReport::new).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HunkReport {
+    pub first: usize,
+    pub last: usize,
+    pub percent: f64,
+    pub changed_lines: Vec<usize>,
+    pub covered_lines: Vec<usize>,
+    pub partial_lines: Vec<usize>,
+    pub uncovered_lines: Vec<usize>,
+}
+
+impl HunkReport {
+    fn new(hunk: &HunkCoverage) -> HunkReport {
+        HunkReport {
+            first: hunk.first,
+            last: hunk.last,
+            percent: percent_of(&hunk.covered_lines, &hunk.changed_lines),
+            changed_lines: hunk.changed_lines.clone(),
+            covered_lines: hunk.covered_lines.clone(),
+            partial_lines: hunk.partial_lines.clone(),
+            uncovered_lines: hunk.uncovered_lines(),
+        }
+    }
+}
+
+fn percent_of(covered: &[usize], changed: &[usize]) -> f64 {
+    covered.len() as f64 * 100.0 / changed.len() as f64
+}
+
+pub fn print_selection(selection: &Selection) {
+    match selection.is_empty() {
+        true => println!("Running the full suite"),
+        false => println!("Running {}", selection.ids().join(" ")),
+    }
+}
+
+pub fn print_failures(selection: &Selection) {
+    for failure in &selection.skipped {
+        tracing::warn!("skipped {}: {}", failure.path, failure.error);
+    }
+}
+
+pub fn print_ids(selection: &Selection) {
+    for id in selection.ids() {
+        println!("{}", id);
+    }
+}
+
+pub fn print_skipped() {
+    println!("No tests affected, skipping run");
+}
+
+pub fn print_result(result: &RunResult) {
+    if !result.executed {
+        println!("{}", result.command);
+        return;
+    }
+    println!("{}", result.stdout);
+}
+
+/// Patch coverage as an LCOV tracefile: a `DA` record per changed line,
+/// with one hit for lines that ran, partial branches included. Unchanged
+/// lines are left out, so viewers highlight only what the patch touched.
+pub fn lcov(coverage: &PatchCoverage) -> String {
+    let mut out = String::new();
+    for file in &coverage.files {
+        out.push_str(&format!("TN:\nSF:{}\n", file.path));
+        for line in &file.changed_lines {
+            let hits =
+                usize::from(file.covered_lines.contains(line) || file.partial_lines.contains(line));
+            out.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        out.push_str(&format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            file.changed_lines.len(),
+            file.covered_lines.len() + file.partial_lines.len()
+        ));
+    }
+    out
+}
+
+/// Patch coverage over all changed lines, then per file and per hunk, with
+/// the lines the run left uncovered.
+pub fn coverage_summary(coverage: &PatchCoverage) -> String {
+    let percent = match coverage.percent() {
+        Some(percent) => percent,
+        None => return "No changed lines".to_string(),
+    };
+    let mut lines = vec![format!(
+        "Patch coverage {:.1}% ({} of {} changed lines)",
+        percent,
+        coverage.covered(),
+        coverage.changed()
+    )];
+    for file in &coverage.files {
+        let mut line = format!(
+            "  {}: {:.1}% ({}/{})",
+            file.path,
+            percent_of(&file.covered_lines, &file.changed_lines),
+            file.covered_lines.len(),
+            file.changed_lines.len()
+        );
+        if file.hunks.is_empty() {
+            line.push_str(&missing(&file.uncovered_lines(), &file.partial_lines));
+        }
+        lines.push(line);
+        for hunk in &file.hunks {
+            let range = match hunk.first == hunk.last {
+                true => format!("line {}", hunk.first),
+                false => format!("lines {}-{}", hunk.first, hunk.last),
+            };
+            let mut line = format!(
+                "    {}: {}/{} covered",
+                range,
+                hunk.covered_lines.len(),
+                hunk.changed_lines.len()
+            );
+            // a wholly uncovered hunk needs no list of what is missing
+            if !hunk.covered_lines.is_empty() || !hunk.partial_lines.is_empty() {
+                line.push_str(&missing(&hunk.uncovered_lines(), &hunk.partial_lines));
+            }
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+/// `, missing 3-4, 8, partial 2` for `uncovered` lines and `partial`
+/// branches, or nothing when there are none.
+fn missing(uncovered: &[usize], partial: &[usize]) -> String {
+    let mut text = String::new();
+    for (label, lines) in [("missing", uncovered), ("partial", partial)] {
+        if lines.is_empty() {
+            continue;
+        }
+        let blocks: Vec<String> = line_blocks(lines)
+            .into_iter()
+            .map(|(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{}-{}", first, last),
+            })
+            .collect();
+        text.push_str(&format!(", {} {}", label, blocks.join(", ")));
+    }
+    text
+}
+
+/// A banner that is hard to miss among test output, for patch coverage
+/// below `threshold`.
+pub fn threshold_banner(coverage: &PatchCoverage, threshold: f64) -> String {
+    let message = format!(
+        "FAILED: patch coverage {:.1}% is below the required {:.1}%",
+        coverage.percent().unwrap_or(100.0),
+        threshold
+    );
+    let rule = "=".repeat(message.len());
+    format!("{}\n{}\n{}", rule, message, rule)
+}
+
+/// Goes to stderr so it shows even when stdout carries a JSON report.
+pub fn print_threshold_banner(coverage: &PatchCoverage, threshold: f64) {
+    eprintln!("{}", threshold_banner(coverage, threshold));
+}
+
+pub fn print_coverage(coverage: &PatchCoverage) {
+    println!("{}", coverage_summary(coverage));
+}
+
+/// Replaces the report at `path` with `content`, so a reader polling it never
+/// sees half a report.
+pub fn write(path: &Path, content: &str) -> Result<(), ReportError> {
+    write_atomic(path, content.as_bytes()).map_err(|source| ReportError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
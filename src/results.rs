@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Variable telling the pytest plugin where to connect.
+pub const REPORT_ENV: &str = "INSTANT_PATCH_REPORT";
+
+/// Module of the companion pytest plugin, loaded with `-p`.
+pub const PYTEST_PLUGIN: &str = "instant_patch.pytest_plugin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    Skipped,
+    Failed,
+}
+
+/// How one test went, over its setup, call and teardown.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestOutcome {
+    pub id: String,
+    /// The worst outcome of any phase.
+    pub outcome: Outcome,
+    pub duration_micros: u64,
+}
+
+/// Everything the plugin reported during one run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestReport {
+    /// Node ids in collection order.
+    pub collected: Vec<String>,
+    /// Outcomes sorted by test id.
+    pub tests: Vec<TestOutcome>,
+    pub exit_status: Option<i32>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event {
+    Collected {
+        nodeid: String,
+    },
+    Report {
+        nodeid: String,
+        outcome: Outcome,
+        #[serde(default)]
+        duration: f64,
+    },
+    Finished {
+        exitstatus: i32,
+    },
+}
+
+/// Folds the plugin's JSON lines into a report. Lines that do not parse are
+/// ignored, so a plugin from a newer release cannot break a run.
+pub fn parse_events<I: IntoIterator<Item = String>>(lines: I) -> TestReport {
+    let mut report = TestReport::default();
+    let mut tests: BTreeMap<String, TestOutcome> = BTreeMap::new();
+    for line in lines {
+        match serde_json::from_str(&line) {
+            Ok(Event::Collected { nodeid }) => report.collected.push(nodeid),
+            Ok(Event::Report {
+                nodeid,
+                outcome,
+                duration,
+            }) => {
+                let micros = (duration.max(0.0) * 1_000_000.0) as u64;
+                let test = tests.entry(nodeid.clone()).or_insert(TestOutcome {
+                    id: nodeid,
+                    outcome,
+                    duration_micros: 0,
+                });
+                test.outcome = test.outcome.max(outcome);
+                test.duration_micros += micros;
+            }
+            Ok(Event::Finished { exitstatus }) => report.exit_status = Some(exitstatus),
+            Err(_) => {}
+        }
+    }
+    report.tests = tests.into_values().collect();
+    report
+}
+
+/// Listens on a local port for the pytest plugin while a test command runs.
+pub struct ResultCollector {
+    addr: SocketAddr,
+    done: Arc<AtomicBool>,
+    handle: JoinHandle<Vec<String>>,
+}
+
+impl ResultCollector {
+    pub fn start() -> io::Result<ResultCollector> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let done = Arc::new(AtomicBool::new(false));
+        let stop = done.clone();
+        let handle = thread::spawn(move || {
+            let mut lines = Vec::new();
+            loop {
+                // check before accepting so connections queued before the
+                // command exited are still drained
+                let finished = stop.load(Ordering::Acquire);
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if stream.set_nonblocking(false).is_ok() {
+                            lines.extend(BufReader::new(stream).lines().map_while(Result::ok));
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock && !finished => {
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(_) => break,
+                }
+            }
+            lines
+        });
+        Ok(ResultCollector { addr, done, handle })
+    }
+
+    /// Variables that make pytest load the plugin and report here.
+    pub fn env(&self) -> Vec<(&'static str, OsString)> {
+        let mut addopts = std::env::var_os("PYTEST_ADDOPTS").unwrap_or_default();
+        if !addopts.is_empty() {
+            addopts.push(" ");
+        }
+        addopts.push(format!("-p {}", PYTEST_PLUGIN));
+        vec![
+            (REPORT_ENV, self.addr.to_string().into()),
+            ("PYTEST_ADDOPTS", addopts),
+        ]
+    }
+
+    /// Collects what was reported. Call once the test command has exited.
+    pub fn finish(self) -> TestReport {
+        self.done.store(true, Ordering::Release);
+        parse_events(self.handle.join().unwrap_or_default())
+    }
+}
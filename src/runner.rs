@@ -0,0 +1,283 @@
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use thiserror::Error;
+
+use crate::results::{Outcome, ResultCollector, TestOutcome};
+use crate::selection::Selection;
+use crate::trace::TRACEPARENT_ENV;
+
+pub const DEFAULT_COMMAND_TEMPLATE: &str = "coverage run -m pytest {tests}";
+
+#[derive(Debug, Error)]
+pub enum RunnerError {
+    #[error("failed to execute process: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("invalid command template {0:?}: {1}")]
+    InvalidTemplate(String, String),
+    #[error("refusing to run suspicious test id {0:?}")]
+    InvalidTestId(String),
+    #[error("remote handoff failed: {0}")]
+    Remote(String),
+}
+
+/// Splits a command template into arguments, checking that `{tests}` appears
+/// exactly once as an argument of its own.
+pub fn parse_template(template: &str) -> Result<Vec<String>, RunnerError> {
+    let invalid =
+        |reason: &str| RunnerError::InvalidTemplate(template.to_string(), reason.to_string());
+    let args = shell_words::split(template).map_err(|e| invalid(&e.to_string()))?;
+    if args.is_empty() {
+        return Err(invalid("empty command"));
+    }
+    match args.iter().filter(|arg| arg.contains("{tests}")).count() {
+        1 if args.iter().any(|arg| arg == "{tests}") => Ok(args),
+        0 => Err(invalid("missing {tests} placeholder")),
+        _ => Err(invalid("{tests} must appear once, as a separate argument")),
+    }
+}
+
+/// Options a language passes tests to its runner through, such as RSpec's
+/// `--example`. The test is attached with `=`, so it is only ever read as
+/// the option's value.
+pub const TEST_OPTIONS: [&str; 3] = ["--example=", "-Dtest=", "--tests="];
+
+/// Test ids come from file names and contents on whatever branch is checked
+/// out, so anything that could be read as an option or smuggle in control
+/// characters is rejected. A test passed through one of `TEST_OPTIONS` may
+/// start with anything.
+pub fn validate_test_id(id: &str) -> Result<(), RunnerError> {
+    let suspicious = match TEST_OPTIONS.iter().find_map(|o| id.strip_prefix(o)) {
+        Some(value) => value.is_empty(),
+        None => id.is_empty() || id.starts_with('-'),
+    };
+    if suspicious || id.chars().any(char::is_control) {
+        return Err(RunnerError::InvalidTestId(id.to_string()));
+    }
+    Ok(())
+}
+
+/// Expands `template` into an argument vector with one argument per test id.
+/// The result is executed directly, never through a shell.
+pub fn render_command(template: &str, tests: &[String]) -> Result<Vec<String>, RunnerError> {
+    for test in tests {
+        validate_test_id(test)?;
+    }
+    let mut args = Vec::new();
+    for arg in parse_template(template)? {
+        match arg.as_str() {
+            "{tests}" => args.extend(tests.iter().cloned()),
+            _ => args.push(arg),
+        }
+    }
+    Ok(args)
+}
+
+/// A process running `args[0]` with the rest as its arguments, never
+/// through a shell.
+pub fn command(args: &[String]) -> Command {
+    let mut command = Command::new(program(&args[0]));
+    command.args(&args[1..]);
+    command
+}
+
+/// Windows only finds `.exe` files by a bare name, so a `.cmd` or `.bat`
+/// shim, as `npm` and some Python installers put on `PATH`, is looked up
+/// through `PATHEXT` instead. The standard library runs those through
+/// `cmd /C`, escaping each argument for it.
+#[cfg(windows)]
+fn program(name: &str) -> PathBuf {
+    let path = Path::new(name);
+    if path.extension().is_some() || path.components().count() > 1 {
+        return path.to_path_buf();
+    }
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    let dirs = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&dirs)
+        .flat_map(|dir| {
+            extensions
+                .split(';')
+                .filter(|extension| !extension.is_empty())
+                .map(move |extension| dir.join(format!("{}{}", name, extension)))
+        })
+        .find(|candidate| candidate.is_file())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+#[cfg(not(windows))]
+fn program(name: &str) -> PathBuf {
+    Path::new(name).to_path_buf()
+}
+
+pub(crate) fn display_command(args: &[String]) -> String {
+    shell_words::join(args)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunResult {
+    pub command: String,
+    /// False when the runner only reported the command without running it.
+    pub executed: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// Node ids pytest collected, when the companion plugin reported them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collected: Vec<String>,
+    /// Per-test outcomes reported by the companion plugin, sorted by id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<TestOutcome>,
+    /// OTLP/JSON `resourceSpans` the executor sent back, exported with the
+    /// cycle's trace.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<serde_json::Value>,
+}
+
+impl RunResult {
+    pub(crate) fn from_output(command: String, output: Output) -> RunResult {
+        RunResult {
+            command,
+            executed: true,
+            exit_code: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            ..RunResult::default()
+        }
+    }
+
+    /// Ids of the tests the plugin reported as failed.
+    pub fn failed_tests(&self) -> Vec<&str> {
+        self.tests
+            .iter()
+            .filter(|test| test.outcome == Outcome::Failed)
+            .map(|test| test.id.as_str())
+            .collect()
+    }
+
+    pub fn success(&self) -> bool {
+        !self.executed || self.exit_code == Some(0)
+    }
+}
+
+pub trait Runner: Send + Sync {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError>;
+}
+
+/// Runs the selection as a local subprocess.
+pub struct LocalRunner {
+    root: PathBuf,
+    template: String,
+    env: Vec<(OsString, OsString)>,
+    collect_results: bool,
+}
+
+impl LocalRunner {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(root: P, template: S) -> LocalRunner {
+        LocalRunner {
+            root: root.into(),
+            template: template.into(),
+            env: Vec::new(),
+            collect_results: false,
+        }
+    }
+
+    /// Sets an environment variable for the test command.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, key: K, value: V) -> LocalRunner {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Load the companion pytest plugin and fill `RunResult::collected` and
+    /// `RunResult::tests` from what it reports. The plugin must be
+    /// importable, e.g. through the `PYTHONPATH` from
+    /// `contexts::ContextSetup::env`.
+    pub fn collect_results(mut self, enabled: bool) -> LocalRunner {
+        self.collect_results = enabled;
+        self
+    }
+}
+
+impl Runner for LocalRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        let args = render_command(&self.template, &selection.ids())?;
+        let collector = match self.collect_results {
+            true => Some(ResultCollector::start()?),
+            false => None,
+        };
+        let mut command = command(&args);
+        command
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .current_dir(&self.root);
+        if let Some(collector) = &collector {
+            command.envs(collector.env());
+        }
+        if let Some(traceparent) = &selection.traceparent {
+            command.env(TRACEPARENT_ENV, traceparent);
+        }
+        let output = command.output();
+        let report = collector.map(ResultCollector::finish).unwrap_or_default();
+        let mut result = RunResult::from_output(display_command(&args), output?);
+        result.collected = report.collected;
+        result.tests = report.tests;
+        Ok(result)
+    }
+}
+
+/// Runs the selection inside a throwaway container with the repository
+/// mounted at `/work`.
+pub struct DockerRunner {
+    root: PathBuf,
+    image: String,
+    template: String,
+}
+
+impl DockerRunner {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(root: P, image: S, template: S) -> DockerRunner {
+        DockerRunner {
+            root: root.into(),
+            image: image.into(),
+            template: template.into(),
+        }
+    }
+
+    fn mount(&self) -> String {
+        let root = std::fs::canonicalize(&self.root).unwrap_or_else(|_| self.root.clone());
+        format!("{}:/work", root.display())
+    }
+}
+
+impl Runner for DockerRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        let mut args: Vec<String> = ["docker", "run", "--rm", "-v", &self.mount(), "-w", "/work"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        args.push(self.image.clone());
+        args.extend(render_command(&self.template, &selection.ids())?);
+        let output = command(&args).output()?;
+        Ok(RunResult::from_output(display_command(&args), output))
+    }
+}
+
+/// Reports the command that would run without executing anything.
+pub struct EmitOnlyRunner {
+    template: String,
+}
+
+impl EmitOnlyRunner {
+    pub fn new<S: Into<String>>(template: S) -> EmitOnlyRunner {
+        EmitOnlyRunner {
+            template: template.into(),
+        }
+    }
+}
+
+impl Runner for EmitOnlyRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        Ok(RunResult {
+            command: display_command(&render_command(&self.template, &selection.ids())?),
+            ..RunResult::default()
+        })
+    }
+}
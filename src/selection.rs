@@ -0,0 +1,639 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use tree_sitter::Tree;
+
+use crate::calls::CallSelector;
+use crate::diff::{edit_tree, hunks_reproduce, BetterDiff};
+use crate::discovery::{
+    create_parser, get_test_ranges, get_tests, DiscoveryError, FileFailure, TestNames, TreeCache,
+};
+use crate::language::Language;
+
+/// Per-test impact data: test id -> file path -> executed line numbers.
+#[derive(Debug, Default, Clone)]
+pub struct ImpactData {
+    pub lines: HashMap<String, HashMap<String, HashSet<usize>>>,
+    /// Branches taken by any test, when they were measured: file path ->
+    /// line -> lines control went to from it. Negative destinations leave
+    /// the enclosing function, as in coverage.py.
+    pub arcs: HashMap<String, HashMap<usize, HashSet<i64>>>,
+}
+
+impl ImpactData {
+    /// Folds in data from a later run. What each test it ran executed
+    /// replaces what that test executed before; branches are not recorded
+    /// per test, so those it took are added to the ones already seen.
+    pub fn merge(&mut self, later: ImpactData) {
+        self.lines.extend(later.lines);
+        for (path, arcs) in later.arcs {
+            let file = self.arcs.entry(path).or_default();
+            for (from, to) in arcs {
+                file.entry(from).or_default().extend(to);
+            }
+        }
+    }
+
+    /// Ids of the tests that executed `line` of `path`, sorted. Lines run
+    /// outside any test, recorded under the empty id, count for coverage but
+    /// name no test.
+    pub fn tests_covering(&self, path: &str, line: usize) -> Vec<String> {
+        let mut tests: Vec<String> = self
+            .lines
+            .iter()
+            .filter(|(test, _)| !test.is_empty())
+            .filter(|(_, files)| files.get(path).is_some_and(|lines| lines.contains(&line)))
+            .map(|(test, _)| test.clone())
+            .collect();
+        tests.sort();
+        tests
+    }
+}
+
+pub struct SelectionContext<'a> {
+    pub hunks: &'a [BetterDiff],
+    pub old_content: &'a HashMap<String, String>,
+    pub new_content: &'a HashMap<String, String>,
+    pub old_trees: &'a HashMap<String, Tree>,
+    pub new_trees: &'a HashMap<String, Tree>,
+    pub old_tests: &'a HashSet<String>,
+    pub new_tests: &'a HashSet<String>,
+    /// Tests removed or renamed since the base.
+    pub changes: &'a TestChanges,
+    pub impact: &'a ImpactData,
+}
+
+/// How the tests at the base fared: removed outright, or renamed, possibly
+/// into another file. A test is taken as renamed when its file was renamed
+/// and it kept its name, or when a new test has the same definition under
+/// another name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestChanges {
+    pub removed: BTreeSet<String>,
+    /// New id -> id at the base.
+    pub renamed: BTreeMap<String, String>,
+    /// Files at the base that no longer exist, so no test in them does.
+    pub deleted_files: BTreeSet<String>,
+}
+
+impl TestChanges {
+    /// Works out which tests disappeared since the base and which of those
+    /// live on under another id. `renamed_files` pairs paths at the base
+    /// with their new paths.
+    pub fn new(
+        old_tests: &HashSet<String>,
+        new_tests: &HashSet<String>,
+        old_ranges: &HashMap<String, Range<usize>>,
+        new_ranges: &HashMap<String, Range<usize>>,
+        old_content: &HashMap<String, String>,
+        new_content: &HashMap<String, String>,
+        renamed_files: &[(String, String)],
+    ) -> TestChanges {
+        let definition = |id: &str,
+                          ranges: &HashMap<String, Range<usize>>,
+                          content: &HashMap<String, String>| {
+            let (file, name) = id.split_once("::")?;
+            let text = content.get(file)?.get(ranges.get(id)?.clone())?;
+            // the definition without its name, to compare across renames
+            Some(text.replacen(name.rsplit("::").next().unwrap_or(name), "", 1))
+        };
+        let mut added: BTreeSet<&str> = new_tests
+            .difference(old_tests)
+            .map(String::as_str)
+            .collect();
+        let mut gone: Vec<&str> = old_tests
+            .difference(new_tests)
+            .map(String::as_str)
+            .collect();
+        gone.sort();
+
+        let mut changes = TestChanges {
+            deleted_files: old_content
+                .keys()
+                .filter(|path| !new_content.contains_key(*path))
+                .cloned()
+                .collect(),
+            ..TestChanges::default()
+        };
+        for old in gone {
+            let moved = old.split_once("::").and_then(|(file, name)| {
+                let (_, new_file) = renamed_files.iter().find(|(from, _)| from == file)?;
+                let id = format!("{}::{}", new_file, name);
+                added.contains(id.as_str()).then_some(id)
+            });
+            let new = moved.or_else(|| {
+                let before = definition(old, old_ranges, old_content)?;
+                added
+                    .iter()
+                    .find(|new| definition(new, new_ranges, new_content).as_ref() == Some(&before))
+                    .map(|new| new.to_string())
+            });
+            match new {
+                Some(new) => {
+                    added.remove(new.as_str());
+                    changes.renamed.insert(new, old.to_string());
+                }
+                None => {
+                    changes.removed.insert(old.to_string());
+                }
+            }
+        }
+        changes
+    }
+
+    /// Whether `id` names a test that no longer exists under it: one removed
+    /// or renamed since the base, including any parametrized instance, or
+    /// one in a deleted file.
+    pub fn is_gone(&self, id: &str) -> bool {
+        let test = id.split_once('[').map_or(id, |(test, _)| test);
+        let file = test.split_once("::").map_or(test, |(file, _)| file);
+        self.removed.contains(test)
+            || self.renamed.values().any(|old| old == test)
+            || self.deleted_files.contains(file)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelectedTest {
+    pub id: String,
+    pub file: String,
+    pub reason: String,
+}
+
+impl SelectedTest {
+    /// Builds a selected test from a pytest-style node id (`path::name`).
+    pub fn new<S: Into<String>>(id: S, reason: S) -> SelectedTest {
+        let id = id.into();
+        let file = match id.split_once("::") {
+            Some((file, _)) => file.to_string(),
+            None => id.clone(),
+        };
+        SelectedTest {
+            id,
+            file,
+            reason: reason.into(),
+        }
+    }
+}
+
+pub trait TestSelector: Send + Sync {
+    fn name(&self) -> &str;
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest>;
+}
+
+/// Selects tests that exist in the working tree but not at the base commit.
+pub struct NewTestsSelector;
+
+impl TestSelector for NewTestsSelector {
+    fn name(&self) -> &str {
+        "new-tests"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        ctx.new_tests
+            .difference(ctx.old_tests)
+            .map(|id| match ctx.changes.renamed.get(id) {
+                Some(old) => SelectedTest::new(id.clone(), format!("renamed from {}", old)),
+                None => SelectedTest::new(id.as_str(), "new test"),
+            })
+            .collect()
+    }
+}
+
+/// Selects tests that existed at the base commit and whose definition a hunk
+/// touches, so editing a test reruns it even though its name is unchanged.
+pub struct ModifiedTestsSelector {
+    language: Language,
+}
+
+impl ModifiedTestsSelector {
+    pub fn new(language: Language) -> ModifiedTestsSelector {
+        ModifiedTestsSelector { language }
+    }
+}
+
+impl TestSelector for ModifiedTestsSelector {
+    fn name(&self) -> &str {
+        "modified-tests"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let ranges = match get_test_ranges(ctx.new_content, ctx.new_trees, self.language) {
+            Ok(ranges) => ranges,
+            Err(e) => {
+                tracing::warn!("cannot locate test definitions: {}", e);
+                return Vec::new();
+            }
+        };
+        ctx.new_tests
+            .intersection(ctx.old_tests)
+            .filter(|id| {
+                let (Some((file, _)), Some(range)) = (id.split_once("::"), ranges.get(*id)) else {
+                    return false;
+                };
+                ctx.hunks
+                    .iter()
+                    .any(|hunk| hunk.path == file && hunk.touches(range))
+            })
+            .map(|id| SelectedTest::new(id.as_str(), "modified test"))
+            .collect()
+    }
+}
+
+/// Selects tests whose recorded coverage runs through a line a hunk touches,
+/// so editing code reruns the tests exercising it. Lines are matched in the
+/// working copy's numbering, which is what the last run recorded; a hunk
+/// that only deletes touches the lines either side of the gap. A hunk no
+/// test ran through, such as newly added code, falls back to every test
+/// that executed anything in its file.
+pub struct CoverageSelector;
+
+impl TestSelector for CoverageSelector {
+    fn name(&self) -> &str {
+        "coverage"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        // the data may predate a test's removal
+        let recorded: Vec<(&str, &HashMap<String, HashSet<usize>>)> = ctx
+            .impact
+            .lines
+            .iter()
+            .filter(|(test, _)| !test.is_empty() && !ctx.changes.is_gone(test))
+            .map(|(test, files)| (test.as_str(), files))
+            .collect();
+        let mut covering: HashMap<&str, &str> = HashMap::new();
+        let mut exercising: HashMap<&str, &str> = HashMap::new();
+        for hunk in ctx.hunks {
+            let touched = touched_lines(hunk);
+            let in_file = || {
+                recorded
+                    .iter()
+                    .filter_map(|(test, files)| Some((*test, files.get(&hunk.path)?)))
+                    .filter(|(_, lines)| !lines.is_empty())
+            };
+            let mut hit = false;
+            for (test, _) in
+                in_file().filter(|(_, lines)| touched.clone().any(|line| lines.contains(&line)))
+            {
+                covering.entry(test).or_insert(&hunk.path);
+                hit = true;
+            }
+            if !hit {
+                for (test, _) in in_file() {
+                    exercising.entry(test).or_insert(&hunk.path);
+                }
+            }
+        }
+        let mut selected: Vec<SelectedTest> = covering
+            .iter()
+            .map(|(test, path)| SelectedTest::new(test.to_string(), format!("covers {}", path)))
+            .collect();
+        selected.extend(
+            exercising
+                .into_iter()
+                .filter(|(test, _)| !covering.contains_key(test))
+                .map(|(test, path)| {
+                    SelectedTest::new(test.to_string(), format!("exercises {}", path))
+                }),
+        );
+        selected
+    }
+}
+
+/// 1-based lines of the working copy a hunk replaced or added.
+fn touched_lines(hunk: &BetterDiff) -> std::ops::RangeInclusive<usize> {
+    let first = hunk.start_point.row + 1;
+    let end = |offset: usize, point: tree_sitter::Point| match offset == hunk.start_offset {
+        true => hunk.start_point.row,
+        false => point.row + usize::from(point.column > 0),
+    };
+    let last = end(hunk.addition_end, hunk.addition_point)
+        .max(end(hunk.deletion_end, hunk.deletion_point));
+    match last < first {
+        true => first.saturating_sub(1).max(1)..=first,
+        false => first..=last,
+    }
+}
+
+/// Consults another selector only while no coverage has been recorded,
+/// standing in for `CoverageSelector` until the first run.
+pub struct UntilCovered<S>(pub S);
+
+impl<S: TestSelector> TestSelector for UntilCovered<S> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        match ctx.impact.lines.is_empty() {
+            true => self.0.select(ctx),
+            false => Vec::new(),
+        }
+    }
+}
+
+/// Selects the tests defined in a changed Rust module or in the modules
+/// below it, such as the unit tests in a file's `mod tests`. A module's
+/// children live in the directory named after it, or for `lib.rs`,
+/// `main.rs` and `mod.rs` in the directory holding it.
+pub struct ModuleSelector;
+
+impl TestSelector for ModuleSelector {
+    fn name(&self) -> &str {
+        "modules"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let changed: BTreeSet<&str> = ctx.hunks.iter().map(|hunk| hunk.path.as_str()).collect();
+        let children = |path: &str| {
+            let module = path.trim_end_matches(".rs");
+            match module.rsplit_once('/') {
+                Some((dir, "lib" | "main" | "mod")) => format!("{}/", dir),
+                Some(_) => format!("{}/", module),
+                None if matches!(module, "lib" | "main" | "mod") => String::new(),
+                None => format!("{}/", module),
+            }
+        };
+        ctx.new_tests
+            .iter()
+            .filter_map(|id| {
+                let (file, _) = id.split_once("::")?;
+                let module = changed
+                    .iter()
+                    .find(|path| **path == file || file.starts_with(&children(path)))?;
+                Some(SelectedTest::new(
+                    id.clone(),
+                    format!("in module {}", module),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Selects the tests in the Go package of a changed file, that is in the
+/// same directory. Packages below it are packages of their own.
+pub struct PackageSelector;
+
+impl TestSelector for PackageSelector {
+    fn name(&self) -> &str {
+        "packages"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let dir = |path: &str| path.rsplit_once('/').map_or("", |(dir, _)| dir).to_string();
+        let changed: BTreeMap<String, &str> = ctx
+            .hunks
+            .iter()
+            .map(|hunk| (dir(&hunk.path), hunk.path.as_str()))
+            .collect();
+        ctx.new_tests
+            .iter()
+            .filter_map(|id| {
+                let (file, _) = id.split_once("::")?;
+                let path = changed.get(&dir(file))?;
+                Some(SelectedTest::new(
+                    id.clone(),
+                    format!("in the package of {}", path),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The selection used when none is registered: tests added or edited since
+/// the base, tests whose recorded coverage the change touches and, until
+/// there is coverage, tests calling a changed function. Rust and Go tests
+/// live next to the code, so the tests in changed modules or packages are
+/// selected too.
+pub fn default_selector(language: Language) -> CompositeSelector {
+    let mut selector = CompositeSelector::new();
+    selector.register(NewTestsSelector);
+    selector.register(ModifiedTestsSelector::new(language));
+    #[cfg(feature = "rust")]
+    if language == Language::Rust {
+        selector.register(ModuleSelector);
+    }
+    #[cfg(feature = "go")]
+    if language == Language::Go {
+        selector.register(PackageSelector);
+    }
+    selector.register(CoverageSelector);
+    selector.register(UntilCovered(CallSelector::new(language)));
+    selector
+}
+
+/// Runs every registered selector and merges their results. When several
+/// selectors pick the same test, the reason from the first one wins.
+#[derive(Default)]
+pub struct CompositeSelector {
+    selectors: Vec<Box<dyn TestSelector>>,
+}
+
+impl CompositeSelector {
+    pub fn new() -> CompositeSelector {
+        CompositeSelector::default()
+    }
+
+    pub fn register<S: TestSelector + 'static>(&mut self, selector: S) {
+        self.selectors.push(Box::new(selector));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selectors.is_empty()
+    }
+}
+
+impl TestSelector for CompositeSelector {
+    fn name(&self) -> &str {
+        "composite"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut selected = Vec::new();
+        for selector in &self.selectors {
+            for test in selector.select(ctx) {
+                if seen.insert(test.id.clone()) {
+                    selected.push(test);
+                }
+            }
+        }
+        selected
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub tests: Vec<SelectedTest>,
+    /// Files left out of the analysis, so tests in them may be missing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<FileFailure>,
+    /// W3C trace context of the cycle that made the selection, for runners
+    /// to pass on so execution shows up in the same trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>,
+    /// Tests at the base that no longer exist, by their old id. Selectors
+    /// picking them, say from stale coverage, are overruled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+}
+
+impl Selection {
+    /// Orders tests by file, then by id, so the same change always produces
+    /// the same command and report.
+    pub fn new(mut tests: Vec<SelectedTest>) -> Selection {
+        tests.sort_by(|a, b| a.file.cmp(&b.file).then_with(|| a.id.cmp(&b.id)));
+        Selection {
+            tests,
+            skipped: Vec::new(),
+            traceparent: None,
+            removed: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty()
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.tests.iter().map(|test| test.id.clone()).collect()
+    }
+}
+
+/// What the engine runs when nothing was selected. Handing an empty selection
+/// to a runner usually means running the whole suite, so that only happens
+/// when asked for with `All`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptySelection {
+    /// Run nothing and wait for the next change.
+    #[default]
+    Skip,
+    /// Run a fixed set of test ids.
+    Smoke(Vec<String>),
+    /// Run the whole suite.
+    All,
+}
+
+/// How the engine decides which tests a change affects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImpactStrategy {
+    /// Parse the tree and use recorded coverage.
+    #[default]
+    Coverage,
+    /// Ask `bazel query` for the test targets depending on changed files and
+    /// run them with `bazel test`.
+    Bazel,
+}
+
+/// Drops parametrized instances such as `path::test_x[1]`, named by
+/// coverage contexts, when `path::test_x` itself is selected, since running
+/// it runs every instance.
+fn without_covered_instances(tests: Vec<SelectedTest>) -> Vec<SelectedTest> {
+    let whole: HashSet<String> = tests
+        .iter()
+        .filter(|test| !test.id.contains('['))
+        .map(|test| test.id.clone())
+        .collect();
+    tests
+        .into_iter()
+        .filter(|test| {
+            test.id
+                .split_once('[')
+                .is_none_or(|(test, _)| !whole.contains(test))
+        })
+        .collect()
+}
+
+/// Parses both versions of the tree, discovers tests named as `names`
+/// allows and asks `selector`
+/// which of them the hunks affect, leaving out tests that no longer exist.
+/// `renamed_files` pairs paths at the base with their new paths, when the
+/// version control system detects renames. Files whose trees are in `trees`
+/// are not parsed again. This is the repository-agnostic
+/// core shared by the engine and the wasm bindings.
+#[allow(clippy::too_many_arguments)]
+pub fn select_changes(
+    selector: &dyn TestSelector,
+    language: Language,
+    names: &TestNames,
+    hunks: &[BetterDiff],
+    old_content: &HashMap<String, String>,
+    new_content: &HashMap<String, String>,
+    renamed_files: &[(String, String)],
+    impact: &ImpactData,
+    trees: &TreeCache,
+) -> Result<Selection, DiscoveryError> {
+    let parse = tracing::info_span!("parse", files = old_content.len()).entered();
+    let mut parser = create_parser(language)?;
+
+    let mut skipped = Vec::new();
+    let mut old_trees: HashMap<String, Tree> = HashMap::new();
+    trees.parse_all(&mut parser, old_content, &mut old_trees, &mut skipped);
+    let old_tests = get_tests(old_content, &old_trees, language, names)?;
+
+    // only trees the hunks account for are reused; the rest are parsed anew
+    let mut file_hunks: HashMap<&str, Vec<&BetterDiff>> = HashMap::new();
+    for hunk in hunks {
+        file_hunks.entry(&hunk.path).or_default().push(hunk);
+    }
+    let mut new_trees: HashMap<String, Tree> = old_trees
+        .iter()
+        .filter(
+            |(path, _)| match (old_content.get(*path), new_content.get(*path)) {
+                (Some(old), Some(new)) => hunks_reproduce(
+                    file_hunks.get(path.as_str()).into_iter().flatten().copied(),
+                    old,
+                    new,
+                ),
+                _ => false,
+            },
+        )
+        .map(|(path, tree)| (path.clone(), tree.clone()))
+        .collect();
+    edit_tree(hunks, &mut new_trees);
+    let mut new_skipped = Vec::new();
+    trees.parse_all(&mut parser, new_content, &mut new_trees, &mut new_skipped);
+    trees.sweep();
+    // a file that only fails to parse now would otherwise keep its old tree
+    for failure in &new_skipped {
+        new_trees.remove(&failure.path);
+    }
+    skipped.extend(new_skipped);
+    let new_tests = get_tests(new_content, &new_trees, language, names)?;
+    let changes = TestChanges::new(
+        &old_tests,
+        &new_tests,
+        &get_test_ranges(old_content, &old_trees, language)?,
+        &get_test_ranges(new_content, &new_trees, language)?,
+        old_content,
+        new_content,
+        renamed_files,
+    );
+    drop(parse);
+
+    let ctx = SelectionContext {
+        hunks,
+        old_content,
+        new_content,
+        old_trees: &old_trees,
+        new_trees: &new_trees,
+        old_tests: &old_tests,
+        new_tests: &new_tests,
+        changes: &changes,
+        impact,
+    };
+    let (tests, stale): (Vec<SelectedTest>, Vec<SelectedTest>) = selector
+        .select(&ctx)
+        .into_iter()
+        .partition(|test| !changes.is_gone(&test.id));
+    if !stale.is_empty() {
+        tracing::debug!(tests = ?stale, "leaving out tests that no longer exist");
+    }
+    let mut selection = Selection::new(without_covered_instances(tests));
+    selection.skipped = skipped;
+    selection.removed = changes.removed.into_iter().collect();
+    Ok(selection)
+}
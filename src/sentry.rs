@@ -0,0 +1,236 @@
+use serde_json::{json, Value};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::runner::RunResult;
+use crate::selection::Selection;
+
+/// How many cycles in a row the same selection has to fail before it is
+/// reported, so one red run while someone is mid-edit stays quiet.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum SentryError {
+    #[error("invalid Sentry DSN {0:?}")]
+    InvalidDsn(String),
+    #[error("failed to send event to Sentry: {0}")]
+    Send(String),
+}
+
+/// The parts of a DSN (`https://<key>@<host>/<project>`) needed to send
+/// events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dsn {
+    pub scheme: String,
+    pub public_key: String,
+    pub host: String,
+    pub project_id: String,
+}
+
+impl FromStr for Dsn {
+    type Err = SentryError;
+
+    fn from_str(dsn: &str) -> Result<Dsn, SentryError> {
+        let invalid = || SentryError::InvalidDsn(dsn.to_string());
+        let (scheme, rest) = dsn.split_once("://").ok_or_else(invalid)?;
+        let (public_key, rest) = rest.split_once('@').ok_or_else(invalid)?;
+        let (host, project_id) = rest.rsplit_once('/').ok_or_else(invalid)?;
+        let public_key = public_key.split(':').next().unwrap_or_default();
+        if public_key.is_empty() || host.is_empty() || project_id.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Dsn {
+            scheme: scheme.to_string(),
+            public_key: public_key.to_string(),
+            host: host.to_string(),
+            project_id: project_id.to_string(),
+        })
+    }
+}
+
+impl Dsn {
+    pub fn envelope_url(&self) -> String {
+        format!(
+            "{}://{}/api/{}/envelope/",
+            self.scheme, self.host, self.project_id
+        )
+    }
+
+    pub fn auth_header(&self) -> String {
+        format!(
+            "Sentry sentry_version=7, sentry_key={}, sentry_client={}/{}",
+            self.public_key,
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+}
+
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn event_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "{:016x}{:016x}",
+        nanos,
+        count ^ u64::from(std::process::id())
+    )
+}
+
+/// One breadcrumb per selected test and skipped file, so the event shows
+/// what the cycle was working on.
+fn breadcrumbs(selection: Option<&Selection>) -> Vec<Value> {
+    let now = timestamp();
+    let selection = match selection {
+        Some(selection) => selection,
+        None => return Vec::new(),
+    };
+    let tests = selection.tests.iter().map(|test| {
+        json!({
+            "timestamp": now,
+            "category": "selection",
+            "message": test.id,
+            "data": { "reason": test.reason },
+        })
+    });
+    let skipped = selection.skipped.iter().map(|failure| {
+        json!({
+            "timestamp": now,
+            "category": "skipped",
+            "level": "warning",
+            "message": failure.path,
+            "data": { "error": failure.error },
+        })
+    });
+    tests.chain(skipped).collect()
+}
+
+fn event(level: &str, kind: &str, message: &str, selection: Option<&Selection>) -> Value {
+    json!({
+        "event_id": event_id(),
+        "timestamp": timestamp(),
+        "platform": "native",
+        "level": level,
+        "logger": env!("CARGO_PKG_NAME"),
+        "release": format!("{}@{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        "exception": { "values": [{ "type": kind, "value": message }] },
+        "breadcrumbs": { "values": breadcrumbs(selection) },
+    })
+}
+
+/// An event for a cycle that failed with `error`.
+pub fn error_event(error: &str, selection: Option<&Selection>) -> Value {
+    event("error", "CycleError", error, selection)
+}
+
+/// An event for a selection that has failed `streak` cycles in a row.
+pub fn failure_event(selection: &Selection, result: &RunResult, streak: u32) -> Value {
+    let mut event = event(
+        "warning",
+        "RecurringTestFailure",
+        &format!(
+            "{} failed {} cycles in a row (exit code {:?})",
+            result.command, streak, result.exit_code
+        ),
+        Some(selection),
+    );
+    event["fingerprint"] = json!(["recurring-test-failure", selection.ids()]);
+    event["tags"] = json!({ "tests": selection.tests.len() });
+    event
+}
+
+/// Counts consecutive failures of the same selection.
+#[derive(Debug)]
+pub struct FailureTracker {
+    threshold: u32,
+    last: Mutex<Option<(Vec<String>, u32)>>,
+}
+
+impl FailureTracker {
+    pub fn new(threshold: u32) -> FailureTracker {
+        FailureTracker {
+            threshold,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Records a run and returns the streak length when it has just reached
+    /// the threshold, which is the one time it should be reported.
+    pub fn observe(&self, selection: &Selection, result: &RunResult) -> Option<u32> {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        if result.success() {
+            *last = None;
+            return None;
+        }
+        let ids = selection.ids();
+        let streak = match last.as_ref() {
+            Some((previous, streak)) if *previous == ids => streak + 1,
+            _ => 1,
+        };
+        *last = Some((ids, streak));
+        (streak == self.threshold).then_some(streak)
+    }
+}
+
+/// Sends cycle errors and recurring failures to a Sentry project.
+#[cfg(feature = "sentry")]
+#[derive(Debug)]
+pub struct SentryReporter {
+    dsn: Dsn,
+    failures: FailureTracker,
+}
+
+#[cfg(feature = "sentry")]
+impl SentryReporter {
+    pub fn new(dsn: Dsn) -> SentryReporter {
+        SentryReporter {
+            dsn,
+            failures: FailureTracker::new(DEFAULT_FAILURE_THRESHOLD),
+        }
+    }
+
+    pub fn report_error(&self, error: &str, selection: Option<&Selection>) {
+        self.send_logged(error_event(error, selection));
+    }
+
+    pub fn report_result(&self, selection: &Selection, result: &RunResult) {
+        if let Some(streak) = self.failures.observe(selection, result) {
+            self.send_logged(failure_event(selection, result, streak));
+        }
+    }
+
+    fn send_logged(&self, event: Value) {
+        if let Err(e) = self.send(&event) {
+            tracing::warn!("{}", e);
+        }
+    }
+
+    /// Posts `event` as a single-item envelope.
+    pub fn send(&self, event: &Value) -> Result<(), SentryError> {
+        let body = format!(
+            "{}\n{}\n{}\n",
+            json!({ "event_id": event["event_id"] }),
+            json!({ "type": "event" }),
+            event
+        );
+        ureq::post(&self.dsn.envelope_url())
+            .set("X-Sentry-Auth", &self.dsn.auth_header())
+            .set("Content-Type", "application/x-sentry-envelope")
+            .send_string(&body)
+            .map_err(|e| SentryError::Send(e.to_string()))?;
+        Ok(())
+    }
+}
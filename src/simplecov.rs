@@ -0,0 +1,92 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::discovery::slash_path;
+use crate::selection::ImpactData;
+
+/// Where SimpleCov keeps the results of every run it merges.
+pub const DATA_FILE: &str = "coverage/.resultset.json";
+
+#[derive(Debug, Error)]
+pub enum SimpleCovError {
+    #[error("failed to read coverage data {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path} is not a SimpleCov result set: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+}
+
+/// The results of one command, such as `RSpec`, by name.
+type ResultSet = BTreeMap<String, SuiteResult>;
+
+#[derive(Debug, Deserialize)]
+struct SuiteResult {
+    coverage: BTreeMap<String, FileCoverage>,
+}
+
+/// Each line's execution count, `null` for lines without code. SimpleCov
+/// before 0.18 stored the counts directly, later versions under `lines`
+/// beside branch coverage.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileCoverage {
+    Lines { lines: Vec<Option<u64>> },
+    Legacy(Vec<Option<u64>>),
+}
+
+impl FileCoverage {
+    fn counts(&self) -> &[Option<u64>] {
+        match self {
+            FileCoverage::Lines { lines } | FileCoverage::Legacy(lines) => lines,
+        }
+    }
+}
+
+/// Reads a SimpleCov `.resultset.json`, merging the results of every
+/// command in it. SimpleCov does not attribute lines to tests, so
+/// everything run is recorded outside any test, which counts for patch
+/// coverage but selects nothing. Files outside `root` are left out.
+pub fn read(path: &Path, root: &Path) -> Result<ImpactData, SimpleCovError> {
+    let content = fs::read_to_string(path).map_err(|source| SimpleCovError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let results: ResultSet =
+        serde_json::from_str(&content).map_err(|source| SimpleCovError::Json {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut files: HashMap<String, HashSet<usize>> = HashMap::new();
+    for (filename, coverage) in results.values().flat_map(|result| &result.coverage) {
+        let Some(relative) = Path::new(filename)
+            .strip_prefix(&root)
+            .ok()
+            .and_then(Path::to_str)
+        else {
+            continue;
+        };
+        let executed = coverage
+            .counts()
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| count.is_some_and(|count| count > 0))
+            .map(|(index, _)| index + 1);
+        files
+            .entry(slash_path(relative))
+            .or_default()
+            .extend(executed);
+    }
+    Ok(ImpactData {
+        lines: HashMap::from([(String::new(), files)]),
+        ..ImpactData::default()
+    })
+}
@@ -0,0 +1,151 @@
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::coverage::PatchCoverage;
+use crate::runner::RunResult;
+use crate::selection::Selection;
+
+#[derive(Debug, Error)]
+pub enum SlackError {
+    #[error("failed to post to Slack: {0}")]
+    Send(String),
+    #[error("Slack rejected the message: {0}")]
+    Api(String),
+}
+
+/// Where notifications go: an incoming webhook, which is bound to one
+/// channel and cannot thread, or a bot token and channel for
+/// `chat.postMessage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlackTarget {
+    Webhook(String),
+    Bot { token: String, channel: String },
+}
+
+impl SlackTarget {
+    /// Reads `SLACK_BOT_TOKEN` with `SLACK_CHANNEL`, or failing that
+    /// `SLACK_WEBHOOK_URL`. `var` looks up environment variables.
+    pub fn from_env<F: Fn(&str) -> Option<String>>(var: F) -> Option<SlackTarget> {
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        if let (Some(token), Some(channel)) = (var("SLACK_BOT_TOKEN"), var("SLACK_CHANNEL")) {
+            return Some(SlackTarget::Bot { token, channel });
+        }
+        var("SLACK_WEBHOOK_URL").map(SlackTarget::Webhook)
+    }
+}
+
+/// One line on how the selective run went, then the patch coverage.
+pub fn summary_text(selection: &Selection, result: &RunResult, coverage: &PatchCoverage) -> String {
+    let outcome = match (result.executed, result.success()) {
+        (false, _) => ":white_circle: Selected tests were not run",
+        (true, true) => ":large_green_circle: Selected tests passed",
+        (true, false) => ":red_circle: Selected tests failed",
+    };
+    let coverage = match coverage.percent() {
+        Some(percent) => format!(
+            "patch coverage *{:.1}%* ({}/{} changed lines)",
+            percent,
+            coverage.covered(),
+            coverage.changed()
+        ),
+        None => "no changed lines".to_string(),
+    };
+    format!(
+        "{} ({} selected), {}",
+        outcome,
+        selection.tests.len(),
+        coverage
+    )
+}
+
+/// The failing node ids, one per line, or `None` when nothing failed.
+pub fn failures_text(failing: &[&str]) -> Option<String> {
+    if failing.is_empty() {
+        return None;
+    }
+    let mut text = format!("{} failing:", failing.len());
+    for id in failing {
+        text.push_str(&format!("\n• `{}`", id));
+    }
+    Some(text)
+}
+
+/// Bodies to post, in order. With a bot the failures go in a thread under
+/// the summary; a webhook cannot thread, so they are appended to it.
+pub fn messages(target: &SlackTarget, summary: &str, failures: Option<&str>) -> Vec<Value> {
+    match (target, failures) {
+        (SlackTarget::Webhook(_), Some(failures)) => {
+            vec![json!({ "text": format!("{}\n{}", summary, failures) })]
+        }
+        (SlackTarget::Webhook(_), None) => vec![json!({ "text": summary })],
+        (SlackTarget::Bot { channel, .. }, failures) => {
+            let mut messages = vec![json!({ "channel": channel, "text": summary })];
+            messages.extend(failures.map(|text| json!({ "channel": channel, "text": text })));
+            messages
+        }
+    }
+}
+
+#[cfg(feature = "slack")]
+const POST_MESSAGE_URL: &str = "https://slack.com/api/chat.postMessage";
+
+/// Posts the outcome of a CI run to Slack.
+#[cfg(feature = "slack")]
+pub struct SlackNotifier {
+    target: SlackTarget,
+}
+
+#[cfg(feature = "slack")]
+impl SlackNotifier {
+    pub fn new(target: SlackTarget) -> SlackNotifier {
+        SlackNotifier { target }
+    }
+
+    pub fn notify(
+        &self,
+        selection: &Selection,
+        result: &RunResult,
+        coverage: &PatchCoverage,
+        failing: &[&str],
+    ) -> Result<(), SlackError> {
+        let summary = summary_text(selection, result, coverage);
+        let failures = failures_text(failing);
+        let mut thread: Option<String> = None;
+        for mut message in messages(&self.target, &summary, failures.as_deref()) {
+            if let Some(ts) = &thread {
+                message["thread_ts"] = json!(ts);
+            }
+            let reply = self.post(message)?;
+            thread = thread.or_else(|| reply["ts"].as_str().map(str::to_string));
+        }
+        Ok(())
+    }
+
+    fn post(&self, message: Value) -> Result<Value, SlackError> {
+        let send = |e: ureq::Error| SlackError::Send(e.to_string());
+        match &self.target {
+            SlackTarget::Webhook(url) => {
+                ureq::post(url).send_json(message).map_err(send)?;
+                Ok(Value::Null)
+            }
+            SlackTarget::Bot { token, .. } => {
+                let reply: Value = ureq::post(POST_MESSAGE_URL)
+                    .set("Authorization", &format!("Bearer {}", token))
+                    .send_json(message)
+                    .map_err(send)?
+                    .into_json()
+                    .map_err(|e| SlackError::Send(e.to_string()))?;
+                // the Web API answers 200 with `ok: false` on errors
+                match reply["ok"].as_bool() {
+                    Some(true) => Ok(reply),
+                    _ => Err(SlackError::Api(
+                        reply["error"]
+                            .as_str()
+                            .unwrap_or("unknown error")
+                            .to_string(),
+                    )),
+                }
+            }
+        }
+    }
+}
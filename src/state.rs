@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::diff::BetterDiff;
+use crate::discovery::{FileFailure, TreeCache};
+use crate::metrics::Metrics;
+use crate::runner::RunResult;
+use crate::selection::Selection;
+
+/// A consistent, point-in-time copy of what the engine is doing, safe to hand
+/// to other threads.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    /// Number of completed cycles.
+    pub cycle: u64,
+    pub running: bool,
+    pub selection: Option<Selection>,
+    pub last_result: Option<RunResult>,
+    pub last_error: Option<String>,
+}
+
+/// File contents at the base commit, reused until the base moves.
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    /// Revision id, as returned by `Vcs::resolve`.
+    pub commit: String,
+    pub content: Arc<HashMap<String, String>>,
+}
+
+/// The diff against the base as of the last cycle, so the next can rediff
+/// only the files the watcher saw change.
+#[derive(Debug, Clone)]
+pub struct LastDiff {
+    /// Revision id the diff was taken against.
+    pub commit: String,
+    pub hunks: Vec<BetterDiff>,
+    pub renames: Vec<(String, String)>,
+    /// Files the full diff could not read, still reported by later cycles.
+    pub failures: Vec<FileFailure>,
+    /// The working content the diff was checked against, so the next cycle
+    /// only reads the files that changed.
+    pub content: Arc<HashMap<String, String>>,
+    /// Files reading the working content skipped, as `failures`.
+    pub content_failures: Vec<FileFailure>,
+    /// Cycles since the whole tree was last diffed.
+    pub scoped: usize,
+}
+
+/// Engine state shared between the watcher and any other front ends. Readers
+/// only ever see whole snapshots; writers go through the methods below.
+#[derive(Debug, Default)]
+pub struct EngineState {
+    snapshot: RwLock<Snapshot>,
+    baseline: RwLock<Option<Baseline>>,
+    last_diff: RwLock<Option<LastDiff>>,
+    trees: TreeCache,
+    metrics: Metrics,
+}
+
+impl EngineState {
+    pub fn new() -> EngineState {
+        EngineState::default()
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Trees parsed in earlier cycles, reused while files keep their
+    /// content.
+    pub fn trees(&self) -> &TreeCache {
+        &self.trees
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn update<F: FnOnce(&mut Snapshot)>(&self, f: F) {
+        let mut snapshot = self.snapshot.write().unwrap_or_else(|e| e.into_inner());
+        f(&mut snapshot);
+    }
+
+    pub fn begin_run(&self, selection: &Selection) {
+        self.update(|s| {
+            s.running = true;
+            s.selection = Some(selection.clone());
+        });
+    }
+
+    pub fn finish_run(&self, result: &RunResult) {
+        self.update(|s| {
+            s.running = false;
+            s.cycle += 1;
+            s.last_result = Some(result.clone());
+            s.last_error = None;
+        });
+    }
+
+    pub fn record_error(&self, error: &str) {
+        self.update(|s| {
+            s.running = false;
+            s.cycle += 1;
+            s.last_error = Some(error.to_string());
+        });
+    }
+
+    /// The cached baseline content, if it was built for `commit`.
+    pub fn baseline(&self, commit: &str) -> Option<Arc<HashMap<String, String>>> {
+        let baseline = self.baseline.read().unwrap_or_else(|e| e.into_inner());
+        baseline
+            .as_ref()
+            .filter(|b| b.commit == commit)
+            .map(|b| b.content.clone())
+    }
+
+    /// Drops the cached baseline so the next cycle rebuilds it from git,
+    /// and diffs the whole tree again.
+    pub fn invalidate_baseline(&self) {
+        let mut baseline = self.baseline.write().unwrap_or_else(|e| e.into_inner());
+        *baseline = None;
+        let mut last_diff = self.last_diff.write().unwrap_or_else(|e| e.into_inner());
+        *last_diff = None;
+    }
+
+    /// Takes the last cycle's diff, if it was taken against `commit`, so
+    /// its content can be updated in place. The cycle puts its own back
+    /// with `set_last_diff`; one that fails diffs the whole tree next time.
+    pub fn take_last_diff(&self, commit: &str) -> Option<LastDiff> {
+        let mut last_diff = self.last_diff.write().unwrap_or_else(|e| e.into_inner());
+        last_diff.take().filter(|d| d.commit == commit)
+    }
+
+    pub fn set_last_diff(&self, diff: LastDiff) {
+        let mut last_diff = self.last_diff.write().unwrap_or_else(|e| e.into_inner());
+        *last_diff = Some(diff);
+    }
+
+    pub fn set_baseline(
+        &self,
+        commit: &str,
+        content: HashMap<String, String>,
+    ) -> Arc<HashMap<String, String>> {
+        let content = Arc::new(content);
+        let mut baseline = self.baseline.write().unwrap_or_else(|e| e.into_inner());
+        *baseline = Some(Baseline {
+            commit: commit.to_string(),
+            content: content.clone(),
+        });
+        content
+    }
+}
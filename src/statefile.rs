@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::coverage::PatchCoverage;
+use crate::nvim::{failing_tests, marks, FileMarks};
+use crate::runner::RunResult;
+use crate::selection::Selection;
+use crate::state::Snapshot;
+
+/// Directory under the repository root holding the state file.
+pub const STATE_DIR: &str = ".instant-patch";
+
+pub const STATE_FILE: &str = "state.json";
+
+/// Bumped whenever a field changes meaning or goes away, so plugins can tell
+/// a file they do not understand.
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum StateFileError {
+    #[error("failed to write {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to encode state: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Everything an editor plugin needs to draw, as written to the state file.
+#[derive(Debug, Clone, Serialize)]
+pub struct StateDocument<'a> {
+    pub version: u32,
+    /// Completed cycles; changes every time a run finishes.
+    pub cycle: u64,
+    pub running: bool,
+    pub selection: Option<&'a Selection>,
+    pub last_result: Option<&'a RunResult>,
+    pub last_error: Option<&'a str>,
+    /// Changed lines per file, with the tests failing in it.
+    pub files: Vec<FileMarks>,
+}
+
+impl<'a> StateDocument<'a> {
+    pub fn new(root: &Path, snapshot: &'a Snapshot, coverage: &PatchCoverage) -> StateDocument<'a> {
+        let failing = match &snapshot.last_result {
+            Some(result) => failing_tests(root, result),
+            None => Vec::new(),
+        };
+        StateDocument {
+            version: STATE_VERSION,
+            cycle: snapshot.cycle,
+            running: snapshot.running,
+            selection: snapshot.selection.as_ref(),
+            last_result: snapshot.last_result.as_ref(),
+            last_error: snapshot.last_error.as_deref(),
+            files: marks(coverage, &failing),
+        }
+    }
+}
+
+/// Where the state file of the repository at `root` lives.
+pub fn path(root: &Path) -> PathBuf {
+    root.join(STATE_DIR).join(STATE_FILE)
+}
+
+/// Replaces `path` with `content` by renaming a sibling temporary file over
+/// it, so readers see either the old file or the new one, never a partial
+/// write.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary);
+    })
+}
+
+/// The state directory of the repository at `root`, created with a
+/// `.gitignore` that keeps it out of `git status` if it does not exist.
+pub fn state_dir(root: &Path) -> io::Result<PathBuf> {
+    let dir = root.join(STATE_DIR);
+    if !dir.is_dir() {
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join(".gitignore"), "*\n")?;
+    }
+    Ok(dir)
+}
+
+/// Writes the state file for `root` into its state directory.
+pub fn write(
+    root: &Path,
+    snapshot: &Snapshot,
+    coverage: &PatchCoverage,
+) -> Result<(), StateFileError> {
+    let path = path(root);
+    let io = |source| StateFileError::Io {
+        path: path.clone(),
+        source,
+    };
+    state_dir(root).map_err(io)?;
+    let document = StateDocument::new(root, snapshot, coverage);
+    let mut content = serde_json::to_vec_pretty(&document)?;
+    content.push(b'\n');
+    write_atomic(&path, &content).map_err(io)
+}
@@ -0,0 +1,236 @@
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Variable carrying the W3C trace context into test processes, as read by
+/// OpenTelemetry SDKs and `pytest-opentelemetry`.
+pub const TRACEPARENT_ENV: &str = "TRACEPARENT";
+
+/// A position in a trace: the trace and the span new work hangs under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+}
+
+impl TraceContext {
+    /// The W3C `traceparent` value, always sampled.
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+
+    /// Reads a version 00 `traceparent`, rejecting the all-zero ids the
+    /// spec forbids.
+    pub fn parse(traceparent: &str) -> Option<TraceContext> {
+        let mut parts = traceparent.trim().split('-');
+        let (version, trace, span, _flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || trace.len() != 32 || span.len() != 16 || parts.next().is_some() {
+            return None;
+        }
+        let context = TraceContext {
+            trace_id: u128::from_str_radix(trace, 16).ok()?,
+            span_id: u64::from_str_radix(span, 16).ok()?,
+        };
+        (context.trace_id != 0 && context.span_id != 0).then_some(context)
+    }
+}
+
+/// A random, non-zero id. Uniqueness is all that matters, so the standard
+/// library's randomly keyed hasher stands in for a random number generator.
+pub fn new_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(unix_nanos(SystemTime::now()));
+        let id = hasher.finish();
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub name: String,
+    pub context: TraceContext,
+    pub parent_span_id: Option<u64>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(String, String)>,
+    pub error: Option<String>,
+}
+
+impl Span {
+    fn to_otlp(&self) -> Value {
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        let mut span = json!({
+            "traceId": format!("{:032x}", self.context.trace_id),
+            "spanId": format!("{:016x}", self.context.span_id),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(self.end).to_string(),
+            "attributes": attributes,
+            "status": match &self.error {
+                Some(message) => json!({ "code": 2, "message": message }),
+                None => json!({ "code": 1 }),
+            },
+        });
+        if let Some(parent) = self.parent_span_id {
+            span["parentSpanId"] = json!(format!("{:016x}", parent));
+        }
+        span
+    }
+}
+
+/// The spans of one cycle, all children of a root `cycle` span, plus any
+/// spans executors sent back for it.
+#[derive(Debug)]
+pub struct CycleTrace {
+    root: TraceContext,
+    start: SystemTime,
+    attributes: Mutex<Vec<(String, String)>>,
+    spans: Mutex<Vec<Span>>,
+    remote: Mutex<Vec<Value>>,
+}
+
+impl CycleTrace {
+    pub fn start() -> CycleTrace {
+        let mut trace_id = 0;
+        while trace_id == 0 {
+            trace_id = (u128::from(new_id()) << 64) | u128::from(new_id());
+        }
+        CycleTrace {
+            root: TraceContext {
+                trace_id,
+                span_id: new_id(),
+            },
+            start: SystemTime::now(),
+            attributes: Mutex::new(Vec::new()),
+            spans: Mutex::new(Vec::new()),
+            remote: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The root span's context.
+    pub fn context(&self) -> TraceContext {
+        self.root
+    }
+
+    /// Runs `f` as a child span of the cycle named `name`. `f` gets the
+    /// span's context to hand on to work it starts elsewhere.
+    pub fn span<T, E: ToString, F>(&self, name: &str, f: F) -> Result<T, E>
+    where
+        F: FnOnce(TraceContext) -> Result<T, E>,
+    {
+        let context = TraceContext {
+            trace_id: self.root.trace_id,
+            span_id: new_id(),
+        };
+        let start = SystemTime::now();
+        let result = f(context);
+        let span = Span {
+            name: name.to_string(),
+            context,
+            parent_span_id: Some(self.root.span_id),
+            start,
+            end: SystemTime::now(),
+            attributes: Vec::new(),
+            error: result.as_ref().err().map(E::to_string),
+        };
+        self.spans
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(span);
+        result
+    }
+
+    /// Records an attribute on the root span.
+    pub fn set_attribute<V: ToString>(&self, key: &str, value: V) {
+        self.attributes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((key.to_string(), value.to_string()));
+    }
+
+    /// Keeps OTLP/JSON `resourceSpans` reported by an executor, to export
+    /// with the cycle's own.
+    pub fn ingest(&self, resource_spans: &[Value]) {
+        self.remote
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(resource_spans.iter().cloned());
+    }
+
+    /// Closes the root span and renders the whole trace as an OTLP/JSON
+    /// `ExportTraceServiceRequest`.
+    pub fn finish(self, service_name: &str, error: Option<String>) -> Value {
+        let mut spans = self.spans.into_inner().unwrap_or_else(|e| e.into_inner());
+        spans.insert(
+            0,
+            Span {
+                name: "cycle".to_string(),
+                context: self.root,
+                parent_span_id: None,
+                start: self.start,
+                end: SystemTime::now(),
+                attributes: self
+                    .attributes
+                    .into_inner()
+                    .unwrap_or_else(|e| e.into_inner()),
+                error,
+            },
+        );
+        let mut resource_spans = vec![json!({
+            "resource": {
+                "attributes": [{
+                    "key": "service.name",
+                    "value": { "stringValue": service_name },
+                }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans.iter().map(Span::to_otlp).collect::<Vec<_>>(),
+            }],
+        })];
+        resource_spans.extend(self.remote.into_inner().unwrap_or_else(|e| e.into_inner()));
+        json!({ "resourceSpans": resource_spans })
+    }
+}
+
+/// The `resourceSpans` of an OTLP/JSON trace export found in `body`, such as
+/// a remote executor's reply. Anything else yields nothing.
+pub fn resource_spans(body: &str) -> Vec<Value> {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| value.get("resourceSpans")?.as_array().cloned())
+        .unwrap_or_default()
+}
+
+/// Posts a trace rendered by `CycleTrace::finish` to an OTLP/HTTP collector.
+#[cfg(feature = "otlp")]
+pub fn export_otlp(endpoint: &str, payload: Value) -> Result<(), crate::metrics::MetricsError> {
+    let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    ureq::post(&url)
+        .send_json(payload)
+        .map_err(|e| crate::metrics::MetricsError::Export {
+            endpoint: url.clone(),
+            message: e.to_string(),
+        })?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use git2::{DiffOptions, ObjectType};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::selection::{SelectedTest, SelectionContext, TestSelector};
+use crate::vcs;
+
+/// Files pytest, or the tools it loads, read settings from. Changing one
+/// may change how every test below its directory runs.
+pub const SETTINGS_FILES: [&str; 4] = ["pytest.ini", "pyproject.toml", "setup.cfg", "tox.ini"];
+
+/// Fixtures and hooks shared by every test in and below its directory.
+pub const CONFTEST: &str = "conftest.py";
+
+/// Whether `path`, relative to the root, is a settings file or a
+/// `conftest.py`.
+pub fn is_trigger(path: &str) -> bool {
+    let name = file_name(path);
+    name == CONFTEST || SETTINGS_FILES.contains(&name)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The directory a trigger file applies to, `""` for the root.
+fn scope(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Selects every test in and below the directory of a changed
+/// `conftest.py` or pytest settings file, which can affect those tests
+/// without touching their code. A change at the root reruns the whole
+/// suite. `conftest.py` files are compared as analysed; settings files are
+/// not Python, so they are compared against the base commit in git.
+pub struct TriggerSelector {
+    root: PathBuf,
+    base: String,
+}
+
+impl TriggerSelector {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(root: P, base: S) -> TriggerSelector {
+        TriggerSelector {
+            root: root.into(),
+            base: base.into(),
+        }
+    }
+
+    /// Settings files that differ from the base, relative to the root.
+    pub fn changed_settings(&self) -> Result<BTreeSet<String>, git2::Error> {
+        let repo = vcs::open_git(&self.root)?;
+        let tree = repo.revparse_single(&self.base)?.peel(ObjectType::Tree)?;
+        let mut options = DiffOptions::new();
+        options.include_untracked(true).recurse_untracked_dirs(true);
+        for name in SETTINGS_FILES {
+            // without pathspec magic `*` also matches `/`
+            options.pathspec(format!("*{}", name));
+        }
+        let diff = repo.diff_tree_to_workdir_with_index(tree.as_tree(), Some(&mut options))?;
+        Ok(diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .filter_map(Path::to_str)
+            .filter(|path| SETTINGS_FILES.contains(&file_name(path)))
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+impl TestSelector for TriggerSelector {
+    fn name(&self) -> &str {
+        "triggers"
+    }
+
+    fn select(&self, ctx: &SelectionContext) -> Vec<SelectedTest> {
+        let mut changed: BTreeSet<String> = ctx
+            .old_content
+            .keys()
+            .chain(ctx.new_content.keys())
+            .filter(|path| file_name(path) == CONFTEST)
+            .filter(|path| ctx.old_content.get(*path) != ctx.new_content.get(*path))
+            .cloned()
+            .collect();
+        match self.changed_settings() {
+            Ok(settings) => changed.extend(settings),
+            Err(e) => tracing::warn!("cannot check test settings files: {}", e),
+        }
+        if changed.is_empty() {
+            return Vec::new();
+        }
+        ctx.new_tests
+            .iter()
+            .filter_map(|id| {
+                let file = id.split_once("::").map_or(id.as_str(), |(file, _)| file);
+                let trigger = changed.iter().find(|trigger| {
+                    let dir = scope(trigger);
+                    dir.is_empty()
+                        || file
+                            .strip_prefix(dir)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })?;
+                Some(SelectedTest::new(
+                    id.clone(),
+                    format!("{} changed", trigger),
+                ))
+            })
+            .collect()
+    }
+}
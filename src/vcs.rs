@@ -0,0 +1,696 @@
+use git2::{Delta, DiffFindOptions, DiffOptions, ErrorCode, Object, ObjectType, Oid, Repository};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+use crate::config::BaseMode;
+use crate::diff::{
+    diff_contents, get_commit_diff, get_diff, get_staged_diff, get_unstaged_diff, BetterDiff,
+    DiffError,
+};
+use crate::discovery::{
+    create_index_content_map, create_new_content_map, create_old_content_map, refresh_content_map,
+    FileFailure, PathFilter,
+};
+use crate::engine::EngineError;
+use crate::language::Language;
+use crate::patch::{self, FilePatch, PatchLine};
+
+/// Names the repository directory when it is not `.git` in the work tree.
+pub const GIT_DIR: &str = "GIT_DIR";
+
+/// Names the work tree of the repository `GIT_DIR` names.
+pub const GIT_WORK_TREE: &str = "GIT_WORK_TREE";
+
+#[derive(Debug, Error)]
+pub enum VcsError {
+    #[error("failed to run {program}: {source}")]
+    Spawn {
+        program: String,
+        source: std::io::Error,
+    },
+    #[error("{program} {command} failed: {message}")]
+    Command {
+        program: String,
+        command: String,
+        message: String,
+    },
+    #[error("cannot resolve base {base}: {message}")]
+    InvalidBase { base: String, message: String },
+    #[error("{0} only works with git")]
+    Unsupported(String),
+}
+
+/// Version control systems the engine can read baselines from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VcsKind {
+    Git,
+    Jujutsu,
+    Mercurial,
+}
+
+impl VcsKind {
+    /// The system managing the checkout at `root`. A jj repository colocated
+    /// with git is read through git, which sees the same commits.
+    pub fn detect(root: &Path) -> Option<VcsKind> {
+        if open_git(root).is_ok() {
+            Some(VcsKind::Git)
+        } else if root.join(".jj").is_dir() {
+            Some(VcsKind::Jujutsu)
+        } else if root.join(".hg").is_dir() {
+            Some(VcsKind::Mercurial)
+        } else {
+            None
+        }
+    }
+}
+
+/// The root of the checkout `path` is in, so the tool can be started from
+/// any subdirectory: git's work tree, found the way `git` looks for it, or
+/// the nearest directory holding `.jj` or `.hg`. Paths are reported relative
+/// to it everywhere. `path` itself is returned when it is the root or not in
+/// a checkout.
+pub fn checkout_root(path: &Path) -> PathBuf {
+    let Ok(canonical) = std::fs::canonicalize(path) else {
+        return path.to_path_buf();
+    };
+    // as in git, an explicit repository makes the current directory the top
+    // of the work tree unless it is named too
+    let found = match (env::var_os(GIT_DIR), env::var_os(GIT_WORK_TREE)) {
+        (_, Some(work_tree)) => Some(PathBuf::from(work_tree)),
+        (Some(_), None) => None,
+        (None, None) => discover_root(&canonical),
+    };
+    match found.and_then(|root| std::fs::canonicalize(root).ok()) {
+        Some(root) if root != canonical => root,
+        _ => path.to_path_buf(),
+    }
+}
+
+fn discover_root(canonical: &Path) -> Option<PathBuf> {
+    match Repository::discover(canonical) {
+        Ok(repo) => repo.workdir().map(Path::to_path_buf),
+        Err(_) => canonical
+            .ancestors()
+            .find(|dir| dir.join(".jj").is_dir() || dir.join(".hg").is_dir())
+            .map(Path::to_path_buf),
+    }
+}
+
+/// Opens the git repository whose work tree is `root`. Linked worktrees
+/// open through their `.git` file; when `GIT_DIR` names the repository, as
+/// for a bare repository checked out elsewhere, `root` is used as its work
+/// tree.
+pub fn open_git(root: &Path) -> Result<Repository, git2::Error> {
+    match env::var_os(GIT_DIR) {
+        Some(git_dir) => {
+            let repo = Repository::open(git_dir)?;
+            repo.set_workdir(root, false)?;
+            Ok(repo)
+        }
+        None => Repository::open(root),
+    }
+}
+
+/// The directory shared by every worktree of `repo`, where hooks live. It
+/// is the repository's own directory except in a linked worktree.
+pub fn common_dir(repo: &Repository) -> PathBuf {
+    let common = std::fs::read_to_string(repo.path().join("commondir"))
+        .and_then(|dir| std::fs::canonicalize(repo.path().join(dir.trim())));
+    match common {
+        Ok(dir) => dir,
+        Err(_) => repo.path().to_path_buf(),
+    }
+}
+
+/// Where the engine gets the base revision's files and the changes made
+/// since. Revisions are the system's full ids, as returned by `resolve`.
+pub trait Vcs: Send + Sync {
+    /// The id of the revision `base` names. `HEAD` means the working copy's
+    /// parent in every system.
+    fn resolve(&self, base: &str) -> Result<String, EngineError>;
+
+    /// The id of the newest revision both the working copy's parent and
+    /// `base` descend from.
+    fn merge_base(&self, base: &str) -> Result<String, EngineError>;
+
+    /// Contents of the `language` files `filter` matches at `rev`, by path
+    /// from the root.
+    fn base_content(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError>;
+
+    /// Contents of the `language` files `filter` matches, as analysed now.
+    fn working_content(
+        &self,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError>;
+
+    /// One `BetterDiff` per changed hunk since `rev` of a `language` file
+    /// `filter` matches.
+    fn diff(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError>;
+
+    /// One `BetterDiff` per changed hunk of a `language` file `filter`
+    /// matches between revisions `from` and `to`, with offsets into the
+    /// files at `to`. Only git diffs two revisions.
+    fn diff_revisions(
+        &self,
+        _from: &str,
+        _to: &str,
+        _language: Language,
+        _filter: &PathFilter,
+        _failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError> {
+        Err(VcsError::Unsupported("diffing two revisions".to_string()).into())
+    }
+
+    /// Whether `path`, relative to the root, is ignored, as by `.gitignore`.
+    /// Systems that cannot tell ignore nothing.
+    fn is_ignored(&self, _path: &str) -> bool {
+        false
+    }
+
+    /// Files renamed since `rev`, as (path at `rev`, path now). Systems
+    /// that do not detect renames report none, so tests in a moved file
+    /// read as removed and added.
+    fn renamed_files(&self, _rev: &str) -> Result<Vec<(String, String)>, EngineError> {
+        Ok(Vec::new())
+    }
+
+    /// Reads `changed` again into `content`, contents `working_content`
+    /// gave before. False when everything has to be read again instead, in
+    /// which case `content` may be partly updated. Systems whose working
+    /// content is not the working tree always read everything.
+    fn refresh_working_content(
+        &self,
+        _content: &mut HashMap<String, String>,
+        _changed: &BTreeSet<String>,
+    ) -> bool {
+        false
+    }
+}
+
+/// Which uncommitted changes of a git checkout are measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChangeScope {
+    /// The working tree against the base.
+    #[default]
+    All,
+    /// The index against the base: what is about to be committed.
+    Staged,
+    /// The working tree against the index: what is not staged yet.
+    Unstaged,
+}
+
+/// Opens the repository at `root` with whichever system manages it.
+/// `scope` restricts a git checkout to staged or unstaged changes.
+pub fn open(root: &Path, scope: ChangeScope) -> Result<Box<dyn Vcs>, EngineError> {
+    let kind = match VcsKind::detect(root) {
+        Some(kind) => kind,
+        // report why git could not open it
+        None => {
+            return Err(open_repository(root)
+                .err()
+                .unwrap_or_else(|| EngineError::NotARepository(root.to_path_buf())))
+        }
+    };
+    let unsupported = match scope {
+        ChangeScope::All => None,
+        ChangeScope::Staged => Some("checking staged changes"),
+        ChangeScope::Unstaged => Some("checking unstaged changes"),
+    };
+    if let (Some(what), false) = (unsupported, kind == VcsKind::Git) {
+        return Err(VcsError::Unsupported(what.to_string()).into());
+    }
+    Ok(match kind {
+        VcsKind::Git => Box::new(GitVcs::new(root, scope)),
+        kind => Box::new(CliVcs::new(root, kind)),
+    })
+}
+
+/// The id of the revision changes are measured against, as `mode` picks it
+/// for `base`.
+pub fn resolve(vcs: &dyn Vcs, base: &str, mode: BaseMode) -> Result<String, EngineError> {
+    match mode {
+        BaseMode::Commit => vcs.resolve(base),
+        BaseMode::MergeBase => vcs.merge_base(base),
+    }
+}
+
+pub(crate) fn open_repository(root: &Path) -> Result<Repository, EngineError> {
+    open_git(root).map_err(|source| match source.code() {
+        ErrorCode::NotFound => EngineError::NotARepository(root.to_path_buf()),
+        _ => EngineError::OpenRepository {
+            path: root.to_path_buf(),
+            source,
+        },
+    })
+}
+
+pub(crate) fn resolve_base<'r>(
+    repo: &'r Repository,
+    base: &str,
+) -> Result<Object<'r>, EngineError> {
+    repo.revparse_single(base)
+        .and_then(|object| object.peel(ObjectType::Commit))
+        .map_err(|source| EngineError::InvalidBase {
+            base: base.to_string(),
+            source,
+        })
+}
+
+/// A git checkout, read through libgit2. With `ChangeScope::Unstaged` the
+/// index stands in for the base revision's files, while revisions still
+/// name the base commit.
+pub struct GitVcs {
+    root: PathBuf,
+    scope: ChangeScope,
+}
+
+impl GitVcs {
+    pub fn new<P: Into<PathBuf>>(root: P, scope: ChangeScope) -> GitVcs {
+        GitVcs {
+            root: root.into(),
+            scope,
+        }
+    }
+
+    fn commit<'r>(&self, repo: &'r Repository, rev: &str) -> Result<Object<'r>, EngineError> {
+        Ok(repo.find_object(Oid::from_str(rev)?, Some(ObjectType::Commit))?)
+    }
+}
+
+impl Vcs for GitVcs {
+    fn resolve(&self, base: &str) -> Result<String, EngineError> {
+        let repo = open_repository(&self.root)?;
+        let commit = resolve_base(&repo, base)?;
+        Ok(commit.id().to_string())
+    }
+
+    fn merge_base(&self, base: &str) -> Result<String, EngineError> {
+        let repo = open_repository(&self.root)?;
+        let commit = resolve_base(&repo, base)?;
+        let head = resolve_base(&repo, "HEAD")?;
+        repo.merge_base(head.id(), commit.id())
+            .map(|oid| oid.to_string())
+            .map_err(|source| EngineError::InvalidBase {
+                base: base.to_string(),
+                source,
+            })
+    }
+
+    fn base_content(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        let repo = open_repository(&self.root)?;
+        if self.scope == ChangeScope::Unstaged {
+            return Ok(create_index_content_map(&repo, language, filter, failures)?);
+        }
+        let commit = self.commit(&repo, rev)?;
+        Ok(create_old_content_map(
+            &repo, &commit, language, filter, failures,
+        )?)
+    }
+
+    fn working_content(
+        &self,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        Ok(match self.scope {
+            ChangeScope::Staged => {
+                let repo = open_repository(&self.root)?;
+                create_index_content_map(&repo, language, filter, failures)?
+            }
+            _ => create_new_content_map(&self.root, language, filter, failures)?,
+        })
+    }
+
+    fn refresh_working_content(
+        &self,
+        content: &mut HashMap<String, String>,
+        changed: &BTreeSet<String>,
+    ) -> bool {
+        match self.scope {
+            ChangeScope::Staged => false,
+            _ => refresh_content_map(&self.root, content, changed.iter().map(String::as_str)),
+        }
+    }
+
+    fn diff(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError> {
+        let repo = open_repository(&self.root)?;
+        if self.scope == ChangeScope::Unstaged {
+            return Ok(get_unstaged_diff(&repo, language, filter, failures)?);
+        }
+        let commit = self.commit(&repo, rev)?;
+        Ok(match self.scope {
+            ChangeScope::Staged => get_staged_diff(&repo, &commit, language, filter, failures)?,
+            _ => get_diff(&repo, &commit, language, filter, failures)?,
+        })
+    }
+
+    fn diff_revisions(
+        &self,
+        from: &str,
+        to: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError> {
+        let repo = open_repository(&self.root)?;
+        let (from, to) = (self.commit(&repo, from)?, self.commit(&repo, to)?);
+        Ok(get_commit_diff(
+            &repo, &from, &to, language, filter, failures,
+        )?)
+    }
+
+    fn is_ignored(&self, path: &str) -> bool {
+        open_git(&self.root)
+            .and_then(|repo| repo.is_path_ignored(path))
+            .unwrap_or(false)
+    }
+
+    fn renamed_files(&self, rev: &str) -> Result<Vec<(String, String)>, EngineError> {
+        let repo = open_repository(&self.root)?;
+        let tree = self.commit(&repo, rev)?.peel_to_tree()?;
+        let mut options = DiffOptions::new();
+        let mut diff = match self.scope {
+            ChangeScope::Staged => {
+                repo.diff_tree_to_index(Some(&tree), None, Some(&mut options))?
+            }
+            ChangeScope::Unstaged => {
+                options.include_untracked(true).recurse_untracked_dirs(true);
+                repo.diff_index_to_workdir(None, Some(&mut options))?
+            }
+            ChangeScope::All => {
+                options.include_untracked(true).recurse_untracked_dirs(true);
+                repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))?
+            }
+        };
+        diff.find_similar(Some(
+            DiffFindOptions::new().renames(true).for_untracked(true),
+        ))?;
+        Ok(diff
+            .deltas()
+            .filter(|delta| delta.status() == Delta::Renamed)
+            .filter_map(|delta| {
+                let old = delta.old_file().path()?.to_str()?;
+                let new = delta.new_file().path()?.to_str()?;
+                Some((old.to_string(), new.to_string()))
+            })
+            .collect())
+    }
+}
+
+/// A Mercurial or native jj checkout, read by running `hg` or `jj`. Hunks
+/// are computed from the contents of each changed file, so they match what
+/// git would report for the same change.
+pub struct CliVcs {
+    root: PathBuf,
+    kind: VcsKind,
+    binary: String,
+}
+
+impl CliVcs {
+    pub fn new<P: Into<PathBuf>>(root: P, kind: VcsKind) -> CliVcs {
+        let binary = match kind {
+            VcsKind::Mercurial => "hg",
+            _ => "jj",
+        };
+        CliVcs {
+            root: root.into(),
+            kind,
+            binary: binary.to_string(),
+        }
+    }
+
+    /// The executable to run, `hg` or `jj` by default.
+    pub fn binary<S: Into<String>>(mut self, binary: S) -> CliVcs {
+        self.binary = binary.into();
+        self
+    }
+
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>, VcsError> {
+        let output = Command::new(&self.binary)
+            .args(args)
+            .current_dir(&self.root)
+            .output()
+            .map_err(|source| VcsError::Spawn {
+                program: self.binary.clone(),
+                source,
+            })?;
+        if !output.status.success() {
+            return Err(VcsError::Command {
+                program: self.binary.clone(),
+                command: args.first().copied().unwrap_or_default().to_string(),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    fn lines(&self, args: &[&str]) -> Result<Vec<String>, VcsError> {
+        Ok(String::from_utf8_lossy(&self.run(args)?)
+            .lines()
+            .map(|line| line.trim().replace('\\', "/"))
+            .filter(|line| !line.is_empty())
+            .collect())
+    }
+
+    /// A pattern matching exactly `path`, relative to the root.
+    fn exact(&self, path: &str) -> String {
+        match self.kind {
+            VcsKind::Mercurial => format!("path:{}", path),
+            _ => format!("root-file:{:?}", path),
+        }
+    }
+
+    fn files(&self, rev: &str) -> Result<Vec<String>, VcsError> {
+        match self.kind {
+            VcsKind::Mercurial => self.lines(&["files", "-r", rev]),
+            _ => self.lines(&["file", "list", "-r", rev]),
+        }
+    }
+
+    fn cat(&self, rev: &str, path: &str) -> Result<Vec<u8>, VcsError> {
+        let pattern = self.exact(path);
+        match self.kind {
+            VcsKind::Mercurial => self.run(&["cat", "-r", rev, &pattern]),
+            _ => self.run(&["file", "show", "-r", rev, &pattern]),
+        }
+    }
+
+    /// The content of each of `paths` at `rev`, read from a git-style diff
+    /// against the empty revision, a batch of paths per process rather
+    /// than a process per file. Files the diff leaves out, being empty or
+    /// binary, and batches that are not UTF-8 are read one by one.
+    fn cat_all(
+        &self,
+        rev: &str,
+        paths: &[String],
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, VcsError> {
+        let mut content = HashMap::new();
+        for batch in paths.chunks(CAT_BATCH) {
+            let patterns: Vec<String> = batch.iter().map(|path| self.exact(path)).collect();
+            let mut args = match self.kind {
+                VcsKind::Mercurial => vec!["diff", "--git", "-r", "null", "-r", rev],
+                _ => vec!["diff", "--git", "--from", "root()", "--to", rev],
+            };
+            args.extend(patterns.iter().map(String::as_str));
+            let files = String::from_utf8(self.run(&args)?)
+                .ok()
+                .and_then(|diff| patch::parse(&diff).ok())
+                .unwrap_or_default();
+            content.extend(
+                files
+                    .iter()
+                    .filter(|file| file.old_path.is_none())
+                    .filter_map(|file| Some((file.new_path.clone()?, added_content(file))))
+                    .filter(|(path, _)| batch.contains(path)),
+            );
+            for path in batch {
+                if content.contains_key(path) {
+                    continue;
+                }
+                if let Some(text) = decode(path, self.cat(rev, path)?, failures) {
+                    content.insert(path.clone(), text);
+                }
+            }
+        }
+        Ok(content)
+    }
+
+    /// Paths added, modified or removed in the working copy since `rev`,
+    /// untracked files included as git's are.
+    fn changed(&self, rev: &str) -> Result<Vec<String>, VcsError> {
+        match self.kind {
+            VcsKind::Mercurial => self.lines(&["status", "--rev", rev, "-mardu", "--no-status"]),
+            _ => self.lines(&["diff", "--from", rev, "--to", "@", "--name-only"]),
+        }
+    }
+}
+
+/// Templates printing each revision's full id on a line of its own, so a
+/// revset naming several can be told from one naming a single revision.
+const HG_ID: &str = r"{node}\n";
+const JJ_ID: &str = r#"commit_id ++ "\n""#;
+
+/// How many paths `CliVcs::cat_all` names on one command line.
+const CAT_BATCH: usize = 500;
+
+/// The content of a file `file` adds from nothing.
+fn added_content(file: &FilePatch) -> String {
+    file.hunks
+        .iter()
+        .flat_map(|hunk| &hunk.lines)
+        .filter_map(|line| match line {
+            PatchLine::Added(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn decode(path: &str, bytes: Vec<u8>, failures: &mut Vec<FileFailure>) -> Option<String> {
+    match String::from_utf8(bytes) {
+        Ok(content) => Some(content),
+        Err(_) => {
+            failures.push(FileFailure::new(
+                path,
+                DiffError::NonUtf8Content(path.to_string()),
+            ));
+            None
+        }
+    }
+}
+
+impl Vcs for CliVcs {
+    fn resolve(&self, base: &str) -> Result<String, EngineError> {
+        let args: Vec<&str> = match (self.kind, base) {
+            (VcsKind::Mercurial, "HEAD") => vec!["log", "-r", ".", "--template", HG_ID],
+            (VcsKind::Mercurial, _) => vec!["log", "-r", base, "--template", HG_ID],
+            (_, "HEAD") => vec!["log", "--no-graph", "-r", "@-", "-T", JJ_ID],
+            (_, _) => vec!["log", "--no-graph", "-r", base, "-T", JJ_ID],
+        };
+        let invalid = |message: String| VcsError::InvalidBase {
+            base: base.to_string(),
+            message,
+        };
+        let output = self.run(&args).map_err(|e| invalid(e.to_string()))?;
+        let ids: Vec<String> = String::from_utf8_lossy(&output)
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        match ids.as_slice() {
+            [id] => Ok(id.clone()),
+            [] => Err(invalid("no such revision".to_string()).into()),
+            _ => Err(invalid("names more than one revision".to_string()).into()),
+        }
+    }
+
+    fn merge_base(&self, base: &str) -> Result<String, EngineError> {
+        let rev = self.resolve(base)?;
+        let revset = match self.kind {
+            VcsKind::Mercurial => format!("ancestor(., {})", rev),
+            _ => format!("fork_point(@- | {})", rev),
+        };
+        self.resolve(&revset)
+    }
+
+    fn base_content(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        let paths: Vec<String> = self
+            .files(rev)?
+            .into_iter()
+            .filter(|path| language.matches_path(Path::new(path)) && filter.matches(path))
+            .collect();
+        Ok(self.cat_all(rev, &paths, failures)?)
+    }
+
+    fn working_content(
+        &self,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<HashMap<String, String>, EngineError> {
+        Ok(create_new_content_map(
+            &self.root, language, filter, failures,
+        )?)
+    }
+
+    fn refresh_working_content(
+        &self,
+        content: &mut HashMap<String, String>,
+        changed: &BTreeSet<String>,
+    ) -> bool {
+        refresh_content_map(&self.root, content, changed.iter().map(String::as_str))
+    }
+
+    fn diff(
+        &self,
+        rev: &str,
+        language: Language,
+        filter: &PathFilter,
+        failures: &mut Vec<FileFailure>,
+    ) -> Result<Vec<BetterDiff>, EngineError> {
+        let base_files: BTreeSet<String> = self.files(rev)?.into_iter().collect();
+        let changed: BTreeSet<String> = self.changed(rev)?.into_iter().collect();
+        let mut hunks = Vec::new();
+        for path in changed {
+            if !language.matches_path(Path::new(&path)) || !filter.matches(&path) {
+                continue;
+            }
+            let old = match base_files.contains(&path) {
+                true => self.cat(rev, &path)?,
+                false => Vec::new(),
+            };
+            let new = match std::fs::read(self.root.join(&path)) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    failures.push(FileFailure::new(
+                        path.as_str(),
+                        DiffError::Io(path.clone(), e),
+                    ));
+                    continue;
+                }
+            };
+            let (old, new) = match (decode(&path, old, failures), decode(&path, new, failures)) {
+                (Some(old), Some(new)) => (old, new),
+                _ => continue,
+            };
+            hunks.extend(diff_contents(&path, &old, &new));
+        }
+        Ok(hunks)
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::{BTreeSet, HashMap};
+use wasm_bindgen::prelude::*;
+
+use crate::diff::diff_contents;
+use crate::discovery::{TestNames, TreeCache};
+use crate::language::Language;
+use crate::selection::{default_selector, select_changes, ImpactData};
+
+/// Takes two JSON objects mapping file paths to their contents at the base
+/// and in the working copy, and returns the selection as JSON.
+#[wasm_bindgen(js_name = selectTests)]
+pub fn select_tests(old_files: &str, new_files: &str) -> Result<String, JsValue> {
+    let old_content: HashMap<String, String> =
+        serde_json::from_str(old_files).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let new_content: HashMap<String, String> =
+        serde_json::from_str(new_files).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let paths: BTreeSet<&String> = old_content.keys().chain(new_content.keys()).collect();
+    let mut hunks = Vec::new();
+    for path in paths {
+        let old = old_content.get(path).map(String::as_str).unwrap_or("");
+        let new = new_content.get(path).map(String::as_str).unwrap_or("");
+        hunks.extend(diff_contents(path, old, new));
+    }
+
+    let selection = select_changes(
+        &default_selector(Language::default()),
+        Language::default(),
+        &TestNames::of(Language::default()),
+        &hunks,
+        &old_content,
+        &new_content,
+        &[],
+        &ImpactData::default(),
+        &TreeCache::default(),
+    )
+    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    serde_json::to_string(&selection).map_err(|e| JsValue::from_str(&e.to_string()))
+}
@@ -0,0 +1,577 @@
+use glob::Pattern;
+use notify_debouncer_full::notify::event::{MetadataKind, ModifyKind};
+use notify_debouncer_full::{
+    new_debouncer_opt, notify::*, DebounceEventResult, Debouncer, FileIdMap,
+};
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::{self, JoinHandle};
+
+use crate::hooks::REBASELINE_MARKER;
+use crate::vcs;
+
+/// How long edits must settle before a cycle starts.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often the polling watcher rescans when no interval is given.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Filesystems whose change events are missing or unreliable, as
+/// `/proc/self/mounts` names them: network shares, WSL's Windows drives and
+/// Docker Desktop's bind mounts. Checkouts on them are polled.
+pub const POLLED_FILESYSTEMS: [&str; 8] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "9p",
+    "drvfs",
+    "fuse.grpcfuse",
+    "fuse.osxfs",
+];
+
+/// Names of directories and files that are never watched, wherever they
+/// are: git's own directory, Python's caches and virtualenv, and what
+/// coverage.py writes.
+pub const IGNORED: [&str; 7] = [
+    ".git",
+    "__pycache__",
+    ".venv",
+    ".pytest_cache",
+    "htmlcov",
+    ".coverage",
+    ".coverage.*",
+];
+
+/// How often a waiting watcher checks whether it was paused or resumed.
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Error)]
+pub enum WatchError {
+    #[error("failed to watch: {0}")]
+    Notify(#[from] notify_debouncer_full::notify::Error),
+    #[error("failed to start the event loop: {0}")]
+    Runtime(#[from] std::io::Error),
+}
+
+/// Paths the watcher skips: the built-in `IGNORED` names, matched against
+/// every component, and globs relative to the root. A skipped directory is
+/// never watched, so nothing below it is seen either. In large trees the
+/// directories registered can also be limited by depth, or to the listed
+/// directories themselves without those below them.
+#[derive(Debug, Clone)]
+pub struct IgnoreList {
+    names: Vec<Pattern>,
+    patterns: Vec<Pattern>,
+    max_depth: Option<usize>,
+    non_recursive: Vec<Pattern>,
+}
+
+impl IgnoreList {
+    pub fn new<S: AsRef<str>>(
+        patterns: &[S],
+    ) -> std::result::Result<IgnoreList, glob::PatternError> {
+        Ok(IgnoreList {
+            names: IGNORED
+                .iter()
+                .map(|name| Pattern::new(name))
+                .collect::<std::result::Result<_, _>>()?,
+            patterns: patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern.as_ref()))
+                .collect::<std::result::Result<_, _>>()?,
+            max_depth: None,
+            non_recursive: Vec::new(),
+        })
+    }
+
+    /// Watches no directory more than `depth` levels below a root; at `0`
+    /// only the files directly in each root are seen.
+    pub fn max_depth(mut self, depth: usize) -> IgnoreList {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Watches the directories matching `patterns`, globs relative to the
+    /// root, but none of the directories below them.
+    pub fn non_recursive<S: AsRef<str>>(
+        mut self,
+        patterns: &[S],
+    ) -> std::result::Result<IgnoreList, glob::PatternError> {
+        for pattern in patterns {
+            self.non_recursive.push(Pattern::new(pattern.as_ref())?);
+        }
+        Ok(self)
+    }
+
+    /// Whether the directory `relative`, a path from the root, is left
+    /// unwatched: it is skipped, deeper than the maximum depth, or below a
+    /// directory watched non-recursively.
+    pub fn skips_dir(&self, relative: &Path) -> bool {
+        let too_deep = self
+            .max_depth
+            .is_some_and(|depth| relative.components().count() > depth);
+        let below_flat = relative
+            .ancestors()
+            .skip(1)
+            .filter_map(Path::to_str)
+            .filter(|path| !path.is_empty())
+            .any(|path| {
+                self.non_recursive
+                    .iter()
+                    .any(|pattern| pattern.matches(path))
+            });
+        too_deep || below_flat || self.is_ignored(relative)
+    }
+
+    /// Whether `relative`, a path from the root, is or lies below a skipped
+    /// directory or file.
+    pub fn is_ignored(&self, relative: &Path) -> bool {
+        let named = relative.components().any(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .is_some_and(|name| self.names.iter().any(|pattern| pattern.matches(name)))
+        });
+        named
+            || relative
+                .ancestors()
+                .filter_map(Path::to_str)
+                .filter(|path| !path.is_empty())
+                .any(|path| self.patterns.iter().any(|pattern| pattern.matches(path)))
+    }
+}
+
+impl Default for IgnoreList {
+    fn default() -> IgnoreList {
+        IgnoreList::new::<&str>(&[]).expect("built-in ignore patterns are valid")
+    }
+}
+
+/// Pauses and resumes a running `watch` from other threads, or from a
+/// signal handler. While paused, changes start no cycles; if any were made,
+/// one cycle starts on resume. A cycle can also be asked for without any
+/// change, and the watcher stopped.
+#[derive(Debug, Clone, Default)]
+pub struct WatchControl {
+    paused: Arc<AtomicBool>,
+    rerun: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl WatchControl {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Pauses if running and resumes if paused, returning whether it is
+    /// now paused.
+    pub fn toggle(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Starts a cycle as soon as the watcher notices, even while paused,
+    /// taking in any changes held back until then.
+    pub fn rerun(&self) {
+        self.rerun.store(true, Ordering::SeqCst);
+    }
+
+    fn take_rerun(&self) -> bool {
+        self.rerun.swap(false, Ordering::SeqCst)
+    }
+
+    /// Makes `watch` return as soon as it notices, dropping any changes
+    /// held back. The control can then be handed to another `watch`.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    fn take_stop(&self) -> bool {
+        self.stop.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Toggles `control` whenever the process receives `SIGUSR1`, so a
+/// script can pause watching around a rebase or checkout with
+/// `kill -USR1`.
+#[cfg(unix)]
+pub fn toggle_on_signal(control: &WatchControl) -> std::io::Result<()> {
+    let paused = control.paused.clone();
+    // SAFETY: the action only flips an atomic, which is async-signal-safe
+    unsafe {
+        signal_hook_registry::register(libc::SIGUSR1, move || {
+            paused.fetch_xor(true, Ordering::SeqCst);
+        })?;
+    }
+    Ok(())
+}
+
+/// Reads commands typed at the terminal, one per line, until `input`
+/// ends: `p` pauses or resumes watching, and `r` or an empty line runs
+/// again right away.
+pub fn read_commands<R: BufRead>(input: R, control: &WatchControl) {
+    for line in input.lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        match line.trim() {
+            "p" => {
+                control.toggle();
+            }
+            "" | "r" => control.rerun(),
+            other => tracing::warn!(
+                "unknown command {:?}; `p` pauses or resumes, `r` or Enter runs again",
+                other
+            ),
+        }
+    }
+}
+
+/// Watches `dir` and every directory below it that `ignore` does not skip,
+/// one at a time, so skipped trees are never registered. Only failing to
+/// watch `dir` itself, or running out of watches, is an error. The files
+/// found on the way are added to `files`.
+fn watch_tree<T: Watcher>(
+    debouncer: &mut Debouncer<T, FileIdMap>,
+    roots: &[PathBuf],
+    dir: &Path,
+    ignore: &IgnoreList,
+    files: &mut Vec<PathBuf>,
+) -> std::result::Result<(), WatchError> {
+    debouncer
+        .watcher()
+        .watch(dir, RecursiveMode::NonRecursive)?;
+    debouncer.cache().add_root(dir, RecursiveMode::NonRecursive);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if ignore.is_ignored(relative(roots, &path)) {
+            continue;
+        }
+        if !entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            files.push(path);
+            continue;
+        }
+        if ignore.skips_dir(relative(roots, &path)) {
+            continue;
+        }
+        match watch_tree(debouncer, roots, &path, ignore, files) {
+            Err(WatchError::Notify(e)) if matches!(e.kind, ErrorKind::MaxFilesWatch) => {
+                return Err(e.into())
+            }
+            // it may be gone already
+            Err(e) => tracing::warn!("cannot watch {}: {}", path.display(), e),
+            Ok(()) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The type of the filesystem `path` is on, as the longest mount point
+/// containing it in `mounts`, a table in the format of `/proc/self/mounts`.
+pub fn mount_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let _device = fields.next()?;
+            // spaces in mount points are escaped
+            let point = PathBuf::from(fields.next()?.replace("\\040", " "));
+            Some((point, fields.next()?))
+        })
+        .filter(|(point, _)| path.starts_with(point))
+        .max_by_key(|(point, _)| point.as_os_str().len())
+        .map(|(_, kind)| kind)
+}
+
+/// Whether `root` is on one of `POLLED_FILESYSTEMS`. Only Linux tells.
+pub fn needs_polling(root: &Path) -> bool {
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return false;
+    };
+    let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    mount_type(&mounts, &root).is_some_and(|kind| POLLED_FILESYSTEMS.contains(&kind))
+}
+
+/// Whether an event of `kind` can mean a file's content changed: files
+/// created, written, renamed or removed. Reads and metadata changes such as
+/// `chmod` or `touch` are not, except a new modification time, which is how
+/// the polling watcher reports writes.
+pub fn is_content_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(_) => false,
+        EventKind::Modify(ModifyKind::Metadata(metadata)) => {
+            matches!(metadata, MetadataKind::WriteTime)
+        }
+        _ => true,
+    }
+}
+
+/// `path` relative to the innermost of `roots` containing it, which is what
+/// ignore globs are matched against.
+fn relative<'a>(roots: &[PathBuf], path: &'a Path) -> &'a Path {
+    roots
+        .iter()
+        .filter_map(|root| path.strip_prefix(root).ok())
+        .min_by_key(|relative| relative.as_os_str().len())
+        .unwrap_or(path)
+}
+
+/// Registers each of `roots`, and the git directory of the first where the
+/// hooks leave their marker, with a new `T` watcher sending batches to
+/// `tx`.
+fn start<T: Watcher>(
+    roots: &[PathBuf],
+    debounce: Duration,
+    ignore: &IgnoreList,
+    tx: UnboundedSender<DebounceEventResult>,
+    config: Config,
+) -> std::result::Result<Debouncer<T, FileIdMap>, WatchError> {
+    let handler = move |result| {
+        // the loop has stopped when nothing receives
+        let _ = tx.send(result);
+    };
+    // no specific tickrate
+    let mut debouncer: Debouncer<T, FileIdMap> =
+        new_debouncer_opt(debounce, None, handler, FileIdMap::new(), config)?;
+
+    for (i, root) in roots.iter().enumerate() {
+        // a root inside another is registered with it
+        if roots[..i].iter().any(|outer| root.starts_with(outer)) {
+            continue;
+        }
+        watch_tree(&mut debouncer, roots, root, ignore, &mut Vec::new())?;
+    }
+
+    // the hooks' marker is in the git directory, which is skipped or, in a
+    // linked worktree or with `GIT_DIR`, elsewhere
+    let checkout = roots.first().map(PathBuf::as_path);
+    if let Some(Ok(repo)) = checkout.map(vcs::open_git) {
+        debouncer
+            .watcher()
+            .watch(repo.path(), RecursiveMode::NonRecursive)?;
+    }
+    Ok(debouncer)
+}
+
+/// Calls `on_change` with the changed paths whenever the content of files
+/// under one of `roots`, the first being the checkout, that `ignore` does
+/// not skip and for which `is_watched` returns true changes, and after the
+/// hooks record a new `HEAD`. Events from every root are batched together
+/// until none arrive for `debounce`. While `control` is paused, no cycle
+/// starts. A rerun asked for through `control` calls `on_change` with no
+/// paths at all. The loop runs on a tokio runtime: events are collected by
+/// a task of their own and `on_change` runs on the blocking pool, so events
+/// are still collected, and pausing or stopping still answered, during a
+/// long cycle; whatever changes meanwhile is passed to the next call.
+///
+/// With a `poll` interval the trees are rescanned that often instead of
+/// relying on the system's change events. They are also polled, every
+/// `DEFAULT_POLL_INTERVAL`, when one is on one of `POLLED_FILESYSTEMS` or
+/// the system's watcher cannot be set up, such as when out of inotify
+/// watches.
+pub fn watch<W, F>(
+    roots: &[PathBuf],
+    debounce: Duration,
+    ignore: &IgnoreList,
+    poll: Option<Duration>,
+    control: &WatchControl,
+    is_watched: W,
+    on_change: F,
+) -> std::result::Result<(), WatchError>
+where
+    W: Fn(&Path) -> bool + Send + 'static,
+    F: FnMut(&[PathBuf]) + Send + 'static,
+{
+    // one worker collects events while the other starts cycles
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_time()
+        .build()?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let unreliable = roots.iter().find(|root| needs_polling(root));
+    let poll = match (poll, unreliable) {
+        (None, Some(root)) => {
+            tracing::info!(
+                "{} is on a filesystem without reliable change events, polling it",
+                root.display()
+            );
+            Some(DEFAULT_POLL_INTERVAL)
+        }
+        (poll, _) => poll,
+    };
+    if poll.is_none() {
+        match start::<RecommendedWatcher>(roots, debounce, ignore, tx.clone(), Config::default()) {
+            Ok(debouncer) => {
+                let serving = serve(debouncer, rx, roots, ignore, control, is_watched, on_change);
+                return runtime.block_on(serving);
+            }
+            Err(e) => tracing::warn!("cannot watch for changes ({}), polling instead", e),
+        }
+    }
+    let config = Config::default().with_poll_interval(poll.unwrap_or(DEFAULT_POLL_INTERVAL));
+    let debouncer = start::<PollWatcher>(roots, debounce, ignore, tx, config)?;
+    runtime.block_on(serve(
+        debouncer, rx, roots, ignore, control, is_watched, on_change,
+    ))
+}
+
+/// Starts cycles for the changes `debouncer` sends to `rx` until it stops,
+/// holding them back while `control` is paused or one is still running.
+async fn serve<T, W, F>(
+    debouncer: Debouncer<T, FileIdMap>,
+    rx: UnboundedReceiver<DebounceEventResult>,
+    roots: &[PathBuf],
+    ignore: &IgnoreList,
+    control: &WatchControl,
+    is_watched: W,
+    on_change: F,
+) -> std::result::Result<(), WatchError>
+where
+    T: Watcher + Send + 'static,
+    W: Fn(&Path) -> bool + Send + 'static,
+    F: FnMut(&[PathBuf]) + Send + 'static,
+{
+    let (found, mut changes) = mpsc::unbounded_channel();
+    let collector = task::spawn(collect(
+        debouncer,
+        rx,
+        roots.to_vec(),
+        ignore.clone(),
+        is_watched,
+        found,
+    ));
+    let mut ticks = tokio::time::interval(TICK);
+    // `on_change` is handed to each cycle and back when it ends
+    let mut idle = Some(on_change);
+    let mut cycle: Option<JoinHandle<F>> = None;
+    let mut paused = false;
+    let mut rerun = false;
+    let mut pending = BTreeSet::new();
+    loop {
+        tokio::select! {
+            changed = changes.recv() => match changed {
+                Some(changed) => pending.extend(changed),
+                None => break,
+            },
+            Some(finished) = async { Some(cycle.as_mut()?.await) } => {
+                cycle = None;
+                match finished {
+                    Ok(on_change) => idle = Some(on_change),
+                    Err(e) => std::panic::resume_unwind(e.into_panic()),
+                }
+            }
+            _ = ticks.tick() => {}
+        }
+        if control.take_stop() {
+            break;
+        }
+        if control.is_paused() != paused {
+            paused = !paused;
+            match paused {
+                true => tracing::info!("paused watching; changes are held until resumed"),
+                false => tracing::info!("resumed watching"),
+            }
+        }
+        if control.take_rerun() {
+            tracing::info!("running again on request");
+            pending.clear();
+            rerun = true;
+        }
+        let job: Option<Vec<PathBuf>> = match cycle.is_some() {
+            true => None,
+            false if std::mem::take(&mut rerun) => Some(Vec::new()),
+            false if !pending.is_empty() && !paused => {
+                Some(std::mem::take(&mut pending).into_iter().collect())
+            }
+            false => None,
+        };
+        if let Some(job) = job {
+            let mut on_change = idle.take().expect("no cycle is running");
+            cycle = Some(task::spawn_blocking(move || {
+                on_change(&job);
+                on_change
+            }));
+        }
+    }
+    collector.abort();
+    // lets the cycle that is running finish
+    if let Some(cycle) = cycle {
+        if let Err(e) = cycle.await {
+            std::panic::resume_unwind(e.into_panic());
+        }
+    }
+    Ok(())
+}
+
+/// Sends each batch of events `debouncer` sends to `rx` on to `found` as
+/// the paths that count, registering new directories as they appear.
+async fn collect<T: Watcher, W: Fn(&Path) -> bool>(
+    mut debouncer: Debouncer<T, FileIdMap>,
+    mut rx: UnboundedReceiver<DebounceEventResult>,
+    roots: Vec<PathBuf>,
+    ignore: IgnoreList,
+    is_watched: W,
+    found: UnboundedSender<Vec<PathBuf>>,
+) {
+    let roots = roots.as_slice();
+    while let Some(result) = rx.recv().await {
+        let events = match result {
+            Ok(events) => events,
+            Err(errors) => {
+                errors.iter().for_each(|error| tracing::error!("{}", error));
+                continue;
+            }
+        };
+        let skipped = |path: &Path| ignore.is_ignored(relative(roots, path));
+        // files may be written into a new directory before it is watched, so
+        // whatever it holds counts as changed
+        let mut created = Vec::new();
+        for event in events.iter().filter(|event| event.kind.is_create()) {
+            for dir in event.paths.iter().filter(|path| path.is_dir()) {
+                if ignore.skips_dir(relative(roots, dir)) {
+                    continue;
+                }
+                if let Err(e) = watch_tree(&mut debouncer, roots, dir, &ignore, &mut created) {
+                    tracing::warn!("cannot watch {}: {}", dir.display(), e);
+                }
+            }
+        }
+        let relevant = |path: &Path| {
+            path.file_name() == Some(OsStr::new(REBASELINE_MARKER))
+                || (!skipped(path) && is_watched(path))
+        };
+        let changed: Vec<PathBuf> = events
+            .iter()
+            .filter(|event| is_content_change(&event.kind))
+            .flat_map(|event| event.paths.iter())
+            .chain(&created)
+            .filter(|path| relevant(path))
+            .cloned()
+            .collect();
+        for path in &changed {
+            tracing::debug!(path = %path.display(), "change detected");
+        }
+        if !changed.is_empty() && found.send(changed).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::calc_repo;
+#[cfg(feature = "python")]
+use common::CALC;
+use hackweek_instant_codecoverage::bazel::rdeps_query;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::bazel::BazelSelector;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::{EngineBuilder, EngineError, ImpactStrategy};
+
+#[test]
+fn query_covers_every_changed_file() {
+    assert_eq!(
+        rdeps_query("py_test", &["calc.py", "lib/util.py"]),
+        "kind(py_test, rdeps(//..., set(\"calc.py\" \"lib/util.py\")))"
+    );
+}
+
+#[test]
+fn bazel_strategy_needs_a_workspace() {
+    let fixture = calc_repo();
+    let result = EngineBuilder::new(fixture.path())
+        .impact_strategy(ImpactStrategy::Bazel)
+        .build();
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+
+    fixture.write("MODULE.bazel", "");
+    EngineBuilder::new(fixture.path())
+        .impact_strategy(ImpactStrategy::Bazel)
+        .build()
+        .unwrap();
+}
+
+#[cfg(feature = "python")]
+#[cfg(unix)]
+#[test]
+fn selector_runs_the_targets_bazel_reports() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let fixture = calc_repo();
+    fixture.write(
+        "fake-bazel",
+        "#!/bin/sh\necho \"$4\" > query.txt\necho //tests:test_calc\necho //tests:test_other\nexit 3\n",
+    );
+    let fake = fixture.path().join("fake-bazel");
+    std::fs::set_permissions(&fake, std::fs::Permissions::from_mode(0o755)).unwrap();
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+
+    let engine = EngineBuilder::new(fixture.path())
+        .selector(BazelSelector::new(fixture.path()).binary(fake.to_string_lossy()))
+        .runner(EmitOnlyRunner::new("bazel test {tests}"))
+        .build()
+        .unwrap();
+    let selection = engine.select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec!["//tests:test_calc", "//tests:test_other"]
+    );
+    let query = std::fs::read_to_string(fixture.path().join("query.txt")).unwrap();
+    assert_eq!(query.trim(), rdeps_query("py_test", &["calc.py"]));
+}
@@ -0,0 +1,87 @@
+use hackweek_instant_codecoverage::cache::ImpactCache;
+use hackweek_instant_codecoverage::selection::ImpactData;
+use std::collections::{HashMap, HashSet};
+
+fn impact() -> ImpactData {
+    ImpactData {
+        lines: HashMap::from([
+            (
+                "tests/test_calc.py::test_add".to_string(),
+                HashMap::from([
+                    ("calc.py".to_string(), HashSet::from([1, 2])),
+                    ("util.py".to_string(), HashSet::from([1])),
+                ]),
+            ),
+            (
+                "tests/test_calc.py::test_sub".to_string(),
+                HashMap::from([("calc.py".to_string(), HashSet::from([5]))]),
+            ),
+        ]),
+        arcs: HashMap::from([(
+            "calc.py".to_string(),
+            HashMap::from([(2, HashSet::from([3, -1]))]),
+        )]),
+    }
+}
+
+#[test]
+fn stored_coverage_is_loaded_for_the_same_base() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("calc.py"),
+        "def add(a, b):\n    return a + b\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("util.py"), "X = 1\n").unwrap();
+    let cache = ImpactCache::open(dir.path()).unwrap();
+
+    cache.store("abc123", &impact(), dir.path()).unwrap();
+
+    let loaded = ImpactCache::open(dir.path())
+        .unwrap()
+        .load("abc123", dir.path())
+        .unwrap();
+    assert_eq!(loaded.lines, impact().lines);
+    assert_eq!(loaded.arcs, impact().arcs);
+    assert!(cache.load("def456", dir.path()).unwrap().lines.is_empty());
+    assert!(cache.path().starts_with(dir.path().join(".instant-patch")));
+}
+
+#[test]
+fn coverage_of_files_changed_since_is_dropped() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("calc.py"),
+        "def add(a, b):\n    return a + b\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("util.py"), "X = 1\n").unwrap();
+    let cache = ImpactCache::open(dir.path()).unwrap();
+    cache.store("abc123", &impact(), dir.path()).unwrap();
+
+    std::fs::write(
+        dir.path().join("calc.py"),
+        "def add(a, b):\n    return b + a\n",
+    )
+    .unwrap();
+    let loaded = cache.load("abc123", dir.path()).unwrap();
+
+    assert_eq!(
+        loaded.lines,
+        HashMap::from([(
+            "tests/test_calc.py::test_add".to_string(),
+            HashMap::from([("util.py".to_string(), HashSet::from([1]))]),
+        )])
+    );
+    assert!(loaded.arcs.is_empty());
+    // changing the file back does not bring stale data back
+    std::fs::write(
+        dir.path().join("calc.py"),
+        "def add(a, b):\n    return a + b\n",
+    )
+    .unwrap();
+    assert!(
+        !cache.load("abc123", dir.path()).unwrap().lines["tests/test_calc.py::test_add"]
+            .contains_key("calc.py")
+    );
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "python")]
+
+use hackweek_instant_codecoverage::calls::{functions, reaches, Function};
+use hackweek_instant_codecoverage::discovery::{create_parser, parse_all};
+use hackweek_instant_codecoverage::Language;
+use std::collections::{BTreeSet, HashMap};
+
+fn parse(source: &str) -> Vec<Function> {
+    let content = HashMap::from([("shop.py".to_string(), source.to_string())]);
+    let mut trees = HashMap::new();
+    let mut parser = create_parser(Language::Python).unwrap();
+    parse_all(&mut parser, &content, &mut trees, &mut Vec::new());
+    functions(Language::Python, &content, &trees)
+}
+
+#[test]
+fn functions_record_plain_and_method_calls() {
+    let functions = parse(
+        "def total(cart):\n    return sum(price(item) for item in cart.items())\n\n\n\
+         class Cart:\n    def add(self, item):\n        self.items.append(validate(item))\n",
+    );
+
+    let names: Vec<(&str, Vec<&str>)> = functions
+        .iter()
+        .map(|f| {
+            (
+                f.name.as_str(),
+                f.calls.iter().map(String::as_str).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            ("total", vec!["items", "price", "sum"]),
+            ("add", vec!["append", "validate"]),
+        ]
+    );
+}
+
+#[test]
+fn calls_are_followed_only_to_the_given_depth() {
+    let functions = parse(
+        "def a():\n    b()\n\n\ndef b():\n    c()\n\n\ndef c():\n    d()\n\n\ndef d():\n    pass\n",
+    );
+    let mut callees: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for function in &functions {
+        callees
+            .entry(function.name.as_str())
+            .or_default()
+            .extend(function.calls.iter().map(String::as_str));
+    }
+    let calls = &functions[0].calls;
+
+    assert_eq!(
+        reaches(calls, &callees, &BTreeSet::from(["c"]), 2),
+        Some("c")
+    );
+    assert_eq!(reaches(calls, &callees, &BTreeSet::from(["d"]), 2), None);
+    assert_eq!(
+        reaches(calls, &callees, &BTreeSet::from(["d"]), 3),
+        Some("d")
+    );
+}
@@ -0,0 +1,68 @@
+mod common;
+
+#[cfg(feature = "python")]
+use common::{calc_repo, CALC};
+use hackweek_instant_codecoverage::codecov::{codecov_json, upload_args};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::{codecov::report, runner::EmitOnlyRunner, EngineBuilder};
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage};
+use serde_json::json;
+#[cfg(feature = "python")]
+use serde_json::Value;
+use std::path::Path;
+
+#[test]
+fn changed_lines_map_to_hits() {
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![3, 4],
+            covered_lines: vec![4],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    assert_eq!(
+        codecov_json(&coverage),
+        json!({ "coverage": { "calc.py": { "3": 0, "4": 1 } } })
+    );
+}
+
+#[test]
+fn upload_pins_the_commit_and_its_base() {
+    let args = upload_args(Path::new("codecov.json"), "instant", "head1", "base1");
+    let joined = args.join(" ");
+    assert!(
+        joined.starts_with("codecovcli upload-process"),
+        "{}",
+        joined
+    );
+    assert!(joined.contains("--file codecov.json"), "{}", joined);
+    assert!(
+        joined.contains("--sha head1 --parent-sha base1"),
+        "{}",
+        joined
+    );
+    assert!(joined.contains("--flag instant"), "{}", joined);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn report_writes_the_changed_lines() {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+    let output = fixture.path().join("codecov.json");
+
+    assert!(report(&engine, &output, false, "instant").unwrap());
+
+    let written: Value = serde_json::from_str(&std::fs::read_to_string(output).unwrap()).unwrap();
+    let lines = written["coverage"]["calc.py"].as_object().unwrap();
+    assert_eq!(lines.len(), 4);
+}
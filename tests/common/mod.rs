@@ -0,0 +1,85 @@
+#![allow(dead_code)]
+
+use git2::{Repository, Signature};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A throwaway git repository for driving the engine end to end.
+pub struct FixtureRepo {
+    dir: TempDir,
+    pub repo: Repository,
+}
+
+impl FixtureRepo {
+    pub fn new() -> FixtureRepo {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        FixtureRepo { dir, repo }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn write(&self, path: &str, content: &str) {
+        let full = self.dir.path().join(path);
+        if let Some(parent) = full.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(full, content).unwrap();
+    }
+
+    pub fn remove(&self, path: &str) {
+        fs::remove_file(self.dir.path().join(path)).unwrap();
+    }
+
+    pub fn commit(&self, message: &str) -> git2::Oid {
+        let mut index = self.repo.index().unwrap();
+        index
+            .add_all(["*"].iter(), git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"].iter(), None).unwrap();
+        index.write().unwrap();
+        let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("fixture", "fixture@example.com").unwrap();
+        let parents = match self.repo.head() {
+            Ok(head) => vec![head.peel_to_commit().unwrap()],
+            Err(_) => vec![],
+        };
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        self.repo
+            .commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+            .unwrap()
+    }
+
+    pub fn stage(&self, path: &str) {
+        let mut index = self.repo.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        index.write().unwrap();
+    }
+
+    pub fn reset_hard(&self, oid: git2::Oid) {
+        let commit = self.repo.find_object(oid, None).unwrap();
+        self.repo
+            .reset(&commit, git2::ResetType::Hard, None)
+            .unwrap();
+    }
+
+    pub fn head(&self) -> git2::Object<'_> {
+        self.repo.revparse_single("HEAD").unwrap()
+    }
+}
+
+pub const CALC: &str = "def add(a, b):\n    return a + b\n";
+
+pub const TEST_CALC: &str = "from calc import add\n\n\ndef test_add():\n    assert add(1, 2) == 3\n";
+
+/// A repository with a module and one committed test.
+pub fn calc_repo() -> FixtureRepo {
+    let fixture = FixtureRepo::new();
+    fixture.write("calc.py", CALC);
+    fixture.write("tests/test_calc.py", TEST_CALC);
+    fixture.commit("initial");
+    fixture
+}
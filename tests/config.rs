@@ -0,0 +1,223 @@
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::config::{ConfigError, CONFIG_FILE, CURRENT_VERSION};
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::{BaseMode, Config, EmptySelection, EngineBuilder, EngineError};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "python")]
+#[test]
+fn parses_current_version() {
+    use hackweek_instant_codecoverage::{ImpactStrategy, Language};
+
+    let config = Config::parse(
+        "version = 1\nbase = \"origin/main\"\ncommand = \"pytest {tests}\"\nlanguage = \"python\"\nimpact = \"bazel\"\n",
+    )
+    .unwrap();
+
+    assert_eq!(config.version, CURRENT_VERSION);
+    assert_eq!(config.base.as_deref(), Some("origin/main"));
+    assert_eq!(config.command.as_deref(), Some("pytest {tests}"));
+    assert_eq!(config.language, Some(Language::Python));
+    assert_eq!(config.impact, Some(ImpactStrategy::Bazel));
+}
+
+#[test]
+fn base_mode_names_are_kebab_case() {
+    let config = Config::parse("base_mode = \"merge-base\"\n").unwrap();
+    assert_eq!(config.base_mode, Some(BaseMode::MergeBase));
+    assert_eq!(BaseMode::MergeBase.default_base(), "origin/main");
+}
+
+#[test]
+fn unversioned_files_are_migrated() {
+    let config = Config::parse("base = \"main\"\n").unwrap();
+    assert_eq!(config.version, CURRENT_VERSION);
+    assert_eq!(config.base.as_deref(), Some("main"));
+}
+
+#[test]
+fn unknown_keys_are_rejected_by_name() {
+    let error = Config::parse("version = 1\nbsae = \"main\"\n").unwrap_err();
+    assert!(error.to_string().contains("bsae"), "{}", error);
+}
+
+#[test]
+fn newer_versions_are_rejected() {
+    let error = Config::parse("version = 99\n").unwrap_err();
+    assert!(matches!(
+        error,
+        ConfigError::UnsupportedVersion { found: 99 }
+    ));
+}
+
+#[test]
+fn test_names_are_a_list_of_globs() {
+    let config = Config::parse("test_names = [\"test_*\", \"*_test\"]\n").unwrap();
+    assert_eq!(config.test_names, vec!["test_*", "*_test"]);
+    assert!(Config::parse("").unwrap().test_names.is_empty());
+}
+
+#[test]
+fn on_empty_accepts_each_policy() {
+    let parse = |value: &str| Config::parse(&format!("on_empty = {}\n", value)).unwrap();
+    assert_eq!(parse("\"skip\"").on_empty, Some(EmptySelection::Skip));
+    assert_eq!(parse("\"all\"").on_empty, Some(EmptySelection::All));
+    assert_eq!(
+        parse("{ smoke = [\"tests/test_smoke.py\"] }").on_empty,
+        Some(EmptySelection::Smoke(vec![
+            "tests/test_smoke.py".to_string()
+        ]))
+    );
+}
+
+#[test]
+fn data_dependencies_are_listed_as_tables() {
+    let config = Config::parse(
+        "[[data_dependencies]]\npaths = [\"models/**\"]\ntests = [\"tests/test_model.py::test_accuracy\"]\n",
+    )
+    .unwrap();
+    assert_eq!(config.data_dependencies.len(), 1);
+    assert_eq!(config.data_dependencies[0].paths, ["models/**"]);
+}
+
+#[test]
+fn project_file_is_discovered_at_the_root() {
+    let fixture = calc_repo();
+    assert_eq!(Config::discover(fixture.path()).unwrap(), None);
+
+    fixture.write(
+        CONFIG_FILE,
+        "base = \"main\"\ndebounce_ms = 250\nwatch = [\"pytest.ini\"]\n",
+    );
+
+    let config = Config::discover(fixture.path()).unwrap().unwrap();
+    assert_eq!(config.base.as_deref(), Some("main"));
+    assert_eq!(config.debounce_ms, Some(250));
+    assert_eq!(config.watch, ["pytest.ini"]);
+}
+
+#[test]
+fn builder_applies_watch_globs_and_debounce() {
+    let fixture = calc_repo();
+    let config =
+        Config::parse("debounce_ms = 250\nwatch = [\"pytest.ini\", \"fixtures/**\"]\n").unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .config(&config)
+        .build()
+        .unwrap();
+    let root = fixture.path().canonicalize().unwrap();
+
+    assert_eq!(engine.debounce(), Duration::from_millis(250));
+    assert!(engine.is_watched(&root.join("pytest.ini")));
+    assert!(engine.is_watched(&root.join("fixtures/users.json")));
+    assert!(!engine.is_watched(&root.join("README.md")));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn builder_watches_configured_roots_besides_the_checkout() {
+    let fixture = calc_repo();
+    fixture.write("vendor/shared/helpers.py", "VALUE = 1\n");
+    let config = Config::parse("watch_roots = [\"vendor/shared\"]\n").unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .config(&config)
+        .build()
+        .unwrap();
+    let shared = fixture.path().join("vendor/shared").canonicalize().unwrap();
+
+    assert_eq!(engine.watch_roots().len(), 2);
+    assert_eq!(engine.watch_roots()[1], shared);
+
+    let outside = tempfile::tempdir().unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .watch_root(outside.path())
+        .build()
+        .unwrap();
+    let outside = outside.path().canonicalize().unwrap();
+    assert!(engine.is_watched(&outside.join("helpers.py")));
+    assert!(!engine.is_watched(&outside.join("notes.txt")));
+
+    let missing = EngineBuilder::new(fixture.path())
+        .watch_root("no/such/dir")
+        .build();
+    assert!(matches!(missing, Err(EngineError::InvalidConfig(_))));
+}
+
+#[test]
+fn builder_applies_the_poll_interval() {
+    let fixture = calc_repo();
+    let engine = EngineBuilder::new(fixture.path()).build().unwrap();
+    assert_eq!(engine.poll_interval(), None);
+
+    let config = Config::parse("poll_ms = 2000\n").unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .config(&config)
+        .build()
+        .unwrap();
+    assert_eq!(engine.poll_interval(), Some(Duration::from_secs(2)));
+}
+
+#[test]
+fn builder_adds_configured_watch_ignores_to_the_built_in_ones() {
+    let fixture = calc_repo();
+    let config = Config::parse("watch_ignore = [\"build\"]\n").unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .config(&config)
+        .build()
+        .unwrap();
+
+    assert!(engine.watch_ignore().is_ignored(Path::new("build/calc.py")));
+    assert!(engine
+        .watch_ignore()
+        .is_ignored(Path::new(".venv/bin/python")));
+    assert!(!engine.watch_ignore().is_ignored(Path::new("calc.py")));
+
+    let invalid = EngineBuilder::new(fixture.path()).watch_ignore("[").build();
+    assert!(matches!(invalid, Err(EngineError::InvalidConfig(_))));
+}
+
+#[test]
+fn builder_applies_configured_watch_limits() {
+    let fixture = calc_repo();
+    let config =
+        Config::parse("watch_max_depth = 3\nwatch_non_recursive = [\"third_party\"]\n").unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .config(&config)
+        .build()
+        .unwrap();
+
+    let ignore = engine.watch_ignore();
+    assert!(!ignore.skips_dir(Path::new("a/b/c")));
+    assert!(ignore.skips_dir(Path::new("a/b/c/d")));
+    assert!(!ignore.skips_dir(Path::new("third_party")));
+    assert!(ignore.skips_dir(Path::new("third_party/lib")));
+}
+
+#[test]
+fn config_changes_stop_a_reloading_watch() {
+    let fixture = calc_repo();
+    let root = fixture.path().canonicalize().unwrap();
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .debounce(Duration::from_millis(50))
+        .poll(Duration::from_millis(50))
+        .reload_config(true)
+        .build()
+        .map(Arc::new)
+        .unwrap();
+    assert!(engine.is_watched(&root.join(CONFIG_FILE)));
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(engine.watch().is_ok()).unwrap());
+    thread::sleep(Duration::from_millis(500));
+
+    fixture.write(CONFIG_FILE, "command = \"pytest -x {tests}\"\n");
+
+    assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+    let engine = EngineBuilder::new(fixture.path()).build().unwrap();
+    assert!(!engine.is_watched(&root.join(CONFIG_FILE)));
+}
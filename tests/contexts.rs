@@ -0,0 +1,81 @@
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::contexts::{install, rcfile, with_cov_context, PLUGIN_MODULE};
+use hackweek_instant_codecoverage::runner::{parse_template, LocalRunner, Runner};
+use hackweek_instant_codecoverage::{SelectedTest, Selection};
+use std::fs;
+
+#[test]
+fn rcfile_adds_the_plugin_to_the_run_section() {
+    assert_eq!(
+        rcfile("", ""),
+        format!("[run]\nplugins = {}\n", PLUGIN_MODULE)
+    );
+
+    let existing = "[run]\nbranch = True\ndynamic_context = test_function\nplugins =\n    other\n\n[report]\nskip_empty = True\n";
+    assert_eq!(
+        rcfile(existing, ""),
+        format!(
+            "[run]\nbranch = True\nplugins =\n    {}\n    other\n\n[report]\nskip_empty = True\n",
+            PLUGIN_MODULE
+        )
+    );
+}
+
+#[test]
+fn rcfile_keeps_only_coverage_sections_of_shared_files() {
+    let setup_cfg = "[metadata]\nname = calc\n\n[coverage:run]\nsource = calc\n";
+    assert_eq!(
+        rcfile(setup_cfg, "coverage:"),
+        format!("[run]\nsource = calc\nplugins = {}\n", PLUGIN_MODULE)
+    );
+}
+
+#[test]
+fn pytest_cov_templates_get_a_test_context() {
+    let args = |template: &str| parse_template(&with_cov_context(template).unwrap()).unwrap();
+    assert_eq!(
+        args("pytest --cov=calc {tests}"),
+        ["pytest", "--cov=calc", "--cov-context=test", "{tests}"]
+    );
+    assert_eq!(
+        args("pytest --cov --cov-context=test {tests}"),
+        ["pytest", "--cov", "--cov-context=test", "{tests}"]
+    );
+    assert_eq!(with_cov_context("coverage run -m pytest {tests}"), None);
+}
+
+#[test]
+fn install_writes_the_plugin_and_project_rcfile() {
+    let fixture = calc_repo();
+    fixture.write(".coveragerc", "[run]\nsource = calc\n");
+
+    let setup = install(fixture.path()).unwrap();
+
+    assert!(setup
+        .python_path
+        .join("instant_patch/coverage_plugin.py")
+        .is_file());
+    let rcfile = fs::read_to_string(&setup.rcfile).unwrap();
+    assert!(rcfile.contains("source = calc"), "{}", rcfile);
+    assert!(rcfile.contains(PLUGIN_MODULE), "{}", rcfile);
+
+    let env = setup.env();
+    assert_eq!(env[0], ("COVERAGE_RCFILE", setup.rcfile.into_os_string()));
+    assert_eq!(env[1].0, "PYTHONPATH");
+}
+
+#[cfg(unix)]
+#[test]
+fn local_runner_passes_its_environment() {
+    let fixture = calc_repo();
+    let runner = LocalRunner::new(fixture.path(), "sh -c 'echo $CONTEXT_TEST' {tests}")
+        .env("CONTEXT_TEST", "recorded");
+
+    let result = runner
+        .run(&Selection::new(vec![SelectedTest::new("t", "new test")]))
+        .unwrap();
+
+    assert_eq!(result.stdout, "recorded\n");
+}
@@ -0,0 +1,140 @@
+#![cfg(feature = "python")]
+
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::coverage::patch_coverage;
+use hackweek_instant_codecoverage::diff::get_diff;
+use hackweek_instant_codecoverage::discovery::PathFilter;
+use hackweek_instant_codecoverage::selection::ImpactData;
+use hackweek_instant_codecoverage::{FilePatchCoverage, HunkCoverage, Language, PatchCoverage};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+#[test]
+fn coverage_is_broken_down_by_hunk() {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    a = int(a)\n    b = int(b)\n    return a + b\n\n\n\n\n\n\n\n\n\
+         def sub(a, b):\n    return a - b\n",
+    );
+    let hunks = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let impact = ImpactData {
+        lines: HashMap::from([(
+            "tests/test_calc.py::test_add".to_string(),
+            HashMap::from([("calc.py".to_string(), HashSet::from([1, 2, 4]))]),
+        )]),
+        ..ImpactData::default()
+    };
+
+    let coverage = patch_coverage(&hunks, &impact);
+
+    assert_eq!(coverage.files.len(), 1);
+    let file = &coverage.files[0];
+    assert_eq!(file.covered_lines, vec![2]);
+    assert_eq!(
+        file.hunks,
+        vec![
+            HunkCoverage {
+                first: 2,
+                last: 3,
+                changed_lines: vec![2, 3],
+                covered_lines: vec![2],
+                ..HunkCoverage::default()
+            },
+            HunkCoverage {
+                first: 5,
+                last: 14,
+                changed_lines: (5..=14).collect(),
+                covered_lines: vec![],
+                ..HunkCoverage::default()
+            },
+        ]
+    );
+}
+
+#[test]
+fn threshold_is_met_at_or_above_the_percentage() {
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![1, 2, 3, 4],
+            covered_lines: vec![1, 2, 3],
+            ..FilePatchCoverage::default()
+        }],
+    };
+    assert!(coverage.meets(75.0));
+    assert!(!coverage.meets(80.0));
+    assert!(PatchCoverage::default().meets(100.0));
+}
+
+#[test]
+fn branches_taken_one_way_are_partial() {
+    let mut coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![2, 3, 4],
+            covered_lines: vec![2, 3],
+            hunks: vec![HunkCoverage {
+                first: 2,
+                last: 4,
+                changed_lines: vec![2, 3, 4],
+                covered_lines: vec![2, 3],
+                ..HunkCoverage::default()
+            }],
+            ..FilePatchCoverage::default()
+        }],
+    };
+    let impact = ImpactData {
+        arcs: HashMap::from([(
+            "calc.py".to_string(),
+            HashMap::from([(2, HashSet::from([3])), (3, HashSet::from([4, 6]))]),
+        )]),
+        ..ImpactData::default()
+    };
+    let branch_lines = HashMap::from([("calc.py".to_string(), BTreeSet::from([2, 3]))]);
+
+    coverage.mark_partial_branches(&impact, &branch_lines);
+
+    let file = &coverage.files[0];
+    assert_eq!(file.covered_lines, vec![3]);
+    assert_eq!(file.partial_lines, vec![2]);
+    assert_eq!(file.uncovered_lines(), vec![4]);
+    assert_eq!(file.hunks[0].partial_lines, vec![2]);
+    assert_eq!(coverage.partial(), 1);
+}
+
+#[test]
+fn merged_runs_replace_rerun_tests_and_add_branches() {
+    let file =
+        |lines: &[usize]| HashMap::from([("calc.py".to_string(), lines.iter().copied().collect())]);
+    let mut impact = ImpactData {
+        lines: HashMap::from([
+            ("tests/test_calc.py::test_add".to_string(), file(&[1, 2])),
+            ("tests/test_calc.py::test_sub".to_string(), file(&[5, 6])),
+        ]),
+        arcs: HashMap::from([(
+            "calc.py".to_string(),
+            HashMap::from([(2, HashSet::from([3]))]),
+        )]),
+    };
+
+    impact.merge(ImpactData {
+        lines: HashMap::from([("tests/test_calc.py::test_add".to_string(), file(&[1, 3]))]),
+        arcs: HashMap::from([(
+            "calc.py".to_string(),
+            HashMap::from([(2, HashSet::from([4]))]),
+        )]),
+    });
+
+    assert_eq!(impact.lines["tests/test_calc.py::test_add"], file(&[1, 3]));
+    assert_eq!(impact.lines["tests/test_calc.py::test_sub"], file(&[5, 6]));
+    assert_eq!(impact.arcs["calc.py"][&2], HashSet::from([3, 4]));
+}
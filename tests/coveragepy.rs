@@ -0,0 +1,220 @@
+#![cfg(feature = "python")]
+
+use hackweek_instant_codecoverage::coveragepy::{
+    branch_lines, context_test_id, numbits_to_lines, read, CoveragePyError, Exclusions,
+};
+use rusqlite::{params, Connection};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+
+/// A data file with coverage.py's line-level schema, holding `rows` of
+/// `(context, path, lines)`.
+fn write_data_file(path: &Path, rows: &[(&str, &str, &[usize])]) {
+    let connection = Connection::open(path).unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE meta (key text, value text, unique (key));
+             CREATE TABLE file (id integer primary key, path text, unique (path));
+             CREATE TABLE context (id integer primary key, context text, unique (context));
+             CREATE TABLE line_bits (file_id integer, context_id integer, numbits blob,
+                                     unique (file_id, context_id));
+             INSERT INTO meta VALUES ('version', '7.4.0');",
+        )
+        .unwrap();
+    for (context, file, lines) in rows {
+        connection
+            .execute(
+                "INSERT OR IGNORE INTO context (context) VALUES (?1)",
+                [context],
+            )
+            .unwrap();
+        connection
+            .execute("INSERT OR IGNORE INTO file (path) VALUES (?1)", [file])
+            .unwrap();
+        let mut numbits = vec![0u8; lines.iter().max().unwrap() / 8 + 1];
+        for line in lines.iter() {
+            numbits[line / 8] |= 1 << (line % 8);
+        }
+        connection
+            .execute(
+                "INSERT INTO line_bits VALUES (
+                     (SELECT id FROM file WHERE path = ?1),
+                     (SELECT id FROM context WHERE context = ?2),
+                     ?3)",
+                params![file, context, numbits],
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn numbits_decode_to_line_numbers() {
+    assert_eq!(
+        numbits_to_lines(&[0b0000_0110, 0, 0b1000_0001]),
+        vec![1, 2, 16, 23]
+    );
+    assert!(numbits_to_lines(&[]).is_empty());
+}
+
+#[test]
+fn context_test_id_strips_the_pytest_cov_phase() {
+    assert_eq!(
+        context_test_id("test_calc.py::test_add|run"),
+        "test_calc.py::test_add"
+    );
+    assert_eq!(
+        context_test_id("test_calc.py::test_add|setup"),
+        "test_calc.py::test_add"
+    );
+    assert_eq!(
+        context_test_id("test_calc.py::test_add"),
+        "test_calc.py::test_add"
+    );
+    assert_eq!(context_test_id(""), "");
+}
+
+#[test]
+fn read_keys_lines_by_test_and_relative_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let calc = root.join("calc.py").to_string_lossy().into_owned();
+    let data_file = root.join(".coverage");
+    write_data_file(
+        &data_file,
+        &[
+            ("", &calc, &[1, 4]),
+            ("test_calc.py::test_add|run", &calc, &[5, 6]),
+            ("test_calc.py::test_add|teardown", &calc, &[9]),
+            (
+                "test_calc.py::test_add|run",
+                "/usr/lib/python3/os.py",
+                &[10],
+            ),
+        ],
+    );
+
+    let impact = read(&data_file, &root).unwrap();
+
+    assert_eq!(impact.lines.len(), 2);
+    assert_eq!(impact.lines[""]["calc.py"], HashSet::from([1, 4]));
+    let test_add = &impact.lines["test_calc.py::test_add"];
+    assert_eq!(test_add.len(), 1);
+    assert_eq!(test_add["calc.py"], HashSet::from([5, 6, 9]));
+    assert_eq!(
+        impact.tests_covering("calc.py", 5),
+        vec!["test_calc.py::test_add"]
+    );
+    assert!(impact.tests_covering("calc.py", 1).is_empty());
+}
+
+#[test]
+fn read_rejects_files_without_line_data() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_file = dir.path().join(".coverage");
+    Connection::open(&data_file)
+        .unwrap()
+        .execute_batch("CREATE TABLE meta (key text, value text);")
+        .unwrap();
+
+    assert!(matches!(
+        read(&data_file, dir.path()),
+        Err(CoveragePyError::NoLineData(_))
+    ));
+    assert!(matches!(
+        read(&dir.path().join("missing"), dir.path()),
+        Err(CoveragePyError::Sqlite { .. })
+    ));
+}
+
+#[test]
+fn read_keeps_arcs_for_branch_coverage() {
+    let dir = tempfile::tempdir().unwrap();
+    let data_file = dir.path().join(".coverage");
+    let connection = Connection::open(&data_file).unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE file (id integer primary key, path text);
+             CREATE TABLE context (id integer primary key, context text);
+             CREATE TABLE arc (file_id integer, context_id integer, fromno integer, tono integer);
+             INSERT INTO file VALUES (1, 'calc.py');
+             INSERT INTO context VALUES (1, 'test_calc.py::test_add|run');
+             INSERT INTO arc VALUES (1, 1, -1, 1), (1, 1, 1, 2), (1, 1, 2, 3), (1, 1, 3, -1);",
+        )
+        .unwrap();
+
+    let impact = read(&data_file, dir.path()).unwrap();
+
+    assert_eq!(
+        impact.lines["test_calc.py::test_add"]["calc.py"],
+        HashSet::from([1, 2, 3])
+    );
+    let arcs = &impact.arcs["calc.py"];
+    assert_eq!(arcs[&2], HashSet::from([3]));
+    assert_eq!(arcs[&3], HashSet::from([-1]));
+    assert!(!arcs.contains_key(&0));
+}
+
+#[test]
+fn branch_lines_are_conditional_and_loop_headers() {
+    let source = "def f(xs):\n    for x in xs:\n        if x:\n            pass\n        elif not x:\n            pass\n    while xs:\n        xs.pop()\n    return xs\n";
+    assert_eq!(branch_lines(source), BTreeSet::from([2, 3, 5, 7]));
+}
+
+#[test]
+fn pragma_excludes_a_line_or_a_whole_statement() {
+    let source = "def f(x):\n    if x:  # pragma: no cover\n        raise ValueError(x)\n    y = x  # pragma: no cover\n    return y\n\n\ndef g():  # pragma: no cover\n    pass\n";
+    assert_eq!(
+        Exclusions::default().excluded_lines(source),
+        BTreeSet::from([2, 3, 4, 8, 9])
+    );
+}
+
+#[test]
+fn exclusions_are_read_from_coveragerc() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".coveragerc"),
+        "[run]\nomit =\n    */migrations/*\n    setup.py\n\n[report]\nexclude_lines =\n    no cover\n    if TYPE_CHECKING:\n",
+    )
+    .unwrap();
+
+    let exclusions = Exclusions::discover(dir.path()).unwrap();
+
+    assert!(exclusions.omits("app/migrations/0001_initial.py", dir.path()));
+    assert!(exclusions.omits("migrations/0001_initial.py", dir.path()));
+    assert!(exclusions.omits("setup.py", dir.path()));
+    assert!(!exclusions.omits("app/models.py", dir.path()));
+    let source = "if TYPE_CHECKING:\n    import os\nx = 1  # no cover\ny = 2  # pragma: nocover\n";
+    // `exclude_lines` replaces the default pragma
+    assert_eq!(exclusions.excluded_lines(source), BTreeSet::from([1, 2, 3]));
+}
+
+#[test]
+fn exclude_also_in_pyproject_keeps_the_default_pragma() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("pyproject.toml"),
+        "[tool.coverage.report]\nexclude_also = [\"raise NotImplementedError\"]\nomit = [\"generated/*\"]\n",
+    )
+    .unwrap();
+
+    let exclusions = Exclusions::discover(dir.path()).unwrap();
+
+    assert!(exclusions.omits("generated/api.py", dir.path()));
+    let source = "x = 1  # pragma: no cover\nraise NotImplementedError\ny = 2\n";
+    assert_eq!(exclusions.excluded_lines(source), BTreeSet::from([1, 2]));
+}
+
+#[test]
+fn invalid_exclusion_patterns_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".coveragerc"),
+        "[report]\nexclude_also = (\n",
+    )
+    .unwrap();
+    assert!(matches!(
+        Exclusions::discover(dir.path()),
+        Err(CoveragePyError::Config { .. })
+    ));
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::config::DataDependency;
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::{Engine, EngineBuilder};
+use std::path::Path;
+
+fn model_dependency() -> DataDependency {
+    DataDependency {
+        paths: vec!["data/*.dvc".to_string(), "models/**".to_string()],
+        tests: vec!["tests/test_model.py::test_accuracy".to_string()],
+    }
+}
+
+fn engine(root: &Path) -> Engine {
+    EngineBuilder::new(root)
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .data_dependency(model_dependency())
+        .build()
+        .unwrap()
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn changed_dvc_pointer_selects_declared_tests() {
+    let fixture = calc_repo();
+    fixture.write("data/train.csv.dvc", "outs:\n- md5: aaa\n");
+    fixture.commit("track data");
+    let engine = engine(fixture.path());
+    assert!(engine.select().unwrap().tests.is_empty());
+
+    fixture.write("data/train.csv.dvc", "outs:\n- md5: bbb\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+    let selection = engine.select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec![
+            "tests/test_model.py::test_accuracy",
+            "tests/test_new.py::test_new"
+        ]
+    );
+    assert_eq!(
+        selection.tests[0].reason,
+        "data dependency data/train.csv.dvc changed"
+    );
+}
+
+#[test]
+fn rewritten_ignored_artifact_selects_declared_tests() {
+    let fixture = calc_repo();
+    fixture.write(".gitignore", "models/\n");
+    fixture.commit("ignore models");
+    fixture.write("models/weights.bin", "v1");
+    let engine = engine(fixture.path());
+    assert!(engine.select().unwrap().tests.is_empty());
+
+    fixture.write("models/weights.bin", "version 2");
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_model.py::test_accuracy"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn data_paths_are_watched() {
+    let fixture = calc_repo();
+    let engine = engine(fixture.path());
+    let root = fixture.path().canonicalize().unwrap();
+
+    assert!(engine.is_watched(&root.join("models/a/weights.bin")));
+    assert!(engine.is_watched(&root.join("calc.py")));
+    assert!(!engine.is_watched(&root.join("README.md")));
+}
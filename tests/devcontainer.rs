@@ -0,0 +1,75 @@
+mod common;
+
+use common::FixtureRepo;
+use hackweek_instant_codecoverage::devcontainer::{
+    load, strip_jsonc, DevcontainerConfig, DevcontainerRunner,
+};
+
+#[test]
+fn strips_comments_and_trailing_commas() {
+    let source = r#"{
+        // the image
+        "image": "python:3.12", /* pinned */
+        "workspaceFolder": "/src//app",
+        "forwardPorts": [8000,],
+    }"#;
+
+    let value: serde_json::Value = serde_json::from_str(&strip_jsonc(source)).unwrap();
+
+    assert_eq!(value["image"], "python:3.12");
+    assert_eq!(value["workspaceFolder"], "/src//app");
+    assert_eq!(value["forwardPorts"][0], 8000);
+}
+
+#[test]
+fn loads_workspace_folder_from_devcontainer_dir() {
+    let fixture = FixtureRepo::new();
+    assert_eq!(load(fixture.path()).unwrap(), None);
+
+    fixture.write(
+        ".devcontainer/devcontainer.json",
+        "{\n  // mounted here\n  \"workspaceFolder\": \"/work\",\n}\n",
+    );
+    let config = load(fixture.path()).unwrap().unwrap();
+
+    assert_eq!(config.workspace_folder(fixture.path()), "/work");
+}
+
+#[test]
+fn workspace_folder_defaults_to_workspaces_dir() {
+    let fixture = FixtureRepo::new();
+    let name = fixture.path().file_name().unwrap().to_str().unwrap();
+
+    assert_eq!(
+        DevcontainerConfig::default().workspace_folder(fixture.path()),
+        format!("/workspaces/{}", name)
+    );
+}
+
+#[test]
+fn exec_command_wraps_test_command() {
+    let fixture = FixtureRepo::new();
+    let config = DevcontainerConfig {
+        workspace_folder: Some("/work".to_string()),
+    };
+    let args = vec!["pytest".to_string(), "tests/test_new.py".to_string()];
+
+    let runner = DevcontainerRunner::new(fixture.path(), "pytest {tests}", &config);
+    let command = runner.exec_command(args.clone());
+    assert_eq!(command[..3], ["devcontainer", "exec", "--workspace-folder"]);
+    assert_eq!(command[4..], args);
+
+    let runner = runner.container("app-dev");
+    assert_eq!(
+        runner.exec_command(args),
+        [
+            "docker",
+            "exec",
+            "-w",
+            "/work",
+            "app-dev",
+            "pytest",
+            "tests/test_new.py"
+        ]
+    );
+}
@@ -0,0 +1,140 @@
+#![cfg(feature = "python")]
+
+mod common;
+
+use common::{calc_repo, CALC, TEST_CALC};
+use hackweek_instant_codecoverage::diff::get_diff;
+use hackweek_instant_codecoverage::discovery::PathFilter;
+use hackweek_instant_codecoverage::Language;
+
+#[test]
+fn clean_worktree_has_no_hunks() {
+    let fixture = calc_repo();
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(diffs.is_empty());
+}
+
+#[test]
+fn appended_lines_produce_one_hunk() {
+    let fixture = calc_repo();
+    let addition = "\n\ndef sub(a, b):\n    return a - b\n";
+    fixture.write("calc.py", &format!("{}{}", CALC, addition));
+
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert_eq!(diffs.len(), 1);
+    let diff = &diffs[0];
+    assert_eq!(diff.path, "calc.py");
+    assert_eq!(diff.start_offset, CALC.len());
+    assert_eq!(diff.deletion_end, CALC.len());
+    assert_eq!(diff.addition_end, CALC.len() + addition.len());
+}
+
+#[test]
+fn untracked_files_are_all_additions() {
+    let fixture = calc_repo();
+    let module = "def mul(a, b):\n    return a * b\n";
+    fixture.write("pkg/mul.py", module);
+
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert_eq!(diffs.len(), 1);
+    let diff = &diffs[0];
+    assert_eq!(diff.path, "pkg/mul.py");
+    assert_eq!(diff.start_offset, 0);
+    assert_eq!(diff.deletion_end, 0);
+    assert_eq!(diff.addition_end, module.len());
+}
+
+#[test]
+fn renamed_files_give_only_their_edits_under_the_new_path() {
+    let fixture = calc_repo();
+    let module = "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n\n\ndef neg(a):\n    return -a\n";
+    fixture.write("calc.py", module);
+    fixture.commit("more calc");
+    fixture.remove("calc.py");
+    fixture.write("arith.py", &module.replace("-a\n", "0 - a\n"));
+
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    assert_eq!(diffs.len(), 1);
+    let diff = &diffs[0];
+    assert_eq!(diff.path, "arith.py");
+    assert_eq!(diff.start_point.row, 9);
+    assert_eq!(
+        diff.deletion_end - diff.start_offset,
+        "    return -a\n".len()
+    );
+    assert_eq!(
+        diff.addition_end - diff.start_offset,
+        "    return 0 - a\n".len()
+    );
+}
+
+#[test]
+fn non_python_files_are_ignored() {
+    let fixture = calc_repo();
+    fixture.write("README.md", "hello\n");
+    fixture.commit("readme");
+    fixture.write("README.md", "hello world\n");
+
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert!(diffs.is_empty());
+}
+
+#[test]
+fn non_utf8_file_is_reported_and_skipped() {
+    let fixture = calc_repo();
+    std::fs::write(fixture.path().join("calc.py"), b"x = '\xff'\n").unwrap();
+    fixture.write("tests/test_calc.py", &format!("{}\n# note\n", TEST_CALC));
+
+    let mut failures = Vec::new();
+    let diffs = get_diff(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut failures,
+    )
+    .unwrap();
+
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].path, "tests/test_calc.py");
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].path, "calc.py");
+}
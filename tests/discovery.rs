@@ -0,0 +1,351 @@
+mod common;
+
+#[cfg(feature = "python")]
+use common::calc_repo;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::discovery::{
+    create_new_content_map, create_old_content_map, print_tree, refresh_content_map, TreeCache,
+};
+use hackweek_instant_codecoverage::discovery::{
+    create_parser, get_tests, slash_path, PathFilter, TestNames,
+};
+use hackweek_instant_codecoverage::Language;
+use std::collections::HashMap;
+
+#[cfg(feature = "python")]
+#[test]
+fn old_content_map_uses_repository_relative_paths() {
+    let fixture = calc_repo();
+    let content = create_old_content_map(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+
+    let mut paths: Vec<_> = content.keys().cloned().collect();
+    paths.sort();
+    assert_eq!(paths, vec!["calc.py", "tests/test_calc.py"]);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn new_content_map_matches_old_paths() {
+    let fixture = calc_repo();
+    let old = create_old_content_map(
+        &fixture.repo,
+        &fixture.head(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let new = create_new_content_map(
+        fixture.path(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(old, new);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn refreshed_content_map_only_reads_changed_files() {
+    let fixture = calc_repo();
+    let mut content = create_new_content_map(
+        fixture.path(),
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap();
+    let test_calc = content["tests/test_calc.py"].clone();
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+    fixture.write("tests/test_calc.py", "");
+
+    assert!(refresh_content_map(
+        fixture.path(),
+        &mut content,
+        ["calc.py"]
+    ));
+    assert_eq!(content["calc.py"], "def add(a, b):\n    return b + a\n");
+    assert_eq!(content["tests/test_calc.py"], test_calc);
+
+    // a file the last walk did not find needs another
+    fixture.write("util.py", "");
+    assert!(!refresh_content_map(
+        fixture.path(),
+        &mut content,
+        ["util.py"]
+    ));
+}
+
+#[test]
+fn paths_use_forward_slashes_on_every_platform() {
+    let path: std::path::PathBuf = ["tests", "unit", "test_calc.py"].iter().collect();
+    assert_eq!(
+        slash_path(path.to_str().unwrap()),
+        "tests/unit/test_calc.py"
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn discovers_test_functions_only() {
+    let source = "def helper():\n    pass\n\n\ndef test_add():\n    assert helper() is None\n";
+    assert_eq!(
+        discover(Language::Python, "tests/test_calc.py", source),
+        vec!["tests/test_calc.py::test_add"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn methods_are_named_after_their_enclosing_classes() {
+    let source = "class TestCalc:\n    def test_add(self):\n        pass\n\n    \
+                  class TestNested:\n        def test_sub(self):\n            pass\n\n\n\
+                  def test_top():\n    pass\n";
+    assert_eq!(
+        discover(Language::Python, "tests/test_calc.py", source),
+        vec![
+            "tests/test_calc.py::TestCalc::TestNested::test_sub",
+            "tests/test_calc.py::TestCalc::test_add",
+            "tests/test_calc.py::test_top",
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn discovers_decorated_and_async_tests() {
+    let source = "import pytest\n\n\n@pytest.mark.parametrize(\"test_value\", [1, 2])\n\
+                  def test_param(test_value):\n    pass\n\n\n@pytest.mark.asyncio\n\
+                  async def test_async():\n    pass\n\n\nclass TestCalc:\n    \
+                  @pytest.mark.skip\n    def test_skipped(self):\n        pass\n";
+    assert_eq!(
+        discover(Language::Python, "tests/test_calc.py", source),
+        vec![
+            "tests/test_calc.py::TestCalc::test_skipped",
+            "tests/test_calc.py::test_async",
+            "tests/test_calc.py::test_param",
+        ]
+    );
+}
+
+fn discover(language: Language, path: &str, source: &str) -> Vec<String> {
+    let content: HashMap<String, String> = [(path.to_string(), source.to_string())].into();
+    let mut parser = create_parser(language).unwrap();
+    let trees: HashMap<_, _> = [(path.to_string(), parser.parse(source, None).unwrap())].into();
+    let mut tests: Vec<_> = get_tests(&content, &trees, language, &TestNames::of(language))
+        .unwrap()
+        .into_iter()
+        .collect();
+    tests.sort();
+    tests
+}
+
+#[cfg(feature = "go")]
+#[test]
+fn discovers_go_tests() {
+    let source = "package calc\n\nfunc TestAdd(t *testing.T) {}\n\nfunc helper() {}\n\n\
+                  func TestMain(m *testing.M) {}\n\nfunc TestdataPath(name string) string {}\n";
+    assert_eq!(
+        discover(Language::Go, "calc_test.go", source),
+        vec!["calc_test.go::TestAdd"]
+    );
+}
+
+#[cfg(feature = "javascript")]
+#[test]
+fn discovers_javascript_tests() {
+    let source = "describe('calc', () => {\n  it('adds', () => {});\n  test('subtracts', () => {});\n  helper('nope');\n});\n";
+    assert_eq!(
+        discover(Language::JavaScript, "calc.test.js", source),
+        vec!["calc.test.js::adds", "calc.test.js::subtracts"]
+    );
+}
+
+#[cfg(feature = "ruby")]
+#[test]
+fn discovers_rspec_examples_in_their_groups() {
+    let source = "RSpec.describe Calculator do\n  context 'with integers' do\n    \
+                  it 'adds' do\n      expect(1 + 2).to eq(3)\n    end\n  end\n\n  \
+                  specify \"subtracts\" do\n  end\n\n  let(:calc) { Calculator.new }\n  \
+                  it { is_expected.to be_a(Calculator) }\nend\n";
+    assert_eq!(
+        discover(Language::Ruby, "spec/calc_spec.rb", source),
+        vec![
+            "spec/calc_spec.rb::Calculator::subtracts",
+            "spec/calc_spec.rb::Calculator::with integers::adds",
+        ]
+    );
+}
+
+#[cfg(feature = "java")]
+#[test]
+fn discovers_junit_tests_in_their_classes() {
+    let source = "package com.example;\n\nclass CalcTest {\n    @Test\n    void adds() {}\n\n    \
+                  @ParameterizedTest\n    @ValueSource(ints = {1, 2})\n    void positive(int n) {}\n\n    \
+                  @Nested\n    class Overflow {\n        @Test\n        void wraps() {}\n    }\n\n    \
+                  @BeforeEach\n    void setUp() {}\n}\n";
+    assert_eq!(
+        discover(
+            Language::Java,
+            "src/test/java/com/example/CalcTest.java",
+            source
+        ),
+        vec![
+            "src/test/java/com/example/CalcTest.java::CalcTest::Overflow::wraps",
+            "src/test/java/com/example/CalcTest.java::CalcTest::adds",
+            "src/test/java/com/example/CalcTest.java::CalcTest::positive",
+        ]
+    );
+}
+
+#[cfg(feature = "java")]
+#[test]
+fn junit_tests_in_interfaces_enums_and_records_are_scoped_by_them() {
+    let source = "interface CalcContract {\n    @Test\n    default void adds() {}\n}\n\n\
+                  enum Op {\n    ADD;\n\n    @Test\n    void applies() {}\n}\n\n\
+                  record Pair(int a, int b) {\n    @Test\n    void sums() {}\n}\n";
+    assert_eq!(
+        discover(Language::Java, "CalcContract.java", source),
+        vec![
+            "CalcContract.java::CalcContract::adds",
+            "CalcContract.java::Op::applies",
+            "CalcContract.java::Pair::sums",
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn unittest_test_cases_are_collected_whatever_their_name() {
+    let source = "import unittest\nfrom django import test\n\n\
+                  class CalcTests(unittest.TestCase):\n    def test_add(self):\n        pass\n\n\
+                  class ViewTests(test.SimpleTestCase):\n    def test_get(self):\n        pass\n\n\
+                  class Base(unittest.TestCase):\n    pass\n\n\
+                  class MoreTests(Base):\n    def test_more(self):\n        pass\n\n\
+                  class Helper:\n    def test_not_collected(self):\n        pass\n";
+    assert_eq!(
+        discover(Language::Python, "tests/test_calc.py", source),
+        vec![
+            "tests/test_calc.py::CalcTests::test_add",
+            "tests/test_calc.py::MoreTests::test_more",
+            "tests/test_calc.py::ViewTests::test_get",
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn configured_name_globs_replace_the_test_prefix() {
+    let source = "def test_adds():\n    pass\n\ndef subtracts_test():\n    pass\n\n\
+                  def check_multiplies():\n    pass\n\ndef helper():\n    pass\n";
+    let content: HashMap<String, String> = [("calc.py".to_string(), source.to_string())].into();
+    let mut parser = create_parser(Language::Python).unwrap();
+    let trees: HashMap<_, _> =
+        [("calc.py".to_string(), parser.parse(source, None).unwrap())].into();
+    let names = TestNames::new(Language::Python, &["*_test", "check_*"]).unwrap();
+
+    let mut tests: Vec<_> = get_tests(&content, &trees, Language::Python, &names)
+        .unwrap()
+        .into_iter()
+        .collect();
+    tests.sort();
+
+    assert_eq!(
+        tests,
+        vec!["calc.py::check_multiplies", "calc.py::subtracts_test"]
+    );
+    assert_eq!(
+        print_tree(content, trees, &names),
+        vec!["subtracts_test", "check_multiplies"]
+    );
+}
+
+#[test]
+fn path_filter_excludes_win_over_includes() {
+    let filter = PathFilter::new(&["src/**", "tests/**"], &["**/migrations/**"]).unwrap();
+    assert!(filter.matches("src/app/models.py"));
+    assert!(!filter.matches("src/app/migrations/0001_initial.py"));
+    assert!(!filter.matches("scripts/deploy.py"));
+    assert!(PathFilter::default().matches("scripts/deploy.py"));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn new_content_map_skips_excluded_files() {
+    let fixture = calc_repo();
+    fixture.write(".venv/lib/site.py", "def test_vendored():\n    pass\n");
+    let filter = PathFilter::new::<&str>(&[], &[".venv/**"]).unwrap();
+
+    let content =
+        create_new_content_map(fixture.path(), Language::Python, &filter, &mut Vec::new()).unwrap();
+
+    let mut paths: Vec<_> = content.keys().cloned().collect();
+    paths.sort();
+    assert_eq!(paths, vec!["calc.py", "tests/test_calc.py"]);
+}
+
+#[cfg(feature = "rust")]
+#[test]
+fn discovers_rust_tests_in_their_modules() {
+    let source = "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\n#[cfg(test)]\nmod tests {\n    \
+                  use super::*;\n\n    #[test]\n    fn adds() {\n        assert_eq!(add(1, 2), 3);\n    }\n\n    \
+                  #[test]\n    #[should_panic]\n    fn panics() {\n        panic!();\n    }\n\n    \
+                  #[tokio::test]\n    async fn awaits() {}\n\n    #[inline]\n    fn helper() {}\n}\n";
+    assert_eq!(
+        discover(Language::Rust, "src/calc.rs", source),
+        vec![
+            "src/calc.rs::tests::adds",
+            "src/calc.rs::tests::awaits",
+            "src/calc.rs::tests::panics",
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn tree_cache_reuses_trees_of_unchanged_files() {
+    let cache = TreeCache::new();
+    let mut parser = create_parser(Language::Python).unwrap();
+    let mut parse = |content: &str| {
+        let content = HashMap::from([("calc.py".to_string(), content.to_string())]);
+        let mut trees = HashMap::new();
+        cache.parse_all(&mut parser, &content, &mut trees, &mut Vec::new());
+        trees.remove("calc.py").unwrap()
+    };
+    // a root node's id is its tree's own; those below are shared by copies,
+    // and the trees are kept alive so those ids cannot be handed out again
+    let first = parse("VALUE = 1\n");
+    let same = parse("VALUE = 1\n");
+    let changed = parse("VALUE = 2\n");
+    assert_eq!(
+        same.root_node().child(0).unwrap().id(),
+        first.root_node().child(0).unwrap().id()
+    );
+    assert_ne!(
+        changed.root_node().child(0).unwrap().id(),
+        first.root_node().child(0).unwrap().id()
+    );
+
+    // trees used since the last sweep are kept, the rest dropped
+    cache.sweep();
+    let kept = parse("VALUE = 1\n");
+    cache.sweep();
+    cache.sweep();
+    let reparsed = parse("VALUE = 1\n");
+    assert_eq!(
+        kept.root_node().child(0).unwrap().id(),
+        first.root_node().child(0).unwrap().id()
+    );
+    assert_ne!(
+        reparsed.root_node().child(0).unwrap().id(),
+        first.root_node().child(0).unwrap().id()
+    );
+}
@@ -0,0 +1,157 @@
+#![cfg(feature = "python")]
+
+mod common;
+
+use common::FixtureRepo;
+use hackweek_instant_codecoverage::diff::{
+    diff_contents, edit_tree, get_diff, hunks_reproduce, BetterDiff,
+};
+use hackweek_instant_codecoverage::discovery::{create_parser, PathFilter};
+use hackweek_instant_codecoverage::Language;
+use proptest::prelude::*;
+use std::collections::HashMap;
+use tree_sitter::Point;
+
+fn point_at(text: &str, byte: usize) -> Point {
+    let prefix = &text[..byte];
+    let row = prefix.matches('\n').count();
+    let column = match prefix.rfind('\n') {
+        Some(newline) => byte - newline - 1,
+        None => byte,
+    };
+    Point { row, column }
+}
+
+fn render(lines: &[String], trailing_newline: bool) -> String {
+    let mut text = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        text.push('\n');
+    }
+    text
+}
+
+fn git_diffs(old: &str, new: &str) -> Vec<BetterDiff> {
+    let fixture = FixtureRepo::new();
+    fixture.write("module.py", old);
+    fixture.commit("old");
+    fixture.write("module.py", new);
+    let head = fixture.head();
+    get_diff(
+        &fixture.repo,
+        &head,
+        Language::Python,
+        &PathFilter::default(),
+        &mut Vec::new(),
+    )
+    .unwrap()
+}
+
+fn check_edits(old: &str, new: &str) -> Result<(), TestCaseError> {
+    check_replay(old, new, &git_diffs(old, new))
+}
+
+/// Replays every edit against the old text, splicing in the new bytes each
+/// edit claims to cover, and checks the result is exactly the new text.
+fn check_replay(old: &str, new: &str, diffs: &[BetterDiff]) -> Result<(), TestCaseError> {
+    let mut text = old.to_string();
+    for d in diffs {
+        prop_assert!(d.start_offset <= d.deletion_end);
+        prop_assert!(d.deletion_end <= text.len());
+        prop_assert!(d.addition_end <= new.len());
+        prop_assert_eq!(&text[..d.start_offset], &new[..d.start_offset]);
+        prop_assert_eq!(d.start_point, point_at(&text, d.start_offset));
+        prop_assert_eq!(d.deletion_point, point_at(&text, d.deletion_end));
+
+        text = format!(
+            "{}{}{}",
+            &text[..d.start_offset],
+            &new[d.start_offset..d.addition_end],
+            &text[d.deletion_end..]
+        );
+        prop_assert_eq!(d.addition_point, point_at(&text, d.addition_end));
+    }
+    prop_assert_eq!(text, new);
+    Ok(())
+}
+
+fn lines() -> impl Strategy<Value = Vec<String>> {
+    prop::collection::vec("[abc]{0,3}", 0..12)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn edits_reproduce_the_new_file(old in lines(), new in lines()) {
+        check_edits(&render(&old, true), &render(&new, true))?;
+    }
+
+    #[test]
+    fn edits_handle_missing_trailing_newline(
+        old in lines(),
+        new in lines(),
+        old_newline in any::<bool>(),
+        new_newline in any::<bool>(),
+    ) {
+        check_edits(&render(&old, old_newline), &render(&new, new_newline))?;
+    }
+
+    #[test]
+    fn content_diffs_reproduce_the_new_file(
+        old in lines(),
+        new in lines(),
+        old_newline in any::<bool>(),
+        new_newline in any::<bool>(),
+    ) {
+        let old = render(&old, old_newline);
+        let new = render(&new, new_newline);
+        check_replay(&old, &new, &diff_contents("module.py", &old, &new))?;
+    }
+}
+
+#[test]
+fn second_hunk_accounts_for_first() {
+    check_edits("a\nb\nc\nd\ne\n", "x\ny\na\nb\nc\nd\n").unwrap();
+}
+
+#[test]
+fn edited_tree_reparses_like_a_fresh_parse() {
+    let old = "def a():\n    return 1\n\n\ndef b():\n    return 2\n\n\ndef c():\n    return 3\n";
+    let new = "import os\n\n\ndef a():\n    return 10\n\n\ndef c():\n    return 3\n\n\ndef d():\n    pass\n";
+    let diffs = git_diffs(old, new);
+    assert!(diffs.len() > 1);
+
+    let mut parser = create_parser(Language::Python).unwrap();
+    let mut trees = HashMap::new();
+    trees.insert("module.py".to_string(), parser.parse(old, None).unwrap());
+    edit_tree(&diffs, &mut trees);
+
+    let incremental = parser.parse(new, Some(&trees["module.py"])).unwrap();
+    let fresh = parser.parse(new, None).unwrap();
+    assert_eq!(
+        incremental.root_node().to_sexp(),
+        fresh.root_node().to_sexp()
+    );
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn content_diffs_are_accepted_for_incremental_parsing(old in lines(), new in lines()) {
+        let (old, new) = (render(&old, true), render(&new, true));
+        prop_assert!(hunks_reproduce(&diff_contents("module.py", &old, &new), &old, &new));
+    }
+}
+
+#[test]
+fn changes_the_hunks_miss_rule_out_incremental_parsing() {
+    let old = "def a():\n    return 1\n";
+    let crlf = "def a():\r\n    return 1\r\n";
+    assert!(hunks_reproduce(&[], old, old));
+    assert!(!hunks_reproduce(&[], old, crlf));
+
+    let stale = diff_contents("module.py", old, "def a():\n    return 2\n");
+    // the name changed outside the one hunk there is
+    assert!(!hunks_reproduce(&stale, old, "def b():\n    return 2\n"));
+}
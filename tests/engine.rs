@@ -0,0 +1,1439 @@
+mod common;
+
+#[cfg(feature = "python")]
+use common::CALC;
+use common::{calc_repo, TEST_CALC};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::report::OutputFormat;
+#[cfg(feature = "java")]
+use hackweek_instant_codecoverage::runner::DEFAULT_COMMAND_TEMPLATE;
+use hackweek_instant_codecoverage::runner::{RunResult, Runner, RunnerError};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::selection::ImpactData;
+#[cfg(any(
+    feature = "python",
+    feature = "go",
+    feature = "rust",
+    feature = "ruby",
+    feature = "java"
+))]
+use hackweek_instant_codecoverage::Language;
+#[cfg(feature = "java")]
+use hackweek_instant_codecoverage::SelectedTest;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::{BaseMode, FULL_RESYNC_EVERY};
+use hackweek_instant_codecoverage::{
+    EmptySelection, Engine, EngineBuilder, EngineError, Selection,
+};
+#[cfg(feature = "python")]
+use serde_json::Value;
+#[cfg(feature = "python")]
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn unchanged_repository_selects_nothing() {
+    let fixture = calc_repo();
+    let selection = Engine::new(fixture.path()).select().unwrap();
+    assert!(selection.is_empty());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn new_test_is_selected() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_calc.py",
+        &format!("{}\n\ndef test_add_negative():\n    assert add(-1, -1) == -2\n", TEST_CALC),
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec!["tests/test_calc.py::test_add_negative"]
+    );
+    assert_eq!(selection.tests[0].file, "tests/test_calc.py");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn test_with_an_edited_body_is_selected() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_calc.py",
+        &format!(
+            "{}\n\ndef test_add_negative():\n    assert add(-1, -1) == -2\n",
+            TEST_CALC.replace("add(1, 2) == 3", "add(2, 2) == 4")
+        ),
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    let reasons: Vec<(&str, &str)> = selection
+        .tests
+        .iter()
+        .map(|test| (test.id.as_str(), test.reason.as_str()))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            ("tests/test_calc.py::test_add", "modified test"),
+            ("tests/test_calc.py::test_add_negative", "new test"),
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn removed_tests_are_left_out_even_when_coverage_names_them() {
+    let fixture = calls_repo();
+    let engine = EngineBuilder::new(fixture.path())
+        .impact_data(ImpactData {
+            lines: HashMap::from([(
+                "tests/test_calc.py::test_sub".to_string(),
+                HashMap::from([("calc.py".to_string(), HashSet::from([5, 6]))]),
+            )]),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a + -b\n",
+    );
+    fixture.write(
+        "tests/test_calc.py",
+        "from calc import add, sub\n\n\ndef check_sub(a, b, expected):\n    \
+         assert sub(a, b) == expected\n\n\ndef test_add():\n    assert add(1, 2) == 3\n",
+    );
+
+    let selection = engine.select().unwrap();
+
+    assert!(selection.tests.is_empty(), "{:?}", selection.tests);
+    assert_eq!(selection.removed, vec!["tests/test_calc.py::test_sub"]);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn renamed_test_is_reported_as_renamed() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_calc.py",
+        &TEST_CALC.replace("test_add", "test_addition"),
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_addition"]);
+    assert_eq!(
+        selection.tests[0].reason,
+        "renamed from tests/test_calc.py::test_add"
+    );
+    assert!(selection.removed.is_empty());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn tests_in_a_moved_file_are_reported_as_renamed() {
+    let fixture = calc_repo();
+    fixture.remove("tests/test_calc.py");
+    fixture.write("tests/test_arithmetic.py", TEST_CALC);
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_arithmetic.py::test_add"]);
+    assert_eq!(
+        selection.tests[0].reason,
+        "renamed from tests/test_calc.py::test_add"
+    );
+}
+
+#[test]
+fn moved_files_add_no_changed_lines() {
+    let fixture = calc_repo();
+    fixture.remove("tests/test_calc.py");
+    fixture.write("tests/test_arithmetic.py", TEST_CALC);
+
+    let coverage = Engine::new(fixture.path()).patch_coverage().unwrap();
+
+    assert_eq!(coverage.changed(), 0);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn test_methods_are_selected_by_their_class_qualified_id() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_calc.py",
+        "from calc import add\n\n\nclass TestAdd:\n    def test_small(self):\n        \
+         assert add(1, 2) == 3\n",
+    );
+    fixture.commit("group tests");
+    fixture.write(
+        "tests/test_calc.py",
+        "from calc import add\n\n\nclass TestAdd:\n    def test_small(self):\n        \
+         assert add(2, 2) == 4\n",
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec!["tests/test_calc.py::TestAdd::test_small"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn edited_parametrize_list_selects_the_test_once() {
+    let fixture = calc_repo();
+    let parametrized = "import pytest\n\nfrom calc import add\n\n\n\
+                        @pytest.mark.parametrize(\"a\", [1, 2])\ndef test_add(a):\n    \
+                        assert add(a, 0) == a\n";
+    fixture.write("tests/test_calc.py", parametrized);
+    fixture.commit("parametrize");
+    let engine = EngineBuilder::new(fixture.path())
+        .impact_data(ImpactData {
+            lines: ["1", "2"]
+                .iter()
+                .map(|param| {
+                    (
+                        format!("tests/test_calc.py::test_add[{}]", param),
+                        HashMap::from([("calc.py".to_string(), HashSet::from([1, 2]))]),
+                    )
+                })
+                .collect(),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+    fixture.write(
+        "tests/test_calc.py",
+        &parametrized.replace("[1, 2]", "[1, 2, 3]"),
+    );
+
+    let selection = engine.select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_add"]);
+    assert_eq!(selection.tests[0].reason, "modified test");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn configured_test_names_find_suffixed_tests() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_calc.py",
+        &format!(
+            "{}\n\ndef add_negative_test():\n    assert add(-1, -1) == -2\n",
+            TEST_CALC
+        ),
+    );
+
+    let selection = EngineBuilder::new(fixture.path())
+        .test_name("test_*")
+        .test_name("*_test")
+        .build()
+        .unwrap()
+        .select()
+        .unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec!["tests/test_calc.py::add_negative_test"]
+    );
+    assert_eq!(selection.tests[0].reason, "new test");
+}
+
+#[test]
+fn invalid_test_name_globs_are_rejected() {
+    let fixture = calc_repo();
+    let result = EngineBuilder::new(fixture.path())
+        .test_name("test_[")
+        .build();
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn untracked_test_file_is_selected() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_other.py::test_other"]);
+}
+
+struct RecordingRunner(Arc<Mutex<Vec<Selection>>>);
+
+impl Runner for RecordingRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        self.0.lock().unwrap().push(selection.clone());
+        Ok(RunResult::default())
+    }
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn run_once_hands_selection_to_runner() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let mut engine = Engine::new(fixture.path());
+    engine.set_runner(RecordingRunner(runs.clone()));
+
+    engine.run_once().unwrap();
+
+    let runs = runs.lock().unwrap();
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].ids(), vec!["tests/test_other.py::test_other"]);
+}
+
+#[test]
+fn opening_a_non_repository_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    assert!(Engine::new(dir.path()).select().is_err());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn selection_is_sorted_by_file_then_name() {
+    let fixture = calc_repo();
+    fixture.write(
+        "tests/test_b.py",
+        "def test_zeta():\n    pass\n\n\ndef test_alpha():\n    pass\n",
+    );
+    fixture.write("tests/test_a.py", "def test_middle():\n    pass\n");
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec![
+            "tests/test_a.py::test_middle",
+            "tests/test_b.py::test_alpha",
+            "tests/test_b.py::test_zeta",
+        ]
+    );
+}
+
+#[test]
+fn builder_rejects_template_without_placeholder() {
+    let fixture = calc_repo();
+    let result = EngineBuilder::new(fixture.path())
+        .command_template("pytest")
+        .build();
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+}
+
+#[test]
+fn builder_rejects_unknown_base() {
+    let fixture = calc_repo();
+    let result = EngineBuilder::new(fixture.path()).base("nope").build();
+    assert!(matches!(result, Err(EngineError::InvalidBase { .. })));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn builder_base_includes_committed_changes() {
+    let fixture = calc_repo();
+    let base = fixture.head().id().to_string();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+    fixture.commit("add test");
+
+    let engine = EngineBuilder::new(fixture.path())
+        .base(base)
+        .language(Language::Python)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_other.py::test_other"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn merge_base_mode_measures_the_branch_since_it_forked() {
+    let fixture = calc_repo();
+    let fork = fixture.head().id();
+    fixture.write("tests/test_main.py", "def test_main():\n    pass\n");
+    let main = fixture.commit("work on main");
+    fixture
+        .repo
+        .reference("refs/remotes/origin/main", main, false, "fetch")
+        .unwrap();
+    fixture.reset_hard(fork);
+    fixture.write("tests/test_branch.py", "def test_branch():\n    pass\n");
+    fixture.commit("work on the branch");
+    fixture.write("tests/test_edit.py", "def test_edit():\n    pass\n");
+
+    let engine = EngineBuilder::new(fixture.path())
+        .base("origin/main")
+        .base_mode(BaseMode::MergeBase)
+        .language(Language::Python)
+        .build()
+        .unwrap();
+
+    assert_eq!(engine.base_commit().unwrap(), fork.to_string());
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec![
+            "tests/test_branch.py::test_branch",
+            "tests/test_edit.py::test_edit"
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn builder_started_in_a_subdirectory_uses_the_checkout_root() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+
+    let engine = EngineBuilder::new(fixture.path().join("tests"))
+        .language(Language::Python)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        engine.root(),
+        std::fs::canonicalize(fixture.path()).unwrap()
+    );
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_other.py::test_other"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn patch_coverage_between_revisions_ignores_the_working_tree() {
+    let fixture = calc_repo();
+    let from = fixture.head().id().to_string();
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+    fixture.commit("add sub");
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+
+    let engine = EngineBuilder::new(fixture.path())
+        .language(Language::Python)
+        .impact_data(ImpactData {
+            lines: HashMap::from([(
+                "tests/test_calc.py::test_sub".to_string(),
+                HashMap::from([("calc.py".to_string(), HashSet::from([5, 6]))]),
+            )]),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    let coverage = engine.patch_coverage_between(&from, "HEAD").unwrap();
+
+    assert_eq!(coverage.files.len(), 1);
+    assert_eq!(coverage.files[0].path, "calc.py");
+    assert_eq!(coverage.files[0].changed_lines, vec![3, 4, 5, 6]);
+    assert_eq!(coverage.covered(), 2);
+}
+
+#[test]
+fn builder_reports_missing_repository() {
+    let dir = tempfile::tempdir().unwrap();
+    let result = EngineBuilder::new(dir.path()).build();
+    assert!(matches!(result, Err(EngineError::NotARepository(_))));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn no_baseline_selects_every_test() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("test_plain.py"),
+        "def test_one():\n    pass\n\n\ndef test_two():\n    pass\n",
+    )
+    .unwrap();
+
+    let engine = EngineBuilder::new(dir.path())
+        .no_baseline_fallback(true)
+        .build()
+        .unwrap();
+
+    assert!(engine.no_baseline());
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["test_plain.py::test_one", "test_plain.py::test_two"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn run_once_updates_shared_state() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+    let mut engine = Engine::new(fixture.path());
+    engine.set_runner(RecordingRunner(Arc::new(Mutex::new(Vec::new()))));
+    let state = engine.state();
+
+    engine.run_once().unwrap();
+
+    let snapshot = state.snapshot();
+    assert_eq!(snapshot.cycle, 1);
+    assert!(!snapshot.running);
+    assert_eq!(
+        snapshot.selection.unwrap().ids(),
+        vec!["tests/test_other.py::test_other"]
+    );
+    assert!(snapshot.last_result.is_some());
+}
+
+#[test]
+fn engine_can_be_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Engine>();
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn run_once_records_metrics() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+    let mut engine = Engine::new(fixture.path());
+    engine.set_runner(RecordingRunner(Arc::new(Mutex::new(Vec::new()))));
+    let state = engine.state();
+
+    engine.run_once().unwrap();
+    engine.run_once().unwrap();
+
+    let metrics = state.metrics().snapshot();
+    assert_eq!(metrics.cycles, 2);
+    assert_eq!(metrics.failed_cycles, 0);
+    assert_eq!(metrics.tests_selected, 2);
+    assert_eq!(metrics.baseline_misses, 1);
+    assert_eq!(metrics.baseline_hits, 1);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn hard_reset_rebuilds_the_baseline() {
+    let fixture = calc_repo();
+    let initial = fixture.head().id();
+    fixture.write(
+        "tests/test_calc.py",
+        &format!(
+            "{}\n\ndef test_add_zero():\n    assert add(0, 0) == 0\n",
+            TEST_CALC
+        ),
+    );
+    fixture.commit("add test_add_zero");
+    let engine = Engine::new(fixture.path());
+    assert!(engine.select().unwrap().is_empty());
+
+    fixture.reset_hard(initial);
+    fixture.write(
+        "tests/test_calc.py",
+        &format!(
+            "{}\n\ndef test_add_one():\n    assert add(1, 0) == 1\n",
+            TEST_CALC
+        ),
+    );
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_calc.py::test_add_one"]
+    );
+}
+
+fn run_unchanged(on_empty: EmptySelection) -> Vec<Selection> {
+    let fixture = calc_repo();
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(RecordingRunner(runs.clone()))
+        .on_empty(on_empty)
+        .build()
+        .unwrap();
+    engine.run_once().unwrap();
+    let runs = runs.lock().unwrap().clone();
+    runs
+}
+
+#[test]
+fn empty_selection_skips_the_run_by_default() {
+    assert!(run_unchanged(EmptySelection::default()).is_empty());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn empty_selection_can_run_a_smoke_set() {
+    let runs = run_unchanged(EmptySelection::Smoke(vec![
+        "tests/test_calc.py::test_add".to_string()
+    ]));
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].ids(), vec!["tests/test_calc.py::test_add"]);
+    assert_eq!(runs[0].tests[0].reason, "smoke");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn empty_selection_runs_everything_only_when_asked() {
+    let runs = run_unchanged(EmptySelection::All);
+    assert_eq!(runs.len(), 1);
+    assert!(runs[0].is_empty());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn dry_run_records_the_selection_without_running_it() {
+    let fixture = calc_repo();
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(RecordingRunner(runs.clone()))
+        .dry_run(true)
+        .build()
+        .unwrap();
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    let result = engine.run_once().unwrap();
+
+    assert!(!result.executed);
+    assert!(runs.lock().unwrap().is_empty());
+    assert_eq!(
+        engine.state().snapshot().selection.unwrap().ids(),
+        vec!["tests/test_new.py::test_new"]
+    );
+}
+
+#[cfg(feature = "python")]
+struct PassingRunner;
+
+#[cfg(feature = "python")]
+impl Runner for PassingRunner {
+    fn run(&self, selection: &Selection) -> Result<RunResult, RunnerError> {
+        Ok(RunResult {
+            command: format!("pytest {}", selection.ids().join(" ")),
+            executed: true,
+            exit_code: Some(0),
+            ..RunResult::default()
+        })
+    }
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn json_report_is_written_to_the_output_file() {
+    let fixture = calc_repo();
+    let report = fixture.path().join("report.json");
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(PassingRunner)
+        .output(OutputFormat::Json)
+        .output_file(&report)
+        .impact_data(ImpactData {
+            lines: HashMap::from([(
+                "tests/test_new.py::test_new".to_string(),
+                HashMap::from([("calc.py".to_string(), HashSet::from([3]))]),
+            )]),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    engine.run_once().unwrap();
+
+    let report: Value = serde_json::from_str(&std::fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["version"], 1);
+    assert_eq!(
+        report["selection"]["tests"][0]["id"],
+        "tests/test_new.py::test_new"
+    );
+    assert_eq!(report["result"]["exit_code"], 0);
+    // the untracked test file's two lines count as added
+    assert_eq!(report["coverage"]["changed"], 6);
+    assert_eq!(report["coverage"]["covered"], 1);
+    let file = &report["coverage"]["files"][0];
+    assert_eq!(file["path"], "calc.py");
+    assert_eq!(file["uncovered_lines"], serde_json::json!([4, 5, 6]));
+    assert_eq!(file["hunks"][0]["first"], 3);
+    assert_eq!(file["hunks"][0]["last"], 6);
+    assert_eq!(file["hunks"][0]["percent"], 25.0);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn lcov_output_defaults_to_lcov_info_at_the_root() {
+    let fixture = calc_repo();
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(PassingRunner)
+        .output(OutputFormat::Lcov)
+        .build()
+        .unwrap();
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    engine.run_once().unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(fixture.path().join("lcov.info")).unwrap(),
+        "TN:\nSF:calc.py\nDA:2,0\nLF:1\nLH:0\nend_of_record\n\
+         TN:\nSF:tests/test_new.py\nDA:1,0\nDA:2,0\nLF:2\nLH:0\nend_of_record\n"
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn html_report_is_written_into_the_directory() {
+    let fixture = calc_repo();
+    let dir = fixture.path().join("report");
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(PassingRunner)
+        .html_dir(&dir)
+        .build()
+        .unwrap();
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    engine.run_once().unwrap();
+
+    let html = std::fs::read_to_string(dir.join("index.html")).unwrap();
+    assert!(html.contains("<h2>calc.py &mdash; 0/1</h2>"));
+    assert!(html.contains("<td>    return b + a</td>"));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn coverage_below_fail_under_is_not_ok() {
+    let fixture = calc_repo();
+    let builder = || EngineBuilder::new(fixture.path()).runner(PassingRunner);
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+
+    assert!(builder().build().unwrap().coverage_ok().unwrap());
+    assert!(!builder()
+        .fail_under(50.0)
+        .build()
+        .unwrap()
+        .coverage_ok()
+        .unwrap());
+    assert!(builder()
+        .fail_under(0.0)
+        .build()
+        .unwrap()
+        .coverage_ok()
+        .unwrap());
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn pragmas_take_lines_out_of_patch_coverage() {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef debug():  # pragma: no cover\n    print(1)\n\n\nX = 1\n",
+    );
+
+    let coverage = Engine::new(fixture.path()).patch_coverage().unwrap();
+
+    assert_eq!(coverage.files[0].changed_lines, vec![3, 4, 7, 8, 9]);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn excluded_files_are_neither_selected_nor_watched() {
+    let fixture = calc_repo();
+    let engine = EngineBuilder::new(fixture.path())
+        .exclude("generated/**")
+        .build()
+        .unwrap();
+    fixture.write("generated/test_stub.py", "def test_stub():\n    pass\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+    let root = fixture.path().canonicalize().unwrap();
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_new.py::test_new"]
+    );
+    assert!(!engine.is_watched(&root.join("generated/test_stub.py")));
+    assert!(engine.is_watched(&root.join("tests/test_new.py")));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn gitignored_files_are_neither_selected_nor_watched() {
+    let fixture = calc_repo();
+    fixture.write(".gitignore", ".venv/\n*.egg-info/\n");
+    fixture.commit("ignore");
+    let engine = Engine::new(fixture.path());
+    fixture.write(
+        ".venv/lib/test_vendored.py",
+        "def test_vendored():\n    pass\n",
+    );
+    fixture.write("calc.egg-info/test_meta.py", "def test_meta():\n    pass\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+    let root = fixture.path().canonicalize().unwrap();
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_new.py::test_new"]
+    );
+    assert!(!engine.is_watched(&root.join(".venv/lib/test_vendored.py")));
+    assert!(!engine.is_watched(&root.join("calc.egg-info/test_meta.py")));
+    assert!(engine.is_watched(&root.join("tests/test_new.py")));
+}
+
+#[test]
+fn submodules_are_skipped_with_a_warning() {
+    let fixture = calc_repo();
+    let vendored = common::FixtureRepo::new();
+    vendored.write("tests/test_vendored.py", "def test_vendored():\n    pass\n");
+    let commit = vendored.commit("vendored");
+    let submodule = fixture.path().join("vendor/lib");
+    std::fs::create_dir_all(submodule.parent().unwrap()).unwrap();
+    std::fs::rename(vendored.path(), &submodule).unwrap();
+    let mut index = fixture.repo.index().unwrap();
+    index
+        .add(&git2::IndexEntry {
+            ctime: git2::IndexTime::new(0, 0),
+            mtime: git2::IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o160000,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: commit,
+            flags: 0,
+            flags_extended: 0,
+            path: b"vendor/lib".to_vec(),
+        })
+        .unwrap();
+    index.write().unwrap();
+    fixture.commit("add submodule");
+    let engine = Engine::new(fixture.path());
+    std::fs::write(
+        submodule.join("tests/test_vendored.py"),
+        "def test_vendored():\n    pass\n\n\ndef test_more():\n    pass\n",
+    )
+    .unwrap();
+    let root = fixture.path().canonicalize().unwrap();
+
+    let selection = engine.select().unwrap();
+
+    assert!(selection.is_empty(), "{:?}", selection.ids());
+    let skipped: Vec<&str> = selection.skipped.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(skipped, vec!["vendor/lib"]);
+    assert!(!engine.is_watched(&root.join("vendor/lib/tests/test_vendored.py")));
+}
+
+#[test]
+fn builder_rejects_empty_smoke_set() {
+    let fixture = calc_repo();
+    let result = EngineBuilder::new(fixture.path())
+        .on_empty(EmptySelection::Smoke(Vec::new()))
+        .build();
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn unreadable_file_is_skipped_not_fatal() {
+    let fixture = calc_repo();
+    std::fs::write(fixture.path().join("latin1.py"), b"name = '\xe9'\n").unwrap();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_other.py::test_other"]);
+    assert_eq!(selection.skipped.len(), 1);
+    assert_eq!(selection.skipped[0].path, "latin1.py");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn existing_tests_covering_a_changed_line_are_selected() {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+    fixture.commit("add sub");
+    let engine = EngineBuilder::new(fixture.path())
+        .impact_data(ImpactData {
+            lines: HashMap::from([
+                (
+                    "tests/test_calc.py::test_add".to_string(),
+                    HashMap::from([("calc.py".to_string(), HashSet::from([1, 2]))]),
+                ),
+                (
+                    "tests/test_calc.py::test_sub".to_string(),
+                    HashMap::from([("calc.py".to_string(), HashSet::from([5, 6]))]),
+                ),
+            ]),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return b + a\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+
+    let selection = engine.select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_add"]);
+    assert_eq!(selection.tests[0].reason, "covers calc.py");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn code_no_test_ran_yet_selects_the_tests_exercising_its_file() {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+    fixture.commit("add sub");
+    let engine = EngineBuilder::new(fixture.path())
+        .impact_data(ImpactData {
+            lines: HashMap::from([
+                (
+                    "tests/test_calc.py::test_add".to_string(),
+                    HashMap::from([("calc.py".to_string(), HashSet::from([1, 2]))]),
+                ),
+                (
+                    "tests/test_calc.py::test_sub".to_string(),
+                    HashMap::from([("calc.py".to_string(), HashSet::from([5, 6]))]),
+                ),
+            ]),
+            ..ImpactData::default()
+        })
+        .build()
+        .unwrap();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return b + a\n\n\ndef sub(a, b):\n    return a - b\n\n\n\
+         def mul(a, b):\n    return a * b\n",
+    );
+
+    let selection = engine.select().unwrap();
+
+    let reasons: Vec<(&str, &str)> = selection
+        .tests
+        .iter()
+        .map(|test| (test.id.as_str(), test.reason.as_str()))
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            ("tests/test_calc.py::test_add", "covers calc.py"),
+            ("tests/test_calc.py::test_sub", "exercises calc.py"),
+        ]
+    );
+}
+
+/// A repository whose tests reach `add` directly and `sub` through a helper.
+#[cfg(feature = "python")]
+fn calls_repo() -> common::FixtureRepo {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+    fixture.write(
+        "tests/test_calc.py",
+        "from calc import add, sub\n\n\ndef check_sub(a, b, expected):\n    \
+         assert sub(a, b) == expected\n\n\ndef test_add():\n    assert add(1, 2) == 3\n\n\n\
+         def test_sub():\n    check_sub(3, 2, 1)\n",
+    );
+    fixture.commit("add sub");
+    fixture
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn without_coverage_tests_calling_a_changed_function_are_selected() {
+    let fixture = calls_repo();
+    fixture.write(
+        "calc.py",
+        "def add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a + -b\n",
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_sub"]);
+    assert_eq!(selection.tests[0].reason, "calls sub");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn without_coverage_module_level_changes_select_importing_tests() {
+    let fixture = calls_repo();
+    fixture.write(
+        "calc.py",
+        "PRECISION = 2\n\n\ndef add(a, b):\n    return a + b\n\n\ndef sub(a, b):\n    return a - b\n",
+    );
+
+    let selection = Engine::new(fixture.path()).select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec![
+            "tests/test_calc.py::test_add",
+            "tests/test_calc.py::test_sub"
+        ]
+    );
+    assert_eq!(selection.tests[0].reason, "imports calc.py");
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn changed_pytest_settings_rerun_every_test() {
+    let fixture = calc_repo();
+    fixture.write("tests/unit/test_more.py", "def test_more():\n    pass\n");
+    fixture.write("pytest.ini", "[pytest]\n");
+    fixture.commit("settings");
+    let engine = EngineBuilder::new(fixture.path()).build().unwrap();
+    fixture.write("pytest.ini", "[pytest]\naddopts = -x\n");
+
+    let selection = engine.select().unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec![
+            "tests/test_calc.py::test_add",
+            "tests/unit/test_more.py::test_more"
+        ]
+    );
+    assert_eq!(selection.tests[0].reason, "pytest.ini changed");
+    assert!(engine.is_watched(&fixture.path().join("pytest.ini")));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn changed_conftest_reruns_the_tests_below_it() {
+    let fixture = calc_repo();
+    fixture.write("tests/unit/test_more.py", "def test_more():\n    pass\n");
+    fixture.commit("more");
+    let engine = EngineBuilder::new(fixture.path()).build().unwrap();
+    fixture.write(
+        "tests/unit/conftest.py",
+        "import pytest\n\n\n@pytest.fixture\ndef calc():\n    return 1\n",
+    );
+
+    let selection = engine.select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/unit/test_more.py::test_more"]);
+    assert_eq!(selection.tests[0].reason, "tests/unit/conftest.py changed");
+}
+
+#[cfg(feature = "rust")]
+const RUST_CALC: &str = "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n\n\
+                         #[cfg(test)]\nmod tests {\n    use super::*;\n\n    #[test]\n    \
+                         fn adds() {\n        assert_eq!(add(1, 2), 3);\n    }\n}\n";
+
+#[cfg(feature = "rust")]
+#[test]
+fn rust_changes_select_the_tests_in_the_changed_module() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("src/lib.rs", "pub mod calc;\n\n#[test]\nfn links() {}\n");
+    fixture.write("src/calc.rs", RUST_CALC);
+    fixture.commit("initial");
+    fixture.write("src/calc.rs", &RUST_CALC.replace("a + b", "b + a"));
+
+    let selection = EngineBuilder::new(fixture.path())
+        .language(Language::Rust)
+        .build()
+        .unwrap()
+        .select()
+        .unwrap();
+
+    assert_eq!(selection.ids(), vec!["src/calc.rs::tests::adds"]);
+    assert_eq!(selection.tests[0].reason, "in module src/calc.rs");
+}
+
+#[cfg(feature = "rust")]
+#[test]
+fn rust_patch_coverage_comes_from_the_llvm_cov_export() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("src/calc.rs", RUST_CALC);
+    fixture.commit("initial");
+    let calc = std::fs::canonicalize(fixture.path())
+        .unwrap()
+        .join("src/calc.rs");
+    fixture.write(
+        "target/llvm-cov.json",
+        &serde_json::json!({
+            "data": [{"files": [{
+                "filename": calc,
+                "segments": [[1, 37, 1, true, true, false], [3, 2, 0, false, false, false]]
+            }]}]
+        })
+        .to_string(),
+    );
+    fixture.write(
+        "src/calc.rs",
+        &RUST_CALC.replace(
+            "a + b",
+            "b + a\n}\n\npub fn sub(a: i32, b: i32) -> i32 {\n    a - b",
+        ),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .language(Language::Rust)
+        .build()
+        .unwrap();
+
+    let coverage = engine.patch_coverage().unwrap();
+
+    assert_eq!(coverage.files[0].changed_lines, vec![2, 3, 4, 5, 6]);
+    assert_eq!(coverage.files[0].covered_lines, vec![2, 3]);
+}
+
+#[cfg(feature = "go")]
+const GO_CALC: &str = "package calc\n\nfunc Add(a, b int) int {\n\treturn a + b\n}\n";
+
+#[cfg(feature = "go")]
+fn go_repo() -> common::FixtureRepo {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("go.mod", "module example.com/calc\n");
+    fixture.write("calc/calc.go", GO_CALC);
+    fixture.write(
+        "calc/calc_test.go",
+        "package calc\n\nimport \"testing\"\n\nfunc TestAdd(t *testing.T) {}\n",
+    );
+    fixture.write(
+        "calc/ops/ops_test.go",
+        "package ops\n\nimport \"testing\"\n\nfunc TestOps(t *testing.T) {}\n",
+    );
+    fixture.commit("initial");
+    fixture
+}
+
+#[cfg(feature = "go")]
+#[test]
+fn go_changes_select_the_tests_in_the_same_package() {
+    let fixture = go_repo();
+    fixture.write("calc/calc.go", &GO_CALC.replace("a + b", "b + a"));
+
+    let selection = EngineBuilder::new(fixture.path())
+        .language(Language::Go)
+        .build()
+        .unwrap()
+        .select()
+        .unwrap();
+
+    assert_eq!(selection.ids(), vec!["calc/calc_test.go::TestAdd"]);
+    assert_eq!(selection.tests[0].reason, "in the package of calc/calc.go");
+}
+
+#[cfg(feature = "go")]
+#[test]
+fn go_patch_coverage_comes_from_the_cover_profile() {
+    let fixture = go_repo();
+    fixture.write(
+        "coverage.out",
+        "mode: set\nexample.com/calc/calc/calc.go:3.25,5.2 1 1\n",
+    );
+    fixture.write(
+        "calc/calc.go",
+        &format!(
+            "{}\nfunc Sub(a, b int) int {{\n\treturn a - b\n}}\n",
+            GO_CALC.replace("a + b", "b + a")
+        ),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .language(Language::Go)
+        .build()
+        .unwrap();
+
+    let coverage = engine.patch_coverage().unwrap();
+
+    assert_eq!(coverage.files[0].changed_lines, vec![4, 5, 6, 7, 8]);
+    assert_eq!(coverage.files[0].covered_lines, vec![4, 5]);
+}
+
+#[cfg(feature = "ruby")]
+const RUBY_CALC: &str = "class Calculator\n  def add(a, b)\n    a + b\n  end\nend\n";
+
+#[cfg(feature = "ruby")]
+const RUBY_SPEC: &str = "RSpec.describe Calculator do\n  it 'adds' do\n    \
+                         expect(Calculator.new.add(1, 2)).to eq(3)\n  end\nend\n";
+
+#[cfg(feature = "ruby")]
+#[test]
+fn ruby_edited_examples_are_selected_by_their_group() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("Gemfile", "gem 'rspec'\n");
+    fixture.write("lib/calc.rb", RUBY_CALC);
+    fixture.write("spec/calc_spec.rb", RUBY_SPEC);
+    fixture.commit("initial");
+    fixture.write("spec/calc_spec.rb", &RUBY_SPEC.replace("eq(3)", "eql(3)"));
+
+    let selection = EngineBuilder::new(fixture.path())
+        .build()
+        .unwrap()
+        .select()
+        .unwrap();
+
+    assert_eq!(selection.ids(), vec!["spec/calc_spec.rb::Calculator::adds"]);
+    assert_eq!(selection.tests[0].reason, "modified test");
+}
+
+#[cfg(feature = "ruby")]
+#[test]
+fn ruby_patch_coverage_comes_from_the_simplecov_result_set() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("lib/calc.rb", RUBY_CALC);
+    fixture.commit("initial");
+    let calc = std::fs::canonicalize(fixture.path())
+        .unwrap()
+        .join("lib/calc.rb");
+    fixture.write(
+        "coverage/.resultset.json",
+        &serde_json::json!({
+            "RSpec": {"coverage": {calc.to_str().unwrap(): {"lines": [1, 1, 1, null, null]}}}
+        })
+        .to_string(),
+    );
+    fixture.write(
+        "lib/calc.rb",
+        &RUBY_CALC.replace(
+            "a + b\n  end",
+            "b + a\n  end\n\n  def sub(a, b)\n    a - b\n  end",
+        ),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .language(Language::Ruby)
+        .build()
+        .unwrap();
+
+    let coverage = engine.patch_coverage().unwrap();
+
+    assert_eq!(coverage.files[0].changed_lines, vec![3, 4, 5, 6, 7]);
+    assert_eq!(coverage.files[0].covered_lines, vec![3]);
+}
+
+#[cfg(feature = "java")]
+const JAVA_CALC: &str = "package com.example;\n\npublic class Calc {\n    \
+                         int add(int a, int b) {\n        return a + b;\n    }\n}\n";
+
+#[cfg(feature = "java")]
+#[test]
+fn java_patch_coverage_comes_from_the_jacoco_report() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("pom.xml", "<project/>\n");
+    fixture.write("src/main/java/com/example/Calc.java", JAVA_CALC);
+    fixture.commit("initial");
+    fixture.write(
+        "target/site/jacoco/jacoco.xml",
+        "<report name=\"calc\"><package name=\"com/example\"><sourcefile name=\"Calc.java\">\
+         <line nr=\"3\" mi=\"0\" ci=\"3\" mb=\"0\" cb=\"0\"/>\
+         <line nr=\"5\" mi=\"0\" ci=\"4\" mb=\"0\" cb=\"0\"/>\
+         </sourcefile></package></report>",
+    );
+    fixture.write(
+        "src/main/java/com/example/Calc.java",
+        &JAVA_CALC.replace(
+            "a + b;\n    }",
+            "b + a;\n    }\n\n    int sub(int a, int b) {\n        return a - b;\n    }",
+        ),
+    );
+    let engine = EngineBuilder::new(fixture.path()).build().unwrap();
+
+    let coverage = engine.patch_coverage().unwrap();
+
+    assert_eq!(
+        coverage.files[0].path,
+        "src/main/java/com/example/Calc.java"
+    );
+    assert_eq!(coverage.files[0].changed_lines, vec![5, 6, 7, 8, 9]);
+    assert_eq!(coverage.files[0].covered_lines, vec![5]);
+}
+
+#[cfg(feature = "java")]
+#[test]
+fn java_tests_naming_no_class_fall_under_the_empty_selection_policy() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("pom.xml", "<project/>\n");
+    fixture.write("src/main/java/com/example/Calc.java", JAVA_CALC);
+    fixture.commit("initial");
+    let classless = Selection::new(vec![SelectedTest::new("Calc.java::adds", "")]);
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let skipping = EngineBuilder::new(fixture.path())
+        .runner(RecordingRunner(runs.clone()))
+        .build()
+        .unwrap();
+    let smoke = EngineBuilder::new(fixture.path())
+        .runner(RecordingRunner(runs.clone()))
+        .on_empty(EmptySelection::Smoke(vec![
+            "src/test/java/com/example/CalcTest.java::CalcTest::adds".to_string(),
+        ]))
+        .build()
+        .unwrap();
+
+    assert!(!skipping.run(&classless).unwrap().executed);
+    assert!(runs.lock().unwrap().is_empty());
+    smoke.run(&classless).unwrap();
+
+    assert_eq!(
+        runs.lock().unwrap()[0].ids(),
+        vec!["-Dtest=com.example.CalcTest#adds"]
+    );
+}
+
+#[cfg(feature = "java")]
+#[test]
+fn a_command_matching_the_python_default_is_kept_for_other_languages() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("build.gradle.kts", "");
+    fixture.write("gradlew", "");
+    fixture.write("src/main/java/com/example/Calc.java", JAVA_CALC);
+    fixture.commit("initial");
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let engine = EngineBuilder::new(fixture.path())
+        .language(Language::Java)
+        .command_template(DEFAULT_COMMAND_TEMPLATE)
+        .runner(RecordingRunner(runs.clone()))
+        .build()
+        .unwrap();
+
+    engine
+        .run(&Selection::new(vec![SelectedTest::new(
+            "src/test/java/com/example/CalcTest.java::CalcTest::adds",
+            "",
+        )]))
+        .unwrap();
+
+    assert_eq!(
+        runs.lock().unwrap()[0].ids(),
+        vec!["-Dtest=com.example.CalcTest#adds"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn unittest_commands_are_given_dotted_test_names() {
+    let fixture = common::FixtureRepo::new();
+    fixture.write("calc.py", "def add(a, b):\n    return a + b\n");
+    fixture.write("tests/__init__.py", "");
+    fixture.write(
+        "tests/test_calc.py",
+        "import unittest\n\nclass CalcTests(unittest.TestCase):\n    \
+         def test_add(self):\n        self.assertEqual(add(1, 2), 3)\n",
+    );
+    fixture.commit("initial");
+    fixture.write(
+        "tests/test_calc.py",
+        "import unittest\n\nclass CalcTests(unittest.TestCase):\n    \
+         def test_add(self):\n        self.assertEqual(add(2, 2), 4)\n",
+    );
+    let runs = Arc::new(Mutex::new(Vec::new()));
+    let engine = EngineBuilder::new(fixture.path())
+        .command_template("python -m unittest {tests}")
+        .runner(RecordingRunner(runs.clone()))
+        .build()
+        .unwrap();
+
+    let selection = engine.select().unwrap();
+    engine.run(&selection).unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec!["tests/test_calc.py::CalcTests::test_add"]
+    );
+    assert_eq!(
+        runs.lock().unwrap()[0].ids(),
+        vec!["tests.test_calc.CalcTests.test_add"]
+    );
+}
+
+#[cfg(feature = "python")]
+fn test_calc_with(names: &[&str]) -> String {
+    let mut content = TEST_CALC.to_string();
+    for name in names {
+        content.push_str(&format!("\n\ndef {}():\n    assert add(0, 0) == 0\n", name));
+    }
+    content
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn rediffing_changed_files_matches_a_full_diff() {
+    let fixture = calc_repo();
+    let engine = Engine::new(fixture.path());
+    let changed = [fixture.path().join("tests/test_calc.py")];
+
+    fixture.write("tests/test_calc.py", &test_calc_with(&["test_one"]));
+    assert_eq!(
+        engine.select_changed(&changed).unwrap().ids(),
+        vec!["tests/test_calc.py::test_one"]
+    );
+    fixture.write(
+        "tests/test_calc.py",
+        &test_calc_with(&["test_one", "test_two"]),
+    );
+    let scoped = engine.select_changed(&changed).unwrap();
+
+    assert_eq!(
+        scoped.ids(),
+        Engine::new(fixture.path()).select().unwrap().ids()
+    );
+    assert_eq!(
+        scoped.ids(),
+        vec![
+            "tests/test_calc.py::test_one",
+            "tests/test_calc.py::test_two"
+        ]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn new_files_are_found_by_a_full_diff() {
+    let fixture = calc_repo();
+    let engine = Engine::new(fixture.path());
+    engine.select().unwrap();
+
+    fixture.write(
+        "tests/test_more.py",
+        "from calc import add\n\n\ndef test_more():\n    assert add(1, 1) == 2\n",
+    );
+    let selection = engine
+        .select_changed(&[fixture.path().join("tests/test_more.py")])
+        .unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_more.py::test_more"]);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn missed_changes_are_picked_up_by_the_periodic_resync() {
+    let fixture = calc_repo();
+    let engine = Engine::new(fixture.path());
+    fixture.write("tests/test_calc.py", &test_calc_with(&["test_one"]));
+    engine.select().unwrap();
+
+    // the watcher never reports this edit
+    fixture.write(
+        "tests/test_calc.py",
+        &test_calc_with(&["test_one"]).replace("add(1, 2) == 3", "add(2, 2) == 4"),
+    );
+    for _ in 1..FULL_RESYNC_EVERY {
+        let selection = engine.select_changed(&[]).unwrap();
+        assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_one"]);
+    }
+    let selection = engine.select_changed(&[]).unwrap();
+
+    assert_eq!(
+        selection.ids(),
+        vec![
+            "tests/test_calc.py::test_add",
+            "tests/test_calc.py::test_one"
+        ]
+    );
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "python")]
+
+mod common;
+
+use common::{calc_repo, CALC, TEST_CALC};
+use hackweek_instant_codecoverage::vcs::{checkout_root, GIT_DIR, GIT_WORK_TREE};
+use hackweek_instant_codecoverage::{EngineBuilder, Language};
+use std::fs;
+
+// the only test in this file, since the variables apply to the whole process
+#[test]
+fn git_dir_and_work_tree_from_the_environment_are_honoured() {
+    let fixture = calc_repo();
+    let work_tree = tempfile::tempdir().unwrap();
+    fs::create_dir(work_tree.path().join("tests")).unwrap();
+    fs::write(work_tree.path().join("calc.py"), CALC).unwrap();
+    fs::write(work_tree.path().join("tests/test_calc.py"), TEST_CALC).unwrap();
+    fs::write(
+        work_tree.path().join("tests/test_other.py"),
+        "def test_other():\n    pass\n",
+    )
+    .unwrap();
+    std::env::set_var(GIT_DIR, fixture.repo.path());
+    std::env::set_var(GIT_WORK_TREE, work_tree.path());
+
+    let root = checkout_root(&work_tree.path().join("tests"));
+    let engine = EngineBuilder::new(&root)
+        .language(Language::Python)
+        .build()
+        .unwrap();
+
+    assert_eq!(root, fs::canonicalize(work_tree.path()).unwrap());
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_other.py::test_other"]
+    );
+}
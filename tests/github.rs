@@ -0,0 +1,143 @@
+mod common;
+
+use common::{calc_repo, FixtureRepo, CALC};
+use hackweek_instant_codecoverage::ci::{ensure_fetched, CiError, PrBase};
+use hackweek_instant_codecoverage::github::{
+    annotations, comment_body, find_comment, pr_base, pr_number, step_summary, COMMENT_MARKER,
+};
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage, RunResult, Selection};
+use std::collections::HashMap;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn base_sha_comes_from_the_event_payload() {
+    let dir = tempfile::tempdir().unwrap();
+    let event = dir.path().join("event.json");
+    std::fs::write(&event, r#"{"pull_request": {"base": {"sha": "abc123"}}}"#).unwrap();
+
+    let base = pr_base(env(&[
+        ("GITHUB_EVENT_PATH", event.to_str().unwrap()),
+        ("GITHUB_BASE_REF", "main"),
+    ]))
+    .unwrap();
+
+    assert_eq!(base.rev, "abc123");
+}
+
+#[test]
+fn base_ref_is_the_fallback() {
+    let base = pr_base(env(&[("GITHUB_BASE_REF", "main")])).unwrap();
+    assert_eq!(base.rev, "origin/main");
+    assert_eq!(base.refspec, "+refs/heads/main:refs/remotes/origin/main");
+
+    assert!(matches!(
+        pr_base(env(&[("GITHUB_BASE_REF", "")])),
+        Err(CiError::NoBase(_))
+    ));
+}
+
+#[test]
+fn missing_base_is_fetched_from_origin() {
+    let origin = calc_repo();
+    let branch = origin.repo.head().unwrap().shorthand().unwrap().to_string();
+    let checkout = FixtureRepo::new();
+    checkout.write("calc.py", CALC);
+    checkout.commit("unrelated");
+    checkout
+        .repo
+        .remote("origin", origin.path().to_str().unwrap())
+        .unwrap();
+    let base = PrBase::branch(&branch);
+
+    ensure_fetched(checkout.path(), &base).unwrap();
+
+    let fetched = checkout.repo.revparse_single(&base.rev).unwrap();
+    assert_eq!(fetched.id(), origin.head().id());
+}
+
+#[test]
+fn uncovered_blocks_become_annotations_and_summary_rows() {
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![3, 4, 5, 9],
+            covered_lines: vec![5],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    assert_eq!(
+        annotations(&coverage),
+        vec![
+            "::warning file=calc.py,line=3,endLine=4,title=Uncovered change::Changed lines are not covered by any test",
+            "::warning file=calc.py,line=9,endLine=9,title=Uncovered change::Changed lines are not covered by any test",
+        ]
+    );
+    let summary = step_summary(&Selection::default(), &RunResult::default(), &coverage);
+    assert!(summary.contains("**25.0%**"), "{}", summary);
+    assert!(
+        summary.contains("| `calc.py` | 4 | 1 | 3-4, 9 |"),
+        "{}",
+        summary
+    );
+}
+
+#[test]
+fn pr_number_comes_from_the_event_or_ref() {
+    let dir = tempfile::tempdir().unwrap();
+    let event = dir.path().join("event.json");
+    std::fs::write(&event, r#"{"pull_request": {"number": 42}}"#).unwrap();
+
+    assert_eq!(
+        pr_number(env(&[("GITHUB_EVENT_PATH", event.to_str().unwrap())])),
+        Some(42)
+    );
+    assert_eq!(
+        pr_number(env(&[("GITHUB_REF", "refs/pull/7/merge")])),
+        Some(7)
+    );
+    assert_eq!(pr_number(env(&[("GITHUB_REF", "refs/heads/main")])), None);
+}
+
+#[test]
+fn comment_lists_uncovered_source_and_is_found_again() {
+    let fixture = calc_repo();
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![1, 2],
+            covered_lines: vec![1],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    let body = comment_body(
+        fixture.path(),
+        &Selection::default(),
+        &RunResult::default(),
+        &coverage,
+    );
+
+    assert!(body.starts_with(COMMENT_MARKER), "{}", body);
+    assert!(body.contains("| `calc.py` | 2 | 1 | 2 |"), "{}", body);
+    let second_line = CALC.lines().nth(1).unwrap();
+    assert!(
+        body.contains(&format!("    2 | {}\n", second_line)),
+        "{}",
+        body
+    );
+
+    let comments = serde_json::json!([
+        { "id": 1, "body": "LGTM" },
+        { "id": 2, "body": body },
+    ]);
+    assert_eq!(find_comment(&comments), Some(2));
+    assert_eq!(find_comment(&serde_json::json!([])), None);
+}
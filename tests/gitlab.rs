@@ -0,0 +1,45 @@
+use hackweek_instant_codecoverage::ci::CiError;
+use hackweek_instant_codecoverage::gitlab::{cobertura, coverage_line, mr_base};
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage};
+
+#[test]
+fn diff_base_sha_wins_over_target_branch() {
+    let var = |name: &str| match name {
+        "CI_MERGE_REQUEST_DIFF_BASE_SHA" => Some("abc123".to_string()),
+        "CI_MERGE_REQUEST_TARGET_BRANCH_NAME" => Some("main".to_string()),
+        _ => None,
+    };
+    assert_eq!(mr_base(var).unwrap().rev, "abc123");
+
+    let var = |name: &str| match name {
+        "CI_MERGE_REQUEST_TARGET_BRANCH_NAME" => Some("main".to_string()),
+        _ => None,
+    };
+    assert_eq!(mr_base(var).unwrap().rev, "origin/main");
+
+    assert!(matches!(mr_base(|_| None), Err(CiError::NoBase(_))));
+}
+
+#[test]
+fn cobertura_lists_changed_lines_with_hits() {
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "pkg/a&b.py".to_string(),
+            changed_lines: vec![3, 4],
+            covered_lines: vec![4],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    let xml = cobertura(&coverage);
+
+    assert!(
+        xml.contains("lines-covered=\"1\" lines-valid=\"2\""),
+        "{}",
+        xml
+    );
+    assert!(xml.contains("filename=\"pkg/a&amp;b.py\""), "{}", xml);
+    assert!(xml.contains("<line number=\"3\" hits=\"0\"/>"), "{}", xml);
+    assert!(xml.contains("<line number=\"4\" hits=\"1\"/>"), "{}", xml);
+    assert_eq!(coverage_line(&coverage), "Patch coverage: 50.00%");
+}
@@ -0,0 +1,43 @@
+use hackweek_instant_codecoverage::gocover::{module_path, read, GoCoverError};
+use std::collections::HashSet;
+use std::fs;
+
+const PROFILE: &str = "mode: set
+example.com/calc/calc.go:3.24,5.2 1 1
+example.com/calc/calc.go:7.24,9.2 1 0
+example.com/calc/ops/ops.go:3.20,4.15 1 1
+golang.org/x/other/other.go:1.1,2.2 1 1
+";
+
+#[test]
+fn maps_blocks_that_ran_onto_files_in_the_module() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("go.mod"),
+        "module example.com/calc\n\ngo 1.22\n",
+    )
+    .unwrap();
+    let path = dir.path().join("coverage.out");
+    fs::write(&path, PROFILE).unwrap();
+
+    assert_eq!(module_path(dir.path()).unwrap(), "example.com/calc");
+    let impact = read(&path, dir.path()).unwrap();
+
+    let files = &impact.lines[""];
+    assert_eq!(files.len(), 2);
+    assert_eq!(files["calc.go"], HashSet::from([3, 4, 5]));
+    assert_eq!(files["ops/ops.go"], HashSet::from([3, 4]));
+}
+
+#[test]
+fn reports_malformed_blocks_by_line() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("go.mod"), "module example.com/calc\n").unwrap();
+    let path = dir.path().join("coverage.out");
+    fs::write(&path, "mode: set\nexample.com/calc/calc.go:3.24 1 1\n").unwrap();
+
+    assert!(matches!(
+        read(&path, dir.path()),
+        Err(GoCoverError::Malformed { line: 2, .. })
+    ));
+}
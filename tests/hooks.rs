@@ -0,0 +1,149 @@
+mod common;
+
+use common::calc_repo;
+#[cfg(feature = "python")]
+use common::TEST_CALC;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::hooks::pre_commit;
+use hackweek_instant_codecoverage::hooks::{install, rebaseline, HookError, HOOKS};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::runner::{RunResult, Runner, RunnerError};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::{EngineBuilder, Selection};
+use std::fs;
+
+#[cfg(feature = "python")]
+struct FailingRunner;
+
+#[cfg(feature = "python")]
+impl Runner for FailingRunner {
+    fn run(&self, _selection: &Selection) -> Result<RunResult, RunnerError> {
+        Ok(RunResult {
+            executed: true,
+            exit_code: Some(1),
+            ..RunResult::default()
+        })
+    }
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn staged_engine_ignores_unstaged_changes() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_staged.py", "def test_staged():\n    pass\n");
+    fixture.stage("tests/test_staged.py");
+    fixture.write("tests/test_unstaged.py", "def test_unstaged():\n    pass\n");
+
+    let engine = EngineBuilder::new(fixture.path())
+        .staged(true)
+        .unstaged(false)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_staged.py::test_staged"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn unstaged_engine_ignores_staged_changes() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_staged.py", "def test_staged():\n    pass\n");
+    fixture.stage("tests/test_staged.py");
+    fixture.write(
+        "tests/test_calc.py",
+        &format!("{}\n\ndef test_unstaged():\n    pass\n", TEST_CALC),
+    );
+
+    let engine = EngineBuilder::new(fixture.path())
+        .unstaged(true)
+        .staged(false)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_calc.py::test_unstaged"]
+    );
+    let coverage = engine.patch_coverage().unwrap();
+    let files: Vec<&str> = coverage.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(files, vec!["tests/test_calc.py"]);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn pre_commit_blocks_on_failing_tests() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_staged.py", "def test_staged():\n    pass\n");
+    fixture.stage("tests/test_staged.py");
+    let engine = EngineBuilder::new(fixture.path())
+        .staged(true)
+        .runner(FailingRunner)
+        .build()
+        .unwrap();
+
+    let report = pre_commit(&engine, None).unwrap();
+
+    assert!(!report.passed());
+    assert_eq!(
+        report.selection.ids(),
+        vec!["tests/test_staged.py::test_staged"]
+    );
+    assert!(
+        report.summary().contains("exit code 1"),
+        "{}",
+        report.summary()
+    );
+}
+
+#[test]
+fn install_refuses_to_replace_foreign_hooks() {
+    let fixture = calc_repo();
+    let hooks = install(fixture.path(), false).unwrap();
+    assert_eq!(hooks.len(), HOOKS.len());
+    for (hook, name) in hooks.iter().zip(HOOKS) {
+        assert!(fs::read_to_string(hook)
+            .unwrap()
+            .contains(&format!("hook {}", name)));
+    }
+    // reinstalling over our own hook is fine
+    install(fixture.path(), false).unwrap();
+
+    fs::write(&hooks[1], "#!/bin/sh\nexit 0\n").unwrap();
+    assert!(matches!(
+        install(fixture.path(), false),
+        Err(HookError::Exists(_))
+    ));
+    install(fixture.path(), true).unwrap();
+}
+
+#[test]
+fn hooks_installed_from_a_linked_worktree_are_shared() {
+    let fixture = calc_repo();
+    let dir = tempfile::tempdir().unwrap();
+    let worktree = dir.path().join("feature");
+    fixture.repo.worktree("feature", &worktree, None).unwrap();
+
+    let hooks = install(&worktree, false).unwrap();
+
+    let shared = fs::canonicalize(fixture.repo.path().join("hooks")).unwrap();
+    assert!(
+        hooks.iter().all(|hook| hook.starts_with(&shared)),
+        "{:?}",
+        hooks
+    );
+}
+
+#[test]
+fn rebaseline_records_the_new_head() {
+    let fixture = calc_repo();
+    fixture.write("tests/test_more.py", "def test_more():\n    pass\n");
+    let head = fixture.commit("more tests");
+
+    let marker = rebaseline(fixture.path()).unwrap();
+
+    assert!(marker.starts_with(fixture.repo.path()));
+    assert_eq!(fs::read_to_string(marker).unwrap().trim(), head.to_string());
+}
@@ -0,0 +1,48 @@
+use hackweek_instant_codecoverage::html::render;
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage};
+use std::collections::HashMap;
+
+#[test]
+fn added_lines_are_coloured_by_coverage() {
+    let old = HashMap::from([(
+        "calc.py".to_string(),
+        "def add(a, b):\n    return a + b\n".to_string(),
+    )]);
+    let new = HashMap::from([(
+        "calc.py".to_string(),
+        "def add(a, b):\n    if a < b:\n        return b + a\n    return a + b\n".to_string(),
+    )]);
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![2, 3],
+            covered_lines: vec![2],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    let html = render(&coverage, &old, &new);
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>Patch coverage 50.0%</h1>"));
+    assert!(html.contains("<h2>calc.py &mdash; 1/2</h2>"));
+    assert!(html.contains(
+        "<tr class=\"covered\"><td class=\"n\"></td><td class=\"n\">2</td>\
+         <td>    if a &lt; b:</td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr class=\"uncovered\"><td class=\"n\"></td><td class=\"n\">3</td>\
+         <td>        return b + a</td></tr>"
+    ));
+    assert!(html.contains(
+        "<tr class=\"\"><td class=\"n\">2</td><td class=\"n\">4</td>\
+         <td>    return a + b</td></tr>"
+    ));
+}
+
+#[test]
+fn empty_patch_says_so() {
+    let html = render(&PatchCoverage::default(), &HashMap::new(), &HashMap::new());
+    assert!(html.contains("<h1>No changed lines</h1>"));
+    assert!(!html.contains("<table>"));
+}
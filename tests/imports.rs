@@ -0,0 +1,91 @@
+#![cfg(feature = "python")]
+
+use hackweek_instant_codecoverage::discovery::{create_parser, parse_all};
+use hackweek_instant_codecoverage::imports::ImportGraph;
+use hackweek_instant_codecoverage::Language;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+fn graph(files: &[(&str, &str)]) -> ImportGraph {
+    let content: HashMap<String, String> = files
+        .iter()
+        .map(|(path, source)| (path.to_string(), source.to_string()))
+        .collect();
+    let mut trees = HashMap::new();
+    let mut parser = create_parser(Language::Python).unwrap();
+    parse_all(&mut parser, &content, &mut trees, &mut Vec::new());
+    ImportGraph::build(Language::Python, &content, &trees, &HashMap::new())
+}
+
+#[test]
+fn imports_resolve_to_files_in_packages_and_source_roots() {
+    let graph = graph(&[
+        ("src/shop/__init__.py", ""),
+        (
+            "src/shop/cart.py",
+            "from .prices import total\nfrom . import tax\n",
+        ),
+        ("src/shop/prices.py", "import decimal\n"),
+        ("src/shop/tax.py", ""),
+        (
+            "tests/helpers.py",
+            "def make_cart():\n    from shop.cart import Cart\n",
+        ),
+        (
+            "tests/test_cart.py",
+            "import helpers as h\nimport shop.cart\n",
+        ),
+    ]);
+
+    let imports = |path: &str| {
+        graph.imports[path]
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        imports("src/shop/cart.py"),
+        vec![
+            "src/shop/__init__.py",
+            "src/shop/prices.py",
+            "src/shop/tax.py"
+        ]
+    );
+    assert!(imports("src/shop/prices.py").is_empty());
+    assert_eq!(
+        imports("tests/helpers.py"),
+        vec!["src/shop/__init__.py", "src/shop/cart.py"]
+    );
+    assert_eq!(
+        imports("tests/test_cart.py"),
+        vec![
+            "src/shop/__init__.py",
+            "src/shop/cart.py",
+            "tests/helpers.py"
+        ]
+    );
+}
+
+#[test]
+fn dependents_are_found_transitively_with_their_origin() {
+    let graph = graph(&[
+        ("calc.py", ""),
+        ("money.py", "from calc import add\n"),
+        ("tests/test_money.py", "import money\n"),
+        ("tests/test_other.py", "import os\n"),
+    ]);
+
+    assert_eq!(
+        graph.dependents(["calc.py"]),
+        BTreeMap::from([
+            ("money.py".to_string(), "calc.py".to_string()),
+            ("tests/test_money.py".to_string(), "calc.py".to_string()),
+        ])
+    );
+    assert_eq!(
+        graph
+            .dependents(["calc.py", "tests/test_money.py"])
+            .keys()
+            .collect::<BTreeSet<_>>(),
+        BTreeSet::from([&"money.py".to_string(), &"tests/test_money.py".to_string()])
+    );
+}
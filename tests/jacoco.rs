@@ -0,0 +1,89 @@
+#![cfg(feature = "java")]
+
+use hackweek_instant_codecoverage::jacoco::{data_file, read, JacocoError};
+use std::collections::HashSet;
+use std::fs;
+
+/// A report covering `add` in `com/example/Calc.java` and never `unused`:
+///
+/// ```text
+/// 1 package com.example;
+/// 2
+/// 3 public class Calc {
+/// 4     int add(int a, int b) {
+/// 5         return a + b;
+/// 6     }
+/// 7
+/// 8     void unused() {
+/// 9         throw new IllegalStateException();
+/// 10    }
+/// 11 }
+/// ```
+const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<!DOCTYPE report PUBLIC "-//JACOCO//DTD Report 1.1//EN" "report.dtd">
+<report name="calc">
+  <sessioninfo id="host-1" start="1700000000000" dump="1700000001000"/>
+  <package name="com/example">
+    <class name="com/example/Calc" sourcefilename="Calc.java">
+      <method name="add" desc="(II)I" line="5"/>
+    </class>
+    <sourcefile name="Calc.java">
+      <line nr="3" mi="0" ci="3" mb="0" cb="0"/>
+      <line nr="5" mi="0" ci="4" mb="0" cb="0"/>
+      <line nr="9" mi="5" ci="0" mb="0" cb="0"/>
+      <counter type="LINE" missed="1" covered="2"/>
+    </sourcefile>
+    <sourcefile name="Gone.java">
+      <line nr="1" mi="0" ci="1" mb="0" cb="0"/>
+    </sourcefile>
+  </package>
+</report>
+"#;
+
+#[test]
+fn reads_executed_lines_onto_the_matching_source_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("src/main/java/com/example");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("Calc.java"), "").unwrap();
+    let path = dir.path().join("jacoco.xml");
+    fs::write(&path, REPORT).unwrap();
+
+    let impact = read(&path, dir.path()).unwrap();
+
+    assert_eq!(impact.lines.len(), 1);
+    let files = &impact.lines[""];
+    assert_eq!(
+        files.keys().collect::<Vec<_>>(),
+        vec!["src/main/java/com/example/Calc.java"]
+    );
+    assert_eq!(
+        files["src/main/java/com/example/Calc.java"],
+        HashSet::from([3, 5])
+    );
+}
+
+#[test]
+fn rejects_other_xml() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("jacoco.xml");
+    fs::write(&path, "<coverage/>").unwrap();
+
+    assert!(matches!(
+        read(&path, dir.path()),
+        Err(JacocoError::NotAReport(_))
+    ));
+}
+
+#[test]
+fn gradle_projects_read_the_gradle_report() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("build.gradle"), "").unwrap();
+    assert_eq!(
+        data_file(dir.path()),
+        "build/reports/jacoco/test/jacocoTestReport.xml"
+    );
+
+    fs::write(dir.path().join("pom.xml"), "").unwrap();
+    assert_eq!(data_file(dir.path()), "target/site/jacoco/jacoco.xml");
+}
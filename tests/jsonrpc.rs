@@ -0,0 +1,96 @@
+#![cfg(feature = "python")]
+
+mod common;
+
+use common::{calc_repo, CALC};
+use hackweek_instant_codecoverage::jsonrpc::RpcServer;
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::EngineBuilder;
+use serde_json::{json, Value};
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn session(messages: &[Value]) -> Vec<Value> {
+    let fixture = calc_repo();
+    fixture.write("tests/test_other.py", "def test_other():\n    pass\n");
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+    let mut input = Vec::new();
+    for message in messages {
+        let body = message.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+    }
+    let output = SharedBuffer::default();
+    RpcServer::new(Arc::new(engine), output.clone())
+        .serve(Cursor::new(input))
+        .unwrap();
+
+    let output = output.0.lock().unwrap();
+    let mut rest = std::str::from_utf8(&output).unwrap();
+    let mut replies = Vec::new();
+    while let Some((header, body)) = rest.split_once("\r\n\r\n") {
+        let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+        replies.push(serde_json::from_str(&body[..length]).unwrap());
+        rest = &body[length..];
+    }
+    replies
+}
+
+#[test]
+fn get_selection_returns_structured_tests() {
+    let replies = session(&[json!({ "jsonrpc": "2.0", "id": 1, "method": "getSelection" })]);
+
+    assert_eq!(replies[0]["id"], 1);
+    let tests = &replies[0]["result"]["tests"];
+    assert_eq!(tests[0]["id"], "tests/test_other.py::test_other");
+    assert_eq!(tests[0]["reason"], "new test");
+}
+
+#[test]
+fn run_selection_replies_and_notifies() {
+    let replies = session(&[json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": "runSelection",
+        "params": { "tests": ["tests/test_calc.py::test_add"] },
+    })]);
+
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[0]["method"], "runFinished");
+    assert_eq!(replies[1]["id"], 2);
+    assert_eq!(
+        replies[1]["result"]["command"],
+        "pytest tests/test_calc.py::test_add"
+    );
+}
+
+#[test]
+fn get_patch_coverage_reports_totals() {
+    let replies = session(&[json!({ "jsonrpc": "2.0", "id": 3, "method": "getPatchCoverage" })]);
+
+    let result = &replies[0]["result"];
+    // four lines of calc.py and the two of the untracked test file
+    assert_eq!(result["changed"], 6);
+    assert_eq!(result["covered"], 0);
+    assert_eq!(result["percent"], 0.0);
+    assert_eq!(result["files"][0]["path"], "calc.py");
+}
@@ -0,0 +1,204 @@
+use hackweek_instant_codecoverage::Language;
+#[cfg(any(
+    feature = "python",
+    feature = "go",
+    feature = "rust",
+    feature = "ruby",
+    feature = "java"
+))]
+use std::fs;
+
+#[cfg(feature = "python")]
+#[test]
+fn detects_python_projects_by_their_tooling_files() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(Language::detect(dir.path()), None);
+
+    fs::write(dir.path().join("pyproject.toml"), "").unwrap();
+    assert_eq!(Language::detect(dir.path()), Some(Language::Python));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn python_runs_tests_by_node_id_under_coverage() {
+    assert_eq!(
+        Language::Python.command_template(),
+        "coverage run -m pytest {tests}"
+    );
+    assert_eq!(
+        Language::Python.test_target("tests/test_calc.py::TestAdd::test_small"),
+        "tests/test_calc.py::TestAdd::test_small"
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn python_unittest_runs_tests_by_dotted_name() {
+    let ids = [
+        "tests/test_calc.py::TestCalc::test_add",
+        "tests/test_calc.py::TestCalc::Nested::test_sub",
+        "tests/test_util.py::test_helper",
+    ]
+    .map(String::from);
+    assert_eq!(
+        Language::Python.test_arguments_for("coverage run -m unittest {tests}", &ids),
+        vec![
+            "tests.test_calc.TestCalc.test_add",
+            "tests.test_calc.TestCalc.Nested.test_sub",
+            "tests.test_util",
+        ]
+    );
+    assert_eq!(
+        Language::Python.test_arguments_for("coverage run -m pytest {tests}", &ids),
+        ids
+    );
+}
+
+#[cfg(feature = "javascript")]
+#[test]
+fn javascript_runs_whole_test_files() {
+    assert_eq!(
+        Language::JavaScript.test_target("src/calc.test.js::adds"),
+        "src/calc.test.js"
+    );
+}
+
+#[cfg(feature = "go")]
+#[test]
+fn go_runs_the_package_holding_a_test() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("go.mod"), "module calc\n").unwrap();
+    assert_eq!(Language::detect(dir.path()), Some(Language::Go));
+
+    assert_eq!(
+        Language::Go.test_target("calc/calc_test.go::TestAdd"),
+        "./calc"
+    );
+    assert_eq!(Language::Go.test_target("calc_test.go::TestAdd"), ".");
+}
+
+#[cfg(feature = "go")]
+#[test]
+fn go_runs_tests_by_a_name_regexp_in_their_packages() {
+    let ids = [
+        "calc/calc_test.go::TestSub",
+        "calc/calc_test.go::TestAdd",
+        "main_test.go::TestMain2",
+    ]
+    .map(String::from);
+    assert_eq!(
+        Language::Go.test_arguments(&ids),
+        vec!["^(TestAdd|TestMain2|TestSub)$", ".", "./calc"]
+    );
+    assert_eq!(Language::Go.test_arguments(&[]), vec![".", "./..."]);
+}
+
+#[cfg(feature = "rust")]
+#[test]
+fn rust_tests_are_run_by_their_path_within_the_crate() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+    assert!(Language::detect(dir.path()).is_some());
+
+    let target = |id| Language::Rust.test_target(id);
+    assert_eq!(target("src/lib.rs::tests::adds"), "tests::adds");
+    assert_eq!(target("src/calc.rs::tests::adds"), "calc::tests::adds");
+    assert_eq!(target("src/calc/mod.rs::tests::adds"), "calc::tests::adds");
+    assert_eq!(
+        target("crates/core/src/calc/ops.rs::tests::adds"),
+        "calc::ops::tests::adds"
+    );
+    assert_eq!(target("tests/engine.rs::selects"), "selects");
+    assert_eq!(target("src/bin/tool.rs::tests::parses"), "tests::parses");
+}
+
+#[cfg(feature = "ruby")]
+#[test]
+fn ruby_runs_test_files_filtered_by_example() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("Gemfile"), "").unwrap();
+    fs::write(dir.path().join("package.json"), "{}").unwrap();
+    assert_eq!(Language::detect(dir.path()), Some(Language::Ruby));
+
+    let ids = [
+        "spec/calc_spec.rb::Calculator::with integers::adds",
+        "spec/calc_spec.rb::Calculator::subtracts",
+        "spec/util_spec.rb::Util::adds",
+    ]
+    .map(String::from);
+    assert_eq!(
+        Language::Ruby.test_arguments(&ids),
+        vec![
+            "spec/calc_spec.rb",
+            "spec/util_spec.rb",
+            "--example=adds",
+            "--example=subtracts",
+        ]
+    );
+}
+
+#[cfg(feature = "java")]
+#[test]
+fn java_runs_tests_through_the_project_build_tool() {
+    let ids = [
+        "src/test/java/com/example/CalcTest.java::CalcTest::subtracts",
+        "src/test/java/com/example/CalcTest.java::CalcTest::adds",
+        "src/test/java/com/example/CalcTest.java::CalcTest::Overflow::wraps",
+    ]
+    .map(String::from);
+    let maven = tempfile::tempdir().unwrap();
+    fs::write(maven.path().join("pom.xml"), "<project/>").unwrap();
+    assert_eq!(Language::detect(maven.path()), Some(Language::Java));
+    assert!(Language::Java
+        .command_template_at(maven.path())
+        .starts_with("mvn "));
+    assert_eq!(
+        Language::Java.test_arguments_for(Language::Java.command_template_at(maven.path()), &ids),
+        vec!["-Dtest=com.example.CalcTest#adds+subtracts,com.example.CalcTest$Overflow#wraps"]
+    );
+
+    let gradle = tempfile::tempdir().unwrap();
+    fs::write(gradle.path().join("build.gradle.kts"), "").unwrap();
+    fs::write(gradle.path().join("gradlew"), "").unwrap();
+    assert_eq!(
+        Language::Java.command_template_at(gradle.path()),
+        "./gradlew test jacocoTestReport {tests}"
+    );
+    assert_eq!(
+        Language::Java.test_arguments_for(Language::Java.command_template_at(gradle.path()), &ids),
+        vec![
+            "--tests=com.example.CalcTest$Overflow.wraps",
+            "--tests=com.example.CalcTest.adds",
+            "--tests=com.example.CalcTest.subtracts",
+        ]
+    );
+
+    let mixed = [
+        "Calc.java::adds".to_string(),
+        "src/test/java/com/example/CalcTest.java::CalcTest::adds".to_string(),
+    ];
+    assert_eq!(
+        Language::Java.test_arguments_for(Language::Java.command_template_at(maven.path()), &mixed),
+        vec!["-Dtest=com.example.CalcTest#adds"]
+    );
+    assert_eq!(
+        Language::Java
+            .test_arguments_for(Language::Java.command_template_at(gradle.path()), &mixed),
+        vec!["--tests=com.example.CalcTest.adds"]
+    );
+    for root in [maven.path(), gradle.path()] {
+        let command = Language::Java.command_template_at(root);
+        assert!(Language::Java
+            .test_arguments_for(command, &mixed[..1])
+            .is_empty());
+    }
+}
+
+#[test]
+fn test_queries_are_compiled_once_per_language() {
+    for language in Language::ALL {
+        let query = language.compiled_test_query();
+        assert!(query.capture_index_for_name("name").is_some());
+        assert!(std::ptr::eq(query, language.compiled_test_query()));
+    }
+}
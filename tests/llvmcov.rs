@@ -0,0 +1,67 @@
+use hackweek_instant_codecoverage::llvmcov::{read, LlvmCovError};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// An export covering `add` in `src/calc.rs` once and never `unused`:
+///
+/// ```text
+/// 1 fn add(a: i32, b: i32) -> i32 {
+/// 2     a + b
+/// 3 }
+/// 4
+/// 5 fn unused() {
+/// 6     todo!()
+/// 7 }
+/// ```
+fn export(root: &Path) -> String {
+    let file = |path: &str| fs::canonicalize(root).unwrap().join(path);
+    serde_json::json!({
+        "type": "llvm.coverage.json.export",
+        "version": "2.0.1",
+        "data": [{
+            "files": [
+                {
+                    "filename": file("src/calc.rs"),
+                    "segments": [
+                        [1, 32, 1, true, true, false],
+                        [3, 2, 0, false, false, false],
+                        [5, 13, 0, true, true, false],
+                        [7, 2, 0, false, false, false]
+                    ]
+                },
+                {
+                    "filename": "/rustc/library/core/src/panicking.rs",
+                    "segments": [[1, 1, 4, true, true]]
+                }
+            ]
+        }]
+    })
+    .to_string()
+}
+
+#[test]
+fn reads_executed_lines_outside_any_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("llvm-cov.json");
+    fs::write(&path, export(dir.path())).unwrap();
+
+    let impact = read(&path, dir.path()).unwrap();
+
+    assert_eq!(impact.lines.len(), 1);
+    let files = &impact.lines[""];
+    assert_eq!(files.keys().collect::<Vec<_>>(), vec!["src/calc.rs"]);
+    assert_eq!(files["src/calc.rs"], HashSet::from([1, 2, 3]));
+}
+
+#[test]
+fn rejects_other_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("llvm-cov.json");
+    fs::write(&path, "{\"files\": []}").unwrap();
+
+    assert!(matches!(
+        read(&path, dir.path()),
+        Err(LlvmCovError::Json { .. })
+    ));
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{calc_repo, CALC};
+use hackweek_instant_codecoverage::lsp::serve;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::lsp::RUN_TESTS_COMMAND;
+use hackweek_instant_codecoverage::selection::ImpactData;
+use hackweek_instant_codecoverage::EngineBuilder;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+
+fn frame(messages: &[Value]) -> Vec<u8> {
+    let mut input = Vec::new();
+    for message in messages {
+        let body = message.to_string();
+        input.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+    }
+    input
+}
+
+fn unframe(output: &[u8]) -> Vec<Value> {
+    let mut output = std::str::from_utf8(output).unwrap();
+    let mut messages = Vec::new();
+    while let Some((header, rest)) = output.split_once("\r\n\r\n") {
+        let length: usize = header["Content-Length: ".len()..].parse().unwrap();
+        messages.push(serde_json::from_str(&rest[..length]).unwrap());
+        output = &rest[length..];
+    }
+    messages
+}
+
+fn session(messages: &[Value]) -> Vec<Value> {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+    let mut impact = ImpactData::default();
+    impact.lines.insert(
+        "tests/test_calc.py::test_sub".to_string(),
+        HashMap::from([("calc.py".to_string(), HashSet::from([5, 6]))]),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .impact_data(impact)
+        .build()
+        .unwrap();
+    let uri = format!(
+        "file://{}/calc.py",
+        fixture.path().canonicalize().unwrap().display()
+    );
+    let messages: Vec<Value> = messages
+        .iter()
+        .map(|m| serde_json::from_str(&m.to_string().replace("$URI", &uri)).unwrap())
+        .collect();
+    let mut output = Vec::new();
+    serve(&engine, Cursor::new(frame(&messages)), &mut output).unwrap();
+    unframe(&output)
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn uncovered_changed_lines_are_published() {
+    let replies = session(&[
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ]);
+
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[0]["id"], 1);
+    assert!(replies[0]["result"]["capabilities"]["codeLensProvider"].is_object());
+    assert_eq!(replies[1]["method"], "textDocument/publishDiagnostics");
+    assert!(replies[1]["params"]["uri"]
+        .as_str()
+        .unwrap()
+        .ends_with("/calc.py"));
+    let diagnostics = replies[1]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+    assert_eq!(diagnostics[0]["range"]["end"]["line"], 4);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn code_lens_offers_to_run_covering_tests() {
+    let replies = session(&[
+        json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/codeLens",
+            "params": { "textDocument": { "uri": "$URI" } },
+        }),
+        json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ]);
+
+    let lenses = replies[0]["result"].as_array().unwrap();
+    assert_eq!(lenses.len(), 1);
+    assert_eq!(
+        lenses[0]["command"]["title"],
+        "covered by 1 test / run them"
+    );
+    assert_eq!(lenses[0]["command"]["command"], RUN_TESTS_COMMAND);
+    assert_eq!(
+        lenses[0]["command"]["arguments"],
+        json!([["tests/test_calc.py::test_sub"]])
+    );
+    assert_eq!(
+        replies[1],
+        json!({ "jsonrpc": "2.0", "id": 3, "result": null })
+    );
+}
+
+#[test]
+fn unknown_requests_get_method_not_found() {
+    let replies = session(&[json!({ "jsonrpc": "2.0", "id": 9, "method": "bogus" })]);
+    assert_eq!(replies[0]["error"]["code"], -32601);
+}
@@ -0,0 +1,38 @@
+use hackweek_instant_codecoverage::metrics::{otlp_payload, Metrics};
+use std::time::Duration;
+
+#[test]
+fn failed_cycles_are_counted() {
+    let metrics = Metrics::default();
+    metrics.record_cycle(Duration::from_millis(3), 2, true);
+    metrics.record_cycle(Duration::from_millis(5), 0, false);
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.cycles, 2);
+    assert_eq!(snapshot.failed_cycles, 1);
+    assert_eq!(snapshot.tests_selected, 2);
+    assert_eq!(snapshot.cycle_micros_total, 8_000);
+    assert_eq!(snapshot.last_cycle_micros, 5_000);
+    assert_eq!(snapshot.baseline_hit_rate(), None);
+}
+
+#[test]
+fn otlp_payload_carries_every_metric() {
+    let metrics = Metrics::default();
+    metrics.record_cycle(Duration::from_millis(1), 4, true);
+    metrics.record_baseline(false);
+
+    let payload = otlp_payload("svc", &metrics.snapshot());
+    let resource = &payload["resourceMetrics"][0];
+    assert_eq!(
+        resource["resource"]["attributes"][0]["value"]["stringValue"],
+        "svc"
+    );
+    let exported = resource["scopeMetrics"][0]["metrics"].as_array().unwrap();
+    assert_eq!(exported.len(), 7);
+    let selected = exported
+        .iter()
+        .find(|m| m["name"] == "instant_patch.tests.selected")
+        .unwrap();
+    assert_eq!(selected["sum"]["dataPoints"][0]["asInt"], "4");
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::calc_repo;
+#[cfg(feature = "python")]
+use common::CALC;
+use hackweek_instant_codecoverage::nvim::{failing_tests, marks};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::{nvim::NvimServer, runner::EmitOnlyRunner, EngineBuilder};
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage, RunResult};
+#[cfg(feature = "python")]
+use rmpv::Value;
+#[cfg(feature = "python")]
+use std::io::{Cursor, Write};
+#[cfg(feature = "python")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "python")]
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(feature = "python")]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "python")]
+fn request(id: u64, method: &str, params: Vec<Value>) -> Value {
+    Value::Array(vec![
+        0.into(),
+        id.into(),
+        method.into(),
+        Value::Array(params),
+    ])
+}
+
+#[cfg(feature = "python")]
+fn session(messages: &[Value]) -> Vec<Value> {
+    let fixture = calc_repo();
+    fixture.write(
+        "calc.py",
+        &format!("{}\n\ndef sub(a, b):\n    return a - b\n", CALC),
+    );
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+    let mut input = Vec::new();
+    for message in messages {
+        rmpv::encode::write_value(&mut input, message).unwrap();
+    }
+    let output = SharedBuffer::default();
+    NvimServer::new(Arc::new(engine), output.clone())
+        .serve(Cursor::new(input))
+        .unwrap();
+
+    let output = output.0.lock().unwrap();
+    let mut rest = Cursor::new(output.as_slice());
+    let mut replies = Vec::new();
+    while (rest.position() as usize) < output.len() {
+        replies.push(rmpv::decode::read_value(&mut rest).unwrap());
+    }
+    replies
+}
+
+#[cfg(feature = "python")]
+fn field<'v>(map: &'v Value, key: &str) -> &'v Value {
+    map.as_map()
+        .unwrap()
+        .iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+        .unwrap()
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn marks_request_returns_changed_lines() {
+    let replies = session(&[request(1, "marks", vec![])]);
+
+    let reply = replies[0].as_array().unwrap();
+    assert_eq!(reply[0], Value::from(1));
+    assert_eq!(reply[1], Value::from(1));
+    assert_eq!(reply[2], Value::Nil);
+    let file = &reply[3].as_array().unwrap()[0];
+    assert_eq!(field(file, "path").as_str(), Some("calc.py"));
+    assert_eq!(field(file, "uncovered").as_array().unwrap().len(), 4);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn run_redraws_the_editor_and_replies() {
+    let tests = Value::Array(vec!["tests/test_calc.py::test_add".into()]);
+    let replies = session(&[request(2, "run", vec![tests]), request(3, "nope", vec![])]);
+
+    assert_eq!(replies.len(), 3);
+    let redraw = replies[0].as_array().unwrap();
+    assert_eq!(redraw[0], Value::from(2));
+    assert_eq!(redraw[1].as_str(), Some("nvim_exec_lua"));
+    let reply = replies[1].as_array().unwrap();
+    assert_eq!(
+        field(&reply[3], "command").as_str(),
+        Some("pytest tests/test_calc.py::test_add")
+    );
+    let error = replies[2].as_array().unwrap();
+    assert_eq!(error[2].as_str(), Some("unknown method nope"));
+}
+
+#[test]
+fn failing_tests_are_located_at_their_definition() {
+    let fixture = calc_repo();
+    let result = RunResult {
+        executed: true,
+        exit_code: Some(1),
+        stdout: "FAILED tests/test_calc.py::test_add - assert 4 == 3\n\
+                 FAILED tests/test_gone.py::test_x[1]\n"
+            .to_string(),
+        ..RunResult::default()
+    };
+
+    let failing = failing_tests(fixture.path(), &result);
+    assert_eq!(failing.len(), 2);
+    assert_eq!(failing[0].id, "tests/test_calc.py::test_add");
+    assert_eq!(failing[0].line, 4);
+    assert_eq!(failing[1].path, "tests/test_gone.py");
+    assert_eq!(failing[1].line, 1);
+
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![3, 4],
+            covered_lines: vec![4],
+            ..FilePatchCoverage::default()
+        }],
+    };
+    let files = marks(&coverage, &failing);
+    let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        ["calc.py", "tests/test_calc.py", "tests/test_gone.py"]
+    );
+    assert_eq!(files[0].uncovered, [3]);
+    assert_eq!(files[1].failing[0].line, 4);
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use common::{CALC, TEST_CALC};
+use hackweek_instant_codecoverage::patch::{self, FilePatch, PatchError};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::{patch::PatchVcs, runner::EmitOnlyRunner, EngineBuilder};
+#[cfg(feature = "python")]
+use std::{fs, path::Path};
+
+const NEW_TEST_CALC: &str = "from calc import add\n\n\ndef test_add():\n    assert add(1, 2) == 3\n\n\ndef test_add_zero():\n    assert add(0, 0) == 0\n";
+
+const GIT_DIFF: &str = "\
+diff --git a/tests/test_calc.py b/tests/test_calc.py
+index 1111111..2222222 100644
+--- a/tests/test_calc.py
++++ b/tests/test_calc.py
+@@ -5 +5,5 @@ def test_add():
+     assert add(1, 2) == 3
++
++
++def test_add_zero():
++    assert add(0, 0) == 0
+diff --git a/old.py b/old.py
+deleted file mode 100644
+--- a/old.py
++++ /dev/null
+@@ -1,2 +0,0 @@
+-def gone():
+-    pass
+\\ No newline at end of file
+diff --git a/util.py b/helpers.py
+similarity index 100%
+rename from util.py
+rename to helpers.py
+";
+
+#[cfg(feature = "python")]
+fn write(root: &Path, path: &str, content: &str) {
+    let full = root.join(path);
+    fs::create_dir_all(full.parent().unwrap()).unwrap();
+    fs::write(full, content).unwrap();
+}
+
+#[test]
+fn parses_modified_deleted_and_renamed_files() {
+    let files = patch::parse(GIT_DIFF).unwrap();
+    let paths: Vec<(Option<&str>, Option<&str>)> = files
+        .iter()
+        .map(|file| (file.old_path.as_deref(), file.new_path.as_deref()))
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            (Some("tests/test_calc.py"), Some("tests/test_calc.py")),
+            (Some("old.py"), None),
+            (Some("util.py"), Some("helpers.py")),
+        ]
+    );
+
+    assert_eq!(files[0].revert(NEW_TEST_CALC).unwrap(), TEST_CALC);
+    // the missing newline is kept off the last removed line
+    assert_eq!(files[1].revert("").unwrap(), "def gone():\n    pass");
+    assert!(files[2].hunks.is_empty());
+}
+
+#[test]
+fn plain_unified_diffs_drop_timestamps() {
+    let text = "\
+--- calc.py\t2024-01-01 00:00:00.000000000 +0000
++++ calc.py\t2024-01-02 00:00:00.000000000 +0000
+@@ -1,2 +1,2 @@
+ def add(a, b):
+-    return a + b
++    return b + a
+--- /dev/null\t1970-01-01 00:00:00.000000000 +0000
++++ new.py\t2024-01-02 00:00:00.000000000 +0000
+@@ -0,0 +1 @@
++VALUE = 1
+";
+    let files = patch::parse(text).unwrap();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].path(), "calc.py");
+    assert_eq!(
+        files[0]
+            .revert("def add(a, b):\n    return b + a\n")
+            .unwrap(),
+        CALC
+    );
+    assert_eq!(files[1].old_path, None);
+    assert_eq!(files[1].revert("VALUE = 1\n").unwrap(), "");
+}
+
+#[test]
+fn hunks_must_match_their_header() {
+    let text = "--- a/calc.py\n+++ b/calc.py\n@@ -1,2 +1,2 @@\n-def add(a, b):\n";
+    assert!(matches!(
+        patch::parse(text),
+        Err(PatchError::Malformed { line: 3, .. })
+    ));
+    assert!(matches!(
+        patch::parse("@@ -1 +1 @@\n-a\n+b\n"),
+        Err(PatchError::Malformed { line: 1, .. })
+    ));
+}
+
+#[test]
+fn working_tree_must_match_the_diff() {
+    let file: &FilePatch = &patch::parse(GIT_DIFF).unwrap()[0];
+    assert!(matches!(
+        file.revert(TEST_CALC),
+        Err(PatchError::Mismatch { .. })
+    ));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn diff_from_a_checkout_without_a_repository_selects_new_tests() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "calc.py", CALC);
+    write(dir.path(), "tests/test_calc.py", NEW_TEST_CALC);
+    write(dir.path(), "helpers.py", "def help():\n    pass\n");
+
+    let engine = EngineBuilder::new(dir.path())
+        .vcs(PatchVcs::parse(dir.path(), GIT_DIFF).unwrap())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+    let selection = engine.select().unwrap();
+
+    assert_eq!(selection.ids(), vec!["tests/test_calc.py::test_add_zero"]);
+    assert!(selection.skipped.is_empty());
+}
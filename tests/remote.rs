@@ -0,0 +1,42 @@
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::remote::{handoff_payload, working_tree_patch};
+use hackweek_instant_codecoverage::selection::{SelectedTest, Selection};
+use serde_json::Value;
+
+#[test]
+fn payload_lists_tests_and_commits() {
+    let selection = Selection::new(vec![SelectedTest::new(
+        "tests/test_new.py::test_new",
+        "new test",
+    )]);
+
+    let payload = handoff_payload(&selection, "abc", "def", "");
+
+    assert_eq!(payload["tests"][0], "tests/test_new.py::test_new");
+    assert_eq!(payload["selection"]["tests"][0]["reason"], "new test");
+    assert_eq!(payload["base"], "abc");
+    assert_eq!(payload["head"], "def");
+    assert_eq!(payload["patch"], Value::Null);
+}
+
+#[test]
+fn patch_includes_modified_and_untracked_files() {
+    let fixture = calc_repo();
+    fixture.write("calc.py", "def add(a, b):\n    return b + a\n");
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    let patch = working_tree_patch(&fixture.repo).unwrap();
+
+    assert!(patch.contains("diff --git a/calc.py b/calc.py"));
+    assert!(patch.contains("+    return b + a\n"));
+    assert!(patch.contains("diff --git a/tests/test_new.py b/tests/test_new.py"));
+    assert!(patch.contains("+def test_new():\n"));
+}
+
+#[test]
+fn clean_tree_has_empty_patch() {
+    let fixture = calc_repo();
+    assert_eq!(working_tree_patch(&fixture.repo).unwrap(), "");
+}
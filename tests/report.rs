@@ -0,0 +1,98 @@
+use hackweek_instant_codecoverage::report::{coverage_summary, lcov, threshold_banner};
+use hackweek_instant_codecoverage::{FilePatchCoverage, HunkCoverage, PatchCoverage};
+
+#[test]
+fn summary_breaks_coverage_down_by_file_and_hunk() {
+    let coverage = PatchCoverage {
+        files: vec![
+            FilePatchCoverage {
+                path: "calc.py".to_string(),
+                changed_lines: vec![2, 3, 4, 8],
+                covered_lines: vec![2],
+                partial_lines: vec![3],
+                hunks: vec![
+                    HunkCoverage {
+                        first: 2,
+                        last: 4,
+                        changed_lines: vec![2, 3, 4],
+                        covered_lines: vec![2],
+                        partial_lines: vec![3],
+                    },
+                    HunkCoverage {
+                        first: 8,
+                        last: 8,
+                        changed_lines: vec![8],
+                        covered_lines: vec![],
+                        ..HunkCoverage::default()
+                    },
+                ],
+            },
+            FilePatchCoverage {
+                path: "util.py".to_string(),
+                changed_lines: vec![1],
+                covered_lines: vec![1],
+                ..FilePatchCoverage::default()
+            },
+        ],
+    };
+
+    assert_eq!(
+        coverage_summary(&coverage),
+        "Patch coverage 40.0% (2 of 5 changed lines)\n  \
+         calc.py: 25.0% (1/4)\n    \
+         lines 2-4: 1/3 covered, missing 4, partial 3\n    \
+         line 8: 0/1 covered\n  \
+         util.py: 100.0% (1/1)"
+    );
+    assert_eq!(
+        coverage_summary(&PatchCoverage::default()),
+        "No changed lines"
+    );
+}
+
+#[test]
+fn lcov_lists_only_changed_lines() {
+    let coverage = PatchCoverage {
+        files: vec![
+            FilePatchCoverage {
+                path: "calc.py".to_string(),
+                changed_lines: vec![2, 3],
+                covered_lines: vec![2],
+                ..FilePatchCoverage::default()
+            },
+            FilePatchCoverage {
+                path: "util/io.py".to_string(),
+                changed_lines: vec![7],
+                covered_lines: vec![],
+                ..FilePatchCoverage::default()
+            },
+        ],
+    };
+
+    assert_eq!(
+        lcov(&coverage),
+        "TN:\nSF:calc.py\nDA:2,1\nDA:3,0\nLF:2\nLH:1\nend_of_record\n\
+         TN:\nSF:util/io.py\nDA:7,0\nLF:1\nLH:0\nend_of_record\n"
+    );
+    assert_eq!(lcov(&PatchCoverage::default()), "");
+}
+
+#[test]
+fn threshold_banner_frames_the_failure() {
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![1, 2],
+            covered_lines: vec![1],
+            ..FilePatchCoverage::default()
+        }],
+    };
+    let rule = "=".repeat(56);
+    assert_eq!(
+        threshold_banner(&coverage, 80.0),
+        format!(
+            "{}\nFAILED: patch coverage 50.0% is below the required 80.0%\n{}",
+            rule, rule
+        )
+    );
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::calc_repo;
+use hackweek_instant_codecoverage::results::{parse_events, Outcome, TestOutcome};
+use hackweek_instant_codecoverage::runner::{LocalRunner, Runner};
+use hackweek_instant_codecoverage::{SelectedTest, Selection};
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn phases_fold_into_one_outcome_per_test() {
+    let lines = [
+        r#"{"event": "collected", "nodeid": "tests/test_a.py::test_a"}"#,
+        r#"{"event": "collected", "nodeid": "tests/test_b.py::test_b"}"#,
+        r#"{"event": "report", "nodeid": "tests/test_b.py::test_b", "when": "call", "outcome": "passed", "duration": 0.25}"#,
+        r#"{"event": "report", "nodeid": "tests/test_b.py::test_b", "when": "teardown", "outcome": "failed", "duration": 0.5}"#,
+        r#"{"event": "report", "nodeid": "tests/test_a.py::test_a", "when": "setup", "outcome": "skipped", "duration": 0.0}"#,
+        r#"{"event": "from the future"}"#,
+        "not json",
+        r#"{"event": "finished", "exitstatus": 1}"#,
+    ];
+
+    let report = parse_events(lines.iter().map(|line| line.to_string()));
+
+    assert_eq!(
+        report.collected,
+        ["tests/test_a.py::test_a", "tests/test_b.py::test_b"]
+    );
+    assert_eq!(
+        report.tests,
+        [
+            TestOutcome {
+                id: "tests/test_a.py::test_a".to_string(),
+                outcome: Outcome::Skipped,
+                duration_micros: 0,
+            },
+            TestOutcome {
+                id: "tests/test_b.py::test_b".to_string(),
+                outcome: Outcome::Failed,
+                duration_micros: 750_000,
+            },
+        ]
+    );
+    assert_eq!(report.exit_status, Some(1));
+}
+
+/// Drives the real plugin's hooks with stand-ins for pytest's objects.
+const FAKE_PYTEST: &str = "
+import sys, types
+from instant_patch import pytest_plugin as plugin
+ids = sys.argv[1:]
+plugin.pytest_configure(types.SimpleNamespace())
+plugin.pytest_collection_finish(types.SimpleNamespace(items=[types.SimpleNamespace(nodeid=i) for i in ids]))
+for i in ids:
+    outcome = 'failed' if 'fail' in i else 'passed'
+    plugin.pytest_runtest_logreport(types.SimpleNamespace(nodeid=i, when='call', outcome=outcome, duration=0.5))
+plugin.pytest_sessionfinish(None, 1)
+";
+
+#[test]
+fn local_runner_collects_what_the_plugin_reports() {
+    if Command::new("python3").arg("--version").output().is_err() {
+        eprintln!("python3 not found, skipping");
+        return;
+    }
+    let fixture = calc_repo();
+    let template = shell_words::join(["python3", "-c", FAKE_PYTEST, "{tests}"]);
+    let runner = LocalRunner::new(fixture.path(), template)
+        .env(
+            "PYTHONPATH",
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("python"),
+        )
+        .collect_results(true);
+
+    let result = runner
+        .run(&Selection::new(vec![
+            SelectedTest::new("tests/test_calc.py::test_add", "new test"),
+            SelectedTest::new("tests/test_calc.py::test_fail", "new test"),
+        ]))
+        .unwrap();
+
+    assert!(result.stderr.is_empty(), "{}", result.stderr);
+    assert_eq!(
+        result.collected,
+        [
+            "tests/test_calc.py::test_add",
+            "tests/test_calc.py::test_fail"
+        ]
+    );
+    assert_eq!(result.failed_tests(), ["tests/test_calc.py::test_fail"]);
+    assert_eq!(result.tests[0].duration_micros, 500_000);
+}
@@ -0,0 +1,110 @@
+use hackweek_instant_codecoverage::runner::{
+    command, render_command, EmitOnlyRunner, Runner, RunnerError, DEFAULT_COMMAND_TEMPLATE,
+};
+use hackweek_instant_codecoverage::{SelectedTest, Selection};
+
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+#[test]
+fn each_test_becomes_its_own_argument() {
+    let args = render_command(
+        DEFAULT_COMMAND_TEMPLATE,
+        &ids(&["tests/a b.py::test_x", "tests/$(rm -rf).py::test_y"]),
+    )
+    .unwrap();
+    assert_eq!(
+        args,
+        vec![
+            "coverage",
+            "run",
+            "-m",
+            "pytest",
+            "tests/a b.py::test_x",
+            "tests/$(rm -rf).py::test_y",
+        ]
+    );
+}
+
+#[test]
+fn option_like_and_control_character_ids_are_rejected() {
+    for id in ["--collect-only", "tests/a.py::test\nrm", ""] {
+        let result = render_command(DEFAULT_COMMAND_TEMPLATE, &ids(&[id]));
+        assert!(
+            matches!(result, Err(RunnerError::InvalidTestId(_))),
+            "{:?}",
+            id
+        );
+    }
+}
+
+#[test]
+fn tests_attached_to_a_test_option_may_look_like_options() {
+    let args = render_command(
+        "rspec {tests}",
+        &ids(&["--example=--adds", "spec/a_spec.rb"]),
+    );
+    assert_eq!(
+        args.unwrap(),
+        vec!["rspec", "--example=--adds", "spec/a_spec.rb"]
+    );
+
+    for id in ["--example=", "--example=adds\nrm"] {
+        let result = render_command("rspec {tests}", &ids(&[id]));
+        assert!(
+            matches!(result, Err(RunnerError::InvalidTestId(_))),
+            "{:?}",
+            id
+        );
+    }
+}
+
+#[test]
+fn placeholder_must_be_a_separate_argument() {
+    for template in ["pytest", "pytest --k={tests}", "pytest {tests} {tests}"] {
+        let result = render_command(template, &ids(&["t.py::test"]));
+        assert!(
+            matches!(result, Err(RunnerError::InvalidTemplate(..))),
+            "{}",
+            template
+        );
+    }
+}
+
+#[test]
+fn emitted_command_is_shell_quoted() {
+    let selection = Selection::new(vec![SelectedTest::new("tests/a b.py::test_x", "new test")]);
+    let result = EmitOnlyRunner::new("pytest {tests}")
+        .run(&selection)
+        .unwrap();
+    assert!(!result.executed);
+    assert_eq!(result.command, "pytest 'tests/a b.py::test_x'");
+}
+
+#[test]
+fn commands_run_the_program_directly() {
+    let args = render_command("python -m pytest {tests}", &ids(&["tests/a.py::test_x"])).unwrap();
+    let command = command(&args);
+    let rest: Vec<&std::ffi::OsStr> = command.get_args().collect();
+    assert_eq!(rest, vec!["-m", "pytest", "tests/a.py::test_x"]);
+}
+
+#[cfg(windows)]
+#[test]
+fn batch_shims_on_the_path_are_found() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("shimmed.cmd"), "@echo %1\r\n").unwrap();
+    let path = std::env::join_paths(
+        std::iter::once(dir.path().to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    std::env::set_var("PATH", path);
+
+    let output = command(&ids(&["shimmed", "tests/a.py::test_x"]))
+        .output()
+        .unwrap();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("tests/a.py::test_x"));
+}
@@ -0,0 +1,62 @@
+use hackweek_instant_codecoverage::sentry::{error_event, failure_event, Dsn, FailureTracker};
+use hackweek_instant_codecoverage::{RunResult, SelectedTest, Selection};
+
+fn failed() -> RunResult {
+    RunResult {
+        command: "pytest tests/test_a.py::test_a".to_string(),
+        executed: true,
+        exit_code: Some(1),
+        ..RunResult::default()
+    }
+}
+
+fn selection(id: &str) -> Selection {
+    Selection::new(vec![SelectedTest::new(id, "new test")])
+}
+
+#[test]
+fn dsn_parts_are_parsed() {
+    let dsn: Dsn = "https://abc123@o1.ingest.sentry.io/42".parse().unwrap();
+    assert_eq!(dsn.public_key, "abc123");
+    assert_eq!(
+        dsn.envelope_url(),
+        "https://o1.ingest.sentry.io/api/42/envelope/"
+    );
+    assert!(dsn.auth_header().contains("sentry_key=abc123"));
+
+    assert!("not a dsn".parse::<Dsn>().is_err());
+}
+
+#[test]
+fn only_recurring_failures_of_the_same_selection_are_reported() {
+    let tracker = FailureTracker::new(2);
+    let a = selection("tests/test_a.py::test_a");
+    let b = selection("tests/test_b.py::test_b");
+
+    assert_eq!(tracker.observe(&a, &failed()), None);
+    assert_eq!(tracker.observe(&b, &failed()), None);
+    assert_eq!(tracker.observe(&b, &failed()), Some(2));
+    // reported once per streak
+    assert_eq!(tracker.observe(&b, &failed()), None);
+    assert_eq!(tracker.observe(&b, &RunResult::default()), None);
+    assert_eq!(tracker.observe(&b, &failed()), None);
+}
+
+#[test]
+fn events_carry_the_selection_as_breadcrumbs() {
+    let a = selection("tests/test_a.py::test_a");
+
+    let event = failure_event(&a, &failed(), 3);
+    assert_eq!(
+        event["exception"]["values"][0]["type"],
+        "RecurringTestFailure"
+    );
+    assert_eq!(
+        event["breadcrumbs"]["values"][0]["message"],
+        "tests/test_a.py::test_a"
+    );
+
+    let event = error_event("boom", None);
+    assert_eq!(event["level"], "error");
+    assert_eq!(event["event_id"].as_str().unwrap().len(), 32);
+}
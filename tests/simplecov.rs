@@ -0,0 +1,68 @@
+use hackweek_instant_codecoverage::simplecov::{read, SimpleCovError};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// A result set covering `add` in `lib/calc.rb` and never `unused`, in the
+/// current format, with the old format for a second suite:
+///
+/// ```text
+/// 1 def add(a, b)
+/// 2   a + b
+/// 3 end
+/// 4
+/// 5 def unused
+/// 6   nil
+/// 7 end
+/// ```
+fn result_set(root: &Path) -> String {
+    let file = |path: &str| fs::canonicalize(root).unwrap().join(path);
+    serde_json::json!({
+        "RSpec": {
+            "coverage": {
+                file("lib/calc.rb").to_str().unwrap(): {
+                    "lines": [1, 2, null, null, 1, 0, null],
+                    "branches": {}
+                },
+                "/usr/lib/ruby/3.3.0/set.rb": {"lines": [1]}
+            },
+            "timestamp": 1700000000
+        },
+        "Minitest": {
+            "coverage": {
+                file("lib/util.rb").to_str().unwrap(): [1, 0]
+            },
+            "timestamp": 1700000000
+        }
+    })
+    .to_string()
+}
+
+#[test]
+fn reads_executed_lines_of_every_suite_outside_any_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".resultset.json");
+    fs::write(&path, result_set(dir.path())).unwrap();
+
+    let impact = read(&path, dir.path()).unwrap();
+
+    assert_eq!(impact.lines.len(), 1);
+    let files = &impact.lines[""];
+    let mut paths: Vec<_> = files.keys().collect();
+    paths.sort();
+    assert_eq!(paths, vec!["lib/calc.rb", "lib/util.rb"]);
+    assert_eq!(files["lib/calc.rb"], HashSet::from([1, 2, 5]));
+    assert_eq!(files["lib/util.rb"], HashSet::from([1]));
+}
+
+#[test]
+fn rejects_other_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(".resultset.json");
+    fs::write(&path, "{\"RSpec\": {\"lines\": []}}").unwrap();
+
+    assert!(matches!(
+        read(&path, dir.path()),
+        Err(SimpleCovError::Json { .. })
+    ));
+}
@@ -0,0 +1,80 @@
+use hackweek_instant_codecoverage::slack::{failures_text, messages, summary_text, SlackTarget};
+use hackweek_instant_codecoverage::{
+    FilePatchCoverage, PatchCoverage, RunResult, SelectedTest, Selection,
+};
+use std::collections::HashMap;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+#[test]
+fn bot_token_wins_over_webhook() {
+    let webhook = (
+        "SLACK_WEBHOOK_URL",
+        "https://hooks.slack.com/services/T/B/x",
+    );
+    assert_eq!(
+        SlackTarget::from_env(env(&[
+            ("SLACK_BOT_TOKEN", "xoxb-1"),
+            ("SLACK_CHANNEL", "#ci"),
+            webhook,
+        ])),
+        Some(SlackTarget::Bot {
+            token: "xoxb-1".to_string(),
+            channel: "#ci".to_string(),
+        })
+    );
+    assert_eq!(
+        SlackTarget::from_env(env(&[("SLACK_BOT_TOKEN", "xoxb-1"), webhook])),
+        Some(SlackTarget::Webhook(webhook.1.to_string()))
+    );
+    assert_eq!(SlackTarget::from_env(env(&[])), None);
+}
+
+#[test]
+fn failures_are_threaded_for_bots_and_inlined_for_webhooks() {
+    let selection = Selection::new(vec![SelectedTest::new(
+        "tests/test_calc.py::test_add",
+        "new test",
+    )]);
+    let result = RunResult {
+        executed: true,
+        exit_code: Some(1),
+        ..RunResult::default()
+    };
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![1, 2],
+            covered_lines: vec![1],
+            ..FilePatchCoverage::default()
+        }],
+    };
+    let summary = summary_text(&selection, &result, &coverage);
+    assert_eq!(
+        summary,
+        ":red_circle: Selected tests failed (1 selected), patch coverage *50.0%* (1/2 changed lines)"
+    );
+    let failures = failures_text(&["tests/test_calc.py::test_add"]).unwrap();
+    assert_eq!(failures, "1 failing:\n• `tests/test_calc.py::test_add`");
+    assert_eq!(failures_text(&[]), None);
+
+    let bot = SlackTarget::Bot {
+        token: "xoxb-1".to_string(),
+        channel: "#ci".to_string(),
+    };
+    let posted = messages(&bot, &summary, Some(&failures));
+    assert_eq!(posted.len(), 2);
+    assert_eq!(posted[0]["channel"], "#ci");
+    assert_eq!(posted[1]["text"], failures.as_str());
+
+    let webhook = SlackTarget::Webhook("https://hooks.slack.com/x".to_string());
+    let posted = messages(&webhook, &summary, Some(&failures));
+    assert_eq!(posted.len(), 1);
+    assert_eq!(posted[0]["text"], format!("{}\n{}", summary, failures));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::calc_repo;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::state::Snapshot;
+use hackweek_instant_codecoverage::statefile::StateDocument;
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::statefile::{self, STATE_DIR};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::EngineBuilder;
+use hackweek_instant_codecoverage::{FilePatchCoverage, PatchCoverage, RunResult, Selection};
+#[cfg(feature = "python")]
+use serde_json::Value;
+
+#[cfg(feature = "python")]
+#[test]
+fn each_cycle_rewrites_the_state_file() {
+    let fixture = calc_repo();
+    let engine = EngineBuilder::new(fixture.path())
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .state_file(true)
+        .build()
+        .unwrap();
+    fixture.write("tests/test_new.py", "def test_new():\n    pass\n");
+
+    engine.run_once().unwrap();
+
+    let path = statefile::path(fixture.path());
+    let state: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(state["version"], 1);
+    assert_eq!(state["cycle"], 1);
+    assert_eq!(state["running"], false);
+    assert_eq!(
+        state["selection"]["tests"][0]["id"],
+        "tests/test_new.py::test_new"
+    );
+    assert_eq!(
+        state["last_result"]["command"],
+        "pytest tests/test_new.py::test_new"
+    );
+    let entries: Vec<String> = std::fs::read_dir(fixture.path().join(STATE_DIR))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(entries.len(), 2, "{:?}", entries);
+    assert!(entries.contains(&".gitignore".to_string()));
+
+    assert!(fixture
+        .repo
+        .is_path_ignored(format!("{}/state.json", STATE_DIR))
+        .unwrap());
+}
+
+#[test]
+fn document_lists_uncovered_lines_and_failures_per_file() {
+    let fixture = calc_repo();
+    let snapshot = Snapshot {
+        cycle: 3,
+        selection: Some(Selection::default()),
+        last_result: Some(RunResult {
+            executed: true,
+            exit_code: Some(1),
+            stdout: "FAILED tests/test_calc.py::test_add - assert 4 == 3\n".to_string(),
+            ..RunResult::default()
+        }),
+        ..Snapshot::default()
+    };
+    let coverage = PatchCoverage {
+        files: vec![FilePatchCoverage {
+            path: "calc.py".to_string(),
+            changed_lines: vec![1, 2],
+            covered_lines: vec![1],
+            ..FilePatchCoverage::default()
+        }],
+    };
+
+    let document =
+        serde_json::to_value(StateDocument::new(fixture.path(), &snapshot, &coverage)).unwrap();
+
+    assert_eq!(document["cycle"], 3);
+    assert_eq!(document["files"][0]["path"], "calc.py");
+    assert_eq!(document["files"][0]["uncovered"], serde_json::json!([2]));
+    assert_eq!(document["files"][1]["path"], "tests/test_calc.py");
+    assert_eq!(document["files"][1]["failing"][0]["line"], 4);
+}
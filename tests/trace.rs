@@ -0,0 +1,98 @@
+use hackweek_instant_codecoverage::runner::{LocalRunner, Runner};
+use hackweek_instant_codecoverage::trace::{resource_spans, CycleTrace, TraceContext};
+use hackweek_instant_codecoverage::{SelectedTest, Selection};
+use serde_json::json;
+
+#[test]
+fn traceparent_round_trips_and_rejects_malformed_values() {
+    let context = TraceContext {
+        trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+        span_id: 0x00f067aa0ba902b7,
+    };
+    let traceparent = context.traceparent();
+    assert_eq!(
+        traceparent,
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    );
+    assert_eq!(TraceContext::parse(&traceparent), Some(context));
+
+    for bad in [
+        "",
+        "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    ] {
+        assert_eq!(TraceContext::parse(bad), None, "{:?}", bad);
+    }
+}
+
+#[test]
+fn finished_trace_nests_spans_under_the_cycle_and_keeps_remote_spans() {
+    let trace = CycleTrace::start();
+    let root = trace.context();
+    let mut run_context = None;
+    trace
+        .span("run", |context| {
+            run_context = Some(context);
+            Ok::<_, String>(())
+        })
+        .unwrap();
+    let _ = trace.span("select", |_| Err::<(), _>("no baseline".to_string()));
+    trace.set_attribute("tests.selected", 2);
+    let remote = json!({ "resource": {}, "scopeSpans": [{ "spans": [{ "name": "pytest" }] }] });
+    trace.ingest(std::slice::from_ref(&remote));
+
+    let run_context = run_context.unwrap();
+    assert_eq!(run_context.trace_id, root.trace_id);
+    assert_ne!(run_context.span_id, root.span_id);
+
+    let payload = trace.finish("test-service", None);
+    let resource_spans = payload["resourceSpans"].as_array().unwrap();
+    assert_eq!(resource_spans.len(), 2);
+    assert_eq!(resource_spans[1], remote);
+    assert_eq!(
+        resource_spans[0]["resource"]["attributes"][0]["value"]["stringValue"],
+        "test-service"
+    );
+
+    let spans = resource_spans[0]["scopeSpans"][0]["spans"]
+        .as_array()
+        .unwrap();
+    let names: Vec<&str> = spans.iter().map(|s| s["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["cycle", "run", "select"]);
+    let root_span_id = format!("{:016x}", root.span_id);
+    assert_eq!(spans[0]["spanId"], root_span_id.as_str());
+    assert!(spans[0].get("parentSpanId").is_none());
+    assert_eq!(spans[0]["attributes"][0]["key"], "tests.selected");
+    assert_eq!(spans[0]["attributes"][0]["value"]["stringValue"], "2");
+    for span in &spans[1..] {
+        assert_eq!(span["parentSpanId"], root_span_id.as_str());
+        assert_eq!(span["traceId"], format!("{:032x}", root.trace_id).as_str());
+    }
+    assert_eq!(spans[1]["status"]["code"], 1);
+    assert_eq!(spans[2]["status"]["code"], 2);
+    assert_eq!(spans[2]["status"]["message"], "no baseline");
+}
+
+#[test]
+fn resource_spans_are_read_only_from_otlp_exports() {
+    let body = r#"{"resourceSpans": [{"scopeSpans": []}]}"#;
+    assert_eq!(resource_spans(body), vec![json!({ "scopeSpans": [] })]);
+    assert!(resource_spans("3 passed in 0.1s").is_empty());
+    assert!(resource_spans(r#"{"stdout": "ok"}"#).is_empty());
+}
+
+#[test]
+fn local_runner_passes_the_traceparent_to_the_test_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let runner = LocalRunner::new(dir.path(), "sh -c 'echo \"$TRACEPARENT\"' sh {tests}");
+    let mut selection = Selection::new(vec![SelectedTest::new("tests/test_a.py::test_a", "new")]);
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    selection.traceparent = Some(traceparent.to_string());
+
+    let result = runner.run(&selection).unwrap();
+
+    assert_eq!(result.stdout.trim(), traceparent);
+}
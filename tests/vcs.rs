@@ -0,0 +1,205 @@
+mod common;
+
+use common::{calc_repo, CALC, TEST_CALC};
+#[cfg(feature = "python")]
+use hackweek_instant_codecoverage::runner::EmitOnlyRunner;
+use hackweek_instant_codecoverage::vcs::{CliVcs, VcsError, VcsKind};
+use hackweek_instant_codecoverage::{EngineBuilder, EngineError};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// A stand-in for `hg` serving the base revision from `snapshot`, which
+/// logs the command of each call to `calls` in `dir`.
+fn fake_hg(dir: &Path, snapshot: &Path) -> PathBuf {
+    let script = format!(
+        r#"#!/bin/sh
+snap='{}'
+echo "$1" >> '{}'
+case "$1" in
+  log) case "$3" in
+         .) nodes='f00dfeed' ;;
+         'f00dfeed+beefcafe') nodes='f00dfeed beefcafe' ;;
+         *) echo "abort: unknown revision '$3'" >&2; exit 255 ;;
+       esac
+       for node in $nodes; do printf "$(printf '%s' "$5" | sed "s/{{node}}/$node/")"; done ;;
+  files) cd "$snap" && find . -type f | sed 's|^\./||' ;;
+  cat) cat "$snap/${{4#path:}}" ;;
+  diff) shift 6
+        for pattern in "$@"; do
+          f=${{pattern#path:}}
+          echo "diff --git a/$f b/$f"
+          diff -u --label /dev/null --label "b/$f" /dev/null "$snap/$f"
+        done ;;
+  status)
+    for f in $( ( (cd "$snap" && find . -type f); find . -path ./.hg -prune -o -type f -print) | sed 's|^\./||' | sort -u); do
+      [ -f "$snap/$f" ] || case "$4" in *u*) ;; *) continue ;; esac
+      cmp -s "$snap/$f" "$f" || echo "$f"
+    done ;;
+esac
+exit 0
+"#,
+        snapshot.display(),
+        dir.join("calls").display()
+    );
+    let path = dir.join("hg");
+    fs::write(&path, script).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+fn write(root: &Path, path: &str, content: &str) {
+    let full = root.join(path);
+    fs::create_dir_all(full.parent().unwrap()).unwrap();
+    fs::write(full, content).unwrap();
+}
+
+/// A Mercurial checkout of the calc project and the fake `hg` for it.
+fn hg_checkout() -> (TempDir, TempDir, PathBuf) {
+    let checkout = tempfile::tempdir().unwrap();
+    let tools = tempfile::tempdir().unwrap();
+    let snapshot = tools.path().join("snapshot");
+    for root in [checkout.path(), &snapshot] {
+        write(root, "calc.py", CALC);
+        write(root, "tests/test_calc.py", TEST_CALC);
+    }
+    fs::create_dir(checkout.path().join(".hg")).unwrap();
+    let hg = fake_hg(tools.path(), &snapshot);
+    (checkout, tools, hg)
+}
+
+#[test]
+fn detects_the_system_at_the_root() {
+    let git = calc_repo();
+    assert_eq!(VcsKind::detect(git.path()), Some(VcsKind::Git));
+
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(VcsKind::detect(dir.path()), None);
+    fs::create_dir(dir.path().join(".hg")).unwrap();
+    assert_eq!(VcsKind::detect(dir.path()), Some(VcsKind::Mercurial));
+    fs::create_dir(dir.path().join(".jj")).unwrap();
+    assert_eq!(VcsKind::detect(dir.path()), Some(VcsKind::Jujutsu));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn linked_worktree_selects_its_own_changes() {
+    let fixture = calc_repo();
+    let dir = tempfile::tempdir().unwrap();
+    let worktree = dir.path().join("feature");
+    fixture.repo.worktree("feature", &worktree, None).unwrap();
+    fs::write(
+        worktree.join("tests/test_other.py"),
+        "def test_other():\n    pass\n",
+    )
+    .unwrap();
+
+    let engine = EngineBuilder::new(&worktree)
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+
+    assert_eq!(VcsKind::detect(&worktree), Some(VcsKind::Git));
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_other.py::test_other"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn mercurial_checkout_selects_new_tests() {
+    let (checkout, _tools, hg) = hg_checkout();
+    let engine = EngineBuilder::new(checkout.path())
+        .vcs(CliVcs::new(checkout.path(), VcsKind::Mercurial).binary(hg.to_str().unwrap()))
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+    assert_eq!(engine.base_commit().unwrap(), "f00dfeed");
+    assert!(engine.select().unwrap().is_empty());
+
+    write(
+        checkout.path(),
+        "tests/test_calc.py",
+        &format!(
+            "{}\n\ndef test_add_zero():\n    assert add(0, 0) == 0\n",
+            TEST_CALC
+        ),
+    );
+
+    assert_eq!(
+        engine.select().unwrap().ids(),
+        vec!["tests/test_calc.py::test_add_zero"]
+    );
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn mercurial_base_is_read_in_one_command() {
+    let (checkout, tools, hg) = hg_checkout();
+    let engine = EngineBuilder::new(checkout.path())
+        .vcs(CliVcs::new(checkout.path(), VcsKind::Mercurial).binary(hg.to_str().unwrap()))
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+
+    assert!(engine.select().unwrap().is_empty());
+
+    let calls = fs::read_to_string(tools.path().join("calls")).unwrap();
+    assert_eq!(calls.lines().filter(|call| *call == "diff").count(), 1);
+    assert_eq!(calls.lines().filter(|call| *call == "cat").count(), 0);
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn mercurial_patch_coverage_includes_untracked_files() {
+    let (checkout, _tools, hg) = hg_checkout();
+    let engine = EngineBuilder::new(checkout.path())
+        .vcs(CliVcs::new(checkout.path(), VcsKind::Mercurial).binary(hg.to_str().unwrap()))
+        .runner(EmitOnlyRunner::new("pytest {tests}"))
+        .build()
+        .unwrap();
+
+    write(
+        checkout.path(),
+        "tests/test_other.py",
+        "def test_other():\n    pass\n",
+    );
+
+    let coverage = engine.patch_coverage().unwrap();
+    let files: Vec<&str> = coverage.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(files, vec!["tests/test_other.py"]);
+}
+
+#[test]
+fn mercurial_base_naming_several_revisions_is_rejected() {
+    let (checkout, _tools, hg) = hg_checkout();
+
+    let result = EngineBuilder::new(checkout.path())
+        .vcs(CliVcs::new(checkout.path(), VcsKind::Mercurial).binary(hg.to_str().unwrap()))
+        .base("f00dfeed+beefcafe")
+        .build();
+
+    match result {
+        Err(EngineError::Vcs(VcsError::InvalidBase { message, .. })) => {
+            assert_eq!(message, "names more than one revision")
+        }
+        _ => panic!("expected an invalid base"),
+    }
+}
+
+#[test]
+fn unknown_mercurial_base_is_rejected() {
+    let (checkout, _tools, hg) = hg_checkout();
+
+    let result = EngineBuilder::new(checkout.path())
+        .vcs(CliVcs::new(checkout.path(), VcsKind::Mercurial).binary(hg.to_str().unwrap()))
+        .base("nope")
+        .build();
+
+    assert!(matches!(
+        result,
+        Err(EngineError::Vcs(VcsError::InvalidBase { .. }))
+    ));
+}
@@ -0,0 +1,307 @@
+use hackweek_instant_codecoverage::watch::{self, IgnoreList, WatchControl};
+use notify::event::{
+    AccessKind, CreateKind, DataChange, EventKind, MetadataKind, ModifyKind, RemoveKind,
+};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const MOUNTS: &str = "\
+overlay / overlay rw,relatime 0 0
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+server:/export /home nfs4 rw,relatime,vers=4.2 0 0
+C:\\134 /mnt/c 9p rw,noatime 0 0
+/dev/sda1 /home/me/local\\040disk ext4 rw,relatime 0 0
+";
+
+#[test]
+fn noisy_directories_are_always_ignored() {
+    let ignore = IgnoreList::default();
+    for path in [
+        ".git/index",
+        "pkg/__pycache__/calc.cpython-312.pyc",
+        ".venv/lib/python3.12/site-packages/six.py",
+        ".pytest_cache/v/cache/lastfailed",
+        "htmlcov/index.html",
+        ".coverage",
+        ".coverage.host.1234.567890",
+    ] {
+        assert!(ignore.is_ignored(Path::new(path)), "{}", path);
+    }
+    assert!(!ignore.is_ignored(Path::new("pkg/calc.py")));
+    assert!(!ignore.is_ignored(Path::new("tests/test_coverage.py")));
+}
+
+#[test]
+fn configured_globs_skip_everything_below_them() {
+    let ignore = IgnoreList::new(&["build", "docs/*/generated"]).unwrap();
+
+    assert!(ignore.is_ignored(Path::new("build")));
+    assert!(ignore.is_ignored(Path::new("build/lib/calc.py")));
+    assert!(ignore.is_ignored(Path::new("docs/api/generated/calc.py")));
+    assert!(!ignore.is_ignored(Path::new("src/build.py")));
+    assert!(!ignore.is_ignored(Path::new("docs/api/calc.py")));
+}
+
+#[test]
+fn directories_can_be_limited_by_depth_or_watched_alone() {
+    let ignore = IgnoreList::default()
+        .max_depth(2)
+        .non_recursive(&["vendor"])
+        .unwrap();
+
+    assert!(!ignore.skips_dir(Path::new("src/pkg")));
+    assert!(ignore.skips_dir(Path::new("src/pkg/sub")));
+    assert!(!ignore.skips_dir(Path::new("vendor")));
+    assert!(ignore.skips_dir(Path::new("vendor/six")));
+    assert!(ignore.skips_dir(Path::new(".venv")));
+    // files are still seen in the directories that are watched
+    assert!(!ignore.is_ignored(Path::new("vendor/six.py")));
+}
+
+#[test]
+fn directories_beyond_the_maximum_depth_are_not_watched() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    fs::create_dir_all(root.join("pkg/deep")).unwrap();
+    let (tx, rx) = mpsc::channel();
+    let watched = root.clone();
+    thread::spawn(move || {
+        watch::watch(
+            &[watched],
+            Duration::from_millis(50),
+            &IgnoreList::default().max_depth(1),
+            Some(Duration::from_millis(50)),
+            &WatchControl::default(),
+            |path| path.extension().is_some_and(|ext| ext == "py"),
+            move |changed| tx.send(changed.to_vec()).unwrap(),
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    fs::write(root.join("pkg/deep/calc.py"), "VALUE = 1\n").unwrap();
+    assert!(rx.recv_timeout(Duration::from_secs(1)).is_err());
+    fs::write(root.join("pkg/calc.py"), "VALUE = 1\n").unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+        vec![root.join("pkg/calc.py")]
+    );
+}
+
+#[test]
+fn mount_type_is_that_of_the_closest_mount_point() {
+    let kind = |path: &str| watch::mount_type(MOUNTS, Path::new(path));
+
+    assert_eq!(kind("/srv/app"), Some("overlay"));
+    assert_eq!(kind("/home/me/app"), Some("nfs4"));
+    assert_eq!(kind("/mnt/c/Users/me/app"), Some("9p"));
+    assert_eq!(kind("/home/me/local disk/app"), Some("ext4"));
+    assert_eq!(kind("relative"), None);
+}
+
+#[test]
+fn polling_watcher_sees_new_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let watched = root.clone();
+    thread::spawn(move || {
+        watch::watch(
+            &[watched],
+            Duration::from_millis(50),
+            &IgnoreList::default(),
+            Some(Duration::from_millis(50)),
+            &WatchControl::default(),
+            |path| path.extension().is_some_and(|ext| ext == "py"),
+            move |changed| tx.send(changed.to_vec()).unwrap(),
+        )
+    });
+    // let the first scan finish before changing anything
+    thread::sleep(Duration::from_millis(500));
+
+    fs::create_dir(root.join("pkg")).unwrap();
+    fs::write(root.join("pkg/calc.py"), "VALUE = 1\n").unwrap();
+    fs::write(root.join("pkg/notes.txt"), "not python\n").unwrap();
+
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+        vec![root.join("pkg/calc.py")]
+    );
+}
+
+#[test]
+fn changes_under_every_root_start_a_cycle() {
+    let checkout = tempfile::tempdir().unwrap();
+    let shared = tempfile::tempdir().unwrap();
+    let roots = vec![
+        checkout.path().canonicalize().unwrap(),
+        shared.path().canonicalize().unwrap(),
+    ];
+    let (tx, rx) = mpsc::channel();
+    let watched = roots.clone();
+    thread::spawn(move || {
+        watch::watch(
+            &watched,
+            Duration::from_millis(50),
+            &IgnoreList::default(),
+            Some(Duration::from_millis(50)),
+            &WatchControl::default(),
+            |path| path.extension().is_some_and(|ext| ext == "py"),
+            move |changed| tx.send(changed.to_vec()).unwrap(),
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    fs::write(roots[1].join("helpers.py"), "VALUE = 1\n").unwrap();
+
+    assert!(rx.recv_timeout(Duration::from_secs(10)).is_ok());
+}
+
+#[test]
+fn changes_while_paused_start_one_cycle_on_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let control = WatchControl::default();
+    control.pause();
+    let (tx, rx) = mpsc::channel();
+    let (watched, paused) = (root.clone(), control.clone());
+    thread::spawn(move || {
+        watch::watch(
+            &[watched],
+            Duration::from_millis(50),
+            &IgnoreList::default(),
+            Some(Duration::from_millis(50)),
+            &paused,
+            |path| path.extension().is_some_and(|ext| ext == "py"),
+            move |changed| tx.send(changed.to_vec()).unwrap(),
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    fs::write(root.join("calc.py"), "VALUE = 1\n").unwrap();
+    fs::write(root.join("other.py"), "VALUE = 2\n").unwrap();
+    assert!(rx.recv_timeout(Duration::from_secs(1)).is_err());
+
+    control.resume();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+        vec![root.join("calc.py"), root.join("other.py")]
+    );
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
+
+#[test]
+fn changes_during_a_cycle_are_collected_and_run_next() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let (seen_tx, seen) = mpsc::channel();
+    let (release, gate) = mpsc::channel::<()>();
+    let watched = root.clone();
+    thread::spawn(move || {
+        watch::watch(
+            &[watched],
+            Duration::from_millis(50),
+            &IgnoreList::default(),
+            Some(Duration::from_millis(50)),
+            &WatchControl::default(),
+            move |path| {
+                seen_tx.send(path.to_path_buf()).unwrap();
+                path.extension().is_some_and(|ext| ext == "py")
+            },
+            move |changed| {
+                tx.send(changed.to_vec()).unwrap();
+                gate.recv().unwrap();
+            },
+        )
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    fs::write(root.join("calc.py"), "VALUE = 1\n").unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+        vec![root.join("calc.py")]
+    );
+    // the first cycle is still running while this change is seen
+    fs::write(root.join("other.py"), "VALUE = 2\n").unwrap();
+    let other = root.join("other.py");
+    while seen.recv_timeout(Duration::from_secs(10)).unwrap() != other {}
+    assert!(rx.try_recv().is_err());
+
+    release.send(()).unwrap();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+        vec![other]
+    );
+    release.send(()).unwrap();
+}
+
+#[test]
+fn typing_p_toggles_pausing() {
+    let control = WatchControl::default();
+
+    watch::read_commands("p\n".as_bytes(), &control);
+    assert!(control.is_paused());
+    watch::read_commands("\np\n".as_bytes(), &control);
+    assert!(!control.is_paused());
+}
+
+#[test]
+fn enter_or_r_runs_again_without_a_change() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().canonicalize().unwrap();
+    let control = WatchControl::default();
+    control.pause();
+    let (tx, rx) = mpsc::channel();
+    let (watched, commands) = (root.clone(), control.clone());
+    thread::spawn(move || {
+        watch::watch(
+            &[watched],
+            Duration::from_millis(50),
+            &IgnoreList::default(),
+            Some(Duration::from_millis(50)),
+            &commands,
+            |_| true,
+            move |changed| tx.send(changed.to_vec()).unwrap(),
+        )
+    });
+
+    watch::read_commands("\n".as_bytes(), &control);
+    assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap().is_empty());
+    watch::read_commands("r\n".as_bytes(), &control);
+    assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap().is_empty());
+    assert!(rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn sigusr1_toggles_pausing() {
+    let control = WatchControl::default();
+    watch::toggle_on_signal(&control).unwrap();
+
+    unsafe { libc::raise(libc::SIGUSR1) };
+    assert!(control.is_paused());
+    unsafe { libc::raise(libc::SIGUSR1) };
+    assert!(!control.is_paused());
+}
+
+#[test]
+fn only_content_changes_count() {
+    for kind in [
+        EventKind::Create(CreateKind::File),
+        EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)),
+        EventKind::Remove(RemoveKind::File),
+    ] {
+        assert!(watch::is_content_change(&kind), "{:?}", kind);
+    }
+    for kind in [
+        EventKind::Access(AccessKind::Any),
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)),
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime)),
+    ] {
+        assert!(!watch::is_content_change(&kind), "{:?}", kind);
+    }
+}